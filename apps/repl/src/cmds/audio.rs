@@ -12,6 +12,7 @@ pub struct Audio {
     framecount: u32,
     play_sample: f32, // count of play samples generated. in f32 to avoid int<->f32 conversions
     freq: f32,
+    sample_rate: SampleRate,
 }
 impl Audio {
     pub fn new(xns: &xous_names::XousNames) -> Self {
@@ -22,12 +23,12 @@ impl Audio {
             framecount: 0,
             play_sample: 0.0,
             freq: 440.0,
+            sample_rate: SampleRate::Fs8000,
         }
     }
 }
 
 const STOP_ID: usize = 1;
-const SAMPLE_RATE_HZ: f32 = 8000.0;
 // note to self: A4 = 440.0, E4 = 329.63, C4 = 261.63
 
 impl<'a> ShellCmdApi<'a> for Audio {
@@ -57,8 +58,14 @@ impl<'a> ShellCmdApi<'a> for Audio {
                     if duration > 10.0 {
                         duration = 10.0; // sanity check the duration so we don't go nuts
                     }
+                    self.sample_rate = if let Some(rate_str) = tokens.next() {
+                        rate_str.parse::<u32>().ok().and_then(|hz| SampleRate::from_hz(hz)).unwrap_or(SampleRate::Fs8000)
+                    } else {
+                        SampleRate::Fs8000
+                    };
 
-                    env.codec.setup_8k_stream().expect("couldn't set the CODEC to expected defaults");
+                    let hw_rate = env.codec.setup_stream(self.sample_rate).expect("couldn't set the CODEC to expected defaults");
+                    log::info!("tone at {}Hz, codec running at {}Hz", self.sample_rate.hz(), hw_rate.hz());
                     env.ticktimer.sleep_ms(50).unwrap();
 
                     env.codec.set_speaker_volume(VolumeOps::RestoreDefault, None).unwrap();
@@ -115,7 +122,7 @@ impl<'a> ShellCmdApi<'a> for Audio {
                     for _ in 0..frames_to_push {
                         let mut frame: [u32; codec::FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
                         // put the "expensive" f32 comparison outside the cosine wave table computation loop
-                        let omega = self.freq * 2.0 * std::f32::consts::PI / SAMPLE_RATE_HZ;
+                        let omega = self.freq * 2.0 * std::f32::consts::PI / self.sample_rate.hz() as f32;
                         for sample in frame.iter_mut() {
                             let raw_sine: i16 = (AMPLITUDE * f32::cos( self.play_sample * omega ) * i16::MAX as f32) as i16;
                             let left = raw_sine as u16;
@@ -0,0 +1,68 @@
+pub(crate) const SERVER_NAME_AUDIO_PLAYER: &str = "_Audio file player_";
+
+/// matches the dictionary and key name limits of the PDDB
+pub(crate) const DICT_NAME_LEN: usize = 111;
+pub(crate) const KEY_NAME_LEN: usize = 95;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum Opcode {
+    /// Start playing a key out of the PDDB. Lends a `PlayRequest`.
+    Play,
+    /// Pause a playback; audio already handed to the codec finishes playing first.
+    Pause,
+    /// Resume a paused playback
+    Resume,
+    /// Move a playback to a position, in milliseconds from the start
+    Seek,
+    /// Stop a playback immediately; the end-of-stream callback reports `PlaybackEnd::Stopped`.
+    Stop,
+    /// Get the position and duration of a playback, in milliseconds
+    Position,
+    /// Sent by a playback thread when it exits (internal)
+    PlaybackDone,
+}
+
+/// Opcodes of the callback server in the client's process
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum CbOp {
+    EndOfStream,
+    Quit,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum PlayerError {
+    /// the key (or its dictionary) doesn't exist, or the PDDB isn't mounted
+    KeyNotFound,
+    /// not a WAV file, or one with an encoding, channel count or sample rate we can't play
+    UnsupportedFormat,
+    /// all the player's (or the codec's) playback slots are in use
+    TooManyPlaybacks,
+    InternalError,
+}
+
+/// Why a playback ended, as reported to the end-of-stream callback
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+pub enum PlaybackEnd {
+    /// the whole file was played
+    Finished,
+    /// `stop()` was called
+    Stopped,
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PlayRequest {
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub priority: codec::StreamPriority,
+    /// 0-100
+    pub volume: u8,
+    /// server in the client's process that receives the end-of-stream callback, if any
+    pub cb_sid: Option<[u32; 4]>,
+    /// set by the server on success
+    pub handle: Option<u32>,
+    /// set by the server on failure
+    pub error: Option<PlayerError>,
+}
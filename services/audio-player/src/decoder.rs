@@ -0,0 +1,426 @@
+use crate::api::*;
+use codec::{FIFO_DEPTH, ZERO_PCM};
+use std::io::{Read, Seek, SeekFrom};
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+
+const IMA_INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45,
+    50, 55, 60, 66, 73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307,
+    337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899,
+    15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Encoding {
+    Pcm8,
+    Pcm16,
+    ImaAdpcm {
+        /// bytes per block, covering all channels
+        block_align: usize,
+        /// samples per channel in a block
+        samples_per_block: usize,
+    },
+}
+
+/// What we learned from a WAV file's header.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct WavInfo {
+    pub encoding: Encoding,
+    pub channels: usize,
+    pub sample_rate: u32,
+    /// offset of the first byte of sample data
+    pub data_start: u64,
+    pub data_len: u64,
+}
+impl WavInfo {
+    /// total length, in samples per channel
+    pub fn samples(&self) -> u64 {
+        match self.encoding {
+            Encoding::Pcm8 => self.data_len / self.channels as u64,
+            Encoding::Pcm16 => self.data_len / (2 * self.channels as u64),
+            Encoding::ImaAdpcm { block_align, samples_per_block } => {
+                let whole = self.data_len / block_align as u64;
+                let partial = self.data_len % block_align as u64;
+                // a short final block still starts with a full header, and then carries two samples per byte
+                let header = 4 * self.channels as u64;
+                let tail = if partial > header {
+                    1 + (partial - header) * 2 / self.channels as u64
+                } else if partial == header {
+                    1
+                } else {
+                    0
+                };
+                whole * samples_per_block as u64 + tail
+            }
+        }
+    }
+    pub fn duration_ms(&self) -> u64 {
+        self.samples() * 1000 / self.sample_rate as u64
+    }
+}
+
+fn read_fully<R: Read>(src: &mut R, buf: &mut [u8]) -> usize {
+    let mut total = 0;
+    while total < buf.len() {
+        match src.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(e) => {
+                log::error!("read error while decoding audio: {:?}", e);
+                break;
+            }
+        }
+    }
+    total
+}
+
+/// Walks the RIFF chunks of a WAV file, and extracts the format and location of the sample data.
+pub(crate) fn parse_header<R: Read + Seek>(src: &mut R) -> Result<WavInfo, PlayerError> {
+    let mut riff = [0u8; 12];
+    src.seek(SeekFrom::Start(0)).or(Err(PlayerError::UnsupportedFormat))?;
+    if read_fully(src, &mut riff) != riff.len() || &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+        return Err(PlayerError::UnsupportedFormat);
+    }
+    let mut format: Option<(u16, usize, u32, usize, usize)> = None;
+    let mut offset = 12u64;
+    loop {
+        let mut chunk = [0u8; 8];
+        if read_fully(src, &mut chunk) != chunk.len() {
+            return Err(PlayerError::UnsupportedFormat);
+        }
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        offset += 8;
+        match &chunk[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                if len < 16 || read_fully(src, &mut fmt) != fmt.len() {
+                    return Err(PlayerError::UnsupportedFormat);
+                }
+                let tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]) as usize;
+                let rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                let block_align = u16::from_le_bytes([fmt[12], fmt[13]]) as usize;
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]) as usize;
+                format = Some((tag, channels, rate, block_align, bits));
+            }
+            b"data" => {
+                let (tag, channels, sample_rate, block_align, bits) = format.ok_or(PlayerError::UnsupportedFormat)?;
+                if channels < 1 || channels > 2 || codec::SampleRate::from_hz(sample_rate).is_none() {
+                    return Err(PlayerError::UnsupportedFormat);
+                }
+                let encoding = match (tag, bits) {
+                    (WAVE_FORMAT_PCM, 8) => Encoding::Pcm8,
+                    (WAVE_FORMAT_PCM, 16) => Encoding::Pcm16,
+                    (WAVE_FORMAT_IMA_ADPCM, 4) if block_align > 4 * channels && block_align % (4 * channels) == 0 => {
+                        Encoding::ImaAdpcm {
+                            block_align,
+                            samples_per_block: (block_align - 4 * channels) * 2 / channels + 1,
+                        }
+                    }
+                    _ => return Err(PlayerError::UnsupportedFormat),
+                };
+                return Ok(WavInfo {
+                    encoding,
+                    channels,
+                    sample_rate,
+                    data_start: offset,
+                    data_len: len,
+                });
+            }
+            _ => {}
+        }
+        // chunks are padded to an even length
+        offset += len + (len & 1);
+        src.seek(SeekFrom::Start(offset)).or(Err(PlayerError::UnsupportedFormat))?;
+    }
+}
+
+fn stereo(l: i16, r: i16) -> u32 {
+    (l as u16 as u32) | (r as u16 as u32) << 16
+}
+
+struct ImaChannel {
+    predictor: i32,
+    index: i32,
+}
+impl ImaChannel {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = IMA_STEP_TABLE[self.index as usize];
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 { diff += step >> 2; }
+        if nibble & 2 != 0 { diff += step >> 1; }
+        if nibble & 4 != 0 { diff += step; }
+        if nibble & 8 != 0 {
+            self.predictor -= diff;
+        } else {
+            self.predictor += diff;
+        }
+        self.predictor = self.predictor.max(i16::MIN as i32).min(i16::MAX as i32);
+        self.index = (self.index + IMA_INDEX_TABLE[nibble as usize & 0xF] as i32).max(0).min(88);
+        self.predictor as i16
+    }
+}
+
+/// Decodes a WAV file into stereo frames for the codec. Mono files are played on both channels.
+pub(crate) struct Decoder<R: Read + Seek> {
+    src: R,
+    info: WavInfo,
+    /// position of the next sample to be decoded, in samples per channel
+    pos: u64,
+    /// the decoded samples of the current chunk of the file
+    chunk: Vec<u32>,
+    chunk_pos: usize,
+    raw: Vec<u8>,
+}
+
+impl<R: Read + Seek> Decoder<R> {
+    pub fn new(mut src: R) -> Result<Decoder<R>, PlayerError> {
+        let info = parse_header(&mut src)?;
+        let mut decoder = Decoder {
+            src,
+            info,
+            pos: 0,
+            chunk: Vec::new(),
+            chunk_pos: 0,
+            raw: Vec::new(),
+        };
+        decoder.seek_sample(0);
+        Ok(decoder)
+    }
+    pub fn info(&self) -> &WavInfo {
+        &self.info
+    }
+    pub fn position_ms(&self) -> u64 {
+        self.pos * 1000 / self.info.sample_rate as u64
+    }
+    /// Moves playback to `ms` from the start; seeking past the end puts the decoder at the end.
+    pub fn seek_ms(&mut self, ms: u64) {
+        let sample = (ms * self.info.sample_rate as u64 / 1000).min(self.info.samples());
+        self.seek_sample(sample);
+    }
+    fn seek_sample(&mut self, sample: u64) {
+        self.chunk.clear();
+        self.chunk_pos = 0;
+        let byte = match self.info.encoding {
+            Encoding::Pcm8 => sample * self.info.channels as u64,
+            Encoding::Pcm16 => sample * 2 * self.info.channels as u64,
+            Encoding::ImaAdpcm { block_align, samples_per_block } => {
+                // ADPCM can only be entered at a block boundary; decode the block and skip forward within it
+                let block = sample / samples_per_block as u64;
+                self.pos = block * samples_per_block as u64;
+                self.src.seek(SeekFrom::Start(self.info.data_start + block * block_align as u64)).ok();
+                let skip = (sample - self.pos) as usize;
+                if skip > 0 && self.fill_chunk() {
+                    self.chunk_pos = skip.min(self.chunk.len());
+                    self.pos += self.chunk_pos as u64;
+                }
+                return;
+            }
+        };
+        self.pos = sample;
+        self.src.seek(SeekFrom::Start(self.info.data_start + byte)).ok();
+    }
+    /// decodes the next chunk of the file into `self.chunk`. Returns false at the end of the data.
+    fn fill_chunk(&mut self) -> bool {
+        self.chunk.clear();
+        self.chunk_pos = 0;
+        let channels = self.info.channels;
+        let end = self.info.data_start + self.info.data_len;
+        let here = self.src.stream_position().unwrap_or(end);
+        let remaining = end.saturating_sub(here) as usize;
+        match self.info.encoding {
+            Encoding::Pcm8 | Encoding::Pcm16 => {
+                let width = if self.info.encoding == Encoding::Pcm8 { 1 } else { 2 };
+                let want = (FIFO_DEPTH * width * channels).min(remaining);
+                self.raw.resize(want, 0);
+                let got = read_fully(&mut self.src, &mut self.raw);
+                let decode = |s: &[u8]| -> i16 {
+                    if width == 1 {
+                        // 8-bit WAV is unsigned
+                        ((s[0] as i16) - 128) << 8
+                    } else {
+                        i16::from_le_bytes([s[0], s[1]])
+                    }
+                };
+                for sample in self.raw[..got].chunks_exact(width * channels) {
+                    let left = decode(&sample[..width]);
+                    let right = decode(&sample[(channels - 1) * width..]);
+                    self.chunk.push(stereo(left, right));
+                }
+            }
+            Encoding::ImaAdpcm { block_align, .. } => {
+                let want = block_align.min(remaining);
+                self.raw.resize(want, 0);
+                let got = read_fully(&mut self.src, &mut self.raw);
+                if got < 4 * channels {
+                    return false;
+                }
+                let block = &self.raw[..got];
+                let mut state: Vec<ImaChannel> = (0..channels).map(|c| ImaChannel {
+                    predictor: i16::from_le_bytes([block[4 * c], block[4 * c + 1]]) as i32,
+                    index: (block[4 * c + 2] as i32).min(88),
+                }).collect();
+                self.chunk.push(stereo(state[0].predictor as i16, state[channels - 1].predictor as i16));
+                // after the headers, each channel's samples come in runs of 4 bytes (8 samples), interleaved by channel
+                let mut decoded: Vec<Vec<i16>> = vec![Vec::new(); channels];
+                for (i, group) in block[4 * channels..].chunks(4).enumerate() {
+                    let c = i % channels;
+                    for &byte in group.iter() {
+                        decoded[c].push(state[c].decode(byte & 0xF));
+                        decoded[c].push(state[c].decode(byte >> 4));
+                    }
+                }
+                let count = decoded.iter().map(|d| d.len()).min().unwrap_or(0);
+                for i in 0..count {
+                    self.chunk.push(stereo(decoded[0][i], decoded[channels - 1][i]));
+                }
+            }
+        }
+        self.chunk.len() > 0
+    }
+    /// Fills `frame` with the next samples of the file, and returns how many were written.
+    /// The rest of the frame is silence; a return value of less than `FIFO_DEPTH` means the end was reached.
+    pub fn next_frame(&mut self, frame: &mut [u32; FIFO_DEPTH]) -> usize {
+        let mut filled = 0;
+        while filled < FIFO_DEPTH {
+            if self.chunk_pos >= self.chunk.len() && !self.fill_chunk() {
+                break;
+            }
+            let n = (self.chunk.len() - self.chunk_pos).min(FIFO_DEPTH - filled);
+            frame[filled..filled + n].copy_from_slice(&self.chunk[self.chunk_pos..self.chunk_pos + n]);
+            self.chunk_pos += n;
+            filled += n;
+        }
+        for sample in frame[filled..].iter_mut() {
+            *sample = ZERO_PCM as u32 | (ZERO_PCM as u32) << 16;
+        }
+        self.pos += filled as u64;
+        filled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn wav(tag: u16, channels: u16, rate: u32, block_align: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut w = Vec::new();
+        w.extend_from_slice(b"RIFF");
+        w.extend_from_slice(&(4 + 8 + 16 + 8 + 4 + 8 + data.len() as u32).to_le_bytes());
+        w.extend_from_slice(b"WAVE");
+        w.extend_from_slice(b"fmt ");
+        w.extend_from_slice(&16u32.to_le_bytes());
+        w.extend_from_slice(&tag.to_le_bytes());
+        w.extend_from_slice(&channels.to_le_bytes());
+        w.extend_from_slice(&rate.to_le_bytes());
+        w.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        w.extend_from_slice(&block_align.to_le_bytes());
+        w.extend_from_slice(&bits.to_le_bytes());
+        // an odd-length chunk we don't care about, to exercise the chunk walk and padding
+        w.extend_from_slice(b"LIST");
+        w.extend_from_slice(&3u32.to_le_bytes());
+        w.extend_from_slice(&[1, 2, 3, 0]);
+        w.extend_from_slice(b"data");
+        w.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        w.extend_from_slice(data);
+        w
+    }
+
+    #[test]
+    fn test_pcm16_stereo() {
+        let mut data = Vec::new();
+        for i in 0..600i16 {
+            data.extend_from_slice(&i.to_le_bytes());
+            data.extend_from_slice(&(-i).to_le_bytes());
+        }
+        let mut dec = Decoder::new(Cursor::new(wav(WAVE_FORMAT_PCM, 2, 16000, 4, 16, &data))).unwrap();
+        assert_eq!(dec.info().encoding, Encoding::Pcm16);
+        assert_eq!(dec.info().samples(), 600);
+        let mut frame = [0u32; FIFO_DEPTH];
+        assert_eq!(dec.next_frame(&mut frame), FIFO_DEPTH);
+        assert_eq!(frame[5], stereo(5, -5));
+        assert_eq!(dec.next_frame(&mut frame), FIFO_DEPTH);
+        assert_eq!(frame[0], stereo(256, -256));
+        assert_eq!(dec.next_frame(&mut frame), 600 - 2 * FIFO_DEPTH);
+        assert_eq!(frame[FIFO_DEPTH - 1], 0, "end of the frame should be padded with silence");
+        assert_eq!(dec.next_frame(&mut frame), 0);
+    }
+
+    #[test]
+    fn test_pcm8_mono_and_seek() {
+        let data: Vec<u8> = (0..8000u32).map(|i| (i % 256) as u8).collect();
+        let mut dec = Decoder::new(Cursor::new(wav(WAVE_FORMAT_PCM, 1, 8000, 1, 8, &data))).unwrap();
+        assert_eq!(dec.info().duration_ms(), 1000);
+        let mut frame = [0u32; FIFO_DEPTH];
+        dec.next_frame(&mut frame);
+        assert_eq!(frame[0], stereo(-128 << 8, -128 << 8));
+        assert_eq!(frame[128], stereo(0, 0));
+        dec.seek_ms(500);
+        assert_eq!(dec.position_ms(), 500);
+        dec.next_frame(&mut frame);
+        assert_eq!(frame[0], stereo((((4000 % 256) as i16) - 128) << 8, (((4000 % 256) as i16) - 128) << 8));
+        dec.seek_ms(5000);
+        assert_eq!(dec.next_frame(&mut frame), 0);
+    }
+
+    #[test]
+    fn test_ima_adpcm() {
+        // a mono block: header with predictor 1000 and step index 0, then 8 samples of nibble 0x7
+        // (largest positive step), then 8 samples of 0x0 (smallest positive step)
+        let mut block = Vec::new();
+        block.extend_from_slice(&1000i16.to_le_bytes());
+        block.push(0);
+        block.push(0);
+        block.extend_from_slice(&[0x77, 0x77, 0x77, 0x77, 0x00, 0x00, 0x00, 0x00]);
+        let mut data = block.clone();
+        data.extend_from_slice(&block);
+        let mut dec = Decoder::new(Cursor::new(wav(WAVE_FORMAT_IMA_ADPCM, 1, 8000, 12, 4, &data))).unwrap();
+        assert_eq!(dec.info().encoding, Encoding::ImaAdpcm { block_align: 12, samples_per_block: 17 });
+        assert_eq!(dec.info().samples(), 34);
+
+        // compute the expected values with a straightforward reference implementation
+        let mut expected = vec![1000i32];
+        let (mut p, mut idx) = (1000i32, 0i32);
+        for &n in [7u8; 8].iter().chain([0u8; 8].iter()) {
+            // the "vpdiff" loop from the IMA reference decoder
+            let mut step = IMA_STEP_TABLE[idx as usize];
+            let mut diff = 0;
+            for bit in [4u8, 2, 1].iter() {
+                if n & bit != 0 {
+                    diff += step;
+                }
+                step >>= 1;
+            }
+            diff += step;
+            p = (p + diff).min(i16::MAX as i32);
+            idx = (idx + IMA_INDEX_TABLE[n as usize] as i32).max(0).min(88);
+            expected.push(p);
+        }
+        let mut frame = [0u32; FIFO_DEPTH];
+        assert_eq!(dec.next_frame(&mut frame), 34);
+        for (i, &e) in expected.iter().enumerate() {
+            assert_eq!(frame[i], stereo(e as i16, e as i16), "sample {}", i);
+            assert_eq!(frame[i + 17], stereo(e as i16, e as i16), "sample {} of the second block", i);
+        }
+        // seeking into the middle of the second block lands on the same samples
+        dec.seek_ms(3); // sample 24
+        dec.next_frame(&mut frame);
+        assert_eq!(frame[0], stereo(expected[24 - 17] as i16, expected[24 - 17] as i16));
+    }
+
+    #[test]
+    fn test_rejects_unsupported() {
+        assert_eq!(parse_header(&mut Cursor::new(b"not a wav file".to_vec())).err(), Some(PlayerError::UnsupportedFormat));
+        // 11.025kHz isn't a rate the codec can run at
+        let w = wav(WAVE_FORMAT_PCM, 1, 11025, 2, 16, &[0; 64]);
+        assert_eq!(parse_header(&mut Cursor::new(w)).err(), Some(PlayerError::UnsupportedFormat));
+        // 24-bit PCM
+        let w = wav(WAVE_FORMAT_PCM, 1, 8000, 3, 24, &[0; 64]);
+        assert_eq!(parse_header(&mut Cursor::new(w)).err(), Some(PlayerError::UnsupportedFormat));
+    }
+}
@@ -0,0 +1,161 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+//! Plays audio files stored in the PDDB through the codec.
+//!
+//! WAV files holding 8 or 16-bit PCM or IMA-ADPCM, in mono or stereo, at any rate the codec supports
+//! (8, 16, 32, 44.1 or 48kHz) can be played. Opus is not supported yet.
+//!
+//! `WavRecorder` does the reverse, recording the microphone into a WAV file in the PDDB.
+
+pub mod api;
+pub use api::*;
+pub use codec::{StreamPriority, SampleRate};
+mod recorder;
+pub use recorder::{WavRecorder, RecordLevel, RecordingInfo};
+use xous::{CID, SID, send_message, Message, msg_scalar_unpack};
+use xous_ipc::Buffer;
+use num_traits::{ToPrimitive, FromPrimitive};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Identifies one playback started by `AudioPlayer::play()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PlaybackHandle(u32);
+
+pub struct AudioPlayer {
+    conn: CID,
+    /// a SID that we share with the player server for the purpose of delivering end-of-stream callbacks
+    cb: Option<(SID, JoinHandle::<()>)>,
+    callbacks: Arc<Mutex<HashMap<PlaybackHandle, Box<dyn Fn(PlaybackHandle, PlaybackEnd) + 'static + Send>>>>,
+}
+impl AudioPlayer {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_AUDIO_PLAYER).expect("Can't connect to audio player server");
+        Ok(AudioPlayer {
+            conn,
+            cb: None,
+            callbacks: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+    fn ensure_async_responder(&mut self) {
+        if self.cb.is_none() {
+            let sid = xous::create_server().unwrap();
+            let handle = thread::spawn({
+                let callbacks = Arc::clone(&self.callbacks);
+                let sid = sid.clone();
+                move || {
+                    loop {
+                        let msg = xous::receive_message(sid).unwrap();
+                        match FromPrimitive::from_usize(msg.body.id()) {
+                            Some(CbOp::EndOfStream) => msg_scalar_unpack!(msg, handle, reason, _, _, {
+                                let handle = PlaybackHandle(handle as u32);
+                                // a playback ends only once, so the callback is retired after it runs
+                                if let Some(cb) = callbacks.lock().unwrap().remove(&handle) {
+                                    cb(handle, FromPrimitive::from_usize(reason).unwrap_or(PlaybackEnd::Stopped));
+                                }
+                            }),
+                            Some(CbOp::Quit) => { // blocking scalar
+                                xous::return_scalar(msg.sender, 0).unwrap();
+                                break;
+                            },
+                            _ => log::warn!("Got unknown opcode: {:?}", msg),
+                        }
+                    }
+                    xous::destroy_server(sid).unwrap();
+                }
+            });
+            self.cb = Some((sid, handle));
+        }
+    }
+    /// Starts playing the audio file stored under `dict`:`key` in the PDDB, on a codec stream of the
+    /// given `priority` and `volume` (0-100). Notification sounds should use `StreamPriority::Notification`,
+    /// so they duck any media playing at the time.
+    ///
+    /// `on_end` is called from a helper thread once the playback finishes or is stopped.
+    pub fn play(&mut self, dict: &str, key: &str, priority: StreamPriority, volume: u8,
+        on_end: Option<impl Fn(PlaybackHandle, PlaybackEnd) + 'static + Send>) -> Result<PlaybackHandle, PlayerError> {
+        if dict.len() > DICT_NAME_LEN - 1 || key.len() > KEY_NAME_LEN - 1 {
+            return Err(PlayerError::KeyNotFound);
+        }
+        if on_end.is_some() {
+            self.ensure_async_responder();
+        }
+        let request = PlayRequest {
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key),
+            priority,
+            volume,
+            cb_sid: if on_end.is_some() {
+                self.cb.as_ref().map(|(sid, _)| sid.to_array())
+            } else {
+                None
+            },
+            handle: None,
+            error: None,
+        };
+        // hold the lock across the request, so an end-of-stream that races the reply finds its callback
+        let mut callbacks = self.callbacks.lock().unwrap();
+        let mut buf = Buffer::into_buf(request).or(Err(PlayerError::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::Play.to_u32().unwrap()).or(Err(PlayerError::InternalError))?;
+        let response = buf.to_original::<PlayRequest, _>().unwrap();
+        match (response.handle, response.error) {
+            (Some(h), None) => {
+                let handle = PlaybackHandle(h);
+                if let Some(cb) = on_end {
+                    callbacks.insert(handle, Box::new(cb));
+                }
+                Ok(handle)
+            }
+            (_, Some(e)) => Err(e),
+            _ => Err(PlayerError::InternalError),
+        }
+    }
+    fn control(&self, op: Opcode, handle: PlaybackHandle, arg: usize) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(op.to_usize().unwrap(), handle.0 as usize, arg, 0, 0)
+        ).map(|_| ())
+    }
+    /// Pauses a playback. Audio already handed to the codec (a fraction of a second) still plays out.
+    pub fn pause(&self, handle: PlaybackHandle) -> Result<(), xous::Error> {
+        self.control(Opcode::Pause, handle, 0)
+    }
+    pub fn resume(&self, handle: PlaybackHandle) -> Result<(), xous::Error> {
+        self.control(Opcode::Resume, handle, 0)
+    }
+    /// Moves a playback to `ms` milliseconds from the start of the file.
+    pub fn seek(&self, handle: PlaybackHandle, ms: u32) -> Result<(), xous::Error> {
+        self.control(Opcode::Seek, handle, ms as usize)
+    }
+    /// Stops a playback immediately.
+    pub fn stop(&self, handle: PlaybackHandle) -> Result<(), xous::Error> {
+        self.control(Opcode::Stop, handle, 0)
+    }
+    /// Returns the (position, duration) of a playback in milliseconds, or `None` if it has ended.
+    pub fn position(&self, handle: PlaybackHandle) -> Result<Option<(u32, u32)>, xous::Error> {
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::Position.to_usize().unwrap(), handle.0 as usize, 0, 0, 0))?;
+        match response {
+            xous::Result::Scalar2(position, duration) => Ok(Some((position as u32, duration as u32))),
+            xous::Result::Scalar1(_) => Ok(None),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for AudioPlayer {
+    fn drop(&mut self) {
+        if let Some((cb_sid, handle)) = self.cb.take() {
+            let cid = xous::connect(cb_sid).unwrap();
+            send_message(cid, Message::new_blocking_scalar(CbOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)).unwrap();
+            unsafe{xous::disconnect(cid).ok();}
+            handle.join().expect("couldn't terminate callback helper thread");
+        }
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+    }
+}
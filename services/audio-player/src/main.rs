@@ -0,0 +1,268 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+mod decoder;
+use decoder::Decoder;
+
+use xous_ipc::Buffer;
+use xous::{msg_scalar_unpack, msg_blocking_scalar_unpack, Message, send_message, CID, SID};
+use num_traits::*;
+use codec::{FrameRing, ZERO_PCM};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, mpsc};
+
+/// number of files that can play at once; each takes one of the codec's mixer streams
+const MAX_PLAYBACKS: usize = 4;
+
+/// Opcodes of the per-playback server, which receives both the codec's frame callbacks and
+/// the controls forwarded from the main loop.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum PlaybackOp {
+    Frame,
+    Pause,
+    Resume,
+    Seek,
+    Stop,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct Progress {
+    position_ms: u32,
+    duration_ms: u32,
+}
+
+struct Playback {
+    /// connection to the playback's thread
+    cid: CID,
+    /// connection to the client's callback server, if it wants to know when playback ends
+    cb_cid: Option<CID>,
+    progress: Arc<Mutex<Progress>>,
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let player_sid = xns.register_name(api::SERVER_NAME_AUDIO_PLAYER, None).expect("can't register server");
+    let player_cid = xous::connect(player_sid).unwrap();
+    log::trace!("registered with NS -- {:?}", player_sid);
+
+    let mut playbacks: HashMap<u32, Playback> = HashMap::new();
+    let mut next_handle: u32 = 1;
+    loop {
+        let msg = xous::receive_message(player_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Play) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<PlayRequest, _>().unwrap();
+                if playbacks.len() >= MAX_PLAYBACKS {
+                    request.error = Some(PlayerError::TooManyPlaybacks);
+                    buffer.replace(request).unwrap();
+                    continue;
+                }
+                let handle = next_handle;
+                next_handle = next_handle.wrapping_add(1).max(1);
+                let sid = xous::create_server().unwrap();
+                let progress = Arc::new(Mutex::new(Progress::default()));
+                let (tx, rx) = mpsc::channel();
+                std::thread::spawn({
+                    let progress = progress.clone();
+                    move || {
+                        playback_thread(sid, handle, request, player_cid, progress, tx);
+                    }
+                });
+                // the thread reports back as soon as it has opened the file and claimed a codec stream
+                match rx.recv().unwrap_or(Err(PlayerError::InternalError)) {
+                    Ok(()) => {
+                        playbacks.insert(handle, Playback {
+                            cid: xous::connect(sid).unwrap(),
+                            cb_cid: request.cb_sid.map(|s| xous::connect(SID::from_array(s)).unwrap()),
+                            progress,
+                        });
+                        request.handle = Some(handle);
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't play {}:{}: {:?}", request.dict.as_str().unwrap_or(""), request.key.as_str().unwrap_or(""), e);
+                        request.error = Some(e);
+                    }
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(Opcode::Pause) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                forward(&playbacks, handle as u32, PlaybackOp::Pause, 0);
+            }),
+            Some(Opcode::Resume) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                forward(&playbacks, handle as u32, PlaybackOp::Resume, 0);
+            }),
+            Some(Opcode::Seek) => msg_scalar_unpack!(msg, handle, ms, _, _, {
+                forward(&playbacks, handle as u32, PlaybackOp::Seek, ms);
+            }),
+            Some(Opcode::Stop) => msg_scalar_unpack!(msg, handle, _, _, _, {
+                forward(&playbacks, handle as u32, PlaybackOp::Stop, 0);
+            }),
+            Some(Opcode::Position) => msg_blocking_scalar_unpack!(msg, handle, _, _, _, {
+                if let Some(playback) = playbacks.get(&(handle as u32)) {
+                    let progress = *playback.progress.lock().unwrap();
+                    xous::return_scalar2(msg.sender, progress.position_ms as usize, progress.duration_ms as usize).unwrap();
+                } else {
+                    // the playback has ended
+                    xous::return_scalar(msg.sender, 0).unwrap();
+                }
+            }),
+            Some(Opcode::PlaybackDone) => msg_scalar_unpack!(msg, handle, reason, _, _, {
+                if let Some(playback) = playbacks.remove(&(handle as u32)) {
+                    unsafe{xous::disconnect(playback.cid).ok()};
+                    if let Some(cb_cid) = playback.cb_cid {
+                        send_message(cb_cid,
+                            Message::new_scalar(CbOp::EndOfStream.to_usize().unwrap(), handle, reason, 0, 0)
+                        ).ok(); // the client may have gone away already
+                        unsafe{xous::disconnect(cb_cid).ok()};
+                    }
+                }
+            }),
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+}
+
+fn forward(playbacks: &HashMap<u32, Playback>, handle: u32, op: PlaybackOp, arg: usize) {
+    if let Some(playback) = playbacks.get(&handle) {
+        // the playback may end while this is in flight, so a failure isn't fatal
+        send_message(playback.cid, Message::new_scalar(op.to_usize().unwrap(), arg, 0, 0, 0)).ok();
+    } else {
+        log::warn!("control for a playback that isn't running: {}", handle);
+    }
+}
+
+/// Opens the file and streams it to the codec, until it ends or is stopped. The outcome of the
+/// setup is reported over `ready`; from then on, the thread is driven by the codec's frame
+/// callbacks and by controls forwarded to `sid`.
+fn playback_thread(sid: SID, handle: u32, request: PlayRequest, main_cid: CID,
+    progress: Arc<Mutex<Progress>>, ready: mpsc::Sender<Result<(), PlayerError>>) {
+    let xns = xous_names::XousNames::new().unwrap();
+    let mut pddb = pddb::Pddb::new();
+    let key = match pddb.get(request.dict.as_str().unwrap_or(""), request.key.as_str().unwrap_or(""),
+        None, false, false, None, None::<fn()>) {
+        Ok(key) => key,
+        Err(_) => {
+            ready.send(Err(PlayerError::KeyNotFound)).unwrap();
+            xous::destroy_server(sid).unwrap();
+            return;
+        }
+    };
+    let mut decoder = match Decoder::new(key) {
+        Ok(d) => d,
+        Err(e) => {
+            ready.send(Err(e)).unwrap();
+            xous::destroy_server(sid).unwrap();
+            return;
+        }
+    };
+    let rate = codec::SampleRate::from_hz(decoder.info().sample_rate).unwrap(); // checked by the decoder
+    let mut codec = codec::Codec::new(&xns).unwrap();
+    if codec.register_stream(request.priority, request.volume).is_err() {
+        ready.send(Err(PlayerError::TooManyPlaybacks)).unwrap();
+        xous::destroy_server(sid).unwrap();
+        return;
+    }
+    if codec.setup_stream(rate).is_err() {
+        ready.send(Err(PlayerError::InternalError)).unwrap();
+        xous::destroy_server(sid).unwrap();
+        return;
+    }
+    let self_cid = xous::connect(sid).unwrap();
+    codec.hook_frame_callback(PlaybackOp::Frame.to_u32().unwrap(), self_cid).unwrap();
+    progress.lock().unwrap().duration_ms = decoder.info().duration_ms() as u32;
+    ready.send(Ok(())).unwrap();
+    codec.resume().unwrap();
+
+    let mut paused = false;
+    let mut ended = false;
+    let reason = loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(PlaybackOp::Frame) => msg_scalar_unpack!(msg, free_play, _avail_rec, _, routing_id, {
+                if routing_id != codec::AUDIO_CB_ROUTING_ID {
+                    continue;
+                }
+                if ended {
+                    // wait until everything we handed over has played, so unregistering the stream doesn't cut it off
+                    if codec.queued_frames().unwrap_or(0) == 0 {
+                        break PlaybackEnd::Finished;
+                    }
+                    continue;
+                }
+                if paused {
+                    continue;
+                }
+                let mut frames = FrameRing::new();
+                let frames_to_push = frames.writeable_count().min(free_play);
+                for _ in 0..frames_to_push {
+                    let mut frame = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; codec::FIFO_DEPTH];
+                    let filled = decoder.next_frame(&mut frame);
+                    if filled > 0 {
+                        frames.nq_frame(frame).unwrap();
+                    }
+                    if filled < codec::FIFO_DEPTH {
+                        ended = true;
+                        break;
+                    }
+                }
+                if frames.readable_count() > 0 {
+                    codec.swap_frames(&mut frames).unwrap();
+                }
+                progress.lock().unwrap().position_ms = decoder.position_ms() as u32;
+                if ended {
+                    codec.pause().unwrap();
+                    // if we were the only stream, the hardware stops once our audio has played, and
+                    // no more callbacks arrive; so check right away as well.
+                    if codec.queued_frames().unwrap_or(0) == 0 {
+                        break PlaybackEnd::Finished;
+                    }
+                }
+            }),
+            Some(PlaybackOp::Pause) => {
+                if !paused && !ended {
+                    paused = true;
+                    codec.pause().unwrap();
+                }
+            }
+            Some(PlaybackOp::Resume) => {
+                if paused {
+                    paused = false;
+                    codec.resume().unwrap();
+                }
+            }
+            Some(PlaybackOp::Seek) => msg_scalar_unpack!(msg, ms, _, _, _, {
+                // drop whatever is queued from the old position
+                codec.abort().unwrap();
+                decoder.seek_ms(ms as u64);
+                progress.lock().unwrap().position_ms = decoder.position_ms() as u32;
+                ended = false;
+                if !paused {
+                    codec.resume().unwrap();
+                }
+            }),
+            Some(PlaybackOp::Stop) => {
+                codec.abort().unwrap();
+                break PlaybackEnd::Stopped;
+            }
+            None => log::error!("couldn't convert opcode: {:?}", msg),
+        }
+    };
+    log::debug!("playback {} ended: {:?}", handle, reason);
+    // dropping the codec unhooks the frame callback and releases the stream
+    drop(codec);
+    send_message(main_cid,
+        Message::new_scalar(Opcode::PlaybackDone.to_usize().unwrap(), handle as usize, reason.to_usize().unwrap(), 0, 0)
+    ).expect("couldn't report the end of a playback");
+    unsafe{xous::disconnect(self_cid).ok()};
+    xous::destroy_server(sid).unwrap();
+}
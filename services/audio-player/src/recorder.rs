@@ -0,0 +1,282 @@
+use codec::{FrameRing, SampleRate};
+use num_traits::{ToPrimitive, FromPrimitive};
+use xous::{msg_scalar_unpack, send_message, Message, CID};
+use std::io::{Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+
+/// length of the canonical 44-byte WAV header we write
+const WAV_HEADER_LEN: usize = 44;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum RecOp {
+    Frames,
+    Stop,
+}
+
+/// Level of the most recently recorded frame, on a linear scale of 0 (silence) to 32767 (full scale).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RecordLevel {
+    pub peak: u16,
+    pub rms: u16,
+}
+impl RecordLevel {
+    pub(crate) fn measure(samples: &[i16]) -> RecordLevel {
+        if samples.len() == 0 {
+            return RecordLevel::default();
+        }
+        let mut peak: u32 = 0;
+        let mut sum_sq: u64 = 0;
+        for &s in samples.iter() {
+            let mag = (s as i32).abs() as u32;
+            peak = peak.max(mag);
+            sum_sq += (mag as u64) * (mag as u64);
+        }
+        RecordLevel {
+            peak: peak.min(i16::MAX as u32) as u16,
+            rms: isqrt(sum_sq / samples.len() as u64).min(i16::MAX as u64) as u16,
+        }
+    }
+}
+
+/// integer square root, as there's no FPU to lean on
+fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+/// A canonical WAV header for 16-bit PCM with `data_len` bytes of samples.
+pub(crate) fn wav_header(rate: u32, channels: u16, data_len: u32) -> [u8; WAV_HEADER_LEN] {
+    let mut h = [0u8; WAV_HEADER_LEN];
+    let block_align = channels * 2;
+    h[0..4].copy_from_slice(b"RIFF");
+    h[4..8].copy_from_slice(&(36 + data_len).to_le_bytes());
+    h[8..12].copy_from_slice(b"WAVE");
+    h[12..16].copy_from_slice(b"fmt ");
+    h[16..20].copy_from_slice(&16u32.to_le_bytes());
+    h[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    h[22..24].copy_from_slice(&channels.to_le_bytes());
+    h[24..28].copy_from_slice(&rate.to_le_bytes());
+    h[28..32].copy_from_slice(&(rate * block_align as u32).to_le_bytes());
+    h[32..34].copy_from_slice(&block_align.to_le_bytes());
+    h[34..36].copy_from_slice(&16u16.to_le_bytes());
+    h[36..40].copy_from_slice(b"data");
+    h[40..44].copy_from_slice(&data_len.to_le_bytes());
+    h
+}
+
+/// Writes mono 16-bit PCM into a WAV file, one codec frame at a time, and keeps the header up to date
+/// once `finish()` is called.
+pub(crate) struct WavWriter<W: Write + Seek> {
+    dest: W,
+    rate: u32,
+    data_len: u32,
+}
+impl<W: Write + Seek> WavWriter<W> {
+    pub(crate) fn new(mut dest: W, rate: u32) -> Result<WavWriter<W>> {
+        dest.seek(SeekFrom::Start(0))?;
+        dest.write_all(&wav_header(rate, 1, 0))?;
+        Ok(WavWriter { dest, rate, data_len: 0 })
+    }
+    /// Appends a frame from the codec, keeping only the left channel (the microphone is mono).
+    /// Returns the level of the frame.
+    pub(crate) fn write_frame(&mut self, frame: &[u32; codec::FIFO_DEPTH]) -> Result<RecordLevel> {
+        let mut samples = [0i16; codec::FIFO_DEPTH];
+        let mut bytes = [0u8; codec::FIFO_DEPTH * 2];
+        for (i, &stereo) in frame.iter().enumerate() {
+            samples[i] = (stereo & 0xFFFF) as u16 as i16;
+            bytes[i * 2..i * 2 + 2].copy_from_slice(&samples[i].to_le_bytes());
+        }
+        self.dest.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(RecordLevel::measure(&samples))
+    }
+    pub(crate) fn duration_ms(&self) -> u32 {
+        ((self.data_len as u64 / 2) * 1000 / self.rate as u64) as u32
+    }
+    /// Rewrites the header with the final length.
+    pub(crate) fn finish(mut self) -> Result<W> {
+        self.dest.seek(SeekFrom::Start(0))?;
+        self.dest.write_all(&wav_header(self.rate, 1, self.data_len))?;
+        self.dest.flush()?;
+        Ok(self.dest)
+    }
+}
+
+/// What was recorded by a `WavRecorder`.
+#[derive(Debug, Copy, Clone)]
+pub struct RecordingInfo {
+    pub sample_rate: SampleRate,
+    pub duration_ms: u32,
+}
+
+/// Records the microphone into a mono 16-bit WAV file in the PDDB, e.g. for a voice memo.
+///
+/// Recording runs on a helper thread from `start()` until `stop()`; `level()` can be polled in the
+/// meantime to drive a level meter.
+pub struct WavRecorder {
+    cid: CID,
+    level: Arc<Mutex<RecordLevel>>,
+    handle: Option<JoinHandle<Result<RecordingInfo>>>,
+}
+impl WavRecorder {
+    /// Starts recording into `dict`:`key`, replacing anything stored there. The CODEC runs at `rate`
+    /// unless it is already playing at another rate, in which case that rate is recorded at.
+    pub fn start(dict: &str, key: &str, rate: SampleRate) -> Result<WavRecorder> {
+        let sid = xous::create_server().unwrap();
+        let level = Arc::new(Mutex::new(RecordLevel::default()));
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn({
+            let level = level.clone();
+            let dict = String::from(dict);
+            let key = String::from(key);
+            move || {
+                let xns = xous_names::XousNames::new().unwrap();
+                let self_cid = xous::connect(sid).unwrap();
+                let mut codec = codec::Codec::new(&xns).unwrap();
+                let mut pddb = pddb::Pddb::new();
+                // set up everything that can fail, and report back before recording a single frame
+                let setup = (|| {
+                    let hw_rate = codec.setup_stream(rate).or(Err(Error::new(ErrorKind::Other, "couldn't set up the codec")))?;
+                    pddb.delete_key(&dict, &key, None).ok(); // start from an empty key
+                    let dest = pddb.get(&dict, &key, None, true, true, None, None::<fn()>)?;
+                    let writer = WavWriter::new(dest, hw_rate.hz())?;
+                    codec.start_recording(RecOp::Frames.to_u32().unwrap(), self_cid)
+                        .or(Err(Error::new(ErrorKind::AddrInUse, "the microphone is in use")))?;
+                    Ok((hw_rate, writer))
+                })();
+                let (hw_rate, mut writer) = match setup {
+                    Ok(s) => {
+                        tx.send(Ok(())).unwrap();
+                        s
+                    }
+                    Err(e) => {
+                        tx.send(Err(e)).unwrap();
+                        unsafe{xous::disconnect(self_cid).ok()};
+                        xous::destroy_server(sid).unwrap();
+                        return Err(Error::new(ErrorKind::Other, "recording never started"));
+                    }
+                };
+                let mut result = Ok(());
+                let drain = |codec: &mut codec::Codec, writer: &mut WavWriter<_>| -> Result<()> {
+                    let mut frames = FrameRing::new();
+                    codec.get_rec_frames(&mut frames).or(Err(Error::new(ErrorKind::Other, "codec error")))?;
+                    while let Some(frame) = frames.dq_frame() {
+                        *level.lock().unwrap() = writer.write_frame(&frame)?;
+                    }
+                    Ok(())
+                };
+                loop {
+                    let msg = xous::receive_message(sid).unwrap();
+                    match FromPrimitive::from_usize(msg.body.id()) {
+                        Some(RecOp::Frames) => msg_scalar_unpack!(msg, _, avail_rec, _, routing_id, {
+                            if routing_id == codec::AUDIO_CB_ROUTING_ID && avail_rec > 0 && result.is_ok() {
+                                result = drain(&mut codec, &mut writer);
+                                if result.is_err() {
+                                    log::error!("recording failed, the rest of it is lost: {:?}", result);
+                                }
+                            }
+                        }),
+                        Some(RecOp::Stop) => {
+                            if result.is_ok() {
+                                // pick up the tail end of the recording
+                                result = drain(&mut codec, &mut writer);
+                            }
+                            codec.stop_recording().ok();
+                            xous::return_scalar(msg.sender, 0).unwrap();
+                            break;
+                        }
+                        None => log::error!("couldn't convert opcode: {:?}", msg),
+                    }
+                }
+                let duration_ms = writer.duration_ms();
+                let finished = writer.finish();
+                pddb.sync().ok();
+                unsafe{xous::disconnect(self_cid).ok()};
+                xous::destroy_server(sid).unwrap();
+                result?;
+                finished?;
+                Ok(RecordingInfo {
+                    sample_rate: hw_rate,
+                    duration_ms,
+                })
+            }
+        });
+        match rx.recv() {
+            Ok(Ok(())) => Ok(WavRecorder {
+                cid: xous::connect(sid).unwrap(),
+                level,
+                handle: Some(handle),
+            }),
+            Ok(Err(e)) => {
+                handle.join().ok();
+                Err(e)
+            }
+            Err(_) => Err(Error::new(ErrorKind::Other, "recorder thread died")),
+        }
+    }
+    /// The level of the most recently recorded frame.
+    pub fn level(&self) -> RecordLevel {
+        *self.level.lock().unwrap()
+    }
+    /// Stops recording and finalizes the WAV file.
+    pub fn stop(mut self) -> Result<RecordingInfo> {
+        self.shutdown().unwrap_or(Err(Error::new(ErrorKind::Other, "recording already stopped")))
+    }
+    fn shutdown(&mut self) -> Option<Result<RecordingInfo>> {
+        let handle = self.handle.take()?;
+        send_message(self.cid, Message::new_blocking_scalar(RecOp::Stop.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+        unsafe{xous::disconnect(self.cid).ok()};
+        Some(handle.join().unwrap_or(Err(Error::new(ErrorKind::Other, "recorder thread panicked"))))
+    }
+}
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        // a recorder that goes out of scope still leaves a well-formed file behind
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(RecordLevel::measure(&[]), RecordLevel::default());
+        assert_eq!(RecordLevel::measure(&[0; 16]), RecordLevel { peak: 0, rms: 0 });
+        let square: Vec<i16> = (0..64).map(|i| if i & 1 == 0 { 1000 } else { -1000 }).collect();
+        assert_eq!(RecordLevel::measure(&square), RecordLevel { peak: 1000, rms: 1000 });
+        // full scale negative samples don't overflow the meter
+        assert_eq!(RecordLevel::measure(&[i16::MIN, 0]).peak, i16::MAX as u16);
+        assert_eq!(isqrt(1_000_001), 1000);
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 16000).unwrap();
+        let mut frame = [0u32; codec::FIFO_DEPTH];
+        for (i, s) in frame.iter_mut().enumerate() {
+            // the right channel must be dropped
+            *s = (i as i16 as u16 as u32) | 0xDEAD_0000;
+        }
+        let level = writer.write_frame(&frame).unwrap();
+        assert_eq!(level.peak, codec::FIFO_DEPTH as u16 - 1);
+        writer.write_frame(&frame).unwrap();
+        assert_eq!(writer.duration_ms(), 32);
+        let wav = writer.finish().unwrap().into_inner();
+        assert_eq!(wav.len(), WAV_HEADER_LEN + 2 * codec::FIFO_DEPTH * 2);
+        assert_eq!(&wav[..WAV_HEADER_LEN], &wav_header(16000, 1, 2 * codec::FIFO_DEPTH as u32 * 2)[..]);
+        assert_eq!(u32::from_le_bytes([wav[4], wav[5], wav[6], wav[7]]) as usize, wav.len() - 8);
+        assert_eq!(&wav[WAV_HEADER_LEN + 6..WAV_HEADER_LEN + 8], &3i16.to_le_bytes());
+    }
+}
@@ -1,5 +1,7 @@
 pub(crate) const SERVER_NAME_CODEC: &str     = "_Low-level Audio Codec Server_";
 
+use num_traits::FromPrimitive;
+
 #[allow(dead_code)]
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum Opcode {
@@ -12,7 +14,7 @@ pub(crate) enum Opcode {
     /// play and record
     SwapFrames,
 
-    /// how many empty play frames, full rec frames are available right now
+    /// how many empty play frames (in the caller's stream), full rec frames are available right now
     FreeFrames,
 
     /// if the CODEC is live
@@ -21,8 +23,9 @@ pub(crate) enum Opcode {
     /// turns off the CODEC, stops streaming
     PowerOff,
 
-    /// Powers on the CODEC, sets up 8k stereo streaming; puts audio in "paused" state
-    Setup8kStereo,
+    /// Powers on the CODEC, sets up stereo streaming at a rate negotiated with a `StreamRequest`;
+    /// puts audio in "paused" state
+    SetupStream,
 
     /// Pause the stream without powering anything off. Will wait until the current playback frames in process are finished.
     PauseStream,
@@ -46,6 +49,72 @@ pub(crate) enum Opcode {
 
     /// Suspend/resume callback
     SuspendResume,
+
+    /// register a playback stream with the mixer
+    RegisterStream,
+    /// remove a playback stream from the mixer
+    UnregisterStream,
+    /// set the mixing volume of a single playback stream
+    SetStreamVolume,
+    /// how many frames of the caller's stream are still waiting to be played
+    QueuedFrames,
+
+    /// subscribe to recorded frames; only one recorder at a time
+    StartRecording,
+    /// stop recording and drop the recorder's subscription
+    StopRecording,
+    /// fetch recorded frames; only the current recorder may do this
+    GetRecFrames,
+}
+
+/// Priority of a playback stream. While a stream has audio queued, any stream of a lower
+/// priority is ducked by the mixer, so e.g. speech stays intelligible over music.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum StreamPriority {
+    /// music and app audio
+    Media = 0,
+    /// UI beeps and notification sounds
+    Notification = 1,
+    /// text to speech
+    Speech = 2,
+}
+
+/// Sample rates supported by the CODEC. The discriminant is the rate in Hz.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive)]
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum SampleRate {
+    Fs8000 = 8000,
+    Fs16000 = 16000,
+    Fs32000 = 32000,
+    Fs44100 = 44100,
+    Fs48000 = 48000,
+}
+impl SampleRate {
+    pub const fn hz(&self) -> u32 {
+        *self as u32
+    }
+    /// returns `None` if the CODEC can't run at the requested rate
+    pub fn from_hz(hz: u32) -> Option<SampleRate> {
+        FromPrimitive::from_u32(hz)
+    }
+}
+
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct StreamRequest {
+    /// the stream's token; assigned by the server on registration
+    pub token: Option<[u32; 4]>,
+    pub priority: StreamPriority,
+    /// 0-100
+    pub volume: u8,
+    /// the rate at which the client produces samples
+    pub rate: SampleRate,
+    /// the rate the hardware ended up running at; set by the server during setup
+    pub hw_rate: SampleRate,
+    /// set by the server to report success
+    pub ok: bool,
 }
 
 
@@ -68,10 +137,21 @@ pub(crate) enum EventCallback {
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
 pub(crate) struct ScalarHook {
     pub sid: (u32, u32, u32, u32),
+    /// the playback stream whose free frame count is reported in the callback; `None` is the default stream
+    pub stream: Option<[u32; 4]>,
     pub id: u32,  // ID of the scalar message to send through (e.g. the discriminant of the Enum on the caller's side API)
     pub cid: xous::CID,   // caller-side connection ID for the scalar message to route to. Created by the caller before hooking.
 }
 
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub(crate) struct RecordHook {
+    pub sid: (u32, u32, u32, u32),
+    pub id: u32,  // ID of the scalar message to send through when recorded frames are available
+    pub cid: xous::CID,   // caller-side connection ID for the scalar message to route to
+    /// set by the server; authorizes `GetRecFrames`. `None` if recording couldn't be started.
+    pub token: Option<[u32; 4]>,
+}
+
 //////////////////////////////////////////////////////////////////////////////////////
 
 pub const ZERO_PCM: u16 = 0x0; // assumes 2's compliment. 0x8000 otherwise.
@@ -93,7 +173,7 @@ We would ideally like to be able to specify various-sized frame rings for
 more efficient memory usage and message passing, but for now, we will fix
 the size at 16 frames.
 */
-const FRAMES: usize = 16;
+pub(crate) const FRAMES: usize = 16;
 #[derive(rkyv::Serialize, rkyv::Deserialize, Debug, rkyv::Archive, Copy, Clone)]
 pub struct FrameRing {
     // a set of frames we will circulate through
@@ -104,7 +184,8 @@ pub struct FrameRing {
     wr_frame: usize,
     // a pointer for more efficient recording during interrupt contexts
     rec_ptr: usize,
-    // authenication token authorizing playback
+    // authenication token authorizing playback; identifies the mixer stream the frames belong to.
+    // `None` plays through the default stream.
     auth_token: Option<[u32; 4]>,
 }
 impl FrameRing {
@@ -117,6 +198,12 @@ impl FrameRing {
             auth_token: None,
         }
     }
+    pub fn set_auth_token(&mut self, token: Option<[u32; 4]>) {
+        self.auth_token = token;
+    }
+    pub fn auth_token(&self) -> Option<[u32; 4]> {
+        self.auth_token
+    }
     pub fn clear(&mut self) {
         self.buffer = [[(ZERO_PCM as u32 | (ZERO_PCM as u32) << 16); FIFO_DEPTH]; FRAMES];
         self.rd_frame = 0;
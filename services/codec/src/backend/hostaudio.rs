@@ -1,13 +1,71 @@
 #![cfg_attr(not(target_os = "none"), allow(dead_code))]
 
 use codec::FIFO_DEPTH;
-
+use crate::api::SampleRate;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// depth of the emulated hardware play FIFO, in frames
+const PLAY_FIFO_FRAMES: usize = 4;
+
+/// Hosted mode model of the CODEC. There is no audio output; instead, played frames are queued into
+/// an emulated play FIFO. When run as the server, the FIFO drains in real time as hardware would;
+/// in tests, `consume_play_frame()` stands in for the I2S interrupt, so results are deterministic.
+/// This lets the stream handling and the mixer be exercised on a workstation.
 pub struct Codec {
+    play_fifo: RefCell<VecDeque<[u32; FIFO_DEPTH]>>,
+    on: bool,
+    live: bool,
+    /// if set, frames are retired from the FIFO based on elapsed wall-clock time
+    realtime: bool,
+    last_retire: Cell<Instant>,
+    sample_rate: SampleRate,
 }
 
 impl Codec {
     pub fn new(_conn: xous::CID, _xns: &xous_names::XousNames) -> Codec {
+        let mut codec = Codec::new_loopback();
+        codec.realtime = true;
+        codec
+    }
+    /// creates a model that isn't connected to any server, for use in tests
+    pub fn new_loopback() -> Codec {
         Codec {
+            play_fifo: RefCell::new(VecDeque::with_capacity(PLAY_FIFO_FRAMES)),
+            on: false,
+            live: false,
+            realtime: false,
+            last_retire: Cell::new(Instant::now()),
+            sample_rate: SampleRate::Fs8000,
+        }
+    }
+    /// playback time of one frame at the current sample rate
+    fn frame_duration(&self) -> Duration {
+        Duration::from_micros((FIFO_DEPTH as u64 * 1_000_000) / self.sample_rate.hz() as u64)
+    }
+    /// takes the oldest frame out of the play FIFO, as the I2S interrupt would on hardware
+    pub fn consume_play_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
+        if self.live {
+            self.play_fifo.borrow_mut().pop_front()
+        } else {
+            None
+        }
+    }
+    fn retire(&self) {
+        if !self.realtime || !self.live {
+            self.last_retire.set(Instant::now());
+            return;
+        }
+        let mut fifo = self.play_fifo.borrow_mut();
+        let frame_duration = self.frame_duration();
+        while self.last_retire.get().elapsed() >= frame_duration {
+            if fifo.pop_front().is_none() {
+                // an underrun: playback restarts from the next frame queued
+                self.last_retire.set(Instant::now());
+                break;
+            }
+            self.last_retire.set(self.last_retire.get() + frame_duration);
         }
     }
     pub fn suspend(&self) {
@@ -16,39 +74,60 @@ impl Codec {
     }
     pub fn init(&mut self) {
     }
+    /// takes effect on the next `init()`
+    pub fn set_sample_rate(&mut self, rate: SampleRate) {
+        self.sample_rate = rate;
+    }
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
 
-    pub fn nq_play_frame(&mut self, _frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
-        Ok(())
+    pub fn nq_play_frame(&mut self, frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
+        self.retire();
+        let mut fifo = self.play_fifo.borrow_mut();
+        if fifo.len() < PLAY_FIFO_FRAMES {
+            fifo.push_back(frame);
+            Ok(())
+        } else {
+            Err(frame)
+        }
     }
     pub fn dq_rec_frame(&mut self) -> Option<[u32; FIFO_DEPTH]> {
         None
     }
     pub fn free_play_frames(&self) -> usize {
-        0
+        self.retire();
+        PLAY_FIFO_FRAMES - self.play_fifo.borrow().len()
     }
 
     pub fn can_play(&self) -> bool {
-        false
+        self.retire();
+        self.live && self.play_fifo.borrow().len() > 0
     }
 
     pub fn drain(&mut self) {
+        self.play_fifo.borrow_mut().clear();
     }
 
     pub fn available_rec_frames(&self) -> usize {
         0
     }
 
-    pub fn power(&mut self, _state: bool) {
+    pub fn power(&mut self, state: bool) {
+        self.on = state;
+        if !state {
+            self.audio_i2s_stop();
+        }
     }
 
     pub fn is_on(&self) -> bool {
-        false
+        self.on
     }
     pub fn is_init(&self) -> bool {
         true
     }
     pub fn is_live(&self) -> bool {
-        false
+        self.live
     }
 
     pub fn get_headset_code(&mut self) -> u8 {
@@ -82,9 +161,13 @@ impl Codec {
 
     /// set up the betrusted-side signals
     pub fn audio_i2s_start(&mut self) {
+        self.live = true;
+        self.last_retire.set(Instant::now());
     }
 
     pub fn audio_i2s_stop(&mut self) {
+        self.live = false;
+        self.play_fifo.borrow_mut().clear();
     }
 
     pub fn set_speaker_gain_db(&mut self, _gain_db: f32) {
@@ -32,6 +32,7 @@ pub struct Codec {
     speaker_gain: f32,
     headphone_left_gain: f32,
     headphone_right_gain: f32,
+    sample_rate: SampleRate,
 }
 
 static SILENCE: [u32; FIFO_DEPTH] = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
@@ -145,6 +146,7 @@ impl Codec {
             speaker_gain: -6.0,
             headphone_left_gain: -15.0,
             headphone_right_gain: -15.0,
+            sample_rate: SampleRate::Fs8000,
         };
 
         xous::claim_interrupt(
@@ -207,6 +209,14 @@ impl Codec {
         self.initialized = true;
     }
 
+    /// takes effect on the next `init()`
+    pub fn set_sample_rate(&mut self, rate: SampleRate) {
+        self.sample_rate = rate;
+    }
+    pub fn sample_rate(&self) -> SampleRate {
+        self.sample_rate
+    }
+
     pub fn nq_play_frame(&mut self, frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
         self.play_buffer.nq_frame(frame)
     }
@@ -334,20 +344,17 @@ impl Codec {
         code
     }
 
-    /// audio_clocks() sets up the default clocks for 8kHz sampling rate, assuming a 12MHz MCLK input
+    /// audio_clocks() sets up the clocks for the configured sampling rate, assuming a 12MHz MCLK input
     ///
     /// fIN = 12 MHz
-    /// M = 2.5
-    /// N = 32   (PLL freq = 153.6MHz)
-    /// N_MOD = 0
-    /// P = 12.5
-    /// fOUT = 12_288_000 Hz
+    /// P = 1, R = 1
+    /// J.D = 7.1680 (PLL_CLK = 86.016 MHz) for the 8kHz family of rates
+    /// J.D = 7.5264 (PLL_CLK = 90.3168 MHz) for 44.1kHz
     ///
-    /// sample rate = 8_000
-    /// oversampling rate (OSR) = 128
-    /// local divider = 12
-    /// 8_000 * 128 * 12 = 12_288_000 Hz
+    /// sample rate = PLL_CLK / (NDAC * MDAC * DOSR) = PLL_CLK / (NADC * MADC * AOSR)
+    /// e.g. at 8kHz: 86_016_000 / (12 * 7 * 128) = 8_000
     ///
+    /// DOSR and AOSR are fixed at 128, so the BCLK divider set up in audio_ports() works at every rate.
     fn audio_clocks(&mut self) {
         self.w(0, &[0]);  // select page 0
         self.w(1, &[1]);  // software reset
@@ -358,27 +365,32 @@ impl Codec {
         // select PLL_CLKIN = MCLK; CODEC_CLKIN = PLL_CLK
         self.w(4, &[0b0000_0011]);
 
-        // fs = 8kHz
-        // PLL_CLKIN = 12MHz
-        // PLLP = 1, PLLR = 1, PLLJ = 7, PLLD = 1680, NDAC = *12*, MDAC = 7, DOSR = 128, MADC = 2 , NADC = *42*
-        // ^^ from page 68 of datasheet, fs=48kHz/12MHz clkin line, with *bold* items multiplied by 6 to get to 8kHz
+        // derived from page 68 of datasheet, fs=48kHz/12MHz clkin line (NDAC = 2, NADC = 7), scaling
+        // the dividers up for the lower rates; and the fs=44.1kHz/12MHz clkin line.
+        let (pll_j, pll_d, ndac, mdac, nadc, madc): (u8, u16, u8, u8, u8, u8) = match self.sample_rate {
+            SampleRate::Fs8000  => (7, 1680, 12, 7, 42, 2),
+            SampleRate::Fs16000 => (7, 1680,  6, 7, 21, 2),
+            SampleRate::Fs32000 => (7, 1680,  3, 7,  7, 3),
+            SampleRate::Fs44100 => (7, 5264,  8, 2,  8, 2),
+            SampleRate::Fs48000 => (7, 1680,  2, 7,  7, 2),
+        };
         self.w(5, &[
             0b1001_0001,  // P, R = 1, 1 and pll powered up
-            7,            // PLLJ = 7
-            ((1680 >> 8) & 0xFF) as u8, // D MSB of 1680
-            (1680 & 0xFF) as u8,        // D LSB of 1680
+            pll_j,        // PLLJ
+            ((pll_d >> 8) & 0xFF) as u8, // D MSB
+            (pll_d & 0xFF) as u8,        // D LSB
             ]);
 
         self.w(11, &[
-            0x80 | 12,  // NADC = 12 (set to 2 for 48kHz)
-            0x80 | 7,   // MDAC = 7
+            0x80 | ndac,  // NDAC
+            0x80 | mdac,  // MDAC
             0,   // DOSR = MSB of 128
             128, // DOSR = LSB of 128
         ]);
 
         self.w(18, &[
-            0x80 | 42,  // NADC = 42 (set to 7 for 48kHz)
-            0x80 | 2,   // MADC = 2
+            0x80 | nadc,  // NADC
+            0x80 | madc,  // MADC
             128, // AOSR = 128
         ]);
     }
@@ -390,8 +402,8 @@ impl Codec {
     fn audio_ports(&mut self) {
         self.w(0, &[0]); // select page 0
 
-        // 32 bits/word * 2 channels * 8000 samples/s = 512_000 = BCLK
-        // pick off of DAC_MOD_CLK = 1.024MHz
+        // 32 bits/word * 2 channels * fs = BCLK, e.g. 512_000 at 8kHz
+        // pick off of DAC_MOD_CLK = fs * DOSR = fs * 128 (1.024MHz at 8kHz), so BCLK_N = 2 at every rate
         self.w(27, &[
             0b00_00_1_1_0_1, // I2S standard, 16 bits per sample, BCLK output, WCLK output, DOUT is Hi-Z when unused
             0b0,           // no offset on left justification
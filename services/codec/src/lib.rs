@@ -12,6 +12,12 @@ pub const AUDIO_CB_ROUTING_ID: usize = 0;
 pub struct Codec {
     conn: CID,
     frame_sid: Option<xous::SID>,
+    /// token of our playback stream in the server's mixer; `None` plays through the default stream
+    stream: Option<[u32; 4]>,
+    /// the rate we render audio at
+    rate: SampleRate,
+    /// our recording token and callback server, while we are recording
+    recording: Option<([u32; 4], xous::SID)>,
 }
 impl Codec {
     pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
@@ -20,8 +26,68 @@ impl Codec {
         Ok(Codec {
             conn,
             frame_sid: None,
+            stream: None,
+            rate: SampleRate::Fs8000,
+            recording: None,
         })
     }
+    /// Registers a playback stream of our own with the CODEC's mixer, so our audio plays alongside
+    /// that of other clients. `volume` is 0-100, and is applied before mixing. While a stream of a
+    /// higher `priority` is playing, ours is ducked.
+    ///
+    /// Call this before `hook_frame_callback()`, so the callbacks report the free frames of our own stream.
+    /// Clients that never register a stream share a default one at `StreamPriority::Media`.
+    /// The stream is registered at the rate last passed to `setup_stream()`, or 8kHz if it was never called.
+    pub fn register_stream(&mut self, priority: StreamPriority, volume: u8) -> Result<(), xous::Error> {
+        if self.stream.is_some() {
+            return Err(xous::Error::MemoryInUse);
+        }
+        let req = StreamRequest {
+            token: None,
+            priority,
+            volume,
+            rate: self.rate,
+            hw_rate: self.rate, // ignored
+            ok: false,
+        };
+        let mut buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::RegisterStream.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<StreamRequest, _>().unwrap();
+        if ret.ok {
+            self.stream = ret.token;
+            Ok(())
+        } else {
+            Err(xous::Error::OutOfMemory)
+        }
+    }
+    /// Releases our playback stream; any audio still queued on it is discarded.
+    pub fn unregister_stream(&mut self) -> Result<(), xous::Error> {
+        if let Some(token) = self.stream.take() {
+            send_message(self.conn,
+                Message::new_scalar(Opcode::UnregisterStream.to_usize().unwrap(),
+                token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize)
+            ).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+    /// Sets the mixing volume (0-100) of our stream. This is independent of the speaker and headphone volume.
+    pub fn set_stream_volume(&self, volume: u8) -> Result<(), xous::Error> {
+        let req = StreamRequest {
+            token: self.stream,
+            priority: StreamPriority::Media, // ignored
+            volume,
+            rate: SampleRate::Fs8000, // ignored
+            hw_rate: SampleRate::Fs8000, // ignored
+            ok: false,
+        };
+        let buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetStreamVolume.to_u32().unwrap()).map(|_| ())
+    }
+    fn stream_args(&self) -> [usize; 4] {
+        let token = self.stream.unwrap_or([0; 4]);
+        [token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize]
+    }
     pub fn hook_frame_callback(&mut self, id: u32, cid: CID) -> Result<(), xous::Error> {
         if self.frame_sid.is_none() {
             let sid = xous::create_server().unwrap();
@@ -30,6 +96,7 @@ impl Codec {
             xous::create_thread_4(frame_cb_server, sid_tuple.0 as usize, sid_tuple.1 as usize, sid_tuple.2 as usize, sid_tuple.3 as usize).unwrap();
             let hookdata = ScalarHook {
                 sid: sid_tuple,
+                stream: self.stream,
                 id,
                 cid,
             };
@@ -41,9 +108,32 @@ impl Codec {
     }
 
     pub fn setup_8k_stream(&mut self) -> Result<(), xous::Error> {
-        send_message(self.conn,
-            Message::new_scalar(Opcode::Setup8kStereo.to_usize().unwrap(), 0, 0, 0, 0)
-        ).map(|_| ())
+        self.setup_stream(SampleRate::Fs8000).map(|_| ())
+    }
+    /// Powers on the CODEC and sets up stereo streaming with our frames rendered at `rate`.
+    ///
+    /// If the hardware is idle, it is configured to run at `rate`. If other streams are playing at a
+    /// different rate, the hardware keeps its rate, and our stream is resampled to match it. Either way,
+    /// frames handed to `swap_frames()` should be rendered at `rate`; the rate the hardware actually runs
+    /// at is returned, for clients that would rather render at the native rate.
+    pub fn setup_stream(&mut self, rate: SampleRate) -> Result<SampleRate, xous::Error> {
+        let req = StreamRequest {
+            token: self.stream,
+            priority: StreamPriority::Media, // ignored
+            volume: 0, // ignored
+            rate,
+            hw_rate: rate,
+            ok: false,
+        };
+        let mut buf = Buffer::into_buf(req).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::SetupStream.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<StreamRequest, _>().unwrap();
+        if ret.ok {
+            self.rate = rate;
+            Ok(ret.hw_rate)
+        } else {
+            Err(xous::Error::InternalError)
+        }
     }
     pub fn power_off(&mut self) -> Result<(), xous::Error> {
         send_message(self.conn,
@@ -51,9 +141,11 @@ impl Codec {
         ).map(|_| ())
     }
 
+    /// Returns the number of frames our stream can accept, and the number of recorded frames available.
     pub fn free_frames(&mut self) -> Result<(usize, usize), xous::Error> {
+        let args = self.stream_args();
         let response = send_message(self.conn,
-            Message::new_blocking_scalar(Opcode::FreeFrames.to_usize().unwrap(), 0, 0, 0, 0))?;
+            Message::new_blocking_scalar(Opcode::FreeFrames.to_usize().unwrap(), args[0], args[1], args[2], args[3]))?;
         if let xous::Result::Scalar2(play_free, rec_avail) = response {
             Ok(
                 (play_free, rec_avail)
@@ -64,7 +156,83 @@ impl Codec {
         }
     }
 
+    /// Starts capturing audio from the microphone. Whenever recorded frames are available, a scalar
+    /// message with the given `id` is sent to `cid`, with the number of frames available in arg2 and
+    /// `AUDIO_CB_ROUTING_ID` in arg4; fetch them with `get_rec_frames()`.
+    ///
+    /// The CODEC must be set up with `setup_stream()` first; frames are recorded at the rate it returned.
+    /// Only one client can record at a time, and while it does, other clients no longer receive
+    /// recorded frames from `swap_frames()`.
+    pub fn start_recording(&mut self, id: u32, cid: CID) -> Result<(), xous::Error> {
+        if self.recording.is_some() {
+            return Err(xous::Error::MemoryInUse);
+        }
+        let sid = xous::create_server().unwrap();
+        let sid_tuple = sid.to_u32();
+        xous::create_thread_4(frame_cb_server, sid_tuple.0 as usize, sid_tuple.1 as usize, sid_tuple.2 as usize, sid_tuple.3 as usize).unwrap();
+        let hook = RecordHook {
+            sid: sid_tuple,
+            id,
+            cid,
+            token: None,
+        };
+        let mut buf = Buffer::into_buf(hook).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::StartRecording.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        let ret = buf.to_original::<RecordHook, _>().unwrap();
+        if let Some(token) = ret.token {
+            self.recording = Some((token, sid));
+            Ok(())
+        } else {
+            // shut down the callback server we made, since the codec never connected to it
+            let cid = xous::connect(sid).unwrap();
+            send_message(cid, Message::new_blocking_scalar(EventCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)).ok();
+            unsafe{xous::disconnect(cid).ok();}
+            Err(xous::Error::ServerExists)
+        }
+    }
+    /// Stops capturing audio from the microphone.
+    pub fn stop_recording(&mut self) -> Result<(), xous::Error> {
+        if let Some((token, _sid)) = self.recording.take() {
+            // the server tears down our callback server as part of this
+            send_message(self.conn,
+                Message::new_scalar(Opcode::StopRecording.to_usize().unwrap(),
+                token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize)
+            ).map(|_| ())
+        } else {
+            Ok(())
+        }
+    }
+    /// Fills `frames` with as many recorded frames as are available. Only valid while recording.
+    pub fn get_rec_frames(&mut self, frames: &mut FrameRing) -> Result<(), xous::Error> {
+        if let Some((token, _sid)) = self.recording {
+            frames.set_auth_token(Some(token));
+            let mut buf = Buffer::into_buf(*frames).or(Err(xous::Error::InternalError))?;
+            buf.lend_mut(self.conn, Opcode::GetRecFrames.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+            *frames = buf.to_original::<FrameRing, _>().unwrap();
+            Ok(())
+        } else {
+            Err(xous::Error::AccessDenied)
+        }
+    }
+
+    /// Returns the number of frames of our stream that have been handed over but not yet played.
+    /// Once this hits zero after a `pause()`, all of our audio has made it out to the hardware.
+    pub fn queued_frames(&self) -> Result<usize, xous::Error> {
+        let args = self.stream_args();
+        let response = send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::QueuedFrames.to_usize().unwrap(), args[0], args[1], args[2], args[3]))?;
+        if let xous::Result::Scalar1(queued) = response {
+            Ok(queued)
+        } else {
+            log::error!("unexpected return value: {:#?}", response);
+            Err(xous::Error::InternalError)
+        }
+    }
+
     pub fn swap_frames(&mut self, frames: &mut FrameRing) -> Result<(), xous::Error> {
+        if frames.auth_token().is_none() {
+            frames.set_auth_token(self.stream);
+        }
         let mut buf = Buffer::into_buf(*frames).or(Err(xous::Error::InternalError))?;
         buf.lend_mut(self.conn, Opcode::SwapFrames.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
 
@@ -77,14 +245,19 @@ impl Codec {
             Message::new_scalar(Opcode::ResumeStream.to_usize().unwrap(), 0, 0, 0, 0)
         ).map(|_| ())
     }
+    /// Stops our stream once the frames already handed over have played. The hardware keeps
+    /// running if other streams are still playing.
     pub fn pause(&mut self) -> Result<(), xous::Error> {
+        let args = self.stream_args();
         send_message(self.conn,
-            Message::new_scalar(Opcode::PauseStream.to_usize().unwrap(), 0, 0, 0, 0)
+            Message::new_scalar(Opcode::PauseStream.to_usize().unwrap(), args[0], args[1], args[2], args[3])
         ).map(|_| ())
     }
+    /// Stops our stream immediately, discarding any queued frames.
     pub fn abort(&mut self) -> Result<(), xous::Error> {
+        let args = self.stream_args();
         send_message(self.conn,
-            Message::new_scalar(Opcode::AbortStream.to_usize().unwrap(), 0, 0, 0, 0)
+            Message::new_scalar(Opcode::AbortStream.to_usize().unwrap(), args[0], args[1], args[2], args[3])
         ).map(|_| ())
     }
 
@@ -148,6 +321,8 @@ use core::sync::atomic::{AtomicU32, Ordering};
 static REFCOUNT: AtomicU32 = AtomicU32::new(0);
 impl Drop for Codec {
     fn drop(&mut self) {
+        self.stop_recording().ok();
+        self.unregister_stream().ok();
         // de-allocate myself. It's unsafe because we are responsible to make sure nobody else is using the connection.
         if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
             unsafe{xous::disconnect(self.conn).unwrap();}
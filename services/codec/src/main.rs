@@ -4,6 +4,9 @@
 mod api;
 mod backend;
 use backend::Codec;
+mod mixer;
+use mixer::Mixer;
+mod resampler;
 
 use num_traits::{ToPrimitive, FromPrimitive};
 use xous_ipc::Buffer;
@@ -18,6 +21,16 @@ struct ScalarCallback {
     server_to_cb_cid: CID,
     cb_to_client_cid: CID,
     cb_to_client_id: u32,
+    stream: [u32; 4],
+}
+
+/// token of the stream used by clients that never registered one of their own
+const DEFAULT_STREAM: [u32; 4] = [0; 4];
+
+/// The client currently recording. Recorded frames go only to it, and only while it holds the token.
+struct Recorder {
+    token: [u32; 4],
+    cb: ScalarCallback,
 }
 
 #[xous::xous_main]
@@ -35,6 +48,9 @@ fn xmain() -> ! {
     let mut codec = Codec::new(codec_conn, &xns);
 
     let ticktimer = ticktimer_server::Ticktimer::new().unwrap();
+    let trng = trng::Trng::new(&xns).unwrap();
+    let mut mixer = Mixer::new();
+    mixer.register(DEFAULT_STREAM, StreamPriority::Media, 100);
     log::trace!("ready to accept requests");
 
     // register a suspend/resume listener
@@ -51,6 +67,7 @@ fn xmain() -> ! {
     let mut speaker_analog_gain_db: f32 = -6.0;
     let mut headphone_analog_gain_db: f32 = -15.0;
     let mut audio_cb_conns: [Option<ScalarCallback>; 32] = [None; 32];
+    let mut recorder: Option<Recorder> = None;
     loop {
         let mut msg = xous::receive_message(codec_sid).unwrap();
         //log::trace!("got message {:?}", msg);
@@ -63,23 +80,58 @@ fn xmain() -> ! {
             Some(api::Opcode::PowerOff) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
                 codec.power(false);
             }),
-            Some(api::Opcode::Setup8kStereo) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
-                log::trace!("turning on codec power");
-                codec.power(true);
-                log::trace!("waiting for power up");
-                ticktimer.sleep_ms(2).unwrap();
-                log::trace!("initializing codec");
-                codec.init();
-            }),
+            Some(api::Opcode::SetupStream) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<StreamRequest, _>().unwrap();
+                let token = request.token.unwrap_or(DEFAULT_STREAM);
+                if codec.is_on() && codec.is_init() && codec.sample_rate() == request.rate {
+                    log::trace!("codec already running at {}Hz", request.rate.hz());
+                } else if codec.is_live() && mixer.has_frames_except(token) {
+                    // other streams are playing; keep the hardware rate, and resample this stream to it
+                    log::info!("codec busy at {}Hz, resampling a {}Hz stream", codec.sample_rate().hz(), request.rate.hz());
+                } else {
+                    if codec.is_live() {
+                        codec.audio_i2s_stop();
+                    }
+                    log::trace!("turning on codec power");
+                    codec.power(true);
+                    log::trace!("waiting for power up");
+                    ticktimer.sleep_ms(2).unwrap();
+                    log::trace!("initializing codec at {}Hz", request.rate.hz());
+                    codec.set_sample_rate(request.rate);
+                    codec.init();
+                    mixer.set_output_rate(request.rate);
+                }
+                request.ok = mixer.set_stream_rate(token, request.rate);
+                request.hw_rate = codec.sample_rate();
+                buffer.replace(request).unwrap();
+            }
             Some(api::Opcode::ResumeStream) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
-                if codec.is_on() && codec.is_init() {
+                if codec.is_live() {
+                    // another stream is keeping the hardware running; restarting it would glitch that stream
+                    mixer.pump(&mut codec);
+                } else if codec.is_on() && codec.is_init() {
                     codec.audio_i2s_start();
                 } else {
                     log::error!("attempted to resume a stream on an unitialized codec, ignoring!")
                 }
             }),
-            Some(api::Opcode::PauseStream) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
-                if codec.is_on() && codec.is_init() && codec.is_live() {
+            Some(api::Opcode::PauseStream) => xous::msg_scalar_unpack!(msg, t0, t1, t2, t3, {
+                // the stream just stops feeding the mixer; the hardware only pauses once every stream is quiet
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                mixer.finish(token);
+                if mixer.has_frames_except(token) {
+                    mixer.pump(&mut codec);
+                } else if recorder.is_some() {
+                    // the microphone still needs the hardware running; just let our frames play out
+                    mixer.pump(&mut codec);
+                } else if codec.is_on() && codec.is_init() && codec.is_live() {
+                    // let the frames this stream already handed over finish playing
+                    while mixer.has_frames() && codec.is_live() {
+                        if mixer.pump(&mut codec) == 0 {
+                            xous::yield_slice();
+                        }
+                    }
                     codec.drain(); // this will suppress any future callbacks from firing
                     while codec.can_play() {
                         xous::yield_slice();
@@ -89,8 +141,12 @@ fn xmain() -> ! {
                     log::error!("attempted to pause a stream on an uninitialized codec, ignoring!")
                 }
             }),
-            Some(api::Opcode::AbortStream) => xous::msg_scalar_unpack!(msg, _, _, _, _, {
-                if codec.is_on() && codec.is_init() && codec.is_live() {
+            Some(api::Opcode::AbortStream) => xous::msg_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                mixer.flush(token);
+                if mixer.has_frames() || recorder.is_some() {
+                    // other streams are still playing or we're recording, leave the hardware running
+                } else if codec.is_on() && codec.is_init() && codec.is_live() {
                     codec.audio_i2s_stop();
                 } else {
                     log::error!("attempted to abort a stream on an uninitialized codec, ignoring!")
@@ -104,43 +160,118 @@ fn xmain() -> ! {
                 };
                 xous::return_scalar(msg.sender, ret).expect("couldn't return if codec is live");
             }),
-            Some(api::Opcode::FreeFrames) => xous::msg_blocking_scalar_unpack!(msg, _, _, _, _, {
-                let play_free = codec.free_play_frames();
-                let rec_avail = codec.available_rec_frames();
+            Some(api::Opcode::FreeFrames) => xous::msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                // free play frames are counted in the caller's stream, as that's where its frames go
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                let play_free = mixer.free_frames(token);
+                // recorded audio is only disclosed to the recorder, when there is one
+                let rec_avail = if recorder.is_none() { codec.available_rec_frames() } else { 0 };
                 xous::return_scalar2(msg.sender, play_free, rec_avail).expect("couldn't return FreeFrames");
             }),
+            Some(api::Opcode::QueuedFrames) => xous::msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                xous::return_scalar(msg.sender, mixer.queued_frames(token)).expect("couldn't return QueuedFrames");
+            }),
+            Some(api::Opcode::StartRecording) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut hook = buffer.to_original::<RecordHook, _>().unwrap();
+                hook.token = None;
+                if recorder.is_some() {
+                    log::warn!("a recording is already in progress, refusing to start another");
+                } else if !(codec.is_on() && codec.is_init()) {
+                    log::error!("attempted to record on an uninitialized codec, ignoring!");
+                } else {
+                    let mut token = [0u32; 4];
+                    for t in token.iter_mut() {
+                        *t = trng.get_u32().unwrap();
+                    }
+                    let (s0, s1, s2, s3) = hook.sid;
+                    recorder = Some(Recorder {
+                        token,
+                        cb: ScalarCallback {
+                            server_to_cb_cid: xous::connect(xous::SID::from_u32(s0, s1, s2, s3)).unwrap(),
+                            cb_to_client_cid: hook.cid,
+                            cb_to_client_id: hook.id,
+                            stream: DEFAULT_STREAM, // unused
+                        },
+                    });
+                    // recording is driven by the frame interrupts, so the hardware has to be running
+                    if !codec.is_live() {
+                        codec.audio_i2s_start();
+                    }
+                    hook.token = Some(token);
+                }
+                buffer.replace(hook).unwrap();
+            }
+            Some(api::Opcode::StopRecording) => msg_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                if recorder.as_ref().map(|r| r.token == token).unwrap_or(false) {
+                    let rec = recorder.take().unwrap();
+                    unhook_one(&rec.cb);
+                    if !mixer.has_frames() && codec.is_live() {
+                        codec.audio_i2s_stop();
+                    }
+                } else {
+                    log::warn!("attempt to stop a recording with the wrong token, ignoring");
+                }
+            }),
+            Some(api::Opcode::GetRecFrames) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut framering = buffer.to_original::<codec::api::FrameRing, _>().unwrap();
+                framering.reset_ptrs();
+                match (&recorder, framering.auth_token()) {
+                    (Some(rec), Some(token)) if rec.token == token => {
+                        while !framering.is_full() {
+                            if let Some(frame) = codec.dq_rec_frame() {
+                                framering.nq_frame(frame).unwrap();
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                    _ => log::warn!("recorded frames requested without the recording token, ignoring"),
+                }
+                buffer.replace(framering).unwrap();
+            }
             Some(api::Opcode::SwapFrames) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut framering = buffer.to_original::<codec::api::FrameRing, _>().unwrap();
 
-                loop {
-                    if let Some(frame) = framering.dq_frame() {
-                        let mut printed = false;
-                        while codec.free_play_frames() == 0 {
-                            if !printed {
-                                log::debug!("swap overrun");
-                                printed = true;
+                let token = framering.auth_token().unwrap_or(DEFAULT_STREAM);
+                if mixer.is_registered(token) {
+                    loop {
+                        if let Some(frame) = framering.dq_frame() {
+                            let mut printed = false;
+                            while mixer.free_frames(token) == 0 {
+                                // make room by moving mixed audio into the hardware
+                                if mixer.pump(&mut codec) == 0 {
+                                    if !printed {
+                                        log::debug!("swap overrun");
+                                        printed = true;
+                                    }
+                                    xous::yield_slice();
+                                }
+                                if !codec.is_live() {
+                                    // handle the case that play stopped while we're trying to run the swap
+                                    break;
+                                }
                             }
-                            xous::yield_slice();
-                            if !codec.is_live() {
-                                // handle the case that play stopped while we're trying to run the swap
-                                break;
+                            if mixer.nq_frame(token, frame).is_err() {
+                                // TODO: need to define a behavior when we have a play overrun. Do we:
+                                // - wait until we can play the frame?
+                                // - throw away the frame?
                             }
-                        }
-                        if codec.free_play_frames() > 0 {
-                            codec.nq_play_frame(frame).unwrap(); // throw away the result because we know this must succeed
                         } else {
-                            // TODO: need to define a behavior when we have a play overrun. Do we:
-                            // - wait until we can play the frame?
-                            // - throw away the frame?
+                            break;
                         }
-                    } else {
-                        break;
                     }
+                    mixer.pump(&mut codec);
+                } else {
+                    log::error!("frames swapped in for an unregistered stream, discarding");
                 }
 
                 framering.reset_ptrs();
-                loop {
+                while recorder.is_none() {
                     if let Some(frame) = codec.dq_rec_frame() {
                         if !framering.is_full() {
                             framering.nq_frame(frame).unwrap(); // always succeeds because we checked if we're full first
@@ -163,8 +294,56 @@ fn xmain() -> ! {
             }
             Some(api::Opcode::AnotherFrame) => xous::msg_scalar_unpack!(msg, _rdcount, _wrcount, _, _, {
                 //log::trace!("A rd {} wr {}", rdcount, wrcount);
-                send_event(&audio_cb_conns, codec.free_play_frames(), codec.available_rec_frames());
+                mixer.pump(&mut codec);
+                if let Some(rec) = &recorder {
+                    send_event(&audio_cb_conns, &mixer, 0);
+                    xous::send_message(rec.cb.server_to_cb_cid,
+                        xous::Message::new_scalar(EventCallback::Event.to_usize().unwrap(),
+                           rec.cb.cb_to_client_cid as usize, rec.cb.cb_to_client_id as usize, 0, codec.available_rec_frames())
+                    ).unwrap();
+                } else {
+                    send_event(&audio_cb_conns, &mixer, codec.available_rec_frames());
+                }
+            }),
+            Some(api::Opcode::RegisterStream) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut request = buffer.to_original::<StreamRequest, _>().unwrap();
+                let mut token = [0u32; 4];
+                // tokens are random so streams can't be hijacked by other processes
+                while token == DEFAULT_STREAM || mixer.is_registered(token) {
+                    for t in token.iter_mut() {
+                        *t = trng.get_u32().unwrap();
+                    }
+                }
+                request.ok = mixer.register(token, request.priority, request.volume)
+                    && mixer.set_stream_rate(token, request.rate);
+                request.token = if request.ok { Some(token) } else { None };
+                if !request.ok {
+                    log::warn!("out of playback streams, can't register a new one");
+                }
+                buffer.replace(request).unwrap();
+            }
+            Some(api::Opcode::UnregisterStream) => msg_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                if token == DEFAULT_STREAM || !mixer.unregister(token) {
+                    log::warn!("attempt to unregister an unknown stream, ignoring");
+                }
+                for entry in audio_cb_conns.iter_mut() {
+                    if let Some(scb) = entry {
+                        if scb.stream == token {
+                            unhook_one(scb);
+                            *entry = None;
+                        }
+                    }
+                }
             }),
+            Some(api::Opcode::SetStreamVolume) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let request = buffer.to_original::<StreamRequest, _>().unwrap();
+                if !mixer.set_volume(request.token.unwrap_or(DEFAULT_STREAM), request.volume) {
+                    log::warn!("attempt to set the volume of an unknown stream, ignoring");
+                }
+            }
             Some(api::Opcode::SetSpeakerVolume) => xous::msg_scalar_unpack!(msg, op, gain_code, _, _, {
                 match FromPrimitive::from_usize(op) {
                     Some(VolumeOps::Set) => {
@@ -228,6 +407,9 @@ fn xmain() -> ! {
     // clean up our program
     log::trace!("main loop exit, destroying servers");
     unhook(&mut audio_cb_conns);
+    if let Some(rec) = recorder.take() {
+        unhook_one(&rec.cb);
+    }
     xns.unregister_server(codec_sid).unwrap();
     xous::destroy_server(codec_sid).unwrap();
     log::trace!("quitting");
@@ -243,6 +425,7 @@ fn do_hook(hookdata: ScalarHook, cb_conns: &mut [Option<ScalarCallback>; 32]) {
         server_to_cb_cid,
         cb_to_client_cid: hookdata.cid,
         cb_to_client_id: hookdata.id,
+        stream: hookdata.stream.unwrap_or(DEFAULT_STREAM),
     });
     let mut found = false;
     for entry in cb_conns.iter_mut() {
@@ -256,20 +439,25 @@ fn do_hook(hookdata: ScalarHook, cb_conns: &mut [Option<ScalarCallback>; 32]) {
         log::error!("ran out of space registering callback");
     }
 }
+fn unhook_one(scb: &ScalarCallback) {
+    xous::send_message(scb.server_to_cb_cid,
+        xous::Message::new_blocking_scalar(EventCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
+    ).unwrap();
+    unsafe{xous::disconnect(scb.server_to_cb_cid).unwrap();}
+}
 fn unhook(cb_conns: &mut [Option<ScalarCallback>; 32]) {
     for entry in cb_conns.iter_mut() {
         if let Some(scb) = entry {
-            xous::send_message(scb.server_to_cb_cid,
-                xous::Message::new_blocking_scalar(EventCallback::Drop.to_usize().unwrap(), 0, 0, 0, 0)
-            ).unwrap();
-            unsafe{xous::disconnect(scb.server_to_cb_cid).unwrap();}
+            unhook_one(scb);
         }
         *entry = None;
     }
 }
-fn send_event(cb_conns: &[Option<ScalarCallback>; 32], free_play: usize, avail_rec: usize) {
+fn send_event(cb_conns: &[Option<ScalarCallback>; 32], mixer: &Mixer, avail_rec: usize) {
     for entry in cb_conns.iter() {
         if let Some(scb) = entry {
+            // each subscriber is told how much room its own stream has in the mixer
+            let free_play = mixer.free_frames(scb.stream);
             // note that the "which" argument is only used for GPIO events, to indicate which pin had the event
             xous::send_message(scb.server_to_cb_cid,
                 xous::Message::new_scalar(EventCallback::Event.to_usize().unwrap(),
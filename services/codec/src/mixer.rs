@@ -0,0 +1,422 @@
+use crate::api::*;
+use crate::backend::Codec;
+use crate::resampler::Resampler;
+use std::collections::VecDeque;
+
+/// maximum number of concurrently registered playback streams
+pub(crate) const MAX_STREAMS: usize = 8;
+/// frames buffered per stream in the mixer; matches the capacity of a FrameRing, so a client can always
+/// hand over one full ring in a single swap.
+const STREAM_DEPTH: usize = FRAMES - 1;
+/// unity gain in the mixer's Q15 fixed point representation (the CPU has no FPU)
+const UNITY_Q15: i32 = 1 << 15;
+/// ducked streams are attenuated to 1/4 of their volume (about -12dB)
+const DUCK_SHIFT: u32 = 2;
+
+struct Stream {
+    token: [u32; 4],
+    priority: StreamPriority,
+    /// the rate the client renders audio at
+    rate: SampleRate,
+    /// 0-100, as set by the client
+    volume: u8,
+    frames: VecDeque<[u32; FIFO_DEPTH]>,
+    /// present if the stream's producer runs at a different rate than the hardware
+    resampler: Option<Resampler>,
+    /// resampled audio that doesn't fill a whole frame yet
+    pending: VecDeque<u32>,
+    /// the gain applied at the end of the last mixed frame. Gain changes are ramped over one frame
+    /// from this value to avoid clicks when a stream is ducked or its volume is changed.
+    gain_q15: i32,
+}
+impl Stream {
+    /// number of output samples the stream can still take in
+    fn free_samples(&self) -> usize {
+        (STREAM_DEPTH - self.frames.len()) * FIFO_DEPTH - self.pending.len()
+    }
+    fn clear(&mut self) {
+        self.frames.clear();
+        self.pending.clear();
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.reset();
+        }
+    }
+    fn target_gain_q15(&self, ducked: bool) -> i32 {
+        let gain = (self.volume.min(100) as i32 * UNITY_Q15) / 100;
+        if ducked {
+            gain >> DUCK_SHIFT
+        } else {
+            gain
+        }
+    }
+}
+
+/// Mixes any number of registered playback streams into the single hardware play FIFO.
+///
+/// Each stream has a volume and a priority; while a stream of a higher priority has audio queued,
+/// all streams of a lower priority are ducked. Mixing is done per channel with saturation.
+/// Streams produced at a rate other than the hardware's are resampled on the way in.
+pub(crate) struct Mixer {
+    streams: Vec<Stream>,
+    /// the rate the hardware is running at
+    rate: SampleRate,
+}
+
+fn left(sample: u32) -> i32 {
+    (sample & 0xFFFF) as u16 as i16 as i32
+}
+fn right(sample: u32) -> i32 {
+    (sample >> 16) as u16 as i16 as i32
+}
+fn pack(l: i32, r: i32) -> u32 {
+    let l = l.max(i16::MIN as i32).min(i16::MAX as i32) as i16 as u16 as u32;
+    let r = r.max(i16::MIN as i32).min(i16::MAX as i32) as i16 as u16 as u32;
+    l | r << 16
+}
+
+impl Mixer {
+    pub(crate) fn new() -> Mixer {
+        Mixer {
+            streams: Vec::new(),
+            rate: SampleRate::Fs8000,
+        }
+    }
+    /// Sets the rate the mixed audio is played at. Any queued audio is discarded, as it was
+    /// rendered for the old rate.
+    pub(crate) fn set_output_rate(&mut self, rate: SampleRate) {
+        self.rate = rate;
+        for stream in self.streams.iter_mut() {
+            stream.resampler = if stream.rate == rate { None } else { Some(Resampler::new(stream.rate, rate)) };
+            stream.clear();
+        }
+    }
+    /// Sets the rate at which a stream's producer renders audio.
+    pub(crate) fn set_stream_rate(&mut self, token: [u32; 4], rate: SampleRate) -> bool {
+        let output_rate = self.rate;
+        if let Some(stream) = self.streams.iter_mut().find(|s| s.token == token) {
+            if stream.rate != rate {
+                stream.rate = rate;
+                stream.resampler = if rate == output_rate { None } else { Some(Resampler::new(rate, output_rate)) };
+                stream.clear();
+            }
+            true
+        } else {
+            false
+        }
+    }
+    /// Registers a new stream under `token`. Returns false if we're out of stream slots, or the token is in use.
+    pub(crate) fn register(&mut self, token: [u32; 4], priority: StreamPriority, volume: u8) -> bool {
+        if self.streams.len() >= MAX_STREAMS || self.streams.iter().any(|s| s.token == token) {
+            return false;
+        }
+        let mut stream = Stream {
+            token,
+            priority,
+            volume,
+            rate: self.rate,
+            frames: VecDeque::with_capacity(STREAM_DEPTH),
+            resampler: None,
+            pending: VecDeque::new(),
+            gain_q15: 0,
+        };
+        stream.gain_q15 = stream.target_gain_q15(false);
+        self.streams.push(stream);
+        true
+    }
+    pub(crate) fn unregister(&mut self, token: [u32; 4]) -> bool {
+        let count = self.streams.len();
+        self.streams.retain(|s| s.token != token);
+        count != self.streams.len()
+    }
+    pub(crate) fn is_registered(&self, token: [u32; 4]) -> bool {
+        self.streams.iter().any(|s| s.token == token)
+    }
+    pub(crate) fn set_volume(&mut self, token: [u32; 4], volume: u8) -> bool {
+        if let Some(stream) = self.streams.iter_mut().find(|s| s.token == token) {
+            stream.volume = volume.min(100);
+            true
+        } else {
+            false
+        }
+    }
+    /// number of frames the stream can accept right now, counted at the stream's own rate
+    pub(crate) fn free_frames(&self, token: [u32; 4]) -> usize {
+        if let Some(stream) = self.streams.iter().find(|s| s.token == token) {
+            if let Some(resampler) = stream.resampler.as_ref() {
+                resampler.max_input(stream.free_samples()) / FIFO_DEPTH
+            } else {
+                STREAM_DEPTH - stream.frames.len()
+            }
+        } else {
+            0
+        }
+    }
+    pub(crate) fn nq_frame(&mut self, token: [u32; 4], frame: [u32; FIFO_DEPTH]) -> Result<(), [u32; FIFO_DEPTH]> {
+        if self.free_frames(token) == 0 {
+            return Err(frame);
+        }
+        let stream = self.streams.iter_mut().find(|s| s.token == token).unwrap(); // a stream with free frames exists
+        if let Some(resampler) = stream.resampler.as_mut() {
+            resampler.process(&frame, &mut stream.pending);
+            while stream.pending.len() >= FIFO_DEPTH {
+                let mut out = [0u32; FIFO_DEPTH];
+                for (dst, src) in out.iter_mut().zip(stream.pending.drain(..FIFO_DEPTH)) {
+                    *dst = src;
+                }
+                stream.frames.push_back(out);
+            }
+        } else {
+            stream.frames.push_back(frame);
+        }
+        Ok(())
+    }
+    /// Pads out any partial frame left over from resampling with silence, so the tail end of
+    /// the stream gets played. Call once the producer has handed over its last frame.
+    pub(crate) fn finish(&mut self, token: [u32; 4]) {
+        if let Some(stream) = self.streams.iter_mut().find(|s| s.token == token) {
+            if stream.pending.len() > 0 {
+                let mut out = [ZERO_PCM as u32 | (ZERO_PCM as u32) << 16; FIFO_DEPTH];
+                for (dst, src) in out.iter_mut().zip(stream.pending.drain(..)) {
+                    *dst = src;
+                }
+                stream.frames.push_back(out);
+            }
+        }
+    }
+    /// discards any audio queued on a stream, without unregistering it
+    pub(crate) fn flush(&mut self, token: [u32; 4]) {
+        if let Some(stream) = self.streams.iter_mut().find(|s| s.token == token) {
+            stream.clear();
+        }
+    }
+    /// number of frames (at the hardware rate) the stream still has waiting to be mixed
+    pub(crate) fn queued_frames(&self, token: [u32; 4]) -> usize {
+        if let Some(stream) = self.streams.iter().find(|s| s.token == token) {
+            stream.frames.len() + if stream.pending.len() > 0 { 1 } else { 0 }
+        } else {
+            0
+        }
+    }
+    pub(crate) fn has_frames(&self) -> bool {
+        self.streams.iter().any(|s| s.frames.len() > 0)
+    }
+    /// true if any stream other than `token` still has audio queued
+    pub(crate) fn has_frames_except(&self, token: [u32; 4]) -> bool {
+        self.streams.iter().any(|s| s.token != token && s.frames.len() > 0)
+    }
+    /// Pulls one frame out of every stream that has audio queued, and mixes them down to a single frame.
+    pub(crate) fn mix(&mut self) -> Option<[u32; FIFO_DEPTH]> {
+        let top_priority = self.streams.iter()
+            .filter(|s| s.frames.len() > 0)
+            .map(|s| s.priority)
+            .max()?;
+        let mut acc_l = [0i32; FIFO_DEPTH];
+        let mut acc_r = [0i32; FIFO_DEPTH];
+        for stream in self.streams.iter_mut() {
+            let target = stream.target_gain_q15(stream.priority < top_priority);
+            if let Some(frame) = stream.frames.pop_front() {
+                let start = stream.gain_q15;
+                for (i, &sample) in frame.iter().enumerate() {
+                    // linear ramp from the previous gain to the target gain across the frame
+                    let gain = start + ((target - start) * (i as i32 + 1)) / FIFO_DEPTH as i32;
+                    acc_l[i] += (left(sample) * gain) >> 15;
+                    acc_r[i] += (right(sample) * gain) >> 15;
+                }
+            }
+            // streams without audio snap to their target, so they come back in at the right level
+            stream.gain_q15 = target;
+        }
+        let mut out = [0u32; FIFO_DEPTH];
+        for (i, dst) in out.iter_mut().enumerate() {
+            *dst = pack(acc_l[i], acc_r[i]);
+        }
+        Some(out)
+    }
+    /// Mixes frames into the hardware play FIFO until either the FIFO is full or we run out of audio.
+    /// Returns the number of frames handed to the hardware.
+    pub(crate) fn pump(&mut self, codec: &mut Codec) -> usize {
+        let mut pumped = 0;
+        while codec.free_play_frames() > 0 {
+            if let Some(frame) = self.mix() {
+                if codec.nq_play_frame(frame).is_err() {
+                    log::warn!("hardware refused a mixed frame; dropping it");
+                    break;
+                }
+                pumped += 1;
+            } else {
+                break;
+            }
+        }
+        pumped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_of(l: i16, r: i16) -> [u32; FIFO_DEPTH] {
+        [pack(l as i32, r as i32); FIFO_DEPTH]
+    }
+
+    #[test]
+    fn test_pack_saturates() {
+        assert_eq!(left(pack(40000, -40000)), i16::MAX as i32);
+        assert_eq!(right(pack(40000, -40000)), i16::MIN as i32);
+        assert_eq!(left(pack(-1234, 0)), -1234);
+    }
+
+    #[test]
+    fn test_mix_two_streams() {
+        let mut mixer = Mixer::new();
+        assert!(mixer.register([1, 0, 0, 0], StreamPriority::Media, 100));
+        assert!(mixer.register([2, 0, 0, 0], StreamPriority::Media, 50));
+        assert!(!mixer.register([2, 0, 0, 0], StreamPriority::Media, 50), "duplicate token accepted");
+        mixer.nq_frame([1, 0, 0, 0], frame_of(1000, -1000)).unwrap();
+        mixer.nq_frame([2, 0, 0, 0], frame_of(1000, 1000)).unwrap();
+        let out = mixer.mix().unwrap();
+        assert_eq!(left(out[FIFO_DEPTH - 1]), 1500);
+        assert_eq!(right(out[FIFO_DEPTH - 1]), -500);
+        assert!(mixer.mix().is_none());
+    }
+
+    #[test]
+    fn test_ducking() {
+        let mut mixer = Mixer::new();
+        let media = [1, 0, 0, 0];
+        let speech = [2, 0, 0, 0];
+        mixer.register(media, StreamPriority::Media, 100);
+        mixer.register(speech, StreamPriority::Speech, 100);
+        mixer.nq_frame(media, frame_of(4000, 4000)).unwrap();
+        let out = mixer.mix().unwrap();
+        assert_eq!(left(out[FIFO_DEPTH - 1]), 4000, "media alone should not be ducked");
+
+        mixer.nq_frame(media, frame_of(4000, 4000)).unwrap();
+        mixer.nq_frame(speech, frame_of(100, 100)).unwrap();
+        let out = mixer.mix().unwrap();
+        // the duck ramps in over the frame, ending at 1/4 volume
+        assert!(left(out[0]) > 3000);
+        assert_eq!(left(out[FIFO_DEPTH - 1]), 1000 + 100);
+
+        // once speech is done, media ramps back up
+        mixer.nq_frame(media, frame_of(4000, 4000)).unwrap();
+        let out = mixer.mix().unwrap();
+        assert_eq!(left(out[FIFO_DEPTH - 1]), 4000);
+    }
+
+    #[test]
+    fn test_stream_bookkeeping() {
+        let mut mixer = Mixer::new();
+        let t = [7, 7, 7, 7];
+        assert!(mixer.nq_frame(t, frame_of(0, 0)).is_err(), "unregistered stream accepted a frame");
+        mixer.register(t, StreamPriority::Notification, 80);
+        for _ in 0..STREAM_DEPTH {
+            mixer.nq_frame(t, frame_of(1, 1)).unwrap();
+        }
+        assert_eq!(mixer.free_frames(t), 0);
+        assert!(mixer.nq_frame(t, frame_of(1, 1)).is_err());
+        mixer.flush(t);
+        assert_eq!(mixer.free_frames(t), STREAM_DEPTH);
+        assert!(mixer.set_volume(t, 200));
+        assert!(mixer.unregister(t));
+        assert!(!mixer.is_registered(t));
+        for i in 0..MAX_STREAMS as u32 {
+            assert!(mixer.register([i, 1, 0, 0], StreamPriority::Media, 100));
+        }
+        assert!(!mixer.register([99, 1, 0, 0], StreamPriority::Media, 100), "stream limit not enforced");
+    }
+
+    #[test]
+    fn test_pump_into_hosted_codec() {
+        let mut codec = Codec::new_loopback();
+        codec.audio_i2s_start();
+        let mut mixer = Mixer::new();
+        let a = [1, 0, 0, 0];
+        let b = [2, 0, 0, 0];
+        mixer.register(a, StreamPriority::Media, 100);
+        mixer.register(b, StreamPriority::Notification, 100);
+        for _ in 0..STREAM_DEPTH {
+            mixer.nq_frame(a, frame_of(2000, 2000)).unwrap();
+        }
+        mixer.nq_frame(b, frame_of(-500, -500)).unwrap();
+
+        let free = codec.free_play_frames();
+        assert_eq!(mixer.pump(&mut codec), free, "pump did not fill the hardware FIFO");
+        assert_eq!(codec.free_play_frames(), 0);
+        // the first frame carries the notification with the media ducked underneath it
+        let first = codec.consume_play_frame().unwrap();
+        assert_eq!(left(first[FIFO_DEPTH - 1]), 500 - 500);
+        // once the hardware drains a frame, pumping again tops it back up
+        assert_eq!(mixer.pump(&mut codec), 1);
+        let second = codec.consume_play_frame().unwrap();
+        assert!(left(second[FIFO_DEPTH - 1]) == 2000);
+    }
+
+    #[test]
+    fn test_resampled_stream() {
+        let mut mixer = Mixer::new();
+        mixer.set_output_rate(SampleRate::Fs48000);
+        let t = [3, 0, 0, 0];
+        mixer.register(t, StreamPriority::Media, 100);
+        assert_eq!(mixer.free_frames(t), STREAM_DEPTH);
+        assert!(mixer.set_stream_rate(t, SampleRate::Fs8000));
+        // each 8kHz frame turns into six frames at 48kHz
+        assert_eq!(mixer.free_frames(t), STREAM_DEPTH / 6);
+        mixer.nq_frame(t, frame_of(1000, 1000)).unwrap();
+        mixer.nq_frame(t, frame_of(1000, 1000)).unwrap();
+        assert!(mixer.nq_frame(t, frame_of(1000, 1000)).is_err());
+        mixer.finish(t); // nothing to pad, the frames divide evenly
+        assert_eq!(mixer.queued_frames(t), 12);
+        let mut count = 0;
+        while let Some(frame) = mixer.mix() {
+            assert_eq!(left(frame[FIFO_DEPTH - 1]), 1000);
+            count += 1;
+        }
+        assert_eq!(count, 12);
+
+        // 44.1kHz doesn't divide evenly into 48kHz frames; the tail is padded out when the stream finishes
+        mixer.set_stream_rate(t, SampleRate::Fs44100);
+        mixer.nq_frame(t, frame_of(1000, 1000)).unwrap();
+        assert!(mixer.mix().is_some());
+        assert!(mixer.mix().is_none());
+        mixer.finish(t);
+        let tail = mixer.mix().unwrap();
+        assert_eq!(left(tail[0]), 1000);
+        assert_eq!(left(tail[FIFO_DEPTH - 1]), 0);
+        assert!(mixer.mix().is_none());
+    }
+
+    #[test]
+    fn test_hosted_codec_at_48k() {
+        let mut codec = Codec::new_loopback();
+        codec.set_sample_rate(SampleRate::Fs48000);
+        codec.init();
+        assert_eq!(codec.sample_rate(), SampleRate::Fs48000);
+        codec.audio_i2s_start();
+        let mut mixer = Mixer::new();
+        mixer.set_output_rate(codec.sample_rate());
+        let speech = [4, 0, 0, 0];
+        let media = [5, 0, 0, 0];
+        mixer.register(speech, StreamPriority::Speech, 100);
+        mixer.register(media, StreamPriority::Media, 100);
+        mixer.set_stream_rate(speech, SampleRate::Fs16000);
+        // media renders at the hardware rate, and isn't resampled
+        assert_eq!(mixer.free_frames(media), STREAM_DEPTH);
+        mixer.nq_frame(speech, frame_of(300, 300)).unwrap();
+        for _ in 0..3 {
+            mixer.nq_frame(media, frame_of(2000, 2000)).unwrap();
+        }
+        let mut played = 0;
+        loop {
+            mixer.pump(&mut codec);
+            if let Some(frame) = codec.consume_play_frame() {
+                // the 16kHz speech covers exactly three frames at 48kHz, ducking the media under it
+                assert_eq!(left(frame[FIFO_DEPTH - 1]), 500 + 300);
+                played += 1;
+            } else {
+                break;
+            }
+        }
+        assert_eq!(played, 3);
+    }
+}
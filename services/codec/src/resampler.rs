@@ -0,0 +1,134 @@
+use crate::api::*;
+use std::collections::VecDeque;
+
+/// Converts a stream of stereo samples from one sample rate to another, by linear interpolation
+/// between adjacent input samples.
+///
+/// The read position is tracked as an exact fraction of an input sample (in units of 1/`to`), so
+/// the conversion doesn't drift even for ratios like 44.1kHz:48kHz. There is no anti-alias filter
+/// when downsampling; this is adequate for speech and UI sounds, but producers that care about
+/// fidelity should render at the hardware rate instead.
+pub(crate) struct Resampler {
+    from: u64,
+    to: u64,
+    /// position of the next output sample, measured from `prev`, in units of 1/`to` input samples
+    pos: u64,
+    /// the last input sample of the previous call, so interpolation is continuous across frames
+    prev: u32,
+}
+
+fn lerp(a: i32, b: i32, frac: u64, den: u64) -> i32 {
+    a + (((b - a) as i64 * frac as i64) / den as i64) as i32
+}
+
+impl Resampler {
+    pub(crate) fn new(from: SampleRate, to: SampleRate) -> Resampler {
+        Resampler {
+            from: from.hz() as u64,
+            to: to.hz() as u64,
+            pos: 0,
+            prev: ZERO_PCM as u32 | (ZERO_PCM as u32) << 16,
+        }
+    }
+    /// upper bound on the number of output samples generated from `input_len` input samples
+    pub(crate) fn max_output(&self, input_len: usize) -> usize {
+        (input_len as u64 * self.to / self.from) as usize + 1
+    }
+    /// the number of whole input samples that are guaranteed to fit into `output_len` output samples
+    pub(crate) fn max_input(&self, output_len: usize) -> usize {
+        (output_len.saturating_sub(1) as u64 * self.from / self.to) as usize
+    }
+    /// forgets the interpolation history, e.g. when a stream is flushed
+    pub(crate) fn reset(&mut self) {
+        self.pos = 0;
+        self.prev = ZERO_PCM as u32 | (ZERO_PCM as u32) << 16;
+    }
+    /// Resamples `input` and appends the result to `output`.
+    pub(crate) fn process(&mut self, input: &[u32], output: &mut VecDeque<u32>) {
+        if input.len() == 0 {
+            return;
+        }
+        // the input is treated as the sequence [prev, input[0], .. input[n-1]]; an output sample
+        // at index `i` interpolates between element i and i+1 of that sequence.
+        let end = input.len() as u64 * self.to;
+        while self.pos < end {
+            let index = (self.pos / self.to) as usize;
+            let frac = self.pos % self.to;
+            let a = if index == 0 { self.prev } else { input[index - 1] };
+            let b = input[index];
+            let l = lerp((a & 0xFFFF) as u16 as i16 as i32, (b & 0xFFFF) as u16 as i16 as i32, frac, self.to);
+            let r = lerp((a >> 16) as u16 as i16 as i32, (b >> 16) as u16 as i16 as i32, frac, self.to);
+            output.push_back((l as i16 as u16 as u32) | (r as i16 as u16 as u32) << 16);
+            self.pos += self.from;
+        }
+        self.pos -= end;
+        self.prev = input[input.len() - 1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo(l: i16, r: i16) -> u32 {
+        (l as u16 as u32) | (r as u16 as u32) << 16
+    }
+
+    #[test]
+    fn test_upsample_count_and_ramp() {
+        let mut rs = Resampler::new(SampleRate::Fs8000, SampleRate::Fs16000);
+        let input: Vec<u32> = (0..FIFO_DEPTH as i16).map(|i| stereo(i * 100, -i * 100)).collect();
+        let mut out = VecDeque::new();
+        rs.process(&input, &mut out);
+        assert_eq!(out.len(), FIFO_DEPTH * 2);
+        // every other output sample lands halfway between two input samples
+        assert_eq!(out[3], stereo(50, -50));
+        assert_eq!(out[4], stereo(100, -100));
+        assert_eq!(out[5], stereo(150, -150));
+    }
+
+    #[test]
+    fn test_rates_do_not_drift() {
+        let rates = [SampleRate::Fs8000, SampleRate::Fs16000, SampleRate::Fs32000, SampleRate::Fs44100, SampleRate::Fs48000];
+        for &from in rates.iter() {
+            for &to in rates.iter() {
+                let mut rs = Resampler::new(from, to);
+                let frame = [stereo(1000, -1000); FIFO_DEPTH];
+                let mut out = VecDeque::new();
+                let frames = from.hz() as usize / 100; // enough input for some multiple of a second's worth of frames
+                for _ in 0..frames {
+                    let before = out.len();
+                    rs.process(&frame, &mut out);
+                    assert!(out.len() - before <= rs.max_output(FIFO_DEPTH), "{:?}->{:?} exceeded max_output", from, to);
+                }
+                // FIFO_DEPTH * from / 100 samples is 2.56s of audio, which divides evenly at every rate
+                assert_eq!(out.len(), frames * FIFO_DEPTH * to.hz() as usize / from.hz() as usize, "{:?}->{:?}", from, to);
+                // a DC input stays DC, once past the interpolation from the initial silence
+                let settle = ((to.hz() + from.hz() - 1) / from.hz()) as usize;
+                assert!(out.iter().skip(settle).all(|&s| s == stereo(1000, -1000)), "{:?}->{:?} distorted DC", from, to);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity_bounds() {
+        let rs = Resampler::new(SampleRate::Fs8000, SampleRate::Fs48000);
+        assert_eq!(rs.max_output(FIFO_DEPTH), FIFO_DEPTH * 6 + 1);
+        assert!(rs.max_output(rs.max_input(FIFO_DEPTH * 6 + 1)) <= FIFO_DEPTH * 6 + 1);
+        let rs = Resampler::new(SampleRate::Fs44100, SampleRate::Fs8000);
+        assert!(rs.max_output(rs.max_input(1000)) <= 1000);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut rs = Resampler::new(SampleRate::Fs16000, SampleRate::Fs32000);
+        let mut out = VecDeque::new();
+        rs.process(&[stereo(1000, 1000)], &mut out);
+        rs.reset();
+        out.clear();
+        rs.process(&[stereo(2000, 2000)], &mut out);
+        // interpolation restarts from silence, not from the sample before the reset
+        assert_eq!(out[0], stereo(0, 0));
+        assert_eq!(out[1], stereo(1000, 1000));
+    }
+}
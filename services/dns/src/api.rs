@@ -3,11 +3,14 @@
 pub(crate) const SERVER_NAME_DNS: &str = "_DNS Resolver Middleware_";
 use net::NetIpAddr;
 use rkyv::{Archive, Deserialize, Serialize};
+use xous_ipc::String;
 
 #[allow(dead_code)]
 pub(crate) const DNS_NAME_LENGTH_LIMIT: usize = 256;
 #[allow(dead_code)]
 pub(crate) const DNS_PKT_MAX_LEN: usize = 512;
+/// most records returned by a single `Query`
+pub(crate) const DNS_MAX_RECORDS: usize = 8;
 
 /// These opcodes can be called by anyone at any time
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
@@ -16,7 +19,8 @@ pub(crate) enum Opcode {
     Lookup = 0,
     Flush = 1,
 
-    /// used internally to update the TTL field, and eventually expire the cache (unless cache is frozen)
+    /// used internally to drop cache entries whose TTL has run out (unless cache is frozen). Lookups
+    /// never return expired entries, so this only bounds the memory the cache holds.
     UpdateTtl = 2,
 
     /// issuing this opcode causes all future attempts to change the DNS server configs to be ignored. This also freezes the cache.
@@ -49,6 +53,37 @@ pub(crate) enum Opcode {
     ///     * 4: Ipv4 Address -- 4 octets follow, for a total of 5 bytes
     ///     * 6: Ipv6 Address -- 16 octets follow, for a total of 17 bytes
     RawLookup = 6,
+
+    /// Look up records of a given type, with a `DnsQuery` as the argument
+    Query = 7,
+}
+
+/// Record types that can be looked up. The values are the QTYPEs on the wire (RFC 1035, 2782, 3596).
+#[derive(
+    Debug,
+    num_derive::FromPrimitive,
+    num_derive::ToPrimitive,
+    Archive,
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Hash,
+)]
+#[repr(u16)]
+pub enum DnsRecordType {
+    A = 1,
+    // NS = 2,
+    // MD = 3,
+    // MF = 4,
+    Cname = 5,
+    // SOA = 6,
+    // MX = 15,
+    Txt = 16,
+    Aaaa = 28,
+    Srv = 33,
 }
 
 #[derive(
@@ -80,3 +115,41 @@ pub struct DnsResponse {
     pub addr: Option<NetIpAddr>,
     pub code: DnsResponseCode,
 }
+
+#[derive(Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String<DNS_NAME_LENGTH_LIMIT>,
+}
+
+#[derive(Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) enum DnsRecord {
+    A([u8; 4]),
+    Aaaa([u8; 16]),
+    Cname(String<DNS_NAME_LENGTH_LIMIT>),
+    /// the character-strings of the record, concatenated. A TXT record can't be longer than the
+    /// packet that carried it.
+    Txt(String<DNS_PKT_MAX_LEN>),
+    Srv(SrvTarget),
+}
+
+/// Argument to `Opcode::Query`: the caller fills in `name` and `rtype`, and the resolver fills
+/// in `code` and `records`.
+#[derive(Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct DnsQuery {
+    pub name: String<DNS_NAME_LENGTH_LIMIT>,
+    pub rtype: DnsRecordType,
+    pub code: DnsResponseCode,
+    pub records: [Option<DnsRecord>; DNS_MAX_RECORDS],
+}
+
+/// A service location from an SRV record (RFC 2782)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: std::string::String,
+}
@@ -1,6 +1,6 @@
 use net::NetIpAddr;
 use std::net::ToSocketAddrs;
-use crate::DnsResponseCode;
+use crate::{DnsResponseCode, SrvRecord};
 
 #[derive(Debug)]
 pub struct Dns {
@@ -34,6 +34,30 @@ impl Dns {
             }
         }
     }
+    pub fn lookup_v6(&self, name: &str) -> Result<NetIpAddr, DnsResponseCode> {
+        match (name, 80).to_socket_addrs() {
+            Ok(mut iter) => match iter.find(|addr| addr.is_ipv6()) {
+                Some(addr) => Ok(NetIpAddr::from(addr)),
+                None => Err(DnsResponseCode::NameError),
+            },
+            Err(e) => {
+                log::debug!("format error: {:?}", e);
+                Err(DnsResponseCode::FormatError)
+            }
+        }
+    }
+    pub fn lookup_cname(&self, _name: &str) -> Result<Option<String>, DnsResponseCode> {
+        log::warn!("CNAME lookup not implemented in hosted mode!");
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn lookup_txt(&self, _name: &str) -> Result<Vec<String>, DnsResponseCode> {
+        log::warn!("TXT lookup not implemented in hosted mode!");
+        Err(DnsResponseCode::NotImplemented)
+    }
+    pub fn lookup_srv(&self, _name: &str) -> Result<Vec<SrvRecord>, DnsResponseCode> {
+        log::warn!("SRV lookup not implemented in hosted mode!");
+        Err(DnsResponseCode::NotImplemented)
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         log::warn!("DNS cache flush not implemented in hosted mode!");
         Ok(())
@@ -4,7 +4,7 @@ use xous_ipc::{Buffer, String};
 use num_traits::ToPrimitive;
 
 use net::NetIpAddr;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv6Addr};
 
 use crate::api::*;
 
@@ -39,6 +39,69 @@ impl Dns {
             }
         }
     }
+    /// Like `lookup()`, but for the name's IPv6 address (its AAAA records).
+    pub fn lookup_v6(&self, name: &str) -> Result<NetIpAddr, DnsResponseCode> {
+        if let Ok(simple_ip) = name.parse::<Ipv6Addr>() {
+            return Ok(NetIpAddr::Ipv6(simple_ip.octets()));
+        }
+        match self.query(name, DnsRecordType::Aaaa)?.first() {
+            Some(DnsRecord::Aaaa(addr)) => Ok(NetIpAddr::Ipv6(*addr)),
+            _ => Err(DnsResponseCode::NameError),
+        }
+    }
+    /// The name that `name` is an alias for, or `None` if it has no CNAME record.
+    pub fn lookup_cname(&self, name: &str) -> Result<Option<std::string::String>, DnsResponseCode> {
+        match self.query(name, DnsRecordType::Cname)?.first() {
+            Some(DnsRecord::Cname(target)) => Ok(target.as_str().ok().map(|t| t.to_string())),
+            _ => Ok(None),
+        }
+    }
+    /// The TXT records of `name`, with the strings in each record concatenated.
+    pub fn lookup_txt(&self, name: &str) -> Result<Vec<std::string::String>, DnsResponseCode> {
+        Ok(self
+            .query(name, DnsRecordType::Txt)?
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::Txt(text) => text.as_str().ok().map(|t| t.to_string()),
+                _ => None,
+            })
+            .collect())
+    }
+    /// The SRV records of `name` (such as `_xmpp-client._tcp.example.com`), sorted by priority
+    /// and then by descending weight, so the first entry is the one to try first.
+    pub fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>, DnsResponseCode> {
+        let mut records: Vec<SrvRecord> = self
+            .query(name, DnsRecordType::Srv)?
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::Srv(srv) => Some(SrvRecord {
+                    priority: srv.priority,
+                    weight: srv.weight,
+                    port: srv.port,
+                    target: srv.target.as_str().ok()?.to_string(),
+                }),
+                _ => None,
+            })
+            .collect();
+        records.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+        Ok(records)
+    }
+    fn query(&self, name: &str, rtype: DnsRecordType) -> Result<Vec<DnsRecord>, DnsResponseCode> {
+        let query = DnsQuery {
+            name: String::<DNS_NAME_LENGTH_LIMIT>::from_str(name),
+            rtype,
+            code: DnsResponseCode::UnknownError,
+            records: [None; DNS_MAX_RECORDS],
+        };
+        let mut buf = Buffer::into_buf(query).or(Err(DnsResponseCode::UnknownError))?;
+        buf.lend_mut(self.conn, Opcode::Query.to_u32().unwrap())
+            .or(Err(DnsResponseCode::UnknownError))?;
+        let response = buf.to_original::<DnsQuery,_>().or(Err(DnsResponseCode::UnknownError))?;
+        match response.code {
+            DnsResponseCode::NoError => Ok(response.records.iter().filter_map(|r| *r).collect()),
+            code => Err(code),
+        }
+    }
     pub fn flush_cache(&self) -> Result<(), xous::Error> {
         xous::send_message(
             self.conn,
@@ -9,7 +9,7 @@ use num_traits::*;
 use xous::msg_scalar_unpack;
 
 use std::collections::HashMap;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::time::Duration;
@@ -22,23 +22,73 @@ use xous_ipc::{Buffer, String};
 // MOROS is MIT licensed.
 // See RFC 1035 for implementation details
 
-#[repr(u16)]
-enum QueryType {
-    A = 1,
-    // NS = 2,
-    // MD = 3,
-    // MF = 4,
-    // CNAME = 5,
-    // SOA = 6,
-    // MX = 15,
-    // TXT = 16,
-}
-
 #[repr(u16)]
 enum QueryClass {
     IN = 1,
 }
 
+/// Most extra queries made to follow a CNAME chain whose target the server didn't resolve for us
+const MAX_CNAME_QUERIES: usize = 8;
+/// Most compression pointers followed while reading a single name
+const MAX_NAME_POINTERS: usize = 16;
+
+/// The data of one resource record. Types we don't look up are kept as `Other`, so they can be skipped.
+#[derive(Debug, Clone, PartialEq)]
+enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(std::string::String),
+    /// the record's character-strings, concatenated
+    Txt(std::string::String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: std::string::String,
+    },
+    Other(u16),
+}
+impl RData {
+    fn is(&self, rtype: DnsRecordType) -> bool {
+        matches!(
+            (self, rtype),
+            (RData::A(_), DnsRecordType::A)
+                | (RData::Aaaa(_), DnsRecordType::Aaaa)
+                | (RData::Cname(_), DnsRecordType::Cname)
+                | (RData::Txt(_), DnsRecordType::Txt)
+                | (RData::Srv { .. }, DnsRecordType::Srv)
+        )
+    }
+    fn to_record(&self) -> Option<DnsRecord> {
+        match self {
+            RData::A(a) => Some(DnsRecord::A(a.octets())),
+            RData::Aaaa(a) => Some(DnsRecord::Aaaa(a.octets())),
+            RData::Cname(name) => Some(DnsRecord::Cname(String::from_str(name))),
+            RData::Txt(text) => Some(DnsRecord::Txt(String::from_str(text))),
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => Some(DnsRecord::Srv(SrvTarget {
+                priority: *priority,
+                weight: *weight,
+                port: *port,
+                target: String::from_str(target),
+            })),
+            RData::Other(_) => None,
+        }
+    }
+}
+
+/// A resource record from the answer section of a response
+#[derive(Debug, Clone, PartialEq)]
+struct Answer {
+    name: std::string::String,
+    ttl: u32,
+    data: RData,
+}
+
 struct Message {
     pub datagram: Vec<u8>,
 }
@@ -52,7 +102,7 @@ impl Message {
         }
     }
 
-    pub fn query(qname: &str, qtype: QueryType, qclass: QueryClass, id: u16) -> Self {
+    pub fn query(qname: &str, qtype: DnsRecordType, qclass: QueryClass, id: u16) -> Self {
         let mut datagram = Vec::new();
 
         for b in id.to_be_bytes().iter() {
@@ -100,136 +150,135 @@ impl Message {
         }
     }
 
-    fn fast_foward_name(&self, start: usize) -> Result<usize, DnsResponseCode> {
+    fn u16_at(&self, index: usize) -> Result<u16, DnsResponseCode> {
+        self.datagram
+            .get(index..index + 2)
+            .map(|b| u16::from_be_bytes(b.try_into().unwrap()))
+            .ok_or(DnsResponseCode::FormatError)
+    }
+
+    fn u32_at(&self, index: usize) -> Result<u32, DnsResponseCode> {
+        self.datagram
+            .get(index..index + 4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .ok_or(DnsResponseCode::FormatError)
+    }
+
+    /// Reads the name starting at `start`, following compression pointers (RFC 1035 section 4.1.4).
+    /// Returns the name, and the index just past where it ends in the record that contains it.
+    fn read_name(&self, start: usize) -> Result<(std::string::String, usize), DnsResponseCode> {
         use DnsResponseCode::FormatError;
+        let mut name = std::string::String::new();
         let mut index = start;
+        let mut end = None;
+        let mut pointers = 0;
         loop {
-            log::trace!("cname index: {}", index);
-            if *(self.datagram.get(index).ok_or(FormatError)?) == 0 {
-                index += 1;
-                break;
+            let len = *self.datagram.get(index).ok_or(FormatError)? as usize;
+            if len == 0 {
+                return Ok((name, end.unwrap_or(index + 1)));
+            } else if len & 0xc0 == 0xc0 {
+                // pointer: the rest of the name is elsewhere in the message
+                if end.is_none() {
+                    end = Some(index + 2);
+                }
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    log::error!("too many compression pointers in name at {}", start);
+                    return Err(FormatError);
+                }
+                index = (self.u16_at(index)? & 0x3fff) as usize;
+            } else if len & 0xc0 != 0 {
+                log::error!("reserved label type at {}", index);
+                return Err(FormatError);
             } else {
-                index += *(self.datagram.get(index).ok_or(FormatError)?) as usize;
-                index += 1;
+                let label = self
+                    .datagram
+                    .get(index + 1..index + 1 + len)
+                    .ok_or(FormatError)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&std::string::String::from_utf8_lossy(label));
+                if name.len() > DNS_NAME_LENGTH_LIMIT {
+                    return Err(FormatError);
+                }
+                index += 1 + len;
             }
         }
-        Ok(index)
     }
 
-    pub fn parse_response(&self) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+    /// Parses the answer section of a response. Records of a class other than IN are dropped.
+    pub fn answers(&self) -> Result<Vec<Answer>, DnsResponseCode> {
         use DnsResponseCode::FormatError;
         log::trace!("parsing packet: {:?}", self.datagram);
 
-        let mut map = HashMap::<IpAddr, u32>::new();
         // ASSUME: the query ID and response bit fields have already been checked
         // and that the rcode is valid
-        let qdcount = u16::from_be_bytes(self.datagram[4..6].try_into().unwrap());
-        let ancount = u16::from_be_bytes(self.datagram[6..8].try_into().unwrap());
+        let qdcount = self.u16_at(4)?;
+        let ancount = self.u16_at(6)?;
 
         let mut index = 12;
-        // fast forward past the qname
-        for queries in 0..qdcount {
-            log::trace!("parsing query{}, index {}", queries, index);
-            index = self.fast_foward_name(index)?;
-            log::trace!("fast forward through qname to {}", index);
-            // index is now at qtype
-            let qtype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if qtype != 1 && qtype != 28 {
-                log::error!("Problem parsing qname, qtype is not 1 or 28: {}", qtype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let qclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if qclass != 1 {
-                log::error!("Problem parsing qname, qclass is not 1: {}", qclass);
-                return Err(FormatError);
-            }
-            index += 2;
+        // fast forward past the questions: a name, then qtype and qclass
+        for _ in 0..qdcount {
+            index = self.read_name(index)?.1 + 4;
         }
-        // index is now at the aname section
-        for aname in 0..ancount {
-            log::trace!("parsing aname{}, index {}", aname, index);
-            // first check to see if we're dealing with a pointer or a name
-            if self.datagram[index] >= 0xc0 {
-                // pointer
-                index += 1;
-                if self.datagram[index] != 0xc {
-                    log::error!(
-                        "Found aname pointer, but value does not conform to length of aname header"
-                    );
-                    return Err(FormatError);
-                }
-                index += 1;
-            } else {
-                // name, fast forward past the name
-                index = self.fast_foward_name(index)?;
-                log::trace!("fast forward aname to {}", index);
+        // index is now at the answer section
+        let mut answers = Vec::new();
+        for _ in 0..ancount {
+            let (name, next) = self.read_name(index)?;
+            index = next;
+            let rtype = self.u16_at(index)?;
+            let class = self.u16_at(index + 2)?;
+            // RFC 2181 section 8: a TTL with the top bit set is treated as zero
+            let ttl = match self.u32_at(index + 4)? {
+                ttl if ttl > i32::MAX as u32 => 0,
+                ttl => ttl,
+            };
+            let rdlen = self.u16_at(index + 8)? as usize;
+            index += 10;
+            let rdata = self.datagram.get(index..index + rdlen).ok_or(FormatError)?;
+            log::trace!("answer {} type {} ttl {} rdlen {}", name, rtype, ttl, rdlen);
+            if class != QueryClass::IN as u16 {
+                index += rdlen;
+                continue;
             }
-            // index is now at type
-            let atype = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            // A = 1, AAAA = 28
-            if atype != 1 && atype != 28 {
-                log::error!("Problem parsing aname, type is not 1 or 28: {}", atype);
-                return Err(FormatError);
-            }
-            index += 2;
-            let aclass = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            if aclass != 1 {
-                log::error!("Problem parsing aname, aclass is not 1: {}", aclass);
-                return Err(FormatError);
-            }
-            index += 2;
-            // this is our TTL
-            let ttl = u32::from_be_bytes(self.datagram[index..index + 4].try_into().unwrap());
-            log::trace!("got ttl: {}", ttl);
-            index += 4;
-            // this is the payload length
-            let addr_len = u16::from_be_bytes(self.datagram[index..index + 2].try_into().unwrap());
-            index += 2;
-            match addr_len {
-                // ipv4
-                4 => {
-                    if atype != 1 {
-                        log::error!("Got a 4-byte address, but ATYPE != A (1)");
-                        return Err(FormatError);
-                    }
-                    // this copy happens because I can't figure out how to get Ipv4Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 4] = [0; 4];
-                    for (&src, dst) in self.datagram[index..index + 4].iter().zip(rdata.iter_mut())
-                    {
-                        *dst = src;
+            let data = match rtype {
+                1 => RData::A(Ipv4Addr::from(
+                    <[u8; 4]>::try_from(rdata).or(Err(FormatError))?,
+                )),
+                28 => RData::Aaaa(Ipv6Addr::from(
+                    <[u8; 16]>::try_from(rdata).or(Err(FormatError))?,
+                )),
+                5 => RData::Cname(self.read_name(index)?.0),
+                16 => {
+                    // one or more length-prefixed character-strings
+                    let mut text = Vec::new();
+                    let mut strings = rdata;
+                    while let Some((&len, rest)) = strings.split_first() {
+                        let s = rest.get(..len as usize).ok_or(FormatError)?;
+                        text.extend_from_slice(s);
+                        strings = &rest[len as usize..];
                     }
-                    let addr = IpAddr::V4(Ipv4Addr::from(rdata));
-                    index += 4;
-                    map.insert(addr, ttl);
+                    RData::Txt(std::string::String::from_utf8_lossy(&text).into_owned())
                 }
-                // ipv6
-                16 => {
-                    if atype != 28 {
-                        log::error!("Got a 16-byte address, but ATYPE != AAAA (28)");
+                33 => {
+                    if rdlen < 7 {
                         return Err(FormatError);
                     }
-                    // this copy happens because I can't figure out how to get Ipv6Addr::from() to realize it's casting from a [u8;4]
-                    let mut rdata: [u8; 16] = [0; 16];
-                    for (&src, dst) in self.datagram[index..index + 16]
-                        .iter()
-                        .zip(rdata.iter_mut())
-                    {
-                        *dst = src;
+                    RData::Srv {
+                        priority: self.u16_at(index)?,
+                        weight: self.u16_at(index + 2)?,
+                        port: self.u16_at(index + 4)?,
+                        target: self.read_name(index + 6)?.0,
                     }
-                    let addr = IpAddr::V6(Ipv6Addr::from(rdata));
-                    index += 16;
-                    map.insert(addr, ttl);
                 }
-                _ => {
-                    log::error!("Length field does not match a known record type");
-                    return Err(FormatError);
-                }
-            }
+                other => RData::Other(other),
+            };
+            answers.push(Answer { name, ttl, data });
+            index += rdlen;
         }
 
-        Ok(map)
+        Ok(answers)
     }
 
     /*
@@ -292,7 +341,7 @@ impl Message {
     */
 
     pub fn rcode(&self) -> DnsResponseCode {
-        match (self.header() >> 11) & 0xF {
+        match self.header() & 0xF {
             0 => DnsResponseCode::NoError,
             1 => DnsResponseCode::FormatError,
             2 => DnsResponseCode::ServerFailure,
@@ -304,6 +353,108 @@ impl Message {
     }
 }
 
+/// Follows the CNAME chain from `name` through `answers`. Returns the name at the end of the chain,
+/// the lowest TTL of the CNAMEs along it, and the `rtype` records found for the end name, with
+/// their TTLs capped to that of the chain. A CNAME lookup returns the CNAME itself instead.
+fn chase(
+    answers: &[Answer],
+    name: &str,
+    rtype: DnsRecordType,
+) -> (std::string::String, u32, Vec<(RData, u32)>) {
+    let mut name = name.to_string();
+    let mut chain_ttl = u32::MAX;
+    if rtype != DnsRecordType::Cname {
+        // a CNAME loop can't make this walk any longer than the answer section
+        for _ in 0..answers.len() {
+            let next = answers.iter().find_map(|a| match &a.data {
+                RData::Cname(target) if a.name.eq_ignore_ascii_case(&name) => Some((target, a.ttl)),
+                _ => None,
+            });
+            match next {
+                Some((target, ttl)) => {
+                    name = target.clone();
+                    chain_ttl = chain_ttl.min(ttl);
+                }
+                None => break,
+            }
+        }
+    }
+    let records = answers
+        .iter()
+        .filter(|a| a.name.eq_ignore_ascii_case(&name) && a.data.is(rtype))
+        .map(|a| (a.data.clone(), a.ttl.min(chain_ttl)))
+        .collect();
+    (name, chain_ttl, records)
+}
+
+/// Resolved records by name and type. Each record is stored with the `elapsed_ms()` time at which
+/// its TTL runs out.
+struct Cache {
+    entries: HashMap<(std::string::String, DnsRecordType), Vec<(RData, u64)>>,
+}
+impl Cache {
+    fn new() -> Self {
+        Cache {
+            entries: HashMap::new(),
+        }
+    }
+    /// The unexpired records for `name`, or `None` on a miss. If `now` is `None`, the cache is
+    /// frozen and expired records are returned as well.
+    fn get(&self, name: &str, rtype: DnsRecordType, now: Option<u64>) -> Option<Vec<RData>> {
+        let records: Vec<RData> = self
+            .entries
+            .get(&(name.to_ascii_lowercase(), rtype))?
+            .iter()
+            .filter(|(_, expiry)| now.map_or(true, |now| *expiry > now))
+            .map(|(record, _)| record.clone())
+            .collect();
+        if records.is_empty() {
+            None
+        } else {
+            Some(records)
+        }
+    }
+    fn insert(&mut self, name: &str, rtype: DnsRecordType, records: &[(RData, u32)], now: u64) {
+        // a name with no records isn't cached; the next lookup asks again
+        if records.is_empty() {
+            return;
+        }
+        self.entries.insert(
+            (name.to_ascii_lowercase(), rtype),
+            records
+                .iter()
+                .map(|(record, ttl)| (record.clone(), now + *ttl as u64 * 1000))
+                .collect(),
+        );
+    }
+    /// Drops the records whose TTL has run out, and then the names left without records.
+    fn expire(&mut self, now: u64) {
+        for records in self.entries.values_mut() {
+            records.retain(|(_, expiry)| *expiry > now);
+        }
+        self.entries.retain(|(name, rtype), records| {
+            if records.is_empty() {
+                log::debug!("DNS cache removing {} {:?}", name, rtype);
+            }
+            !records.is_empty()
+        });
+    }
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+fn addrs(records: &[RData]) -> Vec<IpAddr> {
+    records
+        .iter()
+        .filter_map(|record| match record {
+            RData::A(a) => Some(IpAddr::V4(*a)),
+            RData::Aaaa(a) => Some(IpAddr::V6(*a)),
+            _ => None,
+        })
+        .collect()
+}
+
 pub struct Resolver {
     /// DnsServerManager is a service of the Net crate that automatically updates the DNS server list
     mgr: net::DnsServerManager,
@@ -311,6 +462,8 @@ pub struct Resolver {
     buf: [u8; DNS_PKT_MAX_LEN],
     trng: trng::Trng,
     freeze: bool,
+    cache: Cache,
+    tt: ticktimer_server::Ticktimer,
 }
 impl Resolver {
     pub fn new(xns: &xous_names::XousNames) -> Resolver {
@@ -333,6 +486,8 @@ impl Resolver {
             buf: [0; DNS_PKT_MAX_LEN],
             trng,
             freeze: false,
+            cache: Cache::new(),
+            tt: ticktimer_server::Ticktimer::new().unwrap(),
         }
     }
     pub fn add_server(&mut self, addr: IpAddr) {
@@ -355,26 +510,26 @@ impl Resolver {
     pub fn trng_u32(&self) -> u32 {
         self.trng.get_u32().unwrap()
     }
-    pub fn resolve(&mut self, name: &str) -> Result<HashMap<IpAddr, u32>, DnsResponseCode> {
+    /// Sends one query, and returns the answer section of the response.
+    fn query(&mut self, name: &str, qtype: DnsRecordType) -> Result<Vec<Answer>, DnsResponseCode> {
         if let Some(dns_address) = self.mgr.get_random() {
             let dns_port = 53;
             let server = SocketAddr::new(dns_address, dns_port);
 
-            let qname = name;
-            let qtype = QueryType::A;
             let qclass = QueryClass::IN;
-            let query = Message::query(qname, qtype, qclass, self.trng.get_u32().unwrap() as u16);
+            let query = Message::query(name, qtype, qclass, self.trng.get_u32().unwrap() as u16);
 
             self.socket
                 .send_to(&query.datagram, &server)
                 .map_err(|_| DnsResponseCode::NetworkError)?;
 
             match self.socket.recv(&mut self.buf) {
+                Ok(len) if len < 12 => Err(DnsResponseCode::FormatError),
                 Ok(len) => {
                     let message = Message::from(&self.buf[..len]);
                     if message.id() == query.id() && message.is_response() {
                         return match message.rcode() {
-                            DnsResponseCode::NoError => message.parse_response(),
+                            DnsResponseCode::NoError => message.answers(),
                             rcode => Err(rcode),
                         };
                     } else {
@@ -390,6 +545,56 @@ impl Resolver {
             Err(DnsResponseCode::NoServerSpecified)
         }
     }
+    /// Queries for the `rtype` records of `name`. If the answer ends in a CNAME whose target the
+    /// server didn't also resolve, the target is queried in turn.
+    pub fn resolve(
+        &mut self,
+        name: &str,
+        rtype: DnsRecordType,
+    ) -> Result<Vec<(RData, u32)>, DnsResponseCode> {
+        let mut name = name.to_string();
+        let mut chain_ttl = u32::MAX;
+        for _ in 0..=MAX_CNAME_QUERIES {
+            let answers = self.query(&name, rtype)?;
+            let (end, ttl, records) = chase(&answers, &name, rtype);
+            chain_ttl = chain_ttl.min(ttl);
+            if !records.is_empty() || end.eq_ignore_ascii_case(&name) {
+                return Ok(records
+                    .into_iter()
+                    .map(|(record, ttl)| (record, ttl.min(chain_ttl)))
+                    .collect());
+            }
+            log::debug!("following CNAME {} -> {}", name, end);
+            name = end;
+        }
+        log::warn!("CNAME chain for {} is too long", name);
+        Err(DnsResponseCode::ServerFailure)
+    }
+    /// Looks up the `rtype` records for `name`, answering from the cache while their TTL lasts.
+    pub fn lookup(
+        &mut self,
+        name: &str,
+        rtype: DnsRecordType,
+    ) -> Result<Vec<RData>, DnsResponseCode> {
+        let now = self.tt.elapsed_ms();
+        let expiry_time = if self.freeze { None } else { Some(now) };
+        if let Some(records) = self.cache.get(name, rtype, expiry_time) {
+            log::debug!("DNS cached: {} {:?}->{:?}", name, rtype, records);
+            return Ok(records);
+        }
+        let records = self.resolve(name, rtype)?;
+        self.cache.insert(name, rtype, &records, now);
+        Ok(records.into_iter().map(|(record, _)| record).collect())
+    }
+    pub fn flush_cache(&mut self) {
+        self.cache.clear();
+    }
+    /// Drops expired records from the cache, unless the cache is frozen along with the config.
+    pub fn expire_cache(&mut self) {
+        if !self.freeze {
+            self.cache.expire(self.tt.elapsed_ms());
+        }
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -423,7 +628,7 @@ fn name_from_msg(env: &xous::MessageEnvelope) -> Result<&str, NameConversionErro
     Ok(name_string)
 }
 
-fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>) -> Option<()> {
+fn fill_response(mut env: xous::MessageEnvelope, entries: &[IpAddr]) -> Option<()> {
     let mem = env.body.memory_message_mut()?;
 
     let s: &mut [u8] = mem.buf.as_slice_mut();
@@ -442,7 +647,7 @@ fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>)
     *i.next()? = entry_count.try_into().ok()?;
 
     // Start filling in the addreses
-    for addr in entries.keys() {
+    for addr in entries.iter().take(entry_count) {
         match addr {
             &IpAddr::V4(a) => {
                 // IPv4
@@ -453,10 +658,10 @@ fn fill_response(mut env: xous::MessageEnvelope, entries: &HashMap<IpAddr, u32>)
             }
             &IpAddr::V6(a) => {
                 // IPv6
+                *i.next()? = 6;
                 for entry in a.octets() {
                     *i.next()? = entry;
                 }
-                *i.next()? = 6;
             }
         }
     }
@@ -496,13 +701,11 @@ fn xmain() -> ! {
     // if you wanted to force a server into the initial config, you can do it here, for example:
     // resolver.add_server(IpAddr::V4(Ipv4Addr::new(1,1,1,1)));
 
-    let mut dns_cache = HashMap::<std::string::String, HashMap<IpAddr, u32>>::new();
-
-    // build a thread that pings the UpdateTtl function once every few minutes to expire the DNS cache
+    // build a thread that pings the UpdateTtl function once every few minutes to prune the DNS cache
     thread::spawn({
         let local_cid = xous::connect(dns_sid).unwrap();
         move || {
-            const TTL_INTERVAL_SECS: usize = 300; // every 5 minutes update the map
+            const TTL_INTERVAL_SECS: usize = 300; // every 5 minutes drop the expired entries
             let tt = ticktimer_server::Ticktimer::new().unwrap();
             loop {
                 tt.sleep_ms(TTL_INTERVAL_SECS * 1000).unwrap();
@@ -529,17 +732,9 @@ fn xmain() -> ! {
                 match name_from_msg(&msg).map(|s| s.to_owned()) {
                     Ok(owned_name) => {
                         log::trace!("performing a lookup of {}", owned_name);
-                        // Try to get the result out of the DNS cache
-                        if let Some(entries) = dns_cache.get(&owned_name) {
-                            fill_response(msg, entries);
-                            continue;
-                        }
-
-                        // This entry is not in the cache, so perform a lookup
-                        match resolver.resolve(&owned_name) {
-                            Ok(cache_entry) => {
-                                fill_response(msg, &cache_entry);
-                                dns_cache.insert(owned_name, cache_entry);
+                        match resolver.lookup(&owned_name, DnsRecordType::A) {
+                            Ok(records) => {
+                                fill_response(msg, &addrs(&records));
                                 continue;
                             }
                             Err(e) => {
@@ -562,104 +757,68 @@ fn xmain() -> ! {
                 let name = buf
                     .to_original::<String<DNS_NAME_LENGTH_LIMIT>, _>()
                     .unwrap();
-                let name_std = std::string::String::from(name.as_str().unwrap());
-                if let Some(cache_entry) = dns_cache.get(&name_std) {
-                    // pick a random entry
-                    let rand = resolver.trng_u32() as usize % cache_entry.len();
-                    for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                        if rand == index {
-                            log::debug!("DNS cached: {}->{:?}", name, ip_addr);
-                            let response = DnsResponse {
-                                addr: Some(NetIpAddr::from(*ip_addr)),
-                                code: DnsResponseCode::NoError,
-                            };
-                            buf.replace(response).unwrap();
-                            break;
+                let response = match resolver.lookup(name.as_str().unwrap(), DnsRecordType::A) {
+                    Ok(records) if !records.is_empty() => {
+                        // pick a random entry
+                        let addrs = addrs(&records);
+                        let ip_addr = addrs[resolver.trng_u32() as usize % addrs.len()];
+                        log::debug!("DNS: {}->{:?}", name, ip_addr);
+                        DnsResponse {
+                            addr: Some(NetIpAddr::from(ip_addr)),
+                            code: DnsResponseCode::NoError,
                         }
                     }
-                } else {
-                    match resolver.resolve(name.as_str().unwrap()) {
-                        Ok(cache_entry) => {
-                            if cache_entry.len() > 0 {
-                                dns_cache.insert(name_std, cache_entry);
-
-                                // now pick the entry back out again, as it was consumed...
-                                let name_std = std::string::String::from(name.as_str().unwrap());
-                                let cache_entry = dns_cache.get(&name_std).unwrap();
-
-                                // pick a random entry from the query response
-                                let rand = resolver.trng_u32() as usize % cache_entry.len();
-                                for (index, (ip_addr, _)) in cache_entry.iter().enumerate() {
-                                    if rand == index {
-                                        let response = DnsResponse {
-                                            addr: Some(NetIpAddr::from(*ip_addr)),
-                                            code: DnsResponseCode::NoError,
-                                        };
-                                        buf.replace(response).unwrap();
-                                        break;
-                                    }
-                                }
-                            } else {
-                                // no names found
-                                let response = DnsResponse {
-                                    addr: None,
-                                    code: DnsResponseCode::NameError,
-                                };
-                                buf.replace(response).unwrap();
-                            }
-                        }
-                        Err(e) => {
-                            log::debug!("DNS query failed: {}->{:?}", name, e);
-                            let response = DnsResponse {
-                                addr: None,
-                                code: e,
-                            };
-                            buf.replace(response).unwrap();
+                    // no names found
+                    Ok(_) => DnsResponse {
+                        addr: None,
+                        code: DnsResponseCode::NameError,
+                    },
+                    Err(e) => {
+                        log::debug!("DNS query failed: {}->{:?}", name, e);
+                        DnsResponse {
+                            addr: None,
+                            code: e,
                         }
                     }
-                }
+                };
+                buf.replace(response).unwrap();
             }
-            Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, incr_secs, _, _, _, {
-                let increment = if incr_secs < u32::MAX as usize {
-                    incr_secs as u32
-                } else {
-                    u32::MAX
+            Some(Opcode::Query) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
                 };
-                if !resolver.get_freeze() {
-                    let mut expired_names = Vec::<std::string::String>::new();
-                    for (name, cache_map) in dns_cache.iter_mut() {
-                        // each entry can have multiple names with a different TTL
-                        // decrement the TTL, and note which go to zero
-                        let mut expired_entries = Vec::<IpAddr>::new();
-                        for (entry, ttl) in cache_map.iter_mut() {
-                            log::debug!("entry: {:?}, ttl: {}, incr: {}", entry, ttl, increment);
-                            if *ttl < increment {
-                                *ttl = 0;
-                                expired_entries.push(*entry);
-                            } else {
-                                *ttl = *ttl - increment as u32;
+                let mut query = buf.to_original::<DnsQuery, _>().unwrap();
+                match query.name.as_str() {
+                    Ok(name) if !name.is_empty() => match resolver.lookup(name, query.rtype) {
+                        Ok(records) => {
+                            if records.len() > DNS_MAX_RECORDS {
+                                log::warn!(
+                                    "{} {:?} records for {}, returning the first {}",
+                                    records.len(),
+                                    query.rtype,
+                                    name,
+                                    DNS_MAX_RECORDS
+                                );
                             }
+                            for (dst, src) in query.records.iter_mut().zip(records.iter()) {
+                                *dst = src.to_record();
+                            }
+                            query.code = DnsResponseCode::NoError;
                         }
-                        // remove the entries that are 0
-                        for entry in expired_entries {
-                            log::debug!("DNS cache expiring {:?}", entry);
-                            cache_map.remove(&entry);
-                        }
-                        // if all the entries are removed, mark for removal from the cache entirely
-                        if cache_map.len() == 0 {
-                            // have to copy the name to a new object to track it
-                            let name = std::string::String::from(name.as_str());
-                            expired_names.push(name);
+                        Err(e) => {
+                            log::debug!("DNS query failed: {} {:?}->{:?}", name, query.rtype, e);
+                            query.code = e;
                         }
-                    }
-                    for name in expired_names {
-                        log::debug!("DNS cache removing {}", &name);
-                        dns_cache.remove(&name);
-                    }
+                    },
+                    _ => query.code = DnsResponseCode::FormatError,
                 }
+                buf.replace(query).unwrap();
+            }
+            Some(Opcode::UpdateTtl) => msg_scalar_unpack!(msg, _, _, _, _, {
+                resolver.expire_cache();
             }),
             Some(Opcode::Flush) => {
-                dns_cache.clear();
+                resolver.flush_cache();
             }
             Some(Opcode::FreezeConfig) => {
                 resolver.set_freeze_config(true);
@@ -683,3 +842,311 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// betrusted.io A: the response annotated above `Message::rcode()`
+    const BETRUSTED_A: [u8; 94] = [
+        0x61, 0xca, 0x81, 0x80, 0x00, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x09, 0x62, 0x65,
+        0x74, 0x72, 0x75, 0x73, 0x74, 0x65, 0x64, 0x02, 0x69, 0x6f, 0x00, 0x00, 0x01, 0x00, 0x01,
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7, 0x6c,
+        0x99, 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9, 0xc7,
+        0x6d, 0x99, 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0xb9,
+        0xc7, 0x6e, 0x99, 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04,
+        0xb9, 0xc7, 0x6f, 0x99,
+    ];
+    /// www.github.com A: a CNAME to github.com, along with its A record
+    const GITHUB_CNAME_A: [u8; 62] = [
+        0x3e, 0x1f, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, 0x77, 0x77,
+        0x77, 0x06, 0x67, 0x69, 0x74, 0x68, 0x75, 0x62, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01,
+        0x00, 0x01, 0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x02, 0xc0,
+        0x10, 0xc0, 0x10, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04, 0x8c, 0x52,
+        0x79, 0x04,
+    ];
+    /// docs.example.org A: only a CNAME, which the resolver has to chase itself
+    const DOCS_CNAME_ONLY: [u8; 65] = [
+        0x51, 0xb6, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x64, 0x6f,
+        0x63, 0x73, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x6f, 0x72, 0x67, 0x00,
+        0x00, 0x01, 0x00, 0x01, 0xc0, 0x0c, 0x00, 0x05, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00,
+        0x13, 0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x06, 0x67, 0x69, 0x74, 0x68, 0x75,
+        0x62, 0x02, 0x69, 0x6f, 0x00,
+    ];
+    /// one.one.one.one AAAA
+    const ONE_AAAA: [u8; 89] = [
+        0x9b, 0x02, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x03, 0x6f, 0x6e,
+        0x65, 0x03, 0x6f, 0x6e, 0x65, 0x03, 0x6f, 0x6e, 0x65, 0x03, 0x6f, 0x6e, 0x65, 0x00, 0x00,
+        0x1c, 0x00, 0x01, 0xc0, 0x0c, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x10,
+        0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11,
+        0x11, 0xc0, 0x0c, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x10, 0x26, 0x06,
+        0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x01,
+    ];
+    /// example.com TXT: two records, the second split into two strings
+    const EXAMPLE_TXT: [u8; 112] = [
+        0x0d, 0x7a, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x07, 0x65, 0x78,
+        0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x10, 0x00, 0x01, 0xc0,
+        0x0c, 0x00, 0x10, 0x00, 0x01, 0x00, 0x01, 0x51, 0x80, 0x00, 0x0c, 0x0b, 0x76, 0x3d, 0x73,
+        0x70, 0x66, 0x31, 0x20, 0x2d, 0x61, 0x6c, 0x6c, 0xc0, 0x0c, 0x00, 0x10, 0x00, 0x01, 0x00,
+        0x01, 0x51, 0x80, 0x00, 0x2f, 0x20, 0x77, 0x67, 0x79, 0x66, 0x38, 0x7a, 0x38, 0x63, 0x67,
+        0x76, 0x6d, 0x32, 0x71, 0x6d, 0x78, 0x70, 0x6e, 0x62, 0x6e, 0x6c, 0x64, 0x72, 0x63, 0x6c,
+        0x74, 0x76, 0x6b, 0x34, 0x78, 0x71, 0x66, 0x6e, 0x0d, 0x2d, 0x76, 0x65, 0x72, 0x69, 0x66,
+        0x69, 0x63, 0x61, 0x74, 0x69, 0x6f, 0x6e,
+    ];
+    /// _xmpp-client._tcp.jabber.org SRV, with compressed target names
+    const JABBER_SRV: [u8; 99] = [
+        0x7c, 0x44, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x5f, 0x78,
+        0x6d, 0x70, 0x70, 0x2d, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x04, 0x5f, 0x74, 0x63, 0x70,
+        0x06, 0x6a, 0x61, 0x62, 0x62, 0x65, 0x72, 0x03, 0x6f, 0x72, 0x67, 0x00, 0x00, 0x21, 0x00,
+        0x01, 0xc0, 0x0c, 0x00, 0x21, 0x00, 0x01, 0x00, 0x00, 0x03, 0x84, 0x00, 0x0d, 0x00, 0x1e,
+        0x00, 0x1e, 0x14, 0x66, 0x04, 0x7a, 0x65, 0x75, 0x73, 0xc0, 0x1e, 0xc0, 0x0c, 0x00, 0x21,
+        0x00, 0x01, 0x00, 0x00, 0x03, 0x84, 0x00, 0x10, 0x00, 0x1f, 0x00, 0x1e, 0x14, 0x66, 0x07,
+        0x68, 0x65, 0x72, 0x6d, 0x65, 0x73, 0x32, 0xc0, 0x1e,
+    ];
+    /// nonexistent.betrusted.io A: name error
+    const NXDOMAIN: [u8; 42] = [
+        0x2a, 0x2a, 0x81, 0x83, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0b, 0x6e, 0x6f,
+        0x6e, 0x65, 0x78, 0x69, 0x73, 0x74, 0x65, 0x6e, 0x74, 0x09, 0x62, 0x65, 0x74, 0x72, 0x75,
+        0x73, 0x74, 0x65, 0x64, 0x02, 0x69, 0x6f, 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    fn a(s: &str) -> RData {
+        RData::A(s.parse().unwrap())
+    }
+
+    #[test]
+    fn query_encoding() {
+        let query = Message::query("betrusted.io", DnsRecordType::A, QueryClass::IN, 0x61ca);
+        assert_eq!(query.id(), 0x61ca);
+        assert_eq!(query.header(), FLAG_RD);
+        // same question as the response to it
+        assert_eq!(&query.datagram[4..6], &BETRUSTED_A[4..6]);
+        assert_eq!(&query.datagram[12..], &BETRUSTED_A[12..30]);
+    }
+
+    #[test]
+    fn parse_a() {
+        let message = Message::from(&BETRUSTED_A);
+        assert_eq!(message.id(), 0x61ca);
+        assert!(message.is_response());
+        assert_eq!(message.rcode() as u16, DnsResponseCode::NoError as u16);
+        let answers = message.answers().unwrap();
+        assert_eq!(answers.len(), 4);
+        for (answer, addr) in answers.iter().zip(
+            [
+                "185.199.108.153",
+                "185.199.109.153",
+                "185.199.110.153",
+                "185.199.111.153",
+            ]
+            .iter(),
+        ) {
+            assert_eq!(answer.name, "betrusted.io");
+            assert_eq!(answer.ttl, 3600);
+            assert_eq!(answer.data, a(addr));
+        }
+        let (name, _, records) = chase(&answers, "betrusted.io", DnsRecordType::A);
+        assert_eq!(name, "betrusted.io");
+        assert_eq!(records.len(), 4);
+        // nothing of the other types
+        assert!(chase(&answers, "betrusted.io", DnsRecordType::Aaaa)
+            .2
+            .is_empty());
+    }
+
+    #[test]
+    fn parse_cname_chain() {
+        let answers = Message::from(&GITHUB_CNAME_A).answers().unwrap();
+        assert_eq!(
+            answers,
+            vec![
+                Answer {
+                    name: "www.github.com".to_string(),
+                    ttl: 3600,
+                    data: RData::Cname("github.com".to_string()),
+                },
+                Answer {
+                    name: "github.com".to_string(),
+                    ttl: 60,
+                    data: a("140.82.121.4"),
+                },
+            ]
+        );
+        // the query name is matched case-insensitively
+        let (name, ttl, records) = chase(&answers, "WWW.GitHub.com", DnsRecordType::A);
+        assert_eq!(name, "github.com");
+        assert_eq!(ttl, 3600);
+        assert_eq!(records, vec![(a("140.82.121.4"), 60)]);
+        // a CNAME lookup stops at the alias
+        let (name, _, records) = chase(&answers, "www.github.com", DnsRecordType::Cname);
+        assert_eq!(name, "www.github.com");
+        assert_eq!(
+            records,
+            vec![(RData::Cname("github.com".to_string()), 3600)]
+        );
+    }
+
+    #[test]
+    fn cname_without_target() {
+        let answers = Message::from(&DOCS_CNAME_ONLY).answers().unwrap();
+        let (name, ttl, records) = chase(&answers, "docs.example.org", DnsRecordType::A);
+        // the resolver queries this name next
+        assert_eq!(name, "example.github.io");
+        assert_eq!(ttl, 300);
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn cname_loop() {
+        let answers = vec![
+            Answer {
+                name: "a.example".to_string(),
+                ttl: 10,
+                data: RData::Cname("b.example".to_string()),
+            },
+            Answer {
+                name: "b.example".to_string(),
+                ttl: 20,
+                data: RData::Cname("a.example".to_string()),
+            },
+        ];
+        let (_, ttl, records) = chase(&answers, "a.example", DnsRecordType::A);
+        assert_eq!(ttl, 10);
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn parse_aaaa() {
+        let answers = Message::from(&ONE_AAAA).answers().unwrap();
+        let (_, _, records) = chase(&answers, "one.one.one.one", DnsRecordType::Aaaa);
+        assert_eq!(
+            records,
+            vec![
+                (RData::Aaaa("2606:4700:4700::1111".parse().unwrap()), 300),
+                (RData::Aaaa("2606:4700:4700::1001".parse().unwrap()), 300),
+            ]
+        );
+        assert_eq!(
+            addrs(&[records[0].0.clone()]),
+            vec!["2606:4700:4700::1111".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn parse_txt() {
+        let answers = Message::from(&EXAMPLE_TXT).answers().unwrap();
+        let texts: Vec<RData> = answers.into_iter().map(|a| a.data).collect();
+        assert_eq!(
+            texts,
+            vec![
+                RData::Txt("v=spf1 -all".to_string()),
+                RData::Txt("wgyf8z8cgvm2qmxpnbnldrcltvk4xqfn-verification".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_srv() {
+        let answers = Message::from(&JABBER_SRV).answers().unwrap();
+        let (_, _, records) = chase(&answers, "_xmpp-client._tcp.jabber.org", DnsRecordType::Srv);
+        assert_eq!(
+            records,
+            vec![
+                (
+                    RData::Srv {
+                        priority: 30,
+                        weight: 30,
+                        port: 5222,
+                        target: "zeus.jabber.org".to_string(),
+                    },
+                    900
+                ),
+                (
+                    RData::Srv {
+                        priority: 31,
+                        weight: 30,
+                        port: 5222,
+                        target: "hermes2.jabber.org".to_string(),
+                    },
+                    900
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rcode() {
+        let message = Message::from(&NXDOMAIN);
+        assert_eq!(message.rcode() as u16, DnsResponseCode::NameError as u16);
+        assert!(message.answers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        for len in 12..GITHUB_CNAME_A.len() {
+            assert!(
+                Message::from(&GITHUB_CNAME_A[..len]).answers().is_err(),
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn pointer_loop() {
+        // one answer, whose name is a pointer to itself
+        let mut datagram = BETRUSTED_A[..12].to_vec();
+        datagram[4..8].copy_from_slice(&[0, 0, 0, 1]);
+        datagram.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+        assert!(Message::from(&datagram).answers().is_err());
+    }
+
+    #[test]
+    fn cache_ttl() {
+        let mut cache = Cache::new();
+        cache.insert(
+            "Betrusted.io",
+            DnsRecordType::A,
+            &[(a("185.199.108.153"), 60), (a("185.199.109.153"), 120)],
+            1_000,
+        );
+        // per type, and case-insensitive
+        assert!(cache
+            .get("betrusted.io", DnsRecordType::Aaaa, Some(1_000))
+            .is_none());
+        assert_eq!(
+            cache
+                .get("betrusted.io", DnsRecordType::A, Some(60_999))
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            cache
+                .get("BETRUSTED.IO", DnsRecordType::A, Some(61_000))
+                .unwrap(),
+            vec![a("185.199.109.153")]
+        );
+        assert!(cache
+            .get("betrusted.io", DnsRecordType::A, Some(121_000))
+            .is_none());
+        // frozen
+        assert_eq!(
+            cache
+                .get("betrusted.io", DnsRecordType::A, None)
+                .unwrap()
+                .len(),
+            2
+        );
+        cache.expire(61_000);
+        assert_eq!(
+            cache.get("betrusted.io", DnsRecordType::A, None).unwrap(),
+            vec![a("185.199.109.153")]
+        );
+        cache.expire(121_000);
+        assert!(cache.entries.is_empty());
+        // nothing to cache
+        cache.insert("betrusted.io", DnsRecordType::Txt, &[], 0);
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -64,6 +64,12 @@ pub struct SetAudioOpcode {
     pub opcode: u32,
 }
 
+#[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct InputHistorySetting {
+    pub token: [u32; 4],
+    pub enabled: bool,
+}
+
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
 pub struct SwitchToApp {
     pub token: [u32; 4],
@@ -180,6 +186,27 @@ pub(crate) enum Opcode {
     /// Show a test pattern. Can only call this once (to prevent abuse)
     TestPattern,
 
+    /// clear the IME input history of the calling app
+    ClearInputHistory,
+
+    /// set how many lines of IME input history are kept per app
+    SetInputHistoryLength,
+
+    /// speak a string through the screen reader, if it is on
+    Announce,
+    /// turn the screen reader on or off
+    SetScreenReader,
+    /// query whether the screen reader is on
+    GetScreenReader,
+
+    /// choose the IME predictor for every app that takes text input; an empty name restores each app's own
+    SetPredictor, //(String<64>),
+
+    /// turn the IME input history of the calling app on or off
+    SetInputHistory, //(InputHistorySetting),
+    /// clear the IME input history of every app
+    ClearAllInputHistory,
+
     Quit,
 }
 
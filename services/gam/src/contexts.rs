@@ -6,7 +6,7 @@ use crate::api::Opcode;
 use gam::MAIN_MENU_NAME;
 
 use log::info;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use enum_dispatch::enum_dispatch;
 
 // todo:
@@ -92,6 +92,10 @@ pub(crate) struct ContextManager {
     imef_active: bool,
     kbd: keyboard::Keyboard,
     main_menu_app_token: Option<[u32; 4]>, // app_token of the main menu, if it has been registered
+    /// the predictor picked by the user, used instead of the one each app registered with
+    predictor_override: Option<String::<64>>,
+    /// app_tokens of the apps that asked for their input lines not to be kept in the input history
+    history_disabled: HashSet::<[u32; 4]>,
     /// for internal generation of deface states
     pub trng: trng::Trng,
     tt: ticktimer_server::Ticktimer,
@@ -114,6 +118,8 @@ impl ContextManager {
             imef_active: false,
             kbd,
             main_menu_app_token: None,
+            predictor_override: None,
+            history_disabled: HashSet::new(),
             trng: trng::Trng::new(&xns).expect("couldn't connect to trng"),
             tt: ticktimer_server::Ticktimer::new().unwrap(),
         }
@@ -306,6 +312,28 @@ impl ContextManager {
     fn get_context_by_token(&'_ self, token: [u32; 4]) -> Option<&'_ UxContext> {
         self.contexts.get(&token)
     }
+    /// how the IMEF should be hooked up to the context of `token`, or `None` if the context takes no text input
+    fn imef_descriptor(&self, token: [u32; 4]) -> Option<ImefDescriptor> {
+        let context = self.get_context_by_token(token)?;
+        if context.predictor.is_none() {
+            return None;
+        }
+        let gid_of = |canvas_type: CanvasType| {
+            context.layout.get_gids().iter().filter(|&gr| gr.canvas_type == canvas_type).next().map(|gr| gr.gid)
+        };
+        Some(ImefDescriptor {
+            input_canvas: gid_of(CanvasType::ChatInput),
+            prediction_canvas: gid_of(CanvasType::ChatPreditive),
+            predictor: self.predictor_override.or(context.predictor),
+            token: context.gam_token,
+            // input history is kept per app, under the app's registered name
+            history: if self.history_disabled.contains(&token) {
+                None
+            } else {
+                self.tm.find_name(token).map(|name| String::<128>::from_str(name))
+            },
+        })
+    }
     pub(crate) fn activate(&mut self,
         gfx: &graphics_server::Gfx,
         canvases: &mut HashMap<Gid, Canvas>,
@@ -373,31 +401,12 @@ impl ContextManager {
         }
         log::trace!("rewiring IMEF and recomputing canvases");
         {
+            let descriptor = self.imef_descriptor(token);
             // now re-check-out the new context and finalize things
             let maybe_new_focus = self.get_context_by_token(token);
-            if let Some(context) = maybe_new_focus {
-                if context.predictor.is_some() {
+            if maybe_new_focus.is_some() {
+                if let Some(descriptor) = descriptor {
                     // only hook up the IMEF if a predictor is selected for this context
-                    let descriptor = ImefDescriptor {
-                        input_canvas:
-                            if let Some(gr) =
-                            context.layout.get_gids().iter().filter(|&gr| gr.canvas_type == CanvasType::ChatInput)
-                            .next() {
-                                Some(gr.gid)
-                            } else {
-                                None
-                            },
-                        prediction_canvas:
-                            if let Some(gr) =
-                            context.layout.get_gids().iter().filter(|&gr| gr.canvas_type == CanvasType::ChatPreditive)
-                            .next() {
-                                Some(gr.gid)
-                            } else {
-                                None
-                            },
-                        predictor: context.predictor,
-                        token: context.gam_token,
-                    };
                     self.imef.connect_backend(descriptor).expect("couldn't connect IMEF to the current app");
                     self.imef_active = true;
                 } else {
@@ -500,6 +509,42 @@ impl ContextManager {
         }
         Ok(())
     }
+    /// clears the IME input history of the app that owns `token`
+    pub(crate) fn clear_input_history(&self, token: [u32; 4]) -> Result<(), xous::Error> {
+        if let Some(name) = self.tm.find_name(token) {
+            self.imef.clear_history(Some(name))
+        } else {
+            Err(xous::Error::InvalidString)
+        }
+    }
+    pub(crate) fn set_input_history_length(&self, len: usize) -> Result<(), xous::Error> {
+        self.imef.set_history_length(len)
+    }
+    pub(crate) fn clear_all_input_history(&self) -> Result<(), xous::Error> {
+        self.imef.clear_history(None)
+    }
+    pub(crate) fn set_input_history(&mut self, token: [u32; 4], enabled: bool) {
+        if enabled {
+            self.history_disabled.remove(&token);
+        } else {
+            self.history_disabled.insert(token);
+        }
+        if self.focused_app() == Some(token) {
+            self.reconnect_imef();
+        }
+    }
+    pub(crate) fn set_predictor(&mut self, predictor: Option<String::<64>>) {
+        self.predictor_override = predictor;
+        // an app that already has focus switches over right away; any other picks it up when it's activated
+        self.reconnect_imef();
+    }
+    /// re-sends the IMEF descriptor of the focused app, so a change in its input settings takes effect right away
+    fn reconnect_imef(&self) {
+        if let Some(descriptor) = self.focused_app().and_then(|token| self.imef_descriptor(token)) {
+            self.imef.connect_backend(descriptor).expect("couldn't connect IMEF to the current app");
+            self.imef.redraw(true).ok();
+        }
+    }
     pub(crate) fn find_app_token_by_name(&self, name: &str) -> Option<[u32; 4]> {
         self.tm.find_token(name)
     }
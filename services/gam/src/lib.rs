@@ -35,6 +35,8 @@ pub const STATUS_BAR_NAME: &'static str = "status";
 pub const APP_NAME_SHELLCHAT: &'static str = "shellchat";
 pub const APP_MENU_NAME: &'static str = "app menu";
 pub const KBD_MENU_NAME: &'static str = "keyboard menu";
+pub const WIFI_MENU_NAME: &'static str = "wifi menu";
+pub const IME_MENU_NAME: &'static str = "ime menu";
 
 /// UX context registry. Names here are authorized by the GAM to have Canvases.
 pub const EXPECTED_BOOT_CONTEXTS: &[&'static str] = &[
@@ -48,6 +50,8 @@ pub const EXPECTED_BOOT_CONTEXTS: &[&'static str] = &[
     PDDB_MENU_NAME,
     APP_MENU_NAME,
     KBD_MENU_NAME,
+    WIFI_MENU_NAME,
+    IME_MENU_NAME,
 ];
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -307,6 +311,74 @@ impl Gam {
             ena, 0, 0, 0,)
         ).map(|_| ())
     }
+    /// Clears the IME input history recorded for the app that owns `token`. Apps that take anything
+    /// sensitive through the input line should turn history off around it with `set_input_history()`;
+    /// this is for getting rid of what was recorded before that.
+    pub fn clear_input_history(&self, token: [u32; 4]) -> Result<(), xous::Error> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::ClearInputHistory.to_usize().unwrap(),
+            token[0] as usize, token[1] as usize, token[2] as usize, token[3] as usize)
+        )? {
+            xous::Result::Scalar1(0) => Ok(()),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Clears the IME input history of every app.
+    pub fn clear_all_input_history(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::ClearAllInputHistory.to_usize().unwrap(), 0, 0, 0, 0,)
+        ).map(|_| ())
+    }
+    /// Turns the IME input history of the app that owns `token` on or off. While it is off, the
+    /// lines the app takes are neither recorded nor offered as predictions, so an app should turn it
+    /// off before asking for a password or other secret on its input line, and back on afterwards.
+    pub fn set_input_history(&self, token: [u32; 4], enabled: bool) -> Result<(), xous::Error> {
+        let setting = InputHistorySetting {
+            token,
+            enabled,
+        };
+        let buf = Buffer::into_buf(setting).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetInputHistory.to_u32().unwrap()).map(|_| ())
+    }
+    /// Sets how many lines of IME input history are kept for each app. A length of 0 disables history.
+    pub fn set_input_history_length(&self, len: usize) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetInputHistoryLength.to_usize().unwrap(),
+            len, 0, 0, 0,)
+        ).map(|_| ())
+    }
+    /// Reads `text` out through the screen reader, if it is on, cutting off whatever it was reading before.
+    /// UX elements call this whenever their focus moves, so navigating quickly only reads out where the
+    /// focus lands. Does nothing in builds without text to speech.
+    pub fn announce(&self, text: &str) -> Result<(), xous::Error> {
+        if !cfg!(feature="tts") || text.len() == 0 {
+            return Ok(())
+        }
+        let buf = Buffer::into_buf(String::<2048>::from_str(text)).or(Err(xous::Error::InternalError))?;
+        buf.send(self.conn, Opcode::Announce.to_u32().unwrap()).or(Err(xous::Error::InternalError)).map(|_|())
+    }
+    /// Turns the screen reader on or off. It defaults to on in builds with text to speech.
+    pub fn set_screen_reader(&self, enable: bool) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetScreenReader.to_usize().unwrap(),
+            if enable { 1 } else { 0 }, 0, 0, 0,)
+        ).map(|_| ())
+    }
+    pub fn is_screen_reader_on(&self) -> Result<bool, xous::Error> {
+        match send_message(self.conn,
+            Message::new_blocking_scalar(Opcode::GetScreenReader.to_usize().unwrap(), 0, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(on) => Ok(on != 0),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Has every app that takes text input use the IME predictor registered under `predictor`, instead of
+    /// the one it asked for in its `UxRegistration`. `None` goes back to each app's own predictor. The
+    /// change is picked up the next time an app comes into focus, e.g. when the menu it was picked from closes.
+    pub fn set_predictor(&self, predictor: Option<&str>) -> Result<(), xous::Error> {
+        let buf = Buffer::into_buf(String::<64>::from_str(predictor.unwrap_or(""))).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetPredictor.to_u32().unwrap()).map(|_| ())
+    }
     /// this indicates to the GAM that the currently running app no longer wants to be the focus of attention
     /// we might respect that. or maybe not. depends on the GAM's policies.
     pub fn relinquish_focus(&self) -> Result<(), xous::Error> {
@@ -91,6 +91,10 @@ fn xmain() -> ! {
     let mut powerdown_requested = false;
     let mut last_time: u64 = ticktimer.elapsed_ms();
     let mut did_test = false; // allow one go at the test pattern
+    // the screen reader speaks whatever UX element has focus; it starts out on in builds made for use without the display
+    let mut screen_reader = cfg!(feature="tts");
+    #[cfg(feature="tts")]
+    let tts = tts_frontend::TtsFrontend::new(&xns).unwrap();
     log::trace!("entering main loop");
 
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
@@ -498,7 +502,15 @@ fn xmain() -> ! {
                                 context_mgr.notify_app_switch(new_app_token)
                                 .unwrap_or_else(|_| {log::warn!("Application does not recognize focus changes")});
                                 match context_mgr.activate(&gfx, &mut canvases, new_app_token, false) {
-                                    Ok(_) => (),
+                                    Ok(_) => {
+                                        #[cfg(feature="tts")]
+                                        {
+                                            if screen_reader {
+                                                // apps don't announce themselves, and this also cuts off the menu that led here
+                                                tts.tts_simple(switchapp.app_name.as_str().unwrap()).ok();
+                                            }
+                                        }
+                                    },
                                     Err(_) => log::warn!("failed to switch to {}, silent error!", switchapp.app_name.as_str().unwrap()),
                                 }
                                 continue;
@@ -542,6 +554,52 @@ fn xmain() -> ! {
                 }
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack self test");
             }),
+            Some(Opcode::ClearInputHistory) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, t3, {
+                let token = [t0 as u32, t1 as u32, t2 as u32, t3 as u32];
+                match context_mgr.clear_input_history(token) {
+                    Ok(_) => xous::return_scalar(msg.sender, 0).expect("couldn't unblock caller"),
+                    _ => xous::return_scalar(msg.sender, 1).expect("couldn't unblock caller"),
+                }
+            }),
+            Some(Opcode::SetInputHistoryLength) => msg_scalar_unpack!(msg, len, _, _, _, {
+                context_mgr.set_input_history_length(len).expect("couldn't set IMEF history length");
+            }),
+            Some(Opcode::SetInputHistory) => {
+                let buffer = unsafe{ Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let setting = buffer.to_original::<InputHistorySetting, _>().unwrap();
+                context_mgr.set_input_history(setting.token, setting.enabled);
+            },
+            Some(Opcode::ClearAllInputHistory) => {
+                context_mgr.clear_all_input_history().expect("couldn't clear IMEF history");
+            },
+            Some(Opcode::Announce) => {
+                #[cfg(feature="tts")]
+                {
+                    if screen_reader {
+                        let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                        let text = buffer.to_original::<String::<2048>, _>().unwrap();
+                        // tts_simple() truncates anything still being spoken, which gives us interrupt-on-navigation
+                        tts.tts_simple(text.as_str().unwrap_or("")).ok();
+                    }
+                }
+            },
+            Some(Opcode::SetScreenReader) => msg_scalar_unpack!(msg, ena, _, _, _, {
+                screen_reader = ena != 0;
+                #[cfg(feature="tts")]
+                {
+                    if !screen_reader {
+                        tts.stop().ok();
+                    }
+                }
+            }),
+            Some(Opcode::GetScreenReader) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                xous::return_scalar(msg.sender, if screen_reader { 1 } else { 0 }).expect("couldn't return screen reader state");
+            }),
+            Some(Opcode::SetPredictor) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let predictor = buffer.to_original::<String::<64>, _>().unwrap();
+                context_mgr.set_predictor(if predictor.len() > 0 { Some(predictor) } else { None });
+            }
             Some(Opcode::Quit) => break,
             None => {log::error!("unhandled message {:?}", msg);}
         }
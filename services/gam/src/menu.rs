@@ -172,8 +172,7 @@ impl<'a> Menu<'a> {
 
         if with_marker {
             write!(item_tv.text, "\u{25B6}").unwrap();
-            #[cfg(feature="tts")]
-            self.tts.tts_simple(item.name.as_str().unwrap()).unwrap();
+            self.gam.announce(item.name.as_str().unwrap()).unwrap();
         } else {
             write!(item_tv.text, "\t").unwrap();
         }
@@ -299,9 +298,12 @@ impl<'a> Menu<'a> {
                         log::debug!("doing menu action for {}", mi.name);
                         #[cfg(feature="tts")]
                         {
-                            let mut phrase = "select ".to_string();
-                            phrase.push_str(mi.name.as_str().unwrap());
-                            self.tts.tts_blocking(&phrase).unwrap();
+                            if self.gam.is_screen_reader_on().unwrap_or(false) {
+                                // this one is blocking, so it's heard in full before the action changes the focus
+                                let mut phrase = "select ".to_string();
+                                phrase.push_str(mi.name.as_str().unwrap());
+                                self.tts.tts_blocking(&phrase).unwrap();
+                            }
                         }
                         match mi.action_payload {
                             MenuPayload::Scalar(args) => {
@@ -49,6 +49,10 @@ pub trait ActionApi {
     /// navigation is one of '∴' | '←' | '→' | '↑' | '↓'
     fn key_action(&mut self, _key: char) -> (Option<ValidatorErr>, bool) {(None, true)}
     fn set_action_opcode(&mut self, _op: u32) {}
+    /// what the screen reader should say about the item that has focus, if anything
+    fn focus_text(&self) -> Option<std::string::String> { None }
+    /// what the screen reader should say when `key` is pressed, if anything
+    fn key_text(&self, _key: char) -> Option<std::string::String> { None }
 }
 
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
@@ -167,6 +171,8 @@ pub struct Modal<'a> {
     top_memoized_height: Option<i16>,
     bot_dirty: bool,
     bot_memoized_height: Option<i16>,
+    /// what the screen reader last said about the focused item, so it isn't repeated on every redraw
+    announced_focus: Option<std::string::String>,
 }
 
 fn recompute_canvas(modal: &mut Modal, top_text: Option<&str>, bot_text: Option<&str>, style: GlyphStyle) {
@@ -325,6 +331,7 @@ impl<'a> Modal<'a> {
             bot_dirty: true,
             top_memoized_height: None,
             bot_memoized_height: None,
+            announced_focus: None,
         };
         recompute_canvas(&mut modal, top_text, bot_text, style);
         modal
@@ -375,6 +382,29 @@ impl<'a> Modal<'a> {
         log::debug!("modal redraw");
         let canvas_size = self.gam.get_canvas_bounds(self.canvas).unwrap();
         let do_redraw = self.top_dirty || self.bot_dirty || self.inverted;
+        // the screen reader reads out the prompt and any message below the action when they change,
+        // together with the item that has focus, so they don't cut each other off
+        let mut announcement = std::string::String::new();
+        if self.top_dirty {
+            if let Some(tv) = self.top_text.as_ref() {
+                announcement.push_str(tv.to_str());
+            }
+        }
+        let focus = self.action.focus_text();
+        if focus.is_some() && (focus != self.announced_focus || announcement.len() > 0) {
+            announcement.push_str(". ");
+            announcement.push_str(focus.as_ref().unwrap());
+        }
+        self.announced_focus = focus;
+        if self.bot_dirty {
+            if let Some(tv) = self.bot_text.as_ref() {
+                announcement.push_str(". ");
+                announcement.push_str(tv.to_str());
+            }
+        }
+        if announcement.len() > 0 {
+            self.gam.announce(announcement.trim_start_matches(". ")).unwrap();
+        }
         // draw the outer border
         if do_redraw {
             self.gam.draw_rounded_rectangle(self.canvas,
@@ -448,6 +478,9 @@ impl<'a> Modal<'a> {
         for &k in keys.iter() {
             if k != '\u{0}' {
                 log::debug!("got key '{}'", k);
+                if let Some(text) = self.action.key_text(k) {
+                    self.gam.announce(&text).unwrap();
+                }
                 let (err, close) = self.action.key_action(k);
                 if let Some(err_msg) = err {
                     self.modify(None, None, false, Some(err_msg.to_str()), false, None);
@@ -192,4 +192,11 @@ impl ActionApi for Notification {
         }
         (None, false)
     }
+    fn focus_text(&self) -> Option<std::string::String> {
+        if self.manual_dismiss {
+            Some(t!("notification.dismiss", xous::LANG).to_string())
+        } else {
+            None
+        }
+    }
 }
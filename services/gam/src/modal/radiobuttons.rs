@@ -6,8 +6,6 @@ use xous_ipc::Buffer;
 
 use core::fmt::Write;
 use locales::t;
-#[cfg(feature="tts")]
-use tts_frontend::TtsFrontend;
 
 #[derive(Debug)]
 pub struct RadioButtons {
@@ -17,13 +15,9 @@ pub struct RadioButtons {
     pub action_payload: RadioButtonPayload, // the current "radio button" selection
     pub select_index: i16, // the current candidate to be selected
     pub is_password: bool,
-    #[cfg(feature = "tts")]
-    pub tts: TtsFrontend,
 }
 impl RadioButtons {
     pub fn new(action_conn: xous::CID, action_opcode: u32) -> Self {
-        #[cfg(feature="tts")]
-        let tts = TtsFrontend::new(&xous_names::XousNames::new().unwrap()).unwrap();
         RadioButtons {
             items: Vec::new(),
             action_conn,
@@ -31,8 +25,6 @@ impl RadioButtons {
             action_payload: RadioButtonPayload::new(""),
             select_index: 0,
             is_password: false,
-            #[cfg(feature="tts")]
-            tts,
         }
     }
     pub fn add_item(&mut self, new_item: ItemName) {
@@ -85,10 +77,6 @@ impl ActionApi for RadioButtons {
         for item in self.items.iter() {
             let cur_y = at_height + cur_line * modal.line_height + modal.margin * 2;
             if cur_line == self.select_index {
-                #[cfg(feature="tts")]
-                {
-                    self.tts.tts_simple(item.as_str()).unwrap();
-                }
                 // draw the cursor
                 tv.text.clear();
                 tv.bounds_computed = None;
@@ -130,11 +118,6 @@ impl ActionApi for RadioButtons {
             ));
             write!(tv, "\u{25B6}").unwrap(); // right arrow emoji. use unicode numbers, because text editors do funny shit with emojis
             modal.gam.post_textview(&mut tv).expect("couldn't post tv");
-            #[cfg(feature="tts")]
-            {
-                self.tts.tts_blocking(t!("radio.select_and_close_tts", xous::LANG)).unwrap();
-                self.tts.tts_blocking(self.action_payload.as_str()).unwrap();
-            }
         }
         // draw the "OK" line
         tv.text.clear();
@@ -171,11 +154,6 @@ impl ActionApi for RadioButtons {
             '∴' | '\u{d}' => {
                 if self.select_index < self.items.len() as i16 {
                     self.action_payload = RadioButtonPayload::new(self.items[self.select_index as usize].as_str());
-                    #[cfg(feature="tts")]
-                    {
-                        self.tts.tts_blocking(t!("radio.selection_tts", xous::LANG)).unwrap();
-                        self.tts.tts_simple(self.items[self.select_index as usize].as_str()).unwrap();
-                    }
                 } else {  // the OK button select
                     let buf = Buffer::into_buf(self.action_payload).expect("couldn't convert message to payload");
                     buf.send(self.action_conn, self.action_opcode).map(|_| ()).expect("couldn't send action message");
@@ -191,4 +169,16 @@ impl ActionApi for RadioButtons {
         }
         (None, false)
     }
+    fn focus_text(&self) -> Option<std::string::String> {
+        if (self.select_index as usize) < self.items.len() {
+            let item = self.items[self.select_index as usize].as_str();
+            if item == self.action_payload.as_str() {
+                Some(format!("{}{}", t!("radio.selected_tts", xous::LANG), item))
+            } else {
+                Some(item.to_string())
+            }
+        } else {
+            Some(format!("{}{}", t!("radio.select_and_close_tts", xous::LANG), self.action_payload.as_str()))
+        }
+    }
 }
\ No newline at end of file
@@ -202,4 +202,12 @@ impl ActionApi for Slider {
             }
         }
     }
+    fn focus_text(&self) -> Option<std::string::String> {
+        if self.is_progressbar {
+            // progress is read out as the top text changes, if at all; per-step updates would be noise
+            None
+        } else {
+            Some(format!("{} {}", self.action_payload, self.units.to_str()))
+        }
+    }
 }
@@ -34,6 +34,17 @@ impl ActionApi for TextEntry {
     fn is_password(&self) -> bool {
         self.is_password
     }
+    fn key_text(&self, k: char) -> Option<std::string::String> {
+        match k {
+            '\u{8}' => Some(locales::t!("input.delete-tts", xous::LANG).to_string()),
+            '∴' | '\u{d}' | '↑' | '↓' | '←' | '→' | '\u{0}' => None,
+            // hidden text must not be read out either
+            _ => match self.visibility {
+                TextEntryVisibility::Visible if !self.is_password => Some(k.to_string()),
+                _ => Some(locales::t!("input.character-tts", xous::LANG).to_string()),
+            },
+        }
+    }
     /// The total canvas height is computed with this API call
     /// The canvas height is not dynamically adjustable for modals.
     fn height(&self, glyph_height: i16, margin: i16) -> i16 {
@@ -264,12 +275,6 @@ impl ActionApi for TextEntry {
                 // ignore null messages
             }
             '\u{8}' => { // backspace
-                #[cfg(feature="tts")]
-                {
-                    let xns = xous_names::XousNames::new().unwrap();
-                    let tts = tts_frontend::TtsFrontend::new(&xns).unwrap();
-                    tts.tts_blocking(locales::t!("input.delete-tts", xous::LANG)).unwrap();
-                }
                 // coded in a conservative manner to avoid temporary allocations that can leave the plaintext on the stack
                 if self.action_payload.0.len() > 0 { // don't backspace if we have no string.
                     let mut temp_str = String::<256>::from_str(self.action_payload.0.as_str().unwrap());
@@ -283,12 +288,6 @@ impl ActionApi for TextEntry {
                 }
             }
             _ => { // text entry
-                #[cfg(feature="tts")]
-                {
-                    let xns = xous_names::XousNames::new().unwrap();
-                    let tts = tts_frontend::TtsFrontend::new(&xns).unwrap();
-                    tts.tts_blocking(&k.to_string()).unwrap();
-                }
                 self.action_payload.0.push(k).expect("ran out of space storing password");
                 log::trace!("****update payload: {}", self.action_payload.0);
             }
@@ -74,6 +74,9 @@ impl<'a> TokenManager {
     pub(crate) fn is_token_valid(&self, token: [u32; 4]) -> bool {
         self.tokens.iter().find(|&namedtoken| namedtoken.token == token).is_some()
     }
+    pub(crate) fn find_name(&self, token: [u32; 4]) -> Option<&str> {
+        self.tokens.iter().find(|&namedtoken| namedtoken.token == token).map(|namedtoken| namedtoken.name.as_str())
+    }
     pub(crate) fn find_token(&self, name: &str) -> Option<[u32; 4]> {
         if let Some(i) = self.tokens.iter().position(|namedtoken| namedtoken.name == name) {
             log::debug!("found {}:{:?}", name, self.tokens[i].token);
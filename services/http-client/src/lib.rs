@@ -0,0 +1,583 @@
+//! A small HTTP/1.1 client for Xous applications, over the libstd `TcpStream` that the `net`
+//! service provides (`StdTcpConnect` and friends), so apps don't each reinvent request framing
+//! and chunked decoding.
+//!
+//! Every request uses a fresh connection with `Connection: close`; there is no pooling or
+//! pipelining. Response bodies are read fully into memory, up to a configurable limit.
+//!
+//! ```ignore
+//! let client = http_client::Client::new();
+//! let resp = client.get("http://example.com/").header("Accept", "text/html").send()?;
+//! if resp.is_success() {
+//!     log::info!("{}", resp.text());
+//! }
+//! ```
+//!
+//! `https://` URLs need the `https` feature, which uses the `tls` crate and the trust store it
+//! keeps in the PDDB.
+
+mod response;
+pub use response::Response;
+mod url;
+pub use url::{Scheme, Url};
+
+use std::io::{BufReader, Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How many redirects `send()` follows before giving up, by default.
+pub const DEFAULT_MAX_REDIRECTS: usize = 5;
+/// Largest response body accepted by default.
+pub const DEFAULT_MAX_BODY: usize = 1024 * 1024;
+/// Connect, read and write timeout by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers the client manages itself; values given for these by the caller are ignored.
+const MANAGED_HEADERS: [&str; 4] = ["host", "content-length", "connection", "transfer-encoding"];
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+pub struct Client {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    max_body: usize,
+    headers: Vec<(String, String)>,
+    #[cfg(feature = "https")]
+    tls_config: std::cell::RefCell<Option<std::sync::Arc<rustls::ClientConfig>>>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Client {
+            timeout: Some(DEFAULT_TIMEOUT),
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            max_body: DEFAULT_MAX_BODY,
+            headers: vec![
+                (
+                    "User-Agent".to_string(),
+                    concat!("xous-http-client/", env!("CARGO_PKG_VERSION")).to_string(),
+                ),
+                ("Accept".to_string(), "*/*".to_string()),
+            ],
+            #[cfg(feature = "https")]
+            tls_config: std::cell::RefCell::new(None),
+        }
+    }
+    /// Sets the default timeout for connecting and for each read and write. `None` waits forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Sets how many redirects to follow; 0 returns 3xx responses to the caller.
+    pub fn max_redirects(mut self, max: usize) -> Self {
+        self.max_redirects = max;
+        self
+    }
+    pub fn max_body(mut self, max: usize) -> Self {
+        self.max_body = max;
+        self
+    }
+    /// Adds a header sent with every request, or replaces a default one such as `User-Agent`.
+    /// A malformed name or value makes every request fail with `ErrorKind::InvalidInput`.
+    pub fn default_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+    /// Uses `config` for `https://` requests instead of one built from the PDDB trust store.
+    #[cfg(feature = "https")]
+    pub fn tls_config(self, config: std::sync::Arc<rustls::ClientConfig>) -> Self {
+        self.tls_config.replace(Some(config));
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Request<'_> {
+        self.request("GET", url)
+    }
+    pub fn post(&self, url: &str, body: &[u8]) -> Request<'_> {
+        self.request("POST", url).body(body)
+    }
+    pub fn request(&self, method: &str, url: &str) -> Request<'_> {
+        Request {
+            client: self,
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: None,
+            timeout: self.timeout,
+        }
+    }
+
+    fn connect(&self, url: &Url, timeout: Option<Duration>) -> Result<Box<dyn Stream>> {
+        let mut last_err = None;
+        for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+            let sock = match timeout {
+                Some(t) => TcpStream::connect_timeout(&addr, t),
+                None => TcpStream::connect(addr),
+            };
+            match sock {
+                Ok(sock) => {
+                    sock.set_read_timeout(timeout)?;
+                    sock.set_write_timeout(timeout)?;
+                    return match url.scheme {
+                        Scheme::Http => Ok(Box::new(sock)),
+                        Scheme::Https => self.tls_wrap(url, sock),
+                    };
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| Error::new(ErrorKind::NotFound, "host has no addresses")))
+    }
+
+    #[cfg(feature = "https")]
+    fn tls_wrap(&self, url: &Url, sock: TcpStream) -> Result<Box<dyn Stream>> {
+        let config = match self.tls_config.borrow().clone() {
+            Some(config) => config,
+            None => {
+                let config = tls::Tls::new().client_config()?;
+                self.tls_config.replace(Some(config.clone()));
+                config
+            }
+        };
+        Ok(Box::new(tls::connect_stream(config, &url.host, sock)?))
+    }
+    #[cfg(not(feature = "https"))]
+    fn tls_wrap(&self, _url: &Url, _sock: TcpStream) -> Result<Box<dyn Stream>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "https needs the `https` feature",
+        ))
+    }
+
+    /// One request/response exchange, without following redirects.
+    fn execute(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(String, String)],
+        body: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\n",
+            method,
+            url.path,
+            url.host_header()
+        );
+        let overridden = |name: &str| headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(name));
+        for (name, value) in self
+            .headers
+            .iter()
+            .filter(|(n, _)| !overridden(n))
+            .chain(headers.iter())
+        {
+            if MANAGED_HEADERS.iter().any(|m| name.eq_ignore_ascii_case(m)) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if let Some(body) = body {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        let mut stream = self.connect(url, timeout)?;
+        stream.write_all(head.as_bytes())?;
+        if let Some(body) = body {
+            stream.write_all(body)?;
+        }
+        stream.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let (status, reason, resp_headers) = response::read_head(&mut reader)?;
+        let body = if method.eq_ignore_ascii_case("HEAD") || status == 204 || status == 304 {
+            Vec::new()
+        } else {
+            response::read_body(&mut reader, &resp_headers, self.max_body)?
+        };
+        Ok(Response {
+            status,
+            reason,
+            headers: resp_headers,
+            body,
+        })
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client::new()
+    }
+}
+
+pub struct Request<'a> {
+    client: &'a Client,
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> Request<'a> {
+    /// Adds a header. `Host`, `Content-Length`, `Connection` and `Transfer-Encoding` are set by
+    /// the client and can't be overridden.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+    pub fn body(mut self, body: &[u8]) -> Self {
+        self.body = Some(body.to_vec());
+        self
+    }
+    /// Overrides the client's timeout for this request.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends the request, following redirects. A timeout is reported as `ErrorKind::TimedOut`.
+    pub fn send(self) -> Result<Response> {
+        if !is_token(&self.method) {
+            return Err(Error::new(ErrorKind::InvalidInput, "malformed method"));
+        }
+        for (name, value) in self.client.headers.iter().chain(self.headers.iter()) {
+            if !is_token(name) || !is_header_value(value) {
+                return Err(Error::new(ErrorKind::InvalidInput, "malformed header"));
+            }
+        }
+        let mut url = Url::parse(&self.url)?;
+        let mut method = self.method;
+        let mut headers = self.headers;
+        let mut body = self.body;
+        let mut redirects = 0;
+        loop {
+            let resp = self
+                .client
+                .execute(&method, &url, &headers, body.as_deref(), self.timeout)
+                .map_err(|e| {
+                    if e.kind() == ErrorKind::WouldBlock {
+                        // how an expired read timeout surfaces on some platforms
+                        Error::new(ErrorKind::TimedOut, e)
+                    } else {
+                        e
+                    }
+                })?;
+            let location = match resp.status {
+                301 | 302 | 303 | 307 | 308 if self.client.max_redirects > 0 => {
+                    match resp.header("Location") {
+                        Some(location) => location.to_string(),
+                        None => return Ok(resp),
+                    }
+                }
+                _ => return Ok(resp),
+            };
+            if redirects == self.client.max_redirects {
+                return Err(Error::new(ErrorKind::Other, "too many redirects"));
+            }
+            redirects += 1;
+            let next = url.join(&location)?;
+            if url.scheme == Scheme::Https && next.scheme == Scheme::Http {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "refusing to redirect from https to http",
+                ));
+            }
+            // 303 always becomes a GET; 301 and 302 do too after a POST, as browsers do
+            if resp.status == 303
+                || ((resp.status == 301 || resp.status == 302) && method == "POST")
+            {
+                method = "GET".to_string();
+                body = None;
+                headers.retain(|(n, _)| !n.eq_ignore_ascii_case("Content-Type"));
+            }
+            if next.host != url.host {
+                // credentials are for the host they were given to
+                headers.retain(|(n, _)| {
+                    !n.eq_ignore_ascii_case("Authorization") && !n.eq_ignore_ascii_case("Cookie")
+                });
+            }
+            log::debug!("{} {} redirected to {}", resp.status, url, next);
+            url = next;
+        }
+    }
+}
+
+/// Methods and header names have to be RFC 7230 tokens.
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Header values can't hold control characters other than tab: a CR or LF would end the header early.
+fn is_header_value(s: &str) -> bool {
+    s.bytes().all(|b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+
+    /// Serves one canned response per connection, in order, and hands back each request as it
+    /// was received (head and body).
+    fn serve(responses: Vec<&'static [u8]>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for resp in responses {
+                let (sock, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(sock);
+                let mut req = String::new();
+                let mut len = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        len = v.trim().parse().unwrap();
+                    }
+                    req.push_str(&line);
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                req.push_str(&String::from_utf8(body).unwrap());
+                tx.send(req).unwrap();
+                reader.get_mut().write_all(resp).unwrap();
+            }
+        });
+        (base, rx)
+    }
+
+    #[test]
+    fn test_url_parse() {
+        let u = Url::parse("HTTP://Example.com:8080/a/b?c=d#frag").unwrap();
+        assert_eq!(
+            (u.scheme, u.host.as_str(), u.port, u.path.as_str()),
+            (Scheme::Http, "example.com", 8080, "/a/b?c=d")
+        );
+        assert_eq!(u.host_header(), "example.com:8080");
+        let u = Url::parse("https://example.com?q").unwrap();
+        assert_eq!(
+            (u.port, u.path.as_str(), u.host_header().as_str()),
+            (443, "/?q", "example.com")
+        );
+        let u = Url::parse("http://[::1]:81/").unwrap();
+        assert_eq!(
+            (u.host.as_str(), u.host_header().as_str()),
+            ("::1", "[::1]:81")
+        );
+        assert!(Url::parse("ftp://example.com/").is_err());
+        assert!(Url::parse("http://user@example.com/").is_err());
+        assert!(Url::parse("http://example.com:99999/").is_err());
+        assert!(Url::parse("example.com/").is_err());
+        // nothing that could end the request line or the Host header early gets through
+        assert!(Url::parse("http://example.com/a\r\nX-Injected: 1").is_err());
+        assert!(Url::parse("http://example.com\r\nX-Injected: 1/").is_err());
+        assert!(Url::parse("http://exa mple.com/").is_err());
+        assert!(Url::parse("http://example.com/\0").is_err());
+        assert!(Url::parse("http://[::1\n]/").is_err());
+        let u = Url::parse("http://example.com/a b/\u{e9}?q=\"x y\"&p=%41").unwrap();
+        assert_eq!(u.path, "/a%20b/%C3%A9?q=%22x%20y%22&p=%41");
+    }
+    #[test]
+    fn test_url_join() {
+        let u = Url::parse("http://a.com/x/y?z").unwrap();
+        assert_eq!(u.join("/p").unwrap().to_string(), "http://a.com/p");
+        assert_eq!(u.join("q?r").unwrap().to_string(), "http://a.com/x/q?r");
+        assert_eq!(u.join("?n").unwrap().to_string(), "http://a.com/x/y?n");
+        assert_eq!(
+            u.join("//b.com:81/c").unwrap().to_string(),
+            "http://b.com:81/c"
+        );
+        assert_eq!(
+            u.join("https://c.com/d").unwrap().to_string(),
+            "https://c.com/d"
+        );
+        assert_eq!(
+            u.join("new page").unwrap().to_string(),
+            "http://a.com/x/new%20page"
+        );
+        assert!(u.join("/p\r\nX-Injected: 1").is_err());
+        assert!(u.join("//b.com\t/c").is_err());
+    }
+    #[test]
+    fn test_malformed_request() {
+        // all of these are caught before connecting
+        let url = "http://127.0.0.1:1/";
+        let client = Client::new();
+        let bad_headers = [
+            ("Bad", "x\r\nInjected: 1"),
+            ("Bad\r\nInjected", "1"),
+            ("Bad Name", "x"),
+            ("", "x"),
+        ];
+        for (name, value) in bad_headers.iter() {
+            let err = client.get(url).header(name, value).send().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        let err = Client::new()
+            .default_header("X-Default", "x\nInjected: 1")
+            .get(url)
+            .send()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = client.request("GET / HTTP/1.1\r\n", url).send().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // tabs are fine in a value
+        assert!(is_header_value("a\tb") && !is_header_value("a\rb"));
+    }
+    #[test]
+    fn test_get() {
+        let (base, reqs) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello",
+        ]);
+        let client = Client::new().default_header("User-Agent", "test");
+        let resp = client
+            .get(&format!("{}/index.html?x=1", base))
+            .header("X-Test", "yes")
+            .send()
+            .unwrap();
+        assert_eq!(
+            (resp.status, resp.reason.as_str(), resp.text().as_str()),
+            (200, "OK", "hello")
+        );
+        assert_eq!(resp.header("content-type"), Some("text/plain"));
+        let req = reqs.recv().unwrap();
+        assert!(req.starts_with("GET /index.html?x=1 HTTP/1.1\r\n"));
+        assert!(req.contains(&format!("Host: {}\r\n", &base[7..])));
+        assert!(req.contains("User-Agent: test\r\n") && req.contains("X-Test: yes\r\n"));
+        assert!(req.contains("Connection: close\r\n"));
+    }
+    #[test]
+    fn test_post() {
+        let (base, reqs) = serve(vec![b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n"]);
+        let client = Client::new();
+        let resp = client
+            .post(&format!("{}/submit", base), b"a=1&b=2")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Content-Length", "999")
+            .send()
+            .unwrap();
+        assert_eq!(resp.status, 201);
+        let req = reqs.recv().unwrap();
+        assert!(req.starts_with("POST /submit HTTP/1.1\r\n"));
+        assert!(req.contains("Content-Length: 7\r\n") && !req.contains("999"));
+        assert!(req.ends_with("\r\n\r\na=1&b=2"));
+        assert!(client
+            .get(&base)
+            .header("Bad", "x\r\nInjected: 1")
+            .send()
+            .is_err());
+    }
+    #[test]
+    fn test_chunked() {
+        let (base, _reqs) = serve(vec![
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Trailer: t\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello world\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n10\r\n0123456789abcdef\r\n0\r\n\r\n",
+        ]);
+        let client = Client::new();
+        assert_eq!(client.get(&base).send().unwrap().text(), "hello, world");
+        // chunk longer than its size line says
+        assert_eq!(
+            client.get(&base).send().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert!(Client::new().max_body(8).get(&base).send().is_err());
+    }
+    #[test]
+    fn test_body_until_close() {
+        let (base, _reqs) = serve(vec![b"HTTP/1.0 200 OK\r\n\r\nno length given"]);
+        assert_eq!(
+            Client::new().get(&base).send().unwrap().text(),
+            "no length given"
+        );
+    }
+    #[test]
+    fn test_redirects() {
+        let (base, reqs) = serve(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /moved\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            b"HTTP/1.1 303 See Other\r\nLocation: done\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 307 Temporary Redirect\r\nLocation: /again\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        ]);
+        let client = Client::new();
+        assert_eq!(
+            client
+                .get(&format!("{}/start", base))
+                .send()
+                .unwrap()
+                .text(),
+            "ok"
+        );
+        assert!(reqs.recv().unwrap().starts_with("GET /start "));
+        assert!(reqs.recv().unwrap().starts_with("GET /moved "));
+        // a 303 turns a POST into a GET and drops the body
+        client
+            .post(&format!("{}/form/submit", base), b"data")
+            .header("Content-Type", "text/plain")
+            .send()
+            .unwrap();
+        assert!(reqs.recv().unwrap().starts_with("POST /form/submit "));
+        let req = reqs.recv().unwrap();
+        assert!(
+            req.starts_with("GET /form/done ")
+                && !req.contains("Content-Type")
+                && !req.ends_with("data")
+        );
+        // a 307 repeats the request as it was
+        client.post(&format!("{}/x", base), b"data").send().unwrap();
+        assert!(reqs.recv().unwrap().starts_with("POST /x "));
+        let req = reqs.recv().unwrap();
+        assert!(req.starts_with("POST /again ") && req.ends_with("\r\n\r\ndata"));
+    }
+    #[test]
+    fn test_redirect_limit() {
+        let (base, _reqs) = serve(vec![
+            b"HTTP/1.1 301 Moved Permanently\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 301 Moved Permanently\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n",
+            b"HTTP/1.1 301 Moved Permanently\r\nLocation: /c\r\nContent-Length: 0\r\n\r\n",
+        ]);
+        let err = Client::new()
+            .max_redirects(1)
+            .get(&base)
+            .send()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        let resp = Client::new().max_redirects(0).get(&base).send().unwrap();
+        assert_eq!((resp.status, resp.header("location")), (301, Some("/c")));
+    }
+    #[test]
+    fn test_timeout() {
+        // accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://127.0.0.1:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let hold = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_millis(1000));
+            drop(sock);
+        });
+        let start = std::time::Instant::now();
+        let err = Client::new()
+            .get(&url)
+            .timeout(Some(Duration::from_millis(200)))
+            .send()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(900));
+        hold.join().unwrap();
+    }
+}
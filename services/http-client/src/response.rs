@@ -0,0 +1,191 @@
+use std::io::{BufRead, Error, ErrorKind, Read, Result};
+
+/// Longest status or header line we accept, and the most header lines.
+const MAX_LINE: usize = 8192;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    /// header names are as the server sent them; use `header()` for a case-insensitive lookup
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// The first header named `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+    /// The body as UTF-8, with invalid sequences replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+pub(crate) fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn malformed(why: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, why)
+}
+
+/// Reads one CRLF- (or bare LF-) terminated line, without the terminator.
+fn read_line<R: BufRead>(r: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    let n = r
+        .by_ref()
+        .take(MAX_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if n == 0 {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if n > MAX_LINE {
+            malformed("line too long")
+        } else {
+            Error::new(ErrorKind::UnexpectedEof, "connection closed mid-line")
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| malformed("line is not UTF-8"))
+}
+
+/// Reads header lines up to and including the blank line that ends them.
+fn read_headers<R: BufRead>(r: &mut R) -> Result<Vec<(String, String)>> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let line = read_line(r)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // obsolete line folding: continues the previous header's value
+            match headers.last_mut() {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                    continue;
+                }
+                None => return Err(malformed("continuation line without a header")),
+            }
+        }
+        let colon = line
+            .find(':')
+            .ok_or_else(|| malformed("header without a colon"))?;
+        if headers.len() == MAX_HEADERS {
+            return Err(malformed("too many headers"));
+        }
+        headers.push((
+            line[..colon].trim().to_string(),
+            line[colon + 1..].trim().to_string(),
+        ));
+    }
+}
+
+/// status code, reason phrase, headers
+pub(crate) type Head = (u16, String, Vec<(String, String)>);
+
+/// Reads the status line and headers. 1xx interim responses are skipped.
+pub(crate) fn read_head<R: BufRead>(r: &mut R) -> Result<Head> {
+    loop {
+        let line = read_line(r)?;
+        let mut parts = line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        if !version.starts_with("HTTP/1.") {
+            return Err(malformed("not an HTTP/1.x response"));
+        }
+        let status = parts
+            .next()
+            .and_then(|s| s.parse::<u16>().ok())
+            .filter(|s| (100..1000).contains(s))
+            .ok_or_else(|| malformed("bad status code"))?;
+        let reason = parts.next().unwrap_or("").to_string();
+        let headers = read_headers(r)?;
+        if (100..200).contains(&status) {
+            continue;
+        }
+        return Ok((status, reason, headers));
+    }
+}
+
+/// Reads a body framed by `Content-Length`, chunked transfer coding, or the end of the
+/// connection, per RFC 7230 section 3.3.3. Fails if it would be longer than `limit`.
+pub(crate) fn read_body<R: BufRead>(
+    r: &mut R,
+    headers: &[(String, String)],
+    limit: usize,
+) -> Result<Vec<u8>> {
+    if let Some(te) = header(headers, "Transfer-Encoding") {
+        // chunked has to be the final coding; anything else here would be a compression we
+        // didn't ask for
+        if te
+            .rsplit(',')
+            .next()
+            .map(|c| c.trim().eq_ignore_ascii_case("chunked"))
+            == Some(true)
+        {
+            return read_chunked(r, limit);
+        }
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "unsupported transfer coding",
+        ));
+    }
+    if let Some(len) = header(headers, "Content-Length") {
+        let len = len
+            .parse::<usize>()
+            .map_err(|_| malformed("bad Content-Length"))?;
+        if len > limit {
+            return Err(too_large());
+        }
+        let mut body = vec![0u8; len];
+        r.read_exact(&mut body)?;
+        return Ok(body);
+    }
+    let mut body = Vec::new();
+    r.take(limit as u64 + 1).read_to_end(&mut body)?;
+    if body.len() > limit {
+        return Err(too_large());
+    }
+    Ok(body)
+}
+
+fn too_large() -> Error {
+    Error::new(ErrorKind::Other, "response body exceeds the size limit")
+}
+
+fn read_chunked<R: BufRead>(r: &mut R, limit: usize) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(r)?;
+        // chunk extensions follow a ';' and are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| malformed("bad chunk size"))?;
+        if size == 0 {
+            // trailers, which we don't use, end with a blank line like headers do
+            read_headers(r)?;
+            return Ok(body);
+        }
+        if body.len().saturating_add(size) > limit {
+            return Err(too_large());
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        r.read_exact(&mut body[start..])?;
+        if !read_line(r)?.is_empty() {
+            return Err(malformed("chunk is longer than its size"));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+impl Scheme {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        }
+    }
+}
+
+/// The parts of an `http://` or `https://` URL that a request needs. User info is rejected
+/// and a fragment is dropped, since neither is ever sent to the server.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Url {
+    pub scheme: Scheme,
+    pub host: String,
+    pub port: u16,
+    /// path and query, always starting with `/`
+    pub path: String,
+}
+
+impl Url {
+    /// Parses `url`. Control characters are rejected anywhere, and the host has to be a plain
+    /// name or address; spaces and other characters that can't go in a request line as they are
+    /// get percent-encoded in the path and query.
+    pub fn parse(url: &str) -> Result<Url> {
+        if url.bytes().any(is_control) {
+            return Err(Error::new(ErrorKind::InvalidInput, "control character in URL"));
+        }
+        let bad = |why: &str| Error::new(ErrorKind::InvalidInput, format!("{}: {}", why, url));
+        let (scheme, rest) = match url.find("://") {
+            Some(i) => (&url[..i], &url[i + 3..]),
+            None => return Err(bad("missing scheme")),
+        };
+        let scheme = if scheme.eq_ignore_ascii_case("http") {
+            Scheme::Http
+        } else if scheme.eq_ignore_ascii_case("https") {
+            Scheme::Https
+        } else {
+            return Err(bad("unsupported scheme"));
+        };
+        let rest = match rest.find('#') {
+            Some(i) => &rest[..i],
+            None => rest,
+        };
+        let (authority, path) = match rest.find(&['/', '?'][..]) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.contains('@') {
+            return Err(bad("user info is not supported"));
+        }
+        let (host, port, v6) = if let Some(v6) = authority.strip_prefix('[') {
+            // [v6addr] or [v6addr]:port
+            let end = v6
+                .find(']')
+                .ok_or_else(|| bad("unterminated IPv6 address"))?;
+            let port = match &v6[end + 1..] {
+                "" => None,
+                p => Some(p.strip_prefix(':').ok_or_else(|| bad("bad port"))?),
+            };
+            (&v6[..end], port, true)
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], Some(&authority[i + 1..]), false),
+                None => (authority, None, false),
+            }
+        };
+        if host.is_empty() {
+            return Err(bad("missing host"));
+        }
+        let host_ok = if v6 {
+            host.bytes().all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.')
+        } else {
+            host.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_')
+        };
+        if !host_ok {
+            return Err(bad("bad host"));
+        }
+        let port = match port {
+            Some(p) => p.parse::<u16>().map_err(|_| bad("bad port"))?,
+            None => scheme.default_port(),
+        };
+        let path = if path.starts_with('?') {
+            format!("/{}", encode_path(path))
+        } else {
+            encode_path(path)
+        };
+        Ok(Url {
+            scheme,
+            host: host.to_ascii_lowercase(),
+            port,
+            path,
+        })
+    }
+
+    /// The value for the `Host` header: the port is left out when it's the scheme's default.
+    pub fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == self.scheme.default_port() {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    /// Resolves a `Location` header against this URL. Handles absolute URLs, scheme-relative
+    /// (`//host/path`), absolute paths and paths relative to the current directory.
+    pub fn join(&self, location: &str) -> Result<Url> {
+        if location.bytes().any(is_control) {
+            return Err(Error::new(ErrorKind::InvalidData, "control character in redirect location"));
+        }
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        let scheme = match self.scheme {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        };
+        if location.starts_with("//") {
+            return Url::parse(&format!("{}:{}", scheme, location));
+        }
+        let path = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            // drop the query, then everything after the last '/'
+            let base = match self.path.find('?') {
+                Some(i) => &self.path[..i],
+                None => &self.path[..],
+            };
+            let dir = match base.rfind('/') {
+                Some(i) => &base[..=i],
+                None => "/",
+            };
+            if location.is_empty() {
+                self.path.clone()
+            } else if location.starts_with('?') {
+                format!("{}{}", base, location)
+            } else {
+                format!("{}{}", dir, location)
+            }
+        };
+        let path = match path.find('#') {
+            Some(i) => encode_path(&path[..i]),
+            None => encode_path(&path),
+        };
+        Ok(Url {
+            scheme: self.scheme,
+            host: self.host.clone(),
+            port: self.port,
+            path,
+        })
+    }
+}
+
+fn is_control(b: u8) -> bool {
+    b < 0x20 || b == 0x7f
+}
+
+/// Percent-encodes the bytes of a path and query that can't go in a request line as they are:
+/// spaces, anything outside ASCII, and the few ASCII characters RFC 3986 never allows. A `%` is
+/// left alone, so escapes that are already there aren't encoded twice.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b > 0x7e || b" \"<>\\^`{|}".contains(&b) {
+            encoded.push_str(&format!("%{:02X}", b));
+        } else {
+            encoded.push(b as char);
+        }
+    }
+    encoded
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = match self.scheme {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        };
+        write!(f, "{}://{}{}", scheme, self.host_header(), self.path)
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::{Read, Write};
+
+/// PDDB dictionary holding the input history, one key per app context
+pub const IME_HISTORY_DICT: &str = "ime.history";
+/// PDDB dictionary for IMEF settings
+pub const IME_CONFIG_DICT: &str = "ime.config";
+/// number of lines of history kept per context, as a little-endian u32
+pub const IME_CONFIG_HISTORY_LEN: &str = "history_len";
+pub const DEFAULT_HISTORY_LEN: usize = 32;
+
+/// Input history for the currently connected context, persisted in the PDDB.
+///
+/// The IMEF comes up long before the PDDB is mounted, so the history is attached lazily:
+/// until the mount happens, lines are kept in RAM only, and they are merged into the
+/// stored history once it can be read.
+///
+/// New lines only mark the history dirty; it is written out by `flush()`, which the IMEF calls
+/// on a timer and whenever it switches to another context, so typing doesn't sync the PDDB per line.
+pub struct InputHistory {
+    poller: pddb::PddbMountPoller,
+    pddb: Option<pddb::Pddb>,
+    /// the app context whose history is loaded; None if history is disabled for the context
+    context: Option<String>,
+    /// oldest entry first
+    entries: Vec<String>,
+    max_len: usize,
+    /// `max_len` was set before the PDDB was mounted, and is still to be saved
+    max_len_unsaved: bool,
+    /// `entries` has lines that aren't in the PDDB yet
+    dirty: bool,
+}
+
+impl InputHistory {
+    pub fn new() -> InputHistory {
+        InputHistory {
+            poller: pddb::PddbMountPoller::new(),
+            pddb: None,
+            context: None,
+            entries: Vec::new(),
+            max_len: DEFAULT_HISTORY_LEN,
+            max_len_unsaved: false,
+            dirty: false,
+        }
+    }
+    /// Attaches to the PDDB if it has been mounted since we last checked. Returns true if attached.
+    fn ensure_mounted(&mut self) -> bool {
+        if self.pddb.is_none() && self.poller.is_mounted_nonblocking() {
+            let mut pddb = pddb::Pddb::new();
+            if !self.max_len_unsaved {
+                if let Ok(mut key) = pddb.get(IME_CONFIG_DICT, IME_CONFIG_HISTORY_LEN, None, false, false, None, None::<fn()>) {
+                    let mut len = [0u8; 4];
+                    if key.read(&mut len).unwrap_or(0) == 4 {
+                        self.max_len = u32::from_le_bytes(len) as usize;
+                    }
+                }
+            }
+            self.pddb = Some(pddb);
+            if self.max_len_unsaved {
+                // a length set before the mount wins over the stored one
+                self.save_max_len();
+            }
+            // merge anything typed before the mount in behind the stored history
+            let pending = std::mem::take(&mut self.entries);
+            self.load();
+            if pending.len() > 0 {
+                for line in pending {
+                    self.append(line);
+                }
+                self.dirty = true;
+            }
+        }
+        self.pddb.is_some()
+    }
+    fn load(&mut self) {
+        self.entries.clear();
+        if let (Some(pddb), Some(context)) = (self.pddb.as_mut(), self.context.as_ref()) {
+            if let Ok(mut key) = pddb.get(IME_HISTORY_DICT, context, None, false, false, None, None::<fn()>) {
+                let mut text = String::new();
+                if key.read_to_string(&mut text).is_ok() {
+                    for line in text.lines() {
+                        self.entries.push(line.to_string());
+                    }
+                } else {
+                    log::warn!("input history for {} is corrupt, discarding", context);
+                }
+            }
+        }
+        self.trim();
+    }
+    fn store(&mut self) {
+        if let (Some(pddb), Some(context)) = (self.pddb.as_mut(), self.context.as_ref()) {
+            // delete and re-create, so a shrinking history doesn't leave stale lines at the end of the key
+            pddb.delete_key(IME_HISTORY_DICT, context, None).ok();
+            if self.entries.len() > 0 {
+                let mut text = String::new();
+                for line in self.entries.iter() {
+                    text.push_str(line);
+                    text.push('\n');
+                }
+                match pddb.get(IME_HISTORY_DICT, context, None, true, true, Some(text.len()), None::<fn()>) {
+                    Ok(mut key) => {
+                        if key.write_all(text.as_bytes()).is_err() {
+                            log::error!("couldn't write input history for {}", context);
+                        }
+                    }
+                    Err(e) => log::error!("couldn't open input history for {}: {:?}", context, e),
+                }
+            }
+            pddb.sync().ok();
+        }
+        self.dirty = false;
+    }
+    fn trim(&mut self) {
+        if self.entries.len() > self.max_len {
+            let excess = self.entries.len() - self.max_len;
+            self.entries.drain(..excess);
+        }
+    }
+    fn append(&mut self, line: String) {
+        // a repeated line moves to the front instead of being recorded twice
+        self.entries.retain(|l| *l != line);
+        self.entries.push(line);
+        self.trim();
+    }
+    /// Writes out lines recorded since the last flush. Lines typed before the PDDB is mounted
+    /// stay dirty until it is.
+    pub fn flush(&mut self) {
+        if self.dirty && self.ensure_mounted() {
+            self.store();
+        }
+    }
+    /// Switches to the history of another context. `None` disables history.
+    pub fn set_context(&mut self, context: Option<&str>) {
+        if self.context.as_deref() == context {
+            return;
+        }
+        // the pending lines belong to the context we're leaving
+        self.flush();
+        self.dirty = false;
+        self.context = context.map(|c| c.to_string());
+        self.entries.clear();
+        if self.ensure_mounted() {
+            self.load();
+        }
+    }
+    /// Records a finished input line.
+    pub fn push(&mut self, line: &str) {
+        if self.context.is_none() || self.max_len == 0 || line.len() == 0 || line.contains('\n') {
+            return;
+        }
+        self.append(line.to_string());
+        self.dirty = true;
+    }
+    /// Returns up to `max` history lines that start with `prefix`, newest first. Lines equal to the
+    /// prefix are skipped, since offering them would not save any typing.
+    pub fn search(&mut self, prefix: &str, max: usize) -> Vec<String> {
+        // the PDDB may have been mounted since the context was set
+        self.ensure_mounted();
+        self.entries.iter().rev()
+            .filter(|l| l.starts_with(prefix) && l.as_str() != prefix)
+            .take(max)
+            .cloned()
+            .collect()
+    }
+    /// Clears the history of `context`, or of every context if `None`.
+    pub fn clear(&mut self, context: Option<&str>) {
+        let mounted = self.ensure_mounted();
+        if context.is_none() || context == self.context.as_deref() {
+            self.entries.clear();
+            self.dirty = false;
+        }
+        if mounted {
+            let pddb = self.pddb.as_mut().unwrap();
+            match context {
+                Some(c) => {
+                    pddb.delete_key(IME_HISTORY_DICT, c, None).ok();
+                }
+                None => {
+                    pddb.delete_dict(IME_HISTORY_DICT, None).ok();
+                }
+            }
+            pddb.sync().ok();
+        }
+    }
+    /// False if history is disabled for the context, or for everything with a length of 0.
+    pub fn is_enabled(&self) -> bool {
+        self.context.is_some() && self.max_len > 0
+    }
+    /// Sets the number of lines kept per context. If the PDDB isn't mounted yet, the length is
+    /// saved once it is.
+    pub fn set_max_len(&mut self, len: usize) {
+        self.max_len = len;
+        self.trim();
+        self.max_len_unsaved = true;
+        if self.ensure_mounted() {
+            // attaching just now will have saved it already
+            if self.max_len_unsaved {
+                self.save_max_len();
+            }
+            self.store();
+        }
+    }
+    fn save_max_len(&mut self) {
+        let pddb = self.pddb.as_mut().unwrap();
+        match pddb.get(IME_CONFIG_DICT, IME_CONFIG_HISTORY_LEN, None, true, true, Some(4), None::<fn()>) {
+            Ok(mut key) => {
+                key.write_all(&(self.max_len as u32).to_le_bytes()).ok();
+            }
+            Err(e) => log::error!("couldn't save history length: {:?}", e),
+        }
+        pddb.sync().ok();
+        self.max_len_unsaved = false;
+    }
+}
@@ -3,6 +3,8 @@
 
 mod emoji;
 use emoji::*;
+mod history;
+use history::*;
 
 use gam::api::SetCanvasBoundsRequest;
 use ime_plugin_api::{ImefCallback, ImefDescriptor, ImefOpcode};
@@ -25,6 +27,42 @@ use tts_frontend::*;
 
 /// max number of prediction options to track/render
 const MAX_PREDICTION_OPTIONS: usize = 4;
+/// max number of prediction options that can be taken up by matching lines from the input history
+const MAX_HISTORY_PREDICTIONS: usize = 2;
+/// how often recently entered lines are written out to the input history
+const HISTORY_FLUSH_INTERVAL_MS: usize = 30_000;
+
+/// Puts `prediction` in place of the text that starts at character `offset` of `line`, up to the next
+/// prediction trigger. Returns the new line, and the character position just past the prediction and
+/// the trigger that followed it, which is where typing carries on.
+fn splice_prediction(line: &str, offset: usize, prediction: &str, triggers: Option<PredictionTriggers>) -> (String, usize) {
+    let is_trigger = |c: char| match triggers {
+        Some(trigger) => trigger.whitespace && c.is_ascii_whitespace() || trigger.punctuation && c.is_ascii_punctuation(),
+        None => false,
+    };
+    // the offset only points at a trigger if one was typed; at the start of a line, or right after
+    // an inserted prediction, it points at the first character of the text being replaced.
+    let keep = match line.chars().nth(offset) {
+        Some(c) if is_trigger(c) => offset + 1, // +1 to include the original trigger (don't overwrite it)
+        _ => offset,
+    };
+    // copy the original string up to the offset, and then the selected prediction
+    let mut spliced = String::new();
+    let mut c_iter = line.chars();
+    spliced.extend(c_iter.by_ref().take(keep));
+    spliced.push_str(prediction);
+    // skip the replaced characters, up to and including the next prediction trigger in the original string
+    while let Some(c) = c_iter.next() {
+        if is_trigger(c) {
+            spliced.push(c);
+            break;
+        }
+    }
+    let insertion = spliced.chars().count();
+    // copy the remainder of the line, if any
+    spliced.extend(c_iter);
+    (spliced, insertion)
+}
 
 struct InputTracker {
     /// connection for handling graphical update requests
@@ -65,6 +103,10 @@ struct InputTracker {
 
     /// render the predictions. Slightly awkward because this code comes from before we had libstd
     pred_options: [Option<String>; MAX_PREDICTION_OPTIONS],
+    /// set for the prediction options that came from the input history; these replace the whole line when picked
+    pred_is_history: [bool; MAX_PREDICTION_OPTIONS],
+    /// persistent input history of the connected context
+    history: InputHistory,
     #[cfg(feature = "tts")]
     tts: TtsFrontend,
 }
@@ -88,6 +130,8 @@ impl InputTracker {
             last_height: 0,
             was_grown: false,
             pred_options: Default::default(),
+            pred_is_history: [false; MAX_PREDICTION_OPTIONS],
+            history: InputHistory::new(),
             #[cfg(feature="tts")]
             tts: TtsFrontend::new(xns).unwrap(),
         }
@@ -171,6 +215,18 @@ impl InputTracker {
         Ok(())
     }
 
+    /// Tells the predictor that `phrase` was finished, so it can learn it. Returns false without
+    /// telling it anything while history is off for the context, since what the predictor learns
+    /// is kept in the PDDB.
+    fn feedback_picked(&self, phrase: &str) -> bool {
+        if !self.history.is_enabled() {
+            return false;
+        }
+        self.predictor.unwrap().feedback_picked(
+            xous_ipc::String::<4000>::from_str(phrase)).expect("couldn't send feedback to predictor");
+        true
+    }
+
     fn insert_prediction(&mut self, index: usize) {
         let debug1 = false;
         if debug1{info!("IMEF|insert_prediction index {}", index);}
@@ -179,51 +235,24 @@ impl InputTracker {
             _ => return // if the index doesn't exist for some reason, do nothing without throwing an error
         };
         if debug1{info!("IMEF|insert_prediction string {}, last_trigger {:?}", pred_str, self.last_trigger_char);}
+        if self.pred_is_history[index] {
+            // history entries are whole lines, so they replace everything typed so far
+            self.line.clear();
+            self.line.push_str(pred_str);
+            self.characters = self.line.chars().count();
+            self.insertion = self.characters;
+            self.last_trigger_char = Some(self.characters);
+            self.pred_phrase.clear();
+            self.can_unpick = false;
+            return;
+        }
         if let Some(offset) = self.last_trigger_char {
             if offset < self.characters {
-                // copy the bytes in the original string, up to the offset; and then copy the bytes in the selected predictor
-                let tempstr = self.line.to_string();
-                self.line.clear();
-                let mut chars = 0;
-                let mut c_iter = tempstr.chars();
-                loop {
-                    if chars == offset + 1 { // +1 to include the original trigger (don't overwrite it)
-                        break;
-                    }
-                    if let Some(c) = c_iter.next() {
-                        self.line.push(c);
-                        chars += 1;
-                    } else {
-                        break;
-                    }
-                }
-                // now push the characters in the predicted options onto the line
-                for c in pred_str.as_str().chars() {
-                    self.line.push(c);
-                    chars += 1;
-                }
-                // forward until we find the next prediction trigger in the original string
-                while let Some(c) = c_iter.next() {
-                    if let Some(trigger) = self.pred_triggers {
-                        if trigger.whitespace && c.is_ascii_whitespace() ||
-                           trigger.punctuation && c.is_ascii_punctuation() {
-                               // include the trigger that was found
-                               self.line.push(c);
-                               chars += 1;
-                               break;
-                        }
-                    } else {
-                        // skip the replaced characters
-                    }
-                }
-                self.insertion = chars;
-                self.last_trigger_char = Some(chars);
-                // copy the remainder of the line, if any
-                while let Some(c) = c_iter.next() {
-                    self.line.push(c);
-                    chars += 1;
-                }
-                self.characters = chars;
+                let (line, insertion) = splice_prediction(&self.line, offset, pred_str, self.pred_triggers);
+                self.line = line;
+                self.insertion = insertion;
+                self.last_trigger_char = Some(insertion);
+                self.characters = self.line.chars().count();
             } else {
                 // just append the prediction to the line
                 for c in pred_str.as_str().chars() {
@@ -233,6 +262,8 @@ impl InputTracker {
                 self.last_trigger_char = Some(self.insertion);
                 self.insertion = self.characters;
             }
+            // the phrase being predicted has been replaced, so start tracking a new one
+            self.pred_phrase.clear();
         }
     }
 
@@ -366,14 +397,16 @@ impl InputTracker {
                         let mut ret = xous_ipc::String::<4000>::new();
                         write!(ret, "{}", self.line.as_str()).expect("couldn't copy input line to output");
                         retstring = Some(ret);
-
-                        if let Some(trigger) = self.pred_triggers {
-                            if trigger.newline {
-                                self.predictor.unwrap().feedback_picked(
-                                    xous_ipc::String::<4000>::from_str(&self.line)).expect("couldn't send feedback to predictor");
-                            } else if trigger.punctuation {
-                                self.predictor.unwrap().feedback_picked(
-                                    xous_ipc::String::<4000>::from_str(&self.pred_phrase)).expect("couldn't send feedback to predictor");
+                        // with history off, nothing of the line is kept: not as history, and not as
+                        // words for the predictor to learn
+                        if self.history.is_enabled() {
+                            self.history.push(&self.line);
+                            if let Some(trigger) = self.pred_triggers {
+                                if trigger.newline {
+                                    self.feedback_picked(&self.line);
+                                } else if trigger.punctuation {
+                                    self.feedback_picked(&self.pred_phrase);
+                                }
                             }
                         }
                         self.can_unpick = false;
@@ -407,19 +440,15 @@ impl InputTracker {
                         if let Some(trigger) = self.pred_triggers {
                             if trigger.whitespace && k.is_ascii_whitespace() {
                                 if self.pred_phrase.len() > 0 {
-                                    self.predictor.unwrap().feedback_picked(
-                                        xous_ipc::String::<4000>::from_str(&self.pred_phrase)).expect("couldn't send feedback to predictor");
+                                    self.can_unpick = self.feedback_picked(&self.pred_phrase);
                                     self.pred_phrase.clear();
-                                    self.can_unpick = true;
                                     update_predictor = true;
                                 }
                                 self.last_trigger_char = Some(self.insertion);
                             } else if trigger.punctuation && k.is_ascii_punctuation() {
                                 if self.pred_phrase.len() > 0 {
-                                    self.predictor.unwrap().feedback_picked(
-                                        xous_ipc::String::<4000>::from_str(&self.pred_phrase)).expect("couldn't send feedback to predictor");
+                                    self.can_unpick = self.feedback_picked(&self.pred_phrase);
                                     self.pred_phrase.clear();
-                                    self.can_unpick = true;
                                     update_predictor = true;
                                 }
                                 self.last_trigger_char = Some(self.insertion);
@@ -438,9 +467,10 @@ impl InputTracker {
                                 if !(trigger.punctuation && k.is_ascii_punctuation() ||
                                     trigger.whitespace  && k.is_ascii_whitespace() ) {
                                     self.pred_phrase.push(k);
-                                    update_predictor = true;
                                 }
                             }
+                            // the line changed, so the history matches (if nothing else) need refreshing
+                            update_predictor = true;
                             self.characters += 1;
                             self.insertion += 1;
                             do_redraw = true;
@@ -555,6 +585,30 @@ impl InputTracker {
                         self.pred_options[i] = p;
                     }
                 }
+
+                // offer matching lines from the input history ahead of the plugin's predictions
+                self.pred_is_history = [false; MAX_PREDICTION_OPTIONS];
+                if self.line.len() > 0 {
+                    let matches = self.history.search(&self.line, MAX_HISTORY_PREDICTIONS);
+                    if matches.len() > 0 {
+                        let mut merged: Vec<(String, bool)> = matches.into_iter().map(|m| (m, true)).collect();
+                        for p in self.pred_options.iter() {
+                            if let Some(p) = p {
+                                if !merged.iter().any(|(m, _)| m == p) {
+                                    merged.push((p.clone(), false));
+                                }
+                            }
+                        }
+                        for i in 0..MAX_PREDICTION_OPTIONS {
+                            if let Some((p, from_history)) = merged.get(i) {
+                                self.pred_options[i] = Some(p.clone());
+                                self.pred_is_history[i] = *from_history;
+                            } else {
+                                self.pred_options[i] = None;
+                            }
+                        }
+                    }
+                }
             }
 
             // count the number of valid options
@@ -642,6 +696,20 @@ fn xmain() -> ! {
     // create the emoji menu handler
     emoji_menu(xous::connect(imef_sid).unwrap());
 
+    // write out the input history in batches, instead of syncing the PDDB on every line
+    std::thread::spawn({
+        let flush_conn = xous::connect(imef_sid).unwrap();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                tt.sleep_ms(HISTORY_FLUSH_INTERVAL_MS).unwrap();
+                xous::send_message(flush_conn,
+                    xous::Message::new_scalar(ImefOpcode::FlushHistory.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't request input history flush");
+            }
+        }
+    });
+
     log::trace!("Initialized but still waiting for my canvas Gids");
     loop {
         let msg = xous::receive_message(imef_sid).unwrap();
@@ -686,6 +754,7 @@ fn xmain() -> ! {
                     }
                 }
                 tracker.set_gam_token(descriptor.token);
+                tracker.history.set_context(descriptor.history.as_ref().map(|h| h.as_str().unwrap()));
             }
             Some(ImefOpcode::RegisterListener) => msg_scalar_unpack!(msg, sid0, sid1, sid2, sid3, {
                 let sid = xous::SID::from_u32(sid0 as _, sid1 as _, sid2 as _, sid3 as _);
@@ -750,7 +819,26 @@ fn xmain() -> ! {
                     // ignore keyboard events until we've fully initialized
                 }
             }),
-            Some(ImefOpcode::Quit) => {log::error!("recevied quit, goodbye!"); break;}
+            Some(ImefOpcode::ClearHistory) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let name = buffer.to_original::<xous_ipc::String::<128>, _>().unwrap();
+                let context = name.as_str().unwrap();
+                if context.len() > 0 {
+                    tracker.history.clear(Some(context));
+                } else {
+                    tracker.history.clear(None);
+                }
+            }
+            Some(ImefOpcode::SetHistoryLength) => msg_scalar_unpack!(msg, len, _, _, _, {
+                tracker.history.set_max_len(len);
+            }),
+            Some(ImefOpcode::FlushHistory) => {
+                tracker.history.flush();
+            }
+            Some(ImefOpcode::Quit) => {
+                tracker.history.flush();
+                log::error!("recevied quit, goodbye!"); break;
+            }
             None => {log::error!("couldn't convert opcode");}
         }
     }
@@ -760,3 +848,46 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ime_plugin_cjk::{kana, pinyin, COMPOSITION_TRIGGERS};
+
+    #[test]
+    fn test_compose_pinyin() {
+        // "nihao" was typed after the space at character 5, and its first candidate picked
+        let candidates = pinyin::candidates("nihao");
+        assert_eq!(
+            splice_prediction("wo ai nihao", 5, &candidates[0], Some(COMPOSITION_TRIGGERS)),
+            (String::from("wo ai 你好"), 8)
+        );
+        // the insertion point lands past the trigger, where the next word starts
+        let candidates = pinyin::candidates("zhongguo");
+        assert_eq!(
+            splice_prediction("zhongguo ren", 0, &candidates[0], Some(COMPOSITION_TRIGGERS)),
+            (String::from("中国 ren"), 3)
+        );
+        // apostrophes don't end a word
+        let candidates = pinyin::candidates("xi'an");
+        assert_eq!(
+            splice_prediction("qu xi'an", 2, &candidates[0], Some(COMPOSITION_TRIGGERS)),
+            (format!("qu {}", candidates[0]), 3 + candidates[0].chars().count())
+        );
+    }
+
+    #[test]
+    fn test_compose_kana() {
+        let candidates = kana::candidates("nihon");
+        assert_eq!(
+            splice_prediction("nihon desu", 0, &candidates[1], Some(COMPOSITION_TRIGGERS)),
+            (String::from("日本 desu"), 3)
+        );
+        // composing a word after one that was already composed
+        let candidates = kana::candidates("konnichiha");
+        assert_eq!(
+            splice_prediction("日本 konnichiha", 2, &candidates[0], Some(COMPOSITION_TRIGGERS)),
+            (String::from("日本 こんにちは"), 8)
+        );
+    }
+}
@@ -208,6 +208,15 @@ pub enum ImefOpcode {
     /// force a redraw of the UI
     Redraw,
 
+    /// clear the input history of a context, or of all contexts if the name is empty
+    ClearHistory, //(String<128>),
+
+    /// set the number of lines of input history retained per context
+    SetHistoryLength,
+
+    /// internal use for writing out recent input history from the flush timer
+    FlushHistory,
+
     Quit,
 }
 #[derive(Debug, num_derive::FromPrimitive, num_derive::ToPrimitive)]
@@ -222,6 +231,8 @@ pub struct ImefDescriptor {
     pub prediction_canvas: Option<graphics_server::Gid>,
     pub predictor: Option<String<64>>,
     pub token: [u32; 4], // token used to lookup our connected app inside the GAM
+    /// name of the context whose input history should be used; None disables history for this context
+    pub history: Option<String<128>>,
 }
 
 pub trait ImeFrontEndApi {
@@ -231,6 +242,8 @@ pub trait ImeFrontEndApi {
     fn send_keyevent(&self, keys: [char; 4]) -> Result<(), xous::Error>;
     fn conn(&self) -> xous::CID;
     fn getop_process_keys(&self) -> u32;
+    fn clear_history(&self, context: Option<&str>) -> Result<(), xous::Error>;
+    fn set_history_length(&self, len: usize) -> Result<(), xous::Error>;
 }
 
 pub const SERVER_NAME_IME_FRONT: &str = "_IME front end_";
@@ -349,6 +362,24 @@ impl ImeFrontEndApi for ImeFrontEnd {
         )?;
         Ok(())
     }
+
+    fn clear_history(&self, context: Option<&str>) -> Result<(), xous::Error> {
+        let name = match context {
+            Some(name) => String::<128>::from_str(name),
+            None => String::<128>::new(),
+        };
+        let buf = Buffer::into_buf(name).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.cid, ImefOpcode::ClearHistory.to_u32().unwrap())
+            .map(|_| ())
+    }
+
+    fn set_history_length(&self, len: usize) -> Result<(), xous::Error> {
+        send_message(
+            self.cid,
+            Message::new_scalar(ImefOpcode::SetHistoryLength.to_usize().unwrap(), len, 0, 0, 0),
+        )?;
+        Ok(())
+    }
 }
 
 /// handles callback messages from server, in the library user's process space.
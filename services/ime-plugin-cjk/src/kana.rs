@@ -0,0 +1,167 @@
+/// Romaji to hiragana table. Longer sequences must be matched before shorter ones,
+/// which `to_hiragana()` takes care of by trying the longest match first.
+static ROMAJI: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("sa", "さ"), ("shi", "し"), ("si", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("za", "ざ"), ("ji", "じ"), ("zi", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ta", "た"), ("chi", "ち"), ("ti", "ち"), ("tsu", "つ"), ("tu", "つ"), ("te", "て"), ("to", "と"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("ha", "は"), ("hi", "ひ"), ("fu", "ふ"), ("hu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("wa", "わ"), ("wo", "を"), ("n'", "ん"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("sho", "しょ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("jo", "じょ"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("cho", "ちょ"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("-", "ー"),
+];
+
+/// Common words that are normally written with kanji, keyed by their hiragana reading.
+static KANJI: &[(&str, &str)] = &[
+    ("にほん", "日本"),
+    ("にほんご", "日本語"),
+    ("わたし", "私"),
+    ("なまえ", "名前"),
+    ("でんわ", "電話"),
+    ("じかん", "時間"),
+    ("きょう", "今日"),
+    ("あした", "明日"),
+    ("ともだち", "友達"),
+    ("せんせい", "先生"),
+    ("がくせい", "学生"),
+    ("ひと", "人"),
+    ("みず", "水"),
+    ("やま", "山"),
+    ("かわ", "川"),
+    ("き", "木"),
+    ("ひ", "日"),
+    ("つき", "月"),
+    ("いえ", "家"),
+    ("くるま", "車"),
+];
+
+fn is_consonant(c: char) -> bool {
+    c.is_ascii_alphabetic() && !"aeiou".contains(c)
+}
+
+/// Converts romaji to hiragana. Letters that don't (yet) form a kana are left as-is, so a
+/// partially typed syllable like `kyo` + `u` composes naturally as the user types.
+pub fn to_hiragana(input: &str) -> String {
+    let input = input.to_lowercase();
+    let bytes: Vec<char> = input.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        // a doubled consonant (other than n) is a small tsu: "kitte" -> "きって"
+        if i + 1 < bytes.len() && bytes[i] == bytes[i + 1] && is_consonant(bytes[i]) && bytes[i] != 'n' {
+            out.push('っ');
+            i += 1;
+            continue;
+        }
+        let mut matched = false;
+        for len in (1..=3).rev() {
+            if i + len > bytes.len() {
+                continue;
+            }
+            let seq: String = bytes[i..i + len].iter().collect();
+            if let Some((_, kana)) = ROMAJI.iter().find(|(r, _)| *r == seq) {
+                out.push_str(kana);
+                i += len;
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            // "nn" is ん unless the second n starts a syllable of its own: "konnichiha" -> "こんにちは"
+            if bytes[i] == 'n' && i + 1 < bytes.len() && bytes[i + 1] == 'n'
+            && (i + 2 == bytes.len() || !(bytes[i + 2] == 'y' || "aeiou".contains(bytes[i + 2]))) {
+                out.push('ん');
+                i += 2;
+                continue;
+            }
+            // a lone n before a consonant (or at the very end of a finished word) is ん
+            if bytes[i] == 'n' && i + 1 < bytes.len() && is_consonant(bytes[i + 1]) && bytes[i + 1] != 'y' {
+                out.push('ん');
+            } else {
+                out.push(bytes[i]);
+            }
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Converts hiragana to katakana; anything else passes through unchanged.
+pub fn to_katakana(hiragana: &str) -> String {
+    hiragana.chars().map(|c| {
+        match c {
+            // the hiragana and katakana blocks are laid out in parallel, 0x60 apart
+            '\u{3041}'..='\u{3096}' => core::char::from_u32(c as u32 + 0x60).unwrap_or(c),
+            _ => c,
+        }
+    }).collect()
+}
+
+/// Returns composition candidates for `input`, best first: hiragana, then any kanji
+/// word matching that reading, then katakana.
+pub fn candidates(input: &str) -> Vec<String> {
+    let mut ret: Vec<String> = Vec::new();
+    let mut hira = to_hiragana(input);
+    // at the end of a word a trailing lone n can only be ん
+    if hira.ends_with('n') {
+        hira.pop();
+        hira.push('ん');
+    }
+    if hira.chars().all(|c| c.is_ascii()) {
+        return ret; // nothing composed yet
+    }
+    ret.push(hira.clone());
+    for (reading, kanji) in KANJI.iter() {
+        if *reading == hira {
+            ret.push(kanji.to_string());
+        }
+    }
+    let kata = to_katakana(&hira);
+    if kata != hira {
+        ret.push(kata);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_hiragana() {
+        assert_eq!(to_hiragana("konnichiha"), "こんにちは");
+        assert_eq!(to_hiragana("kitte"), "きって");
+        assert_eq!(to_hiragana("toukyou"), "とうきょう");
+        assert_eq!(to_hiragana("shinbun"), "しんぶn");
+        assert_eq!(to_hiragana("kyo"), "きょ");
+        assert_eq!(to_hiragana("onna"), "おんな");
+        assert_eq!(to_hiragana("hon'ya"), "ほんや");
+    }
+    #[test]
+    fn test_katakana() {
+        assert_eq!(to_katakana("こーひー"), "コーヒー");
+    }
+    #[test]
+    fn test_candidates() {
+        assert_eq!(candidates("nihon"), vec!["にほん", "日本", "ニホン"]);
+        assert!(candidates("k").is_empty());
+    }
+}
@@ -0,0 +1,21 @@
+#![cfg_attr(target_os = "none", no_std)]
+
+/// composes pinyin into simplified Chinese characters
+pub const SERVER_NAME_IME_PLUGIN_PINYIN: &str = "_IME pinyin plugin_";
+/// composes romaji into Japanese kana (and a small set of common kanji words)
+pub const SERVER_NAME_IME_PLUGIN_KANA: &str = "_IME kana plugin_";
+
+/// the composers are in the library so the IME front end can test against them
+pub mod pinyin;
+pub mod kana;
+
+// just inherit all the default from the ime_plugin_api
+pub use ime_plugin_api::*;
+
+/// Composition runs over whole words: apostrophes (xi'an, hon'ya) and dashes (ー) are part of the
+/// input, so only whitespace ends a word.
+pub const COMPOSITION_TRIGGERS: PredictionTriggers = PredictionTriggers {
+    newline: false,
+    punctuation: false,
+    whitespace: true,
+};
@@ -0,0 +1,93 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+use ime_plugin_cjk::{kana, pinyin};
+
+use ime_plugin_api::*;
+
+use log::{error, info};
+
+use xous_ipc::{String, Buffer};
+use num_traits::FromPrimitive;
+
+/// Runs one composition server. The IME front end hands us the latin letters typed since the
+/// last trigger via `Input`; the composed candidates are offered in the prediction row, and
+/// picking one with F1-F4 replaces the latin letters through the front end's `insert_prediction` path.
+fn composition_server(sid: xous::SID, compose: fn(&str) -> Vec<std::string::String>) {
+    let mytriggers = ime_plugin_cjk::COMPOSITION_TRIGGERS;
+    let mut candidates: Vec<std::string::String> = Vec::new();
+    loop {
+        let mut msg = xous::receive_message(sid).unwrap();
+        log::trace!("received message {:?}", msg);
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Input) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                candidates = compose(s.as_str());
+                log::trace!("composed {} -> {:?}", s.as_str(), candidates);
+            }
+            Some(Opcode::Picked) => {
+                // the word is finished (either a candidate was inserted, or the latin text was kept)
+                candidates.clear();
+            }
+            Some(Opcode::Prediction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                if let Some(c) = candidates.get(prediction.index as usize) {
+                    prediction.string.clear();
+                    for ch in c.chars() {
+                        if prediction.string.push(ch).is_err() {
+                            break;
+                        }
+                    }
+                    prediction.valid = true;
+                } else {
+                    prediction.valid = false;
+                }
+                buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
+            }
+            Some(Opcode::Unpick) => {
+                // nothing is learned, so there is nothing to undo
+            }
+            Some(Opcode::GetPredictionTriggers) => {
+                xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
+            }
+            Some(Opcode::Quit) => {
+                error!("received quit, goodbye!"); break;
+            }
+            None => {error!("unknown Opcode");}
+        }
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // one connection only per server, should be the IME front end
+    let pinyin_sid = xns.register_name(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_PINYIN, Some(1)).expect("can't register server");
+    let kana_sid = xns.register_name(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_KANA, Some(1)).expect("can't register server");
+    log::trace!("registered with NS -- {:?} {:?}", pinyin_sid, kana_sid);
+
+    let kana_handle = std::thread::spawn({
+        let kana_sid = kana_sid.clone();
+        move || {
+            composition_server(kana_sid, kana::candidates);
+        }
+    });
+
+    info!("ready to accept requests");
+    composition_server(pinyin_sid, pinyin::candidates);
+    kana_handle.join().expect("kana server thread did not exit cleanly");
+
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(pinyin_sid).unwrap();
+    xns.unregister_server(kana_sid).unwrap();
+    xous::destroy_server(pinyin_sid).unwrap();
+    xous::destroy_server(kana_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
@@ -0,0 +1,308 @@
+/// Pinyin syllables (toneless) and their candidate characters, most common first.
+/// Only characters that are present in the `zh` glyph table are listed here.
+static SYLLABLES: &[(&str, &str)] = &[
+    ("a", "啊阿"),
+    ("ai", "爱在哎埃矮"),
+    ("an", "安按暗岸案"),
+    ("ba", "把吧八爸巴拔"),
+    ("bai", "白百拜败摆"),
+    ("ban", "办半班般板版"),
+    ("bang", "帮棒邦榜"),
+    ("bao", "报包保宝抱饱"),
+    ("bei", "被北备背杯悲"),
+    ("ben", "本奔笨"),
+    ("bi", "比必笔闭币避"),
+    ("bian", "变边便遍编"),
+    ("biao", "表标"),
+    ("bie", "别"),
+    ("bing", "并病兵冰"),
+    ("bu", "不部步布补"),
+    ("ca", "擦"),
+    ("cai", "才菜财采彩猜"),
+    ("can", "参餐残"),
+    ("cha", "查茶差察"),
+    ("chang", "长常场唱厂"),
+    ("chao", "超朝吵"),
+    ("che", "车彻"),
+    ("chen", "陈沉晨"),
+    ("cheng", "成城程称承"),
+    ("chi", "吃持迟尺"),
+    ("chu", "出处初除楚"),
+    ("chuan", "穿传船川"),
+    ("chuang", "床窗创"),
+    ("chun", "春纯"),
+    ("ci", "次此词"),
+    ("cong", "从聪"),
+    ("cuo", "错"),
+    ("da", "大打达答"),
+    ("dai", "带代待袋"),
+    ("dan", "但单蛋担"),
+    ("dang", "当党"),
+    ("dao", "到道倒刀"),
+    ("de", "的得德地"),
+    ("deng", "等灯登"),
+    ("di", "地第弟低底"),
+    ("dian", "点电店"),
+    ("diao", "掉调"),
+    ("ding", "定顶"),
+    ("dong", "东动懂冬"),
+    ("dou", "都斗豆"),
+    ("du", "读度都独"),
+    ("duan", "段短断"),
+    ("dui", "对队"),
+    ("duo", "多朵"),
+    ("e", "饿额恶"),
+    ("er", "二而儿耳"),
+    ("fa", "发法"),
+    ("fan", "饭反放烦"),
+    ("fang", "方放房访"),
+    ("fei", "非飞费"),
+    ("fen", "分份"),
+    ("feng", "风封"),
+    ("fu", "服父付复福"),
+    ("gai", "该改"),
+    ("gan", "干感敢"),
+    ("gang", "刚"),
+    ("gao", "高告"),
+    ("ge", "个哥歌各"),
+    ("gei", "给"),
+    ("gen", "跟根"),
+    ("gong", "工公共"),
+    ("gou", "够狗"),
+    ("gu", "故古姑"),
+    ("gua", "挂"),
+    ("guan", "关管观"),
+    ("guang", "光广"),
+    ("gui", "贵鬼"),
+    ("guo", "国过果"),
+    ("hai", "还孩海害"),
+    ("han", "汉喊"),
+    ("hao", "好号"),
+    ("he", "和喝合河"),
+    ("hen", "很"),
+    ("hong", "红"),
+    ("hou", "后候"),
+    ("hu", "湖户"),
+    ("hua", "话花化画"),
+    ("huan", "欢换还"),
+    ("hui", "会回"),
+    ("huo", "或火活"),
+    ("ji", "几机记己急"),
+    ("jia", "家加假"),
+    ("jian", "见件间"),
+    ("jiang", "将讲"),
+    ("jiao", "叫教觉"),
+    ("jie", "姐接节"),
+    ("jin", "进今近金"),
+    ("jing", "经京"),
+    ("jiu", "就九久"),
+    ("ju", "句局"),
+    ("jue", "觉决"),
+    ("kai", "开"),
+    ("kan", "看"),
+    ("ke", "可课客"),
+    ("kou", "口"),
+    ("kuai", "快块"),
+    ("lai", "来"),
+    ("lao", "老"),
+    ("le", "了乐"),
+    ("lei", "累"),
+    ("leng", "冷"),
+    ("li", "里理力"),
+    ("liang", "两亮"),
+    ("lin", "林"),
+    ("liu", "六"),
+    ("long", "龙"),
+    ("lu", "路"),
+    ("ma", "吗妈马"),
+    ("mai", "买卖"),
+    ("man", "慢满"),
+    ("mang", "忙"),
+    ("mao", "毛猫"),
+    ("me", "么"),
+    ("mei", "没美每"),
+    ("men", "们门"),
+    ("mi", "米"),
+    ("mian", "面"),
+    ("ming", "明名"),
+    ("mu", "木目"),
+    ("na", "那拿哪"),
+    ("nan", "男难南"),
+    ("ne", "呢"),
+    ("neng", "能"),
+    ("ni", "你"),
+    ("nian", "年"),
+    ("nin", "您"),
+    ("niu", "牛"),
+    ("nv", "女"),
+    ("peng", "朋"),
+    ("pian", "片"),
+    ("piao", "票"),
+    ("qi", "起七气"),
+    ("qian", "前钱千"),
+    ("qing", "请情"),
+    ("qu", "去"),
+    ("ren", "人认"),
+    ("ri", "日"),
+    ("san", "三"),
+    ("shang", "上"),
+    ("shao", "少"),
+    ("shei", "谁"),
+    ("shen", "什身"),
+    ("sheng", "生"),
+    ("shi", "是时事"),
+    ("shou", "手"),
+    ("shu", "书"),
+    ("shui", "水"),
+    ("shuo", "说"),
+    ("si", "四"),
+    ("ta", "他她它"),
+    ("tai", "太"),
+    ("tian", "天"),
+    ("ting", "听"),
+    ("tong", "同"),
+    ("wan", "完晚"),
+    ("wei", "为"),
+    ("wen", "问"),
+    ("wo", "我"),
+    ("wu", "五"),
+    ("xi", "喜"),
+    ("xia", "下"),
+    ("xian", "先现"),
+    ("xiang", "想"),
+    ("xiao", "小"),
+    ("xie", "谢写"),
+    ("xin", "新心"),
+    ("xing", "行"),
+    ("xue", "学"),
+    ("yao", "要"),
+    ("ye", "也"),
+    ("yi", "一"),
+    ("you", "有"),
+    ("yu", "雨"),
+    ("zai", "在"),
+    ("zao", "早"),
+    ("zen", "怎"),
+    ("zhe", "这"),
+    ("zhong", "中"),
+    ("zi", "字"),
+    ("zou", "走"),
+    ("zuo", "做"),
+];
+
+/// Common multi-syllable words, keyed by the toneless pinyin without separators.
+static PHRASES: &[(&str, &str)] = &[
+    ("nihao", "你好"),
+    ("xiexie", "谢谢"),
+    ("zaijian", "再见"),
+    ("women", "我们"),
+    ("nimen", "你们"),
+    ("tamen", "他们"),
+    ("shenme", "什么"),
+    ("zenme", "怎么"),
+    ("meiyou", "没有"),
+    ("keyi", "可以"),
+    ("xianzai", "现在"),
+    ("zhongguo", "中国"),
+    ("zhongwen", "中文"),
+    ("pengyou", "朋友"),
+    ("mingtian", "明天"),
+    ("jintian", "今天"),
+    ("shijian", "时间"),
+    ("dianhua", "电话"),
+    ("xuesheng", "学生"),
+    ("laoshi", "老师"),
+    ("xihuan", "喜欢"),
+    ("duibuqi", "对不起"),
+    ("meiguanxi", "没关系"),
+];
+
+fn chars_for(syllable: &str) -> Option<&'static str> {
+    SYLLABLES.iter().find(|(s, _)| *s == syllable).map(|(_, c)| *c)
+}
+
+/// Splits a run of latin letters into pinyin syllables using greedy longest match.
+/// An apostrophe forces a syllable boundary (e.g. `xi'an`). Any trailing letters that
+/// don't form a complete syllable are returned as the remainder.
+pub fn segment(input: &str) -> (Vec<&'static str>, String) {
+    let mut syllables = Vec::new();
+    let mut rest = input;
+    'outer: while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('\'') {
+            rest = r;
+            continue;
+        }
+        let limit = rest.find('\'').unwrap_or(rest.len());
+        // pinyin syllables are at most 6 letters long (e.g. "chuang", "zhuang")
+        for len in (1..=limit.min(6)).rev() {
+            if !rest.is_char_boundary(len) {
+                continue;
+            }
+            if let Some((s, _)) = SYLLABLES.iter().find(|(s, _)| *s == &rest[..len]) {
+                syllables.push(*s);
+                rest = &rest[len..];
+                continue 'outer;
+            }
+        }
+        break;
+    }
+    (syllables, rest.to_string())
+}
+
+/// Returns composition candidates for `input`, best first.
+pub fn candidates(input: &str) -> Vec<String> {
+    let input = input.to_lowercase();
+    let mut ret: Vec<String> = Vec::new();
+    let compact: String = input.chars().filter(|&c| c != '\'').collect();
+    for (p, hanzi) in PHRASES.iter() {
+        if *p == compact {
+            ret.push(hanzi.to_string());
+        }
+    }
+    let (syllables, rest) = segment(&input);
+    if syllables.is_empty() {
+        return ret;
+    }
+    let last = syllables.len() - 1;
+    // the leading syllables take their most common character; the alternatives for the
+    // final syllable are offered so the user can pick a character one syllable at a time.
+    let mut head = String::new();
+    for s in syllables[..last].iter() {
+        if let Some(c) = chars_for(s).and_then(|c| c.chars().next()) {
+            head.push(c);
+        }
+    }
+    if let Some(alternatives) = chars_for(syllables[last]) {
+        for c in alternatives.chars() {
+            let mut cand = head.clone();
+            cand.push(c);
+            cand.push_str(&rest);
+            if !ret.contains(&cand) {
+                ret.push(cand);
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_segment() {
+        assert_eq!(segment("nihao"), (vec!["ni", "hao"], String::new()));
+        assert_eq!(segment("zhongguo"), (vec!["zhong", "guo"], String::new()));
+        assert_eq!(segment("woh"), (vec!["wo"], String::from("h")));
+        assert_eq!(segment("xi'an"), (vec!["xi", "an"], String::new()));
+    }
+    #[test]
+    fn test_candidates() {
+        let c = candidates("nihao");
+        assert_eq!(c[0], "你好");
+        let c = candidates("ma");
+        assert_eq!(c, vec!["吗", "妈", "马"]);
+        let c = candidates("Wo");
+        assert_eq!(c[0], "我");
+        assert!(candidates("q").is_empty());
+    }
+}
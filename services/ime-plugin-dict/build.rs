@@ -0,0 +1,90 @@
+// Compiles the plain-text word list in `dict/` into a front-coded dictionary image.
+//
+// The word list is ordered by frequency (most frequent first), which keeps it easy to
+// maintain by hand. The image that is embedded in the plugin is sorted alphabetically and
+// front-coded, so a prefix lookup only has to touch a handful of restart blocks.
+//
+// Image layout (all integers little-endian):
+//   magic:          b"FCD1"
+//   entries:        u32
+//   restart:        u32, number of entries per restart block
+//   blocks:         u32
+//   block offsets:  u32 * blocks, offset of each block relative to the start of entry data
+//   entry data:     [prefix_len: u8, suffix_len: u8, suffix: [u8; suffix_len], freq: u8] * entries
+//
+// The first entry of every block has a prefix_len of 0, so any block can be decoded on its own.
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+const RESTART_INTERVAL: usize = 16;
+
+fn main() {
+    let src = Path::new("dict").join("en.txt");
+    println!("cargo:rerun-if-changed={}", src.display());
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let mut text = String::new();
+    File::open(&src)
+        .expect("couldn't open word list")
+        .read_to_string(&mut text)
+        .expect("couldn't read word list");
+
+    let words: Vec<String> = text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_lowercase())
+        .collect();
+
+    // rank -> frequency score on a log scale, so the top few hundred words are well separated
+    // and the long tail still sorts above "unknown". The score is never 0.
+    let n = words.len() as f64;
+    let mut entries: Vec<(String, u8)> = Vec::new();
+    for (rank, word) in words.iter().enumerate() {
+        if word.len() > u8::MAX as usize {
+            panic!("word too long for dictionary image: {}", word);
+        }
+        if entries.iter().any(|(w, _)| w == word) {
+            continue; // keep the higher-ranked copy of any duplicate
+        }
+        let score = 255.0 * (1.0 - ((rank + 1) as f64).ln() / (n + 1.0).ln());
+        entries.push((word.clone(), (score as u8).max(1)));
+    }
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    let mut data: Vec<u8> = Vec::new();
+    let mut offsets: Vec<u32> = Vec::new();
+    let mut prev: &[u8] = &[];
+    for (i, (word, freq)) in entries.iter().enumerate() {
+        let w = word.as_bytes();
+        let prefix_len = if i % RESTART_INTERVAL == 0 {
+            offsets.push(data.len() as u32);
+            0
+        } else {
+            prev.iter().zip(w.iter()).take_while(|(a, b)| a == b).count()
+        };
+        data.push(prefix_len as u8);
+        data.push((w.len() - prefix_len) as u8);
+        data.extend_from_slice(&w[prefix_len..]);
+        data.push(*freq);
+        prev = w;
+    }
+
+    let mut image: Vec<u8> = Vec::new();
+    image.extend_from_slice(b"FCD1");
+    image.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    image.extend_from_slice(&(RESTART_INTERVAL as u32).to_le_bytes());
+    image.extend_from_slice(&(offsets.len() as u32).to_le_bytes());
+    for o in offsets.iter() {
+        image.extend_from_slice(&o.to_le_bytes());
+    }
+    image.extend_from_slice(&data);
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("en.fcd");
+    File::create(&out)
+        .expect("couldn't create dictionary image")
+        .write_all(&image)
+        .expect("couldn't write dictionary image");
+}
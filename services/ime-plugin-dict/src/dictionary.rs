@@ -0,0 +1,142 @@
+/// Reader for the front-coded dictionary image generated by `build.rs`.
+///
+/// The image is sorted, so completions for a prefix are found by binary searching the
+/// restart blocks for the first block that could contain the prefix, and then decoding
+/// forward until the words no longer share the prefix.
+pub struct FrontCodedDict<'a> {
+    entries: usize,
+    restart: usize,
+    offsets: &'a [u8],
+    data: &'a [u8],
+}
+
+const MAGIC: &[u8; 4] = b"FCD1";
+const HEADER_LEN: usize = 16;
+
+fn read_u32(slice: &[u8], offset: usize) -> usize {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&slice[offset..offset + 4]);
+    u32::from_le_bytes(b) as usize
+}
+
+impl<'a> FrontCodedDict<'a> {
+    /// Returns `None` if the image is not a valid dictionary.
+    pub fn new(image: &'a [u8]) -> Option<FrontCodedDict<'a>> {
+        if image.len() < HEADER_LEN || &image[..4] != MAGIC {
+            return None;
+        }
+        let entries = read_u32(image, 4);
+        let restart = read_u32(image, 8);
+        let blocks = read_u32(image, 12);
+        if restart == 0 || image.len() < HEADER_LEN + blocks * 4 {
+            return None;
+        }
+        Some(FrontCodedDict {
+            entries,
+            restart,
+            offsets: &image[HEADER_LEN..HEADER_LEN + blocks * 4],
+            data: &image[HEADER_LEN + blocks * 4..],
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+    fn blocks(&self) -> usize {
+        self.offsets.len() / 4
+    }
+    /// The first word of a block is stored in full, so it can be compared without decoding anything else.
+    fn block_head(&self, block: usize) -> &'a [u8] {
+        let offset = read_u32(self.offsets, block * 4);
+        let suffix_len = self.data[offset + 1] as usize;
+        &self.data[offset + 2..offset + 2 + suffix_len]
+    }
+    /// Calls `f` with every word that starts with `prefix` and its frequency score (1-255, higher is
+    /// more frequent). `prefix` is expected to be lower case. Words are visited in sorted order.
+    pub fn completions<F>(&self, prefix: &str, mut f: F)
+    where
+        F: FnMut(&str, u8),
+    {
+        let p = prefix.as_bytes();
+        if self.blocks() == 0 {
+            return;
+        }
+        // find the last block whose head sorts at or before the prefix
+        let mut lo = 0;
+        let mut hi = self.blocks();
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if self.block_head(mid) <= p {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let mut word: Vec<u8> = Vec::new();
+        let mut offset = read_u32(self.offsets, lo * 4);
+        let mut index = lo * self.restart;
+        while index < self.entries && offset + 2 <= self.data.len() {
+            let prefix_len = self.data[offset] as usize;
+            let suffix_len = self.data[offset + 1] as usize;
+            word.truncate(prefix_len);
+            word.extend_from_slice(&self.data[offset + 2..offset + 2 + suffix_len]);
+            let freq = self.data[offset + 2 + suffix_len];
+            offset += 3 + suffix_len;
+            index += 1;
+
+            if word.starts_with(p) {
+                if let Ok(w) = core::str::from_utf8(&word) {
+                    f(w, freq);
+                }
+            } else if word.as_slice() > p {
+                // sorted order: once we're past the prefix, nothing further can match
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    static IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/en.fcd"));
+
+    #[test]
+    fn test_image_valid() {
+        let dict = FrontCodedDict::new(IMAGE).expect("image header is invalid");
+        assert!(dict.len() > 0);
+        let mut count = 0;
+        dict.completions("", |_, _| count += 1);
+        assert_eq!(count, dict.len(), "full scan did not visit every entry");
+    }
+    #[test]
+    fn test_completions_sorted_and_prefixed() {
+        let dict = FrontCodedDict::new(IMAGE).unwrap();
+        let mut words: Vec<String> = Vec::new();
+        dict.completions("wh", |w, _| words.push(w.to_string()));
+        assert!(words.contains(&"which".to_string()));
+        assert!(words.contains(&"where".to_string()));
+        assert!(words.iter().all(|w| w.starts_with("wh")));
+        let mut sorted = words.clone();
+        sorted.sort();
+        assert_eq!(words, sorted);
+    }
+    #[test]
+    fn test_frequency_ranking() {
+        let dict = FrontCodedDict::new(IMAGE).unwrap();
+        let mut the = 0;
+        let mut therefore = 0;
+        dict.completions("the", |w, f| {
+            if w == "the" { the = f }
+            if w == "therefore" { therefore = f }
+        });
+        assert!(the > therefore, "common words should outrank rare ones");
+    }
+    #[test]
+    fn test_no_match() {
+        let dict = FrontCodedDict::new(IMAGE).unwrap();
+        let mut count = 0;
+        dict.completions("zzzq", |_, _| count += 1);
+        assert_eq!(count, 0);
+        assert!(FrontCodedDict::new(b"bogus").is_none());
+    }
+}
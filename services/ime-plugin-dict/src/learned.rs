@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+/// dictionary in the PDDB where the plugin keeps its state
+pub const IME_DICT_DICTIONARY: &str = "ime.dict";
+/// key holding the learned words, stored as `word\tcount\n` records
+pub const IME_DICT_LEARNED_KEY: &str = "learned";
+/// the learned list is capped so the key stays small; the least-used words are evicted first
+const MAX_LEARNED_WORDS: usize = 512;
+/// words shorter than this are not worth predicting
+pub const MIN_WORD_LEN: usize = 2;
+
+/// Words the user has typed, with the number of times each was used. These are loaded
+/// from and written back to the PDDB, so they survive reboots; the list is only available
+/// once the PDDB is mounted, until then the plugin predicts from the built-in dictionary only.
+/// Changes are only written out by `flush()`, so a burst of typing costs one PDDB write.
+pub struct LearnedWords {
+    words: HashMap<String, u32>,
+    /// most recent learned word, so an unpick can undo it
+    last: Option<String>,
+    /// set when `words` has changed since it was last written out
+    dirty: bool,
+    pddb: Option<pddb::Pddb>,
+}
+
+impl LearnedWords {
+    pub fn new() -> LearnedWords {
+        LearnedWords {
+            words: HashMap::new(),
+            last: None,
+            dirty: false,
+            pddb: None,
+        }
+    }
+    pub fn is_loaded(&self) -> bool {
+        self.pddb.is_some()
+    }
+    /// Loads the learned words from the PDDB. Must only be called once the PDDB is mounted.
+    pub fn load(&mut self) {
+        let mut pddb = pddb::Pddb::new();
+        match pddb.get(IME_DICT_DICTIONARY, IME_DICT_LEARNED_KEY, None, false, false, None, None::<fn()>) {
+            Ok(mut key) => {
+                let mut text = String::new();
+                if key.read_to_string(&mut text).is_ok() {
+                    for line in text.lines() {
+                        let mut fields = line.split('\t');
+                        if let (Some(word), Some(count)) = (fields.next(), fields.next()) {
+                            if let Ok(count) = count.parse::<u32>() {
+                                // merge with anything learned before the PDDB was mounted
+                                *self.words.entry(word.to_string()).or_insert(0) += count;
+                            }
+                        }
+                    }
+                } else {
+                    log::warn!("learned word list is corrupt, starting a new one");
+                }
+            }
+            Err(e) => log::info!("no learned words yet ({:?})", e.kind()),
+        }
+        log::info!("{} learned words loaded", self.words.len());
+        self.pddb = Some(pddb);
+    }
+    /// Writes the learned words to the PDDB, if they've changed. Changes made before the PDDB is
+    /// mounted are kept until it is.
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Some(pddb) = self.pddb.as_mut() {
+            let mut text = String::new();
+            for (word, count) in self.words.iter() {
+                text.push_str(word);
+                text.push('\t');
+                text.push_str(&count.to_string());
+                text.push('\n');
+            }
+            // delete and re-create, so a shrinking list doesn't leave stale records at the end of the key
+            pddb.delete_key(IME_DICT_DICTIONARY, IME_DICT_LEARNED_KEY, None).ok();
+            match pddb.get(IME_DICT_DICTIONARY, IME_DICT_LEARNED_KEY, None, true, true, Some(text.len()), None::<fn()>) {
+                Ok(mut key) => {
+                    if key.write_all(text.as_bytes()).is_err() {
+                        log::error!("couldn't write learned words");
+                    }
+                }
+                Err(e) => log::error!("couldn't open learned word key: {:?}", e),
+            }
+            pddb.sync().ok();
+            self.dirty = false;
+        }
+    }
+    /// Records a word the user typed. Returns true if the word was accepted.
+    pub fn learn(&mut self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        if word.chars().count() < MIN_WORD_LEN || !word.chars().all(|c| c.is_alphabetic() || c == '\'') {
+            return false;
+        }
+        *self.words.entry(word.clone()).or_insert(0) += 1;
+        if self.words.len() > MAX_LEARNED_WORDS {
+            let evict = self.words.iter()
+                .filter(|(w, _)| **w != word)
+                .min_by_key(|(_, &c)| c)
+                .map(|(w, _)| w.clone());
+            if let Some(evict) = evict {
+                self.words.remove(&evict);
+            }
+        }
+        self.last = Some(word);
+        self.dirty = true;
+        true
+    }
+    /// Reverses the most recent `learn()`. Repeated calls do nothing.
+    pub fn unlearn_last(&mut self) {
+        if let Some(word) = self.last.take() {
+            if let Some(count) = self.words.get_mut(&word) {
+                if *count <= 1 {
+                    self.words.remove(&word);
+                } else {
+                    *count -= 1;
+                }
+            }
+            self.dirty = true;
+        }
+    }
+    /// Calls `f` with every learned word that starts with `prefix`, and its use count.
+    pub fn completions<F>(&self, prefix: &str, mut f: F)
+    where
+        F: FnMut(&str, u32),
+    {
+        for (word, &count) in self.words.iter() {
+            if word.starts_with(prefix) {
+                f(word, count);
+            }
+        }
+    }
+}
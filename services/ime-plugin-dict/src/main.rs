@@ -0,0 +1,166 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod dictionary;
+use dictionary::*;
+mod learned;
+use learned::*;
+
+use ime_plugin_api::*;
+
+use log::{error, info};
+
+use xous_ipc::{String, Buffer};
+use num_traits::FromPrimitive;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// the compressed word list, generated by build.rs from dict/en.txt
+static DICT_IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/en.fcd"));
+
+/// number of ranked completions we keep around for the frontend to query
+const MAX_PREDICTIONS: usize = 8;
+/// a learned word gets this much score per use, so words the user actually types
+/// quickly climb above dictionary words of similar frequency
+const LEARNED_WEIGHT: u32 = 48;
+/// how often the learned words are written back to the PDDB, if they've changed
+const LEARNED_FLUSH_INTERVAL_MS: usize = 30_000;
+
+/// Re-applies the capitalization of what the user typed to a (lower case) completion.
+fn match_case(input: &str, word: &str) -> std::string::String {
+    let mut chars = input.chars();
+    let first_upper = chars.next().map(|c| c.is_uppercase()).unwrap_or(false);
+    let all_upper = first_upper && input.chars().count() > 1 && input.chars().all(|c| !c.is_lowercase());
+    if all_upper {
+        word.to_uppercase()
+    } else if first_upper {
+        let mut wc = word.chars();
+        match wc.next() {
+            Some(f) => f.to_uppercase().chain(wc).collect(),
+            None => std::string::String::new(),
+        }
+    } else {
+        word.to_string()
+    }
+}
+
+/// Computes the ranked completions for `input`, best first.
+fn rank(dict: &FrontCodedDict, learned: &LearnedWords, input: &str) -> Vec<std::string::String> {
+    let prefix = input.to_lowercase();
+    if prefix.chars().count() == 0 {
+        return Vec::new();
+    }
+    let mut scored: std::collections::HashMap<std::string::String, u32> = std::collections::HashMap::new();
+    dict.completions(&prefix, |w, freq| {
+        scored.insert(w.to_string(), freq as u32);
+    });
+    learned.completions(&prefix, |w, count| {
+        *scored.entry(w.to_string()).or_insert(0) += count.saturating_mul(LEARNED_WEIGHT);
+    });
+    // there is nothing to complete if the word is already typed out in full
+    scored.remove(&prefix);
+
+    let mut ranked: Vec<(std::string::String, u32)> = scored.into_iter().collect();
+    // ties are broken by the shorter word, then alphabetically, so the ordering is stable
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.len().cmp(&b.0.len())).then(a.0.cmp(&b.0)));
+    ranked.truncate(MAX_PREDICTIONS);
+    ranked.into_iter().map(|(w, _)| match_case(input, &w)).collect()
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    // one connection only, should be the IME front end
+    let ime_dict_sid = xns.register_name(ime_plugin_dict::SERVER_NAME_IME_PLUGIN_DICT, Some(1)).expect("can't register server");
+    log::trace!("registered with NS -- {:?}", ime_dict_sid);
+
+    let dict = FrontCodedDict::new(DICT_IMAGE).expect("built-in dictionary image is corrupt");
+    info!("dictionary has {} words", dict.len());
+    let learned = Arc::new(Mutex::new(LearnedWords::new()));
+    // learned words are written back on a timer rather than after every word, so that typing
+    // doesn't turn into a stream of PDDB commits
+    thread::spawn({
+        let learned = learned.clone();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                tt.sleep_ms(LEARNED_FLUSH_INTERVAL_MS).ok();
+                learned.lock().unwrap().flush();
+            }
+        }
+    });
+    // the plugin starts before the PDDB is mounted; learned words are pulled in on the first
+    // request after the mount happens, so we never block the IMEF waiting on the PDDB.
+    let pddb_poller = pddb::PddbMountPoller::new();
+
+    let mut predictions: Vec<std::string::String> = Vec::new();
+
+    // word-level predictions: the front end sends us the word in progress, and the finished word on a trigger
+    let mytriggers = PredictionTriggers {
+        newline: false,
+        punctuation: true,
+        whitespace: true,
+    };
+
+    info!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(ime_dict_sid).unwrap();
+        log::trace!("received message {:?}", msg);
+        let mut learned = learned.lock().unwrap();
+        if !learned.is_loaded() && pddb_poller.is_mounted_nonblocking() {
+            learned.load();
+        }
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::Input) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                predictions = rank(&dict, &learned, s.as_str());
+                log::trace!("input {} -> {:?}", s.as_str(), predictions);
+            }
+            Some(Opcode::Picked) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let s = buffer.as_flat::<String::<4000>, _>().unwrap();
+                log::trace!("picked {}", s.as_str());
+                learned.learn(s.as_str().trim());
+                // the word is finished, so the old completions no longer apply
+                predictions.clear();
+            }
+            Some(Opcode::Prediction) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut prediction: Prediction = buffer.to_original::<Prediction, _>().unwrap();
+                if let Some(p) = predictions.get(prediction.index as usize) {
+                    prediction.string.clear();
+                    for ch in p.chars() {
+                        if prediction.string.push(ch).is_err() {
+                            break;
+                        }
+                    }
+                    prediction.valid = true;
+                } else {
+                    prediction.valid = false;
+                }
+                buffer.replace(Return::Prediction(prediction)).expect("couldn't return Prediction");
+            }
+            Some(Opcode::Unpick) => {
+                learned.unlearn_last();
+            }
+            Some(Opcode::GetPredictionTriggers) => {
+                xous::return_scalar(msg.sender, mytriggers.into()).expect("couldn't return GetPredictionTriggers");
+            }
+            Some(Opcode::Quit) => {
+                learned.flush();
+                error!("received quit, goodbye!"); break;
+            }
+            None => {error!("unknown Opcode");}
+        }
+    }
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(ime_dict_sid).unwrap();
+    xous::destroy_server(ime_dict_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
@@ -19,20 +19,14 @@ fn xmain() -> ! {
     let ime_sh_sid = xns.register_name(ime_plugin_shell::SERVER_NAME_IME_PLUGIN_SHELL, Some(1)).expect("can't register server");
     log::trace!("registered with NS -- {:?}", ime_sh_sid);
 
-    let mut history: Vec<String<64>> = Vec::new();
+    // long-term, searchable history is kept by the IME front end; this is just the last few lines, for quick recall
+    let mut history: Vec<std::string::String> = Vec::new();
     let history_max = 4;
 
     if false { // loads defaults into the predictor array to test things
-        use core::fmt::Write as CoreWriter;
-        let mut test1: String::<64> = String::new();
-        write!(test1, "This〰should overflow the box").unwrap();
-        history.push(test1);
-        let mut test2: String::<64> = String::new();
-        write!(test2, "Another string too long").unwrap();
-        history.push(test2);
-        let mut test3: String::<64> = String::new();
-        write!(test3, "未雨绸缪").unwrap();
-        history.push(test3);
+        history.push("This〰should overflow the box".to_string());
+        history.push("Another string too long".to_string());
+        history.push("未雨绸缪".to_string());
     }
 
     let mytriggers = PredictionTriggers {
@@ -53,11 +47,8 @@ fn xmain() -> ! {
             Some(Opcode::Picked) => {
                 let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                 let s = buffer.as_flat::<String::<4000>, _>().unwrap();
-                // the API allows for large picked feedback, but this implementation only keeps the first 64 characters
-                let mut local_s: String<64> = String::new();
-                use core::fmt::Write;
-                write!(local_s, "{}", s.as_str()).expect("overflowed history variable");
-                log::trace!("storing history value | {}", s.as_str());
+                let local_s = s.as_str().to_string();
+                log::trace!("storing history value | {}", local_s);
                 if history.len() == history_max {
                     history.remove(0);
                 }
@@ -75,14 +66,14 @@ fn xmain() -> ! {
                         index = history.len() as u32 - 1;
                     }
                     let mut i = 1;
-                    for &s in history.iter() {
+                    for s in history.iter() {
                         // iterator is from oldest to newest, so do some math to go from newest to oldest
                         // TIL: there is a .rev() feature in iterators. Next time maybe use that instead.
                         if (history.len() as u32 - i) == index {
                             // decompose the string into a character-by-character sequence
                             // and then stuff byte-by-byte, as fits, into the return array
                             prediction.string.clear();
-                            for ch in s.as_str().unwrap().chars() {
+                            for ch in s.chars() {
                                 if let Ok(_) = prediction.string.push(ch) {
                                     // it's ok, carry on.
                                 } else {
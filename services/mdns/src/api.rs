@@ -0,0 +1,70 @@
+#[allow(dead_code)]
+pub(crate) const SERVER_NAME_MDNS: &str = "_mDNS responder_";
+use rkyv::{Archive, Deserialize, Serialize};
+use xous_ipc::String;
+
+/// longest DNS label, and so the longest host name or service instance name
+pub(crate) const MDNS_LABEL_LIMIT: usize = 63;
+/// longest service type, such as `_http._tcp`
+pub(crate) const MDNS_SERVICE_LIMIT: usize = 32;
+/// most `key=value` entries in a registered service's TXT record
+pub(crate) const MDNS_TXT_ENTRIES: usize = 8;
+/// longest single `key=value` entry
+pub(crate) const MDNS_TXT_LENGTH: usize = 64;
+/// most services that can be registered at once
+#[allow(dead_code)]
+pub(crate) const MDNS_MAX_SERVICES: usize = 8;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+#[repr(C)]
+pub(crate) enum Opcode {
+    /// Sets the host name advertised as `<name>.local`, and probes for it again. A `Buffer` lend
+    /// of a `String<MDNS_LABEL_LIMIT>`.
+    SetHostname = 0,
+
+    /// The host name in use, which differs from the one that was set if another host on the link
+    /// already had it, and is empty until there is a name to use. A `Buffer` mutable lend of a
+    /// `String<MDNS_LABEL_LIMIT>`.
+    GetHostname = 1,
+
+    /// Advertises a service. A `Buffer` mutable lend of an `MdnsService`; `code` is set on return.
+    Register = 2,
+
+    /// Withdraws a service, matched by its instance name and service type. A `Buffer` lend of an
+    /// `MdnsService`.
+    Unregister = 3,
+
+    /// Sends goodbyes for everything advertised, and exits the server.
+    Quit = 4,
+
+    /// Starts (arg1 = 1) or stops (arg1 = 0) answering and advertising on the network. The
+    /// responder is stopped until this is called, and sends goodbyes when it is stopped. A
+    /// BlockingScalar that returns 0.
+    SetEnabled = 5,
+
+    /// Internal: a `com::WlanStatusIpc` from the net server's wifi state subscription, which is how
+    /// we learn our address. A `Buffer` lend.
+    WifiState = 6,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Serialize, Deserialize)]
+pub(crate) enum RegisterCode {
+    Ok,
+    /// an instance of that name is already registered for the service type
+    Duplicate,
+    /// `MDNS_MAX_SERVICES` are already registered
+    TableFull,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Archive, Serialize, Deserialize)]
+pub(crate) struct MdnsService {
+    /// the user-visible instance name, such as `Precursor Web`
+    pub instance: String<MDNS_LABEL_LIMIT>,
+    /// the service type, such as `_http._tcp`
+    pub service: String<MDNS_SERVICE_LIMIT>,
+    pub port: u16,
+    pub txt: [Option<String<MDNS_TXT_LENGTH>>; MDNS_TXT_ENTRIES],
+    pub code: RegisterCode,
+}
@@ -0,0 +1,312 @@
+pub mod api;
+use api::*;
+#[allow(dead_code)]
+mod packet;
+use packet::*;
+
+use num_traits::*;
+use std::io::{self, Error, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+use xous::{send_message, Message, CID};
+use xous_ipc::{Buffer, String};
+
+/// A service found by `Mdns::browse()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceInstance {
+    /// the instance name, such as `Printer Page`
+    pub name: std::string::String,
+    /// the service type that was browsed for, such as `_http._tcp`
+    pub service: std::string::String,
+    /// the host the service runs on, such as `printer.local`
+    pub host: std::string::String,
+    /// the host's address, if it answered in time
+    pub addr: Option<Ipv4Addr>,
+    pub port: u16,
+    /// the `key=value` entries of the service's TXT record
+    pub txt: Vec<std::string::String>,
+}
+
+/// How often a browse or host lookup asks again for answers it hasn't had yet
+const QUERY_INTERVAL: Duration = Duration::from_millis(1000);
+
+#[derive(Debug)]
+pub struct Mdns {
+    conn: CID,
+    trng: trng::Trng,
+}
+impl Mdns {
+    pub fn new(xns: &xous_names::XousNames) -> Result<Self, xous::Error> {
+        REFCOUNT.fetch_add(1, Ordering::Relaxed);
+        let conn = xns.request_connection_blocking(api::SERVER_NAME_MDNS).expect("Can't connect to mDNS server");
+        Ok(Mdns {
+            conn,
+            trng: trng::Trng::new(xns)?,
+        })
+    }
+
+    /// Starts or stops the responder. Nothing is answered or advertised on the network until it
+    /// is started, so a device doesn't announce itself unless an app or a setting asks it to.
+    /// Stopping it withdraws everything that was advertised; names and services are kept for the
+    /// next start.
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), xous::Error> {
+        send_message(
+            self.conn,
+            Message::new_blocking_scalar(Opcode::SetEnabled.to_usize().unwrap(), enabled as usize, 0, 0, 0),
+        )
+        .map(|_| ())
+    }
+    /// Sets the name the device answers to as `<hostname>.local`. The name is a single label: at
+    /// most 63 bytes, and no dots. Until a name is set, the device uses `precursor-` followed by
+    /// the end of its MAC address, such as `precursor-a1b2c3`.
+    pub fn set_hostname(&self, hostname: &str) -> Result<(), xous::Error> {
+        if hostname.is_empty() || hostname.len() > MDNS_LABEL_LIMIT || hostname.contains('.') {
+            return Err(xous::Error::InvalidString);
+        }
+        let buf = Buffer::into_buf(String::<MDNS_LABEL_LIMIT>::from_str(hostname))
+            .or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetHostname.to_u32().unwrap()).map(|_| ())
+    }
+    /// The host name in use, without `.local`. If another device already had the name that was
+    /// set, this is the name that was picked instead (`vault-2`, and so on). Empty if no name was
+    /// set and the MAC address isn't known yet.
+    pub fn hostname(&self) -> Result<std::string::String, xous::Error> {
+        let mut buf = Buffer::into_buf(String::<MDNS_LABEL_LIMIT>::new())
+            .or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::GetHostname.to_u32().unwrap())?;
+        let hostname = buf.to_original::<String<MDNS_LABEL_LIMIT>, _>()
+            .or(Err(xous::Error::InternalError))?;
+        Ok(hostname.as_str().or(Err(xous::Error::InvalidString))?.to_string())
+    }
+    /// Advertises a service running on this device, such as a `StdTcpListen` server on `port`.
+    /// `service` is the DNS-SD service type, such as `_http._tcp`, and `txt` holds `key=value`
+    /// entries describing it. If another device already advertises `instance`, a number is added
+    /// to the end of the name. The service goes out once the responder is enabled.
+    pub fn register(&self, instance: &str, service: &str, port: u16, txt: &[&str]) -> Result<(), xous::Error> {
+        let mut svc = to_ipc(instance, service)?;
+        if txt.len() > MDNS_TXT_ENTRIES || txt.iter().any(|t| t.is_empty() || t.len() > MDNS_TXT_LENGTH) {
+            return Err(xous::Error::InvalidString);
+        }
+        svc.port = port;
+        for (dst, src) in svc.txt.iter_mut().zip(txt.iter()) {
+            *dst = Some(String::<MDNS_TXT_LENGTH>::from_str(src));
+        }
+        let mut buf = Buffer::into_buf(svc).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::Register.to_u32().unwrap())?;
+        match buf.to_original::<MdnsService, _>().or(Err(xous::Error::InternalError))?.code {
+            RegisterCode::Ok => Ok(()),
+            RegisterCode::Duplicate => Err(xous::Error::ServerExists),
+            RegisterCode::TableFull => Err(xous::Error::OutOfMemory),
+        }
+    }
+    /// Withdraws a service added with `register()`, telling the network it's gone.
+    pub fn unregister(&self, instance: &str, service: &str) -> Result<(), xous::Error> {
+        let buf = Buffer::into_buf(to_ipc(instance, service)?).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::Unregister.to_u32().unwrap()).map(|_| ())
+    }
+
+    /// Looks for instances of `service` (such as `_http._tcp`) on the local network, collecting
+    /// answers until `timeout` runs out. Only instances whose port was learned are returned.
+    /// Services registered on this device aren't seen, since our own multicasts don't loop back.
+    pub fn browse(&self, service: &str, timeout: Duration) -> io::Result<Vec<ServiceInstance>> {
+        let type_name = format!("{}.local", service.trim_end_matches('.'));
+        let querier = Querier::new(&self.trng)?;
+        let deadline = Instant::now() + timeout;
+        // PTR answers, by instance name
+        let mut found: Vec<(std::string::String, ServiceInstance)> = Vec::new();
+        // A answers, by host name
+        let mut hosts: Vec<(std::string::String, Ipv4Addr)> = Vec::new();
+        let mut next_query = Instant::now();
+        loop {
+            if Instant::now() >= next_query {
+                // ask for the list, and then for whatever is still missing about what's on it
+                let mut questions = vec![question(&type_name, TYPE_PTR)];
+                for (instance_name, instance) in found.iter() {
+                    if instance.host.is_empty() {
+                        questions.push(question(instance_name, TYPE_SRV));
+                        questions.push(question(instance_name, TYPE_TXT));
+                    } else if !hosts.iter().any(|(h, _)| name_eq(h, &instance.host)) {
+                        questions.push(question(&instance.host, TYPE_A));
+                    }
+                }
+                let mut query = Packet::query(questions);
+                // known-answer suppression: instances we've already heard of don't need repeating
+                for (instance_name, _) in found.iter() {
+                    query.answers.push(Record {
+                        name: type_name.clone(),
+                        cache_flush: false,
+                        ttl: 4500,
+                        data: RData::Ptr(instance_name.clone()),
+                    });
+                }
+                querier.send(&query)?;
+                next_query = Instant::now() + QUERY_INTERVAL;
+            }
+            let packet = match querier.recv(deadline) {
+                Some(Some(packet)) => packet,
+                Some(None) => continue,
+                None => break,
+            };
+            for r in packet.records() {
+                match &r.data {
+                    RData::Ptr(instance_name) if name_eq(&r.name, &type_name) => {
+                        if r.ttl == 0 {
+                            // a goodbye
+                            found.retain(|(n, _)| !name_eq(n, instance_name));
+                        } else if !found.iter().any(|(n, _)| name_eq(n, instance_name)) {
+                            found.push((
+                                instance_name.clone(),
+                                ServiceInstance {
+                                    name: instance_label(instance_name, &type_name),
+                                    service: service.to_string(),
+                                    host: std::string::String::new(),
+                                    addr: None,
+                                    port: 0,
+                                    txt: Vec::new(),
+                                },
+                            ));
+                        }
+                    }
+                    RData::A(addr) => hosts.push((r.name.clone(), *addr)),
+                    _ => {}
+                }
+            }
+            for (instance_name, instance) in found.iter_mut() {
+                for r in packet.records().filter(|r| name_eq(&r.name, instance_name)) {
+                    match &r.data {
+                        RData::Srv { port, target, .. } => {
+                            instance.port = *port;
+                            instance.host = target.clone();
+                        }
+                        RData::Txt(txt) => instance.txt = txt.clone(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(found
+            .into_iter()
+            .map(|(_, mut instance)| {
+                instance.addr = hosts.iter().find(|(h, _)| name_eq(h, &instance.host)).map(|(_, a)| *a);
+                instance
+            })
+            .filter(|instance| !instance.host.is_empty())
+            .collect())
+    }
+    /// Looks up the address of a host on the local network, such as `printer.local`. `.local` is
+    /// added if `name` doesn't end with it.
+    pub fn resolve_host(&self, name: &str, timeout: Duration) -> io::Result<Ipv4Addr> {
+        let name = name.trim_end_matches('.');
+        let name = if name.to_ascii_lowercase().ends_with(".local") {
+            name.to_string()
+        } else {
+            format!("{}.local", name)
+        };
+        let querier = Querier::new(&self.trng)?;
+        let deadline = Instant::now() + timeout;
+        let mut next_query = Instant::now();
+        loop {
+            if Instant::now() >= next_query {
+                querier.send(&Packet::query(vec![question(&name, TYPE_A)]))?;
+                next_query = Instant::now() + QUERY_INTERVAL;
+            }
+            let packet = match querier.recv(deadline) {
+                Some(Some(packet)) => packet,
+                Some(None) => continue,
+                None => break,
+            };
+            for r in packet.records() {
+                match r.data {
+                    RData::A(addr) if name_eq(&r.name, &name) => return Ok(addr),
+                    _ => {}
+                }
+            }
+        }
+        Err(Error::new(ErrorKind::TimedOut, format!("no answer for {}", name)))
+    }
+}
+
+fn to_ipc(instance: &str, service: &str) -> Result<MdnsService, xous::Error> {
+    // a service type is `_name._tcp` or `_name._udp`
+    let valid_service = service.len() <= MDNS_SERVICE_LIMIT
+        && service.starts_with('_')
+        && (service.ends_with("._tcp") || service.ends_with("._udp"))
+        && service.matches('.').count() == 1;
+    if instance.is_empty() || instance.len() > MDNS_LABEL_LIMIT || instance.contains('.') || !valid_service {
+        return Err(xous::Error::InvalidString);
+    }
+    Ok(MdnsService {
+        instance: String::<MDNS_LABEL_LIMIT>::from_str(instance),
+        service: String::<MDNS_SERVICE_LIMIT>::from_str(service),
+        port: 0,
+        txt: [None; MDNS_TXT_ENTRIES],
+        code: RegisterCode::Ok,
+    })
+}
+
+/// The instance label of a full instance name: `Printer Page` for
+/// `Printer Page._http._tcp.local`.
+fn instance_label(instance_name: &str, type_name: &str) -> std::string::String {
+    let split = instance_name.len().saturating_sub(type_name.len() + 1);
+    if split > 0 && instance_name.is_char_boundary(split) && name_eq(&instance_name[split + 1..], type_name) {
+        instance_name[..split].to_string()
+    } else {
+        instance_name.to_string()
+    }
+}
+
+fn question(name: &str, qtype: u16) -> Question {
+    Question {
+        name: name.to_string(),
+        qtype,
+        unicast: false,
+    }
+}
+
+/// A socket for one-shot (legacy unicast) mDNS queries. Sending from a port other than 5353
+/// gets the answers sent straight back to us, which is all a short browse needs, and leaves
+/// port 5353 to the responder.
+struct Querier {
+    socket: UdpSocket,
+}
+impl Querier {
+    fn new(trng: &trng::Trng) -> io::Result<Querier> {
+        let local_port = (49152 + trng.get_u32().map_err(|_| Error::new(ErrorKind::Other, "TRNG failed"))? % 16384) as u16;
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, local_port))?;
+        Ok(Querier { socket })
+    }
+    fn send(&self, query: &Packet) -> io::Result<()> {
+        self.socket.send_to(&query.to_bytes(), SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)).map(|_| ())
+    }
+    /// Waits for a response until `deadline`, or for a short while so the caller gets a chance
+    /// to send again. Returns `None` once the deadline has passed, and `Some(None)` if nothing
+    /// usable arrived.
+    fn recv(&self, deadline: Instant) -> Option<Option<Packet>> {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        let wait = (deadline - now).min(Duration::from_millis(100)).max(Duration::from_millis(1));
+        self.socket.set_read_timeout(Some(wait)).ok()?;
+        let mut buf = [0u8; 1500];
+        match self.socket.recv_from(&mut buf) {
+            Ok((len, SocketAddr::V4(_))) => Some(Packet::parse(&buf[..len]).filter(|p| p.is_response())),
+            _ => Some(None),
+        }
+    }
+}
+
+use core::sync::atomic::{AtomicU32, Ordering};
+static REFCOUNT: AtomicU32 = AtomicU32::new(0);
+impl Drop for Mdns {
+    fn drop(&mut self) {
+        // the connection to the server side must be reference counted, so that multiple instances of this object within
+        // a single process do not end up de-allocating the CID on other threads before they go out of scope.
+        // Note to future me: you want this. Don't get rid of it because you think, "nah, nobody will ever make more than one copy of this object".
+        if REFCOUNT.fetch_sub(1, Ordering::Relaxed) == 1 {
+            unsafe{xous::disconnect(self.conn).unwrap();}
+        }
+        // if there was object-specific state (such as a one-time use server for async callbacks, specific to the object instance),
+        // de-allocate those items here. They don't need a reference count because they are object-specific
+    }
+}
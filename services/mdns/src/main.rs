@@ -0,0 +1,1033 @@
+#![cfg_attr(target_os = "none", no_std)]
+#![cfg_attr(target_os = "none", no_main)]
+
+mod api;
+use api::*;
+#[allow(dead_code)]
+mod packet;
+use packet::*;
+
+use num_traits::*;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use xous::msg_blocking_scalar_unpack;
+use xous_ipc::{Buffer, String};
+
+/// start of the host name used until one is set; the end of the MAC address makes it per-device
+const DEVICE_NAME_PREFIX: &str = "precursor";
+/// the name DNS-SD browsers query to list every service type on the link
+const SERVICES_META_QUERY: &str = "_services._dns-sd._udp.local";
+
+/// TTL of records naming a host (A, SRV), per RFC 6762 section 10
+const HOST_TTL: u32 = 120;
+/// TTL of every other record
+const SERVICE_TTL: u32 = 4500;
+/// longest TTL allowed in a reply to a legacy (non-5353 port) querier
+const LEGACY_TTL: u32 = 10;
+
+const PROBE_COUNT: u32 = 3;
+const PROBE_INTERVAL_MS: u64 = 250;
+const ANNOUNCE_COUNT: u32 = 2;
+const ANNOUNCE_INTERVAL_MS: u64 = 1000;
+
+/// largest datagram we read; replies are split per service to stay below the MTU
+const MDNS_PKT_MAX_LEN: usize = 1500;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Service {
+    /// instance name as registered
+    name: std::string::String,
+    /// number of times the instance has been renamed to resolve a conflict
+    renames: u32,
+    /// service type, such as `_http._tcp`
+    service: std::string::String,
+    port: u16,
+    txt: Vec<std::string::String>,
+}
+impl Service {
+    fn instance(&self) -> std::string::String {
+        if self.renames == 0 {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.renames + 1)
+        }
+    }
+    fn type_name(&self) -> std::string::String {
+        format!("{}.local", self.service)
+    }
+    fn instance_name(&self) -> std::string::String {
+        format!("{}.{}.local", self.instance(), self.service)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    /// stopped, or missing an address or a name, so nothing to advertise
+    Idle,
+    /// `sent` probes are out, and the next step is due at `next`
+    Probing {
+        sent: u32,
+        next: u64,
+    },
+    Announcing {
+        sent: u32,
+        next: u64,
+    },
+    Running,
+}
+
+/// The responder's state. It doesn't do any I/O: received packets are passed to `handle()`, time
+/// is moved along with `tick()`, and whatever needs sending is left in the outbox. It stays quiet
+/// until `set_enabled()` turns it on.
+struct Responder {
+    /// the name set with `SetHostname`, if any
+    hostname: Option<std::string::String>,
+    /// the name used until one is set, made from our MAC address once it is known
+    device_name: Option<std::string::String>,
+    /// number of times the host name has been renamed to resolve a conflict
+    renames: u32,
+    addr: Option<Ipv4Addr>,
+    services: Vec<Service>,
+    enabled: bool,
+    state: State,
+    outbox: Vec<(Packet, SocketAddrV4)>,
+    quit: bool,
+}
+impl Responder {
+    fn new() -> Responder {
+        Responder {
+            hostname: None,
+            device_name: None,
+            renames: 0,
+            addr: None,
+            services: Vec::new(),
+            enabled: false,
+            state: State::Idle,
+            outbox: Vec::new(),
+            quit: false,
+        }
+    }
+    fn base_name(&self) -> Option<&str> {
+        self.hostname.as_deref().or(self.device_name.as_deref())
+    }
+    /// The host label in use, after any renames, or an empty string if we have no name yet.
+    fn host(&self) -> std::string::String {
+        match self.base_name() {
+            None => std::string::String::new(),
+            Some(name) if self.renames == 0 => name.to_string(),
+            Some(name) => format!("{}-{}", name, self.renames + 1),
+        }
+    }
+    fn host_name(&self) -> std::string::String {
+        format!("{}.local", self.host())
+    }
+    fn advertising(&self) -> bool {
+        matches!(self.state, State::Announcing { .. } | State::Running)
+    }
+
+    /// When `tick()` next has something to do, if anything.
+    fn next_deadline(&self) -> Option<u64> {
+        match self.state {
+            State::Probing { next, .. } | State::Announcing { next, .. } => Some(next),
+            State::Idle | State::Running => None,
+        }
+    }
+
+    /// Probes for everything again, after a change to what we advertise.
+    fn restart(&mut self, now: u64) {
+        self.state = if self.enabled && self.addr.is_some() && self.base_name().is_some() {
+            State::Probing { sent: 0, next: now }
+        } else {
+            State::Idle
+        };
+    }
+    /// Starts answering and advertising, or says goodbye to everything and goes quiet.
+    fn set_enabled(&mut self, enabled: bool, now: u64) {
+        if enabled == self.enabled {
+            return;
+        }
+        if !enabled && self.advertising() {
+            for packet in self.announcements(0) {
+                self.outbox
+                    .push((packet, SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)));
+            }
+        }
+        self.enabled = enabled;
+        self.restart(now);
+    }
+    fn set_hostname(&mut self, hostname: &str, now: u64) {
+        if self.advertising() {
+            self.send_multicast(self.host_records(0));
+        }
+        self.hostname = Some(hostname.to_string());
+        self.renames = 0;
+        self.restart(now);
+    }
+    /// Names the device after the end of its MAC address, for use until a host name is set.
+    fn set_mac(&mut self, mac: [u8; 6], now: u64) {
+        let device_name = format!(
+            "{}-{:02x}{:02x}{:02x}",
+            DEVICE_NAME_PREFIX, mac[3], mac[4], mac[5]
+        );
+        if self.device_name.as_ref() != Some(&device_name) {
+            self.device_name = Some(device_name);
+            if self.hostname.is_none() {
+                self.renames = 0;
+                self.restart(now);
+            }
+        }
+    }
+    fn set_addr(&mut self, addr: Option<Ipv4Addr>, now: u64) {
+        if addr != self.addr {
+            log::info!("address changed to {:?}", addr);
+            self.addr = addr;
+            self.restart(now);
+        }
+    }
+    fn register(&mut self, service: Service, now: u64) -> RegisterCode {
+        if self.services.iter().any(|s| {
+            s.name.eq_ignore_ascii_case(&service.name)
+                && s.service.eq_ignore_ascii_case(&service.service)
+        }) {
+            return RegisterCode::Duplicate;
+        }
+        if self.services.len() >= MDNS_MAX_SERVICES {
+            return RegisterCode::TableFull;
+        }
+        self.services.push(service);
+        self.restart(now);
+        RegisterCode::Ok
+    }
+    fn unregister(&mut self, name: &str, service: &str) {
+        if let Some(i) = self.services.iter().position(|s| {
+            s.name.eq_ignore_ascii_case(name) && s.service.eq_ignore_ascii_case(service)
+        }) {
+            let removed = self.services.remove(i);
+            if self.advertising() {
+                self.send_multicast(self.service_records(&removed, 0));
+            }
+        }
+    }
+    /// Says goodbye to everything we advertise, and flags the network thread to exit once it has
+    /// sent the goodbyes.
+    fn quit(&mut self) {
+        if self.advertising() {
+            for packet in self.announcements(0) {
+                self.outbox
+                    .push((packet, SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)));
+            }
+        }
+        self.state = State::Idle;
+        self.quit = true;
+    }
+    fn take_outbox(&mut self) -> Vec<(Packet, SocketAddrV4)> {
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn send_multicast(&mut self, records: Vec<Record>) {
+        self.outbox.push((
+            Packet::response(records, Vec::new()),
+            SocketAddrV4::new(MDNS_GROUP, MDNS_PORT),
+        ));
+    }
+
+    /// Our A record, or nothing if we have no address. A `ttl` of 0 makes it a goodbye.
+    fn host_records(&self, ttl: u32) -> Vec<Record> {
+        self.addr
+            .map(|addr| Record {
+                name: self.host_name(),
+                cache_flush: true,
+                ttl: ttl.min(HOST_TTL),
+                data: RData::A(addr),
+            })
+            .into_iter()
+            .collect()
+    }
+    fn type_ptr(&self, service: &Service, ttl: u32) -> Record {
+        Record {
+            name: service.type_name(),
+            cache_flush: false,
+            ttl: ttl.min(SERVICE_TTL),
+            data: RData::Ptr(service.instance_name()),
+        }
+    }
+    fn srv(&self, service: &Service, ttl: u32) -> Record {
+        Record {
+            name: service.instance_name(),
+            cache_flush: true,
+            ttl: ttl.min(HOST_TTL),
+            data: RData::Srv {
+                priority: 0,
+                weight: 0,
+                port: service.port,
+                target: self.host_name(),
+            },
+        }
+    }
+    fn txt(&self, service: &Service, ttl: u32) -> Record {
+        Record {
+            name: service.instance_name(),
+            cache_flush: true,
+            ttl: ttl.min(SERVICE_TTL),
+            data: RData::Txt(service.txt.clone()),
+        }
+    }
+    /// The service's entry in the list of service types on the link.
+    fn meta_ptr(&self, service: &Service, ttl: u32) -> Record {
+        Record {
+            name: SERVICES_META_QUERY.to_string(),
+            cache_flush: false,
+            ttl: ttl.min(SERVICE_TTL),
+            data: RData::Ptr(service.type_name()),
+        }
+    }
+    /// All the records for one service.
+    fn service_records(&self, service: &Service, ttl: u32) -> Vec<Record> {
+        vec![
+            self.meta_ptr(service, ttl),
+            self.type_ptr(service, ttl),
+            self.srv(service, ttl),
+            self.txt(service, ttl),
+        ]
+    }
+    /// Everything we advertise, one packet for the host and one per service.
+    fn announcements(&self, ttl: u32) -> Vec<Packet> {
+        let mut packets = vec![Packet::response(self.host_records(ttl), Vec::new())];
+        for service in self.services.iter() {
+            packets.push(Packet::response(
+                self.service_records(service, ttl),
+                Vec::new(),
+            ));
+        }
+        packets
+    }
+    /// Asks whether anyone else is using our names, proposing the records we'd use them for in
+    /// the authority section (RFC 6762 section 8.1).
+    fn probe(&self) -> Packet {
+        let mut questions = vec![Question {
+            name: self.host_name(),
+            qtype: TYPE_ANY,
+            unicast: true,
+        }];
+        let mut authority = self.host_records(HOST_TTL);
+        for service in self.services.iter() {
+            questions.push(Question {
+                name: service.instance_name(),
+                qtype: TYPE_ANY,
+                unicast: true,
+            });
+            authority.push(self.srv(service, HOST_TTL));
+            authority.push(self.txt(service, SERVICE_TTL));
+        }
+        let mut packet = Packet::query(questions);
+        packet.authority = authority;
+        packet
+    }
+
+    /// Sends whatever probes and announcements are due by `now`.
+    fn tick(&mut self, now: u64) {
+        match self.state {
+            State::Probing { sent, next } if next <= now => {
+                if sent < PROBE_COUNT {
+                    self.outbox
+                        .push((self.probe(), SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)));
+                    self.state = State::Probing {
+                        sent: sent + 1,
+                        next: now + PROBE_INTERVAL_MS,
+                    };
+                } else {
+                    log::info!("claimed {}", self.host_name());
+                    self.state = State::Announcing { sent: 0, next: now };
+                    self.tick(now);
+                }
+            }
+            State::Announcing { sent, next } if next <= now => {
+                for packet in self.announcements(u32::MAX) {
+                    self.outbox
+                        .push((packet, SocketAddrV4::new(MDNS_GROUP, MDNS_PORT)));
+                }
+                self.state = if sent + 1 < ANNOUNCE_COUNT {
+                    State::Announcing {
+                        sent: sent + 1,
+                        next: now + ANNOUNCE_INTERVAL_MS,
+                    }
+                } else {
+                    State::Running
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Deals with a packet received from `src`.
+    fn handle(&mut self, packet: &Packet, src: SocketAddrV4, now: u64) {
+        if packet.is_response() {
+            self.check_conflicts(packet, now);
+        } else if self.advertising() {
+            self.answer(packet, src);
+        }
+    }
+
+    /// Looks for another host claiming our names with different data, and picks new names if
+    /// one does. Simultaneous probes (section 8.2) aren't tie-broken: the loser finds out when
+    /// the winner announces.
+    fn check_conflicts(&mut self, packet: &Packet, now: u64) {
+        if self.state == State::Idle {
+            return;
+        }
+        let host_name = self.host_name();
+        let mut conflict = false;
+        if packet.records().any(|r| {
+            name_eq(&r.name, &host_name) && matches!(r.data, RData::A(a) if Some(a) != self.addr)
+        }) {
+            self.renames += 1;
+            log::warn!("{} is in use, trying {}", host_name, self.host_name());
+            conflict = true;
+        }
+        for i in 0..self.services.len() {
+            let ours = self.srv(&self.services[i], HOST_TTL).data;
+            let instance_name = self.services[i].instance_name();
+            if packet.records().any(|r| {
+                name_eq(&r.name, &instance_name)
+                    && matches!(r.data, RData::Srv { .. })
+                    && !srv_eq(&r.data, &ours)
+            }) {
+                self.services[i].renames += 1;
+                log::warn!(
+                    "{} is in use, trying {}",
+                    instance_name,
+                    self.services[i].instance_name()
+                );
+                conflict = true;
+            }
+        }
+        if conflict {
+            self.restart(now);
+        }
+    }
+
+    /// Answers the questions in a query that are about us.
+    fn answer(&mut self, query: &Packet, src: SocketAddrV4) {
+        let mut answers = Vec::new();
+        let mut additional = Vec::new();
+        for q in query.questions.iter() {
+            let wants = |t: u16| q.qtype == t || q.qtype == TYPE_ANY;
+            if name_eq(&q.name, &self.host_name()) && wants(TYPE_A) {
+                answers.extend(self.host_records(HOST_TTL));
+            }
+            if name_eq(&q.name, SERVICES_META_QUERY) && wants(TYPE_PTR) {
+                for service in self.services.iter() {
+                    answers.push(self.meta_ptr(service, SERVICE_TTL));
+                }
+            }
+            for service in self.services.iter() {
+                if name_eq(&q.name, &service.type_name()) && wants(TYPE_PTR) {
+                    answers.push(self.type_ptr(service, SERVICE_TTL));
+                    // save the browser from asking for these next (RFC 6763 section 12.1)
+                    additional.push(self.srv(service, HOST_TTL));
+                    additional.push(self.txt(service, SERVICE_TTL));
+                    additional.extend(self.host_records(HOST_TTL));
+                }
+                if name_eq(&q.name, &service.instance_name()) {
+                    if wants(TYPE_SRV) {
+                        answers.push(self.srv(service, HOST_TTL));
+                        additional.extend(self.host_records(HOST_TTL));
+                    }
+                    if wants(TYPE_TXT) {
+                        answers.push(self.txt(service, SERVICE_TTL));
+                    }
+                }
+            }
+        }
+        // known-answer suppression (RFC 6762 section 7.1): leave out what the querier already
+        // has, as long as its copy has at least half its life left
+        answers.retain(|a| {
+            !query.answers.iter().any(|known| {
+                name_eq(&known.name, &a.name) && known.data == a.data && known.ttl >= a.ttl / 2
+            })
+        });
+        dedup(&mut answers);
+        dedup(&mut additional);
+        additional.retain(|r| !answers.contains(r));
+        if answers.is_empty() {
+            return;
+        }
+        if src.port() != MDNS_PORT {
+            // a legacy unicast query (section 6.7) from a plain DNS resolver: reply like a DNS
+            // server would, to the port it came from
+            for r in answers.iter_mut().chain(additional.iter_mut()) {
+                r.ttl = r.ttl.min(LEGACY_TTL);
+                r.cache_flush = false;
+            }
+            let mut reply = Packet::response(answers, additional);
+            reply.id = query.id;
+            reply.questions = query.questions.clone();
+            self.outbox.push((reply, src));
+        } else if query.questions.iter().all(|q| q.unicast) {
+            self.outbox
+                .push((Packet::response(answers, additional), src));
+        } else {
+            self.outbox.push((
+                Packet::response(answers, additional),
+                SocketAddrV4::new(MDNS_GROUP, MDNS_PORT),
+            ));
+        }
+    }
+}
+
+/// Compares SRV data, ignoring case in the target name.
+fn srv_eq(a: &RData, b: &RData) -> bool {
+    match (a, b) {
+        (
+            RData::Srv {
+                priority: p1,
+                weight: w1,
+                port: port1,
+                target: t1,
+            },
+            RData::Srv {
+                priority: p2,
+                weight: w2,
+                port: port2,
+                target: t2,
+            },
+        ) => p1 == p2 && w1 == w2 && port1 == port2 && name_eq(t1, t2),
+        _ => false,
+    }
+}
+
+fn dedup(records: &mut Vec<Record>) {
+    let mut i = 0;
+    while i < records.len() {
+        if records[..i].contains(&records[i]) {
+            records.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Takes our address and MAC from the net server's view of the link.
+fn update_link(responder: &mut Responder, config: &com::Ipv4Conf, now: u64) {
+    if config.mac != [0u8; 6] {
+        responder.set_mac(config.mac, now);
+    }
+    let addr = Some(Ipv4Addr::from(config.addr)).filter(|addr| !addr.is_unspecified());
+    responder.set_addr(addr, now);
+}
+
+/// Feeds whatever arrives on the mDNS socket to the responder, blocking until something does.
+/// Anything the responder has to say in return goes out from `send_thread`.
+fn receive_thread(responder: Arc<Mutex<Responder>>, socket: Arc<UdpSocket>, wake: Sender<()>) {
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let mut buf = [0u8; MDNS_PKT_MAX_LEN];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, SocketAddr::V4(src))) => match Packet::parse(&buf[..len]) {
+                Some(packet) => {
+                    responder
+                        .lock()
+                        .unwrap()
+                        .handle(&packet, src, tt.elapsed_ms());
+                    if wake.send(()).is_err() {
+                        // the server has quit
+                        break;
+                    }
+                }
+                None => log::debug!("malformed packet from {}", src),
+            },
+            Ok(_) => {}
+            Err(e) => log::warn!("mDNS receive error: {:?}", e),
+        }
+    }
+}
+
+/// Sends what the responder produces and drives its probe and announcement timers. Sleeps until
+/// `wake` says the responder has changed, or until its next deadline, so an idle responder costs
+/// nothing. Returns once the responder has been told to quit.
+fn send_thread(responder: Arc<Mutex<Responder>>, socket: Arc<UdpSocket>, wake: Receiver<()>) {
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    loop {
+        let now = tt.elapsed_ms();
+        let (outbox, deadline, quit) = {
+            let mut responder = responder.lock().unwrap();
+            responder.tick(now);
+            (
+                responder.take_outbox(),
+                responder.next_deadline(),
+                responder.quit,
+            )
+        };
+        for (packet, dst) in outbox {
+            if let Err(e) = socket.send_to(&packet.to_bytes(), dst) {
+                log::warn!("couldn't send mDNS packet to {}: {:?}", dst, e);
+            }
+        }
+        if quit {
+            break;
+        }
+        let woken = match deadline {
+            Some(deadline) => {
+                match wake.recv_timeout(Duration::from_millis(deadline.saturating_sub(now))) {
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => true,
+                    Err(RecvTimeoutError::Disconnected) => false,
+                }
+            }
+            None => wake.recv().is_ok(),
+        };
+        if !woken {
+            break;
+        }
+        // one pass covers any number of changes
+        while wake.try_recv().is_ok() {}
+    }
+}
+
+#[xous::xous_main]
+fn xmain() -> ! {
+    log_server::init_wait().unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    log::info!("my PID is {}", xous::process::id());
+
+    let xns = xous_names::XousNames::new().unwrap();
+    let mdns_sid = xns
+        .register_name(api::SERVER_NAME_MDNS, None)
+        .expect("can't register server");
+    log::trace!("registered with NS -- {:?}", mdns_sid);
+
+    let tt = ticktimer_server::Ticktimer::new().unwrap();
+    let mut netmgr = net::NetManager::new();
+    let socket = Arc::new(
+        UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT))
+            .expect("couldn't bind the mDNS port"),
+    );
+    let responder = Arc::new(Mutex::new(Responder::new()));
+    let (wake, woken) = mpsc::channel();
+    // the receiver blocks in the socket for good; it goes away with the process
+    thread::spawn({
+        let responder = responder.clone();
+        let socket = socket.clone();
+        let wake = wake.clone();
+        move || receive_thread(responder, socket, wake)
+    });
+    let sender = thread::spawn({
+        let responder = responder.clone();
+        let socket = socket.clone();
+        move || send_thread(responder, socket, woken)
+    });
+    // our address and MAC come from the wifi state updates, after whatever is known now
+    if let Some(config) = netmgr.get_ipv4_config() {
+        update_link(&mut responder.lock().unwrap(), &config, tt.elapsed_ms());
+    }
+    let cb_cid = xous::connect(mdns_sid).unwrap();
+    netmgr
+        .wifi_state_subscribe(cb_cid, Opcode::WifiState.to_u32().unwrap())
+        .expect("couldn't subscribe to wifi state");
+
+    log::trace!("ready to accept requests");
+    loop {
+        let mut msg = xous::receive_message(mdns_sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(Opcode::SetEnabled) => msg_blocking_scalar_unpack!(msg, enabled, _, _, _, {
+                let enabled = enabled != 0;
+                let mut responder = responder.lock().unwrap();
+                if enabled != responder.enabled {
+                    log::info!("responder {}", if enabled { "started" } else { "stopped" });
+                    // only listen to the group while there's something to answer
+                    let membership = if enabled {
+                        netmgr.join_multicast_v4(MDNS_GROUP)
+                    } else {
+                        netmgr.leave_multicast_v4(MDNS_GROUP)
+                    };
+                    if let Err(e) = membership {
+                        log::warn!("couldn't change mDNS group membership: {:?}", e);
+                    }
+                    if let Some(config) = netmgr.get_ipv4_config() {
+                        update_link(&mut responder, &config, tt.elapsed_ms());
+                    }
+                    responder.set_enabled(enabled, tt.elapsed_ms());
+                }
+                xous::return_scalar(msg.sender, 0).unwrap();
+            }),
+            Some(Opcode::WifiState) => {
+                let buffer =
+                    unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let status = com::WlanStatus::from_ipc(
+                    buffer.to_original::<com::WlanStatusIpc, _>().unwrap(),
+                );
+                update_link(
+                    &mut responder.lock().unwrap(),
+                    &status.ipv4,
+                    tt.elapsed_ms(),
+                );
+            }
+            Some(Opcode::SetHostname) => {
+                let buffer =
+                    unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let hostname = buffer.to_original::<String<MDNS_LABEL_LIMIT>, _>().unwrap();
+                log::info!("host name set to {}", hostname);
+                responder
+                    .lock()
+                    .unwrap()
+                    .set_hostname(hostname.as_str().unwrap(), tt.elapsed_ms());
+            }
+            Some(Opcode::GetHostname) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let host = responder.lock().unwrap().host();
+                buffer
+                    .replace(String::<MDNS_LABEL_LIMIT>::from_str(&host))
+                    .unwrap();
+            }
+            Some(Opcode::Register) => {
+                let mut buffer = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut service = buffer.to_original::<MdnsService, _>().unwrap();
+                let txt = service
+                    .txt
+                    .iter()
+                    .filter_map(|entry| entry.as_ref())
+                    .filter_map(|entry| entry.as_str().ok())
+                    .map(|entry| entry.to_string())
+                    .collect();
+                service.code = responder.lock().unwrap().register(
+                    Service {
+                        name: service.instance.as_str().unwrap().to_string(),
+                        renames: 0,
+                        service: service.service.as_str().unwrap().to_string(),
+                        port: service.port,
+                        txt,
+                    },
+                    tt.elapsed_ms(),
+                );
+                log::info!(
+                    "register {}.{}: {:?}",
+                    service.instance,
+                    service.service,
+                    service.code
+                );
+                buffer.replace(service).unwrap();
+            }
+            Some(Opcode::Unregister) => {
+                let buffer =
+                    unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let service = buffer.to_original::<MdnsService, _>().unwrap();
+                responder.lock().unwrap().unregister(
+                    service.instance.as_str().unwrap(),
+                    service.service.as_str().unwrap(),
+                );
+            }
+            Some(Opcode::Quit) => {
+                log::warn!("got quit!");
+                responder.lock().unwrap().quit();
+                wake.send(()).ok();
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+        // whatever changed, the sender works out what to send and when
+        wake.send(()).ok();
+    }
+    // clean up our program
+    sender.join().ok();
+    netmgr.wifi_state_unsubscribe().ok();
+    if responder.lock().unwrap().enabled {
+        netmgr.leave_multicast_v4(MDNS_GROUP).ok();
+    }
+    log::trace!("main loop exit, destroying servers");
+    xns.unregister_server(mdns_sid).unwrap();
+    xous::destroy_server(mdns_sid).unwrap();
+    log::trace!("quitting");
+    xous::terminate_process(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUR_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 245, 7);
+    const PEER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 245, 20), MDNS_PORT);
+    const GROUP: SocketAddrV4 = SocketAddrV4::new(MDNS_GROUP, MDNS_PORT);
+    const OUR_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0xa1, 0xb2, 0xc3];
+
+    fn web() -> Service {
+        Service {
+            name: "Precursor Web".to_string(),
+            renames: 0,
+            service: "_http._tcp".to_string(),
+            port: 80,
+            txt: vec!["path=/".to_string()],
+        }
+    }
+
+    /// a responder that has finished probing and announcing
+    fn running() -> Responder {
+        let mut r = Responder::new();
+        r.set_mac(OUR_MAC, 0);
+        r.set_addr(Some(OUR_ADDR), 0);
+        assert_eq!(r.register(web(), 0), RegisterCode::Ok);
+        r.set_enabled(true, 0);
+        for now in (0..5000).step_by(50) {
+            r.tick(now);
+        }
+        assert_eq!(r.state, State::Running);
+        r.take_outbox();
+        r
+    }
+
+    fn question(name: &str, qtype: u16) -> Packet {
+        Packet::query(vec![Question {
+            name: name.to_string(),
+            qtype,
+            unicast: false,
+        }])
+    }
+
+    #[test]
+    fn probe_then_announce() {
+        let mut r = Responder::new();
+        r.set_enabled(true, 0);
+        r.tick(0);
+        assert!(
+            r.take_outbox().is_empty(),
+            "nothing to say without an address"
+        );
+
+        r.set_mac(OUR_MAC, 0);
+        r.set_addr(Some(OUR_ADDR), 0);
+        r.register(web(), 0);
+        let mut sent = Vec::new();
+        for now in (0..5000).step_by(50) {
+            r.tick(now);
+            sent.extend(r.take_outbox().into_iter().map(|(p, dst)| (now, p, dst)));
+        }
+        assert!(sent.iter().all(|(_, _, dst)| *dst == GROUP));
+        let probes: Vec<_> = sent.iter().filter(|(_, p, _)| !p.is_response()).collect();
+        assert_eq!(
+            probes.iter().map(|(t, _, _)| *t).collect::<Vec<_>>(),
+            vec![0, 250, 500]
+        );
+        let probe = &probes[0].1;
+        assert_eq!(probe.questions[0].name, "precursor-a1b2c3.local");
+        assert!(probe.questions[0].unicast);
+        assert_eq!(probe.questions[1].name, "Precursor Web._http._tcp.local");
+        assert_eq!(probe.authority[0].data, RData::A(OUR_ADDR));
+
+        // two announcements, one packet for the host and one for the service each time
+        let announced: Vec<_> = sent.iter().filter(|(_, p, _)| p.is_response()).collect();
+        assert_eq!(
+            announced.iter().map(|(t, _, _)| *t).collect::<Vec<_>>(),
+            vec![750, 750, 1750, 1750]
+        );
+        assert_eq!(
+            announced[1].1.answers[1].data,
+            RData::Ptr("Precursor Web._http._tcp.local".to_string())
+        );
+    }
+
+    #[test]
+    fn quiet_until_enabled() {
+        let mut r = Responder::new();
+        r.set_addr(Some(OUR_ADDR), 0);
+        r.register(web(), 0);
+        r.set_enabled(true, 0);
+        assert_eq!(r.host(), "", "no name until one is set or the MAC is known");
+        assert_eq!(r.next_deadline(), None);
+
+        r.set_enabled(false, 0);
+        r.set_mac(OUR_MAC, 0);
+        assert_eq!(r.host(), "precursor-a1b2c3");
+        for now in (0..5000).step_by(50) {
+            r.tick(now);
+        }
+        r.handle(&question("precursor-a1b2c3.local", TYPE_A), PEER, 5000);
+        assert!(r.take_outbox().is_empty(), "nothing goes out while stopped");
+        assert_eq!(r.next_deadline(), None);
+
+        r.set_enabled(true, 5000);
+        assert_eq!(r.next_deadline(), Some(5000));
+        r.tick(5000);
+        assert!(!r.take_outbox()[0].0.is_response());
+        assert_eq!(r.next_deadline(), Some(5000 + PROBE_INTERVAL_MS));
+        for now in (5000..10000).step_by(50) {
+            r.tick(now);
+        }
+        assert_eq!(r.state, State::Running);
+        assert_eq!(
+            r.next_deadline(),
+            None,
+            "nothing to wake up for once running"
+        );
+        r.take_outbox();
+
+        // stopping says goodbye, and then nothing is answered
+        r.set_enabled(false, 10000);
+        let goodbyes = r.take_outbox();
+        assert_eq!(goodbyes.len(), 2);
+        assert!(goodbyes
+            .iter()
+            .all(|(p, _)| p.answers.iter().all(|a| a.ttl == 0)));
+        r.handle(&question("precursor-a1b2c3.local", TYPE_A), PEER, 10000);
+        assert!(r.take_outbox().is_empty());
+
+        // a chosen name wins over the device name
+        r.set_hostname("vault", 10000);
+        assert_eq!(r.host(), "vault");
+        r.set_mac(OUR_MAC, 10000);
+        assert_eq!(r.host(), "vault");
+    }
+
+    #[test]
+    fn host_conflict() {
+        let mut r = Responder::new();
+        r.set_mac(OUR_MAC, 0);
+        r.set_addr(Some(OUR_ADDR), 0);
+        r.set_enabled(true, 0);
+        r.tick(0);
+        r.take_outbox();
+        let mut claim = Packet::response(
+            vec![Record {
+                name: "Precursor-A1B2C3.local".to_string(),
+                cache_flush: true,
+                ttl: 120,
+                data: RData::A(Ipv4Addr::new(10, 0, 245, 99)),
+            }],
+            Vec::new(),
+        );
+        r.handle(&claim, PEER, 100);
+        assert_eq!(r.host(), "precursor-a1b2c3-2");
+        assert_eq!(r.state, State::Probing { sent: 0, next: 100 });
+
+        // our own address coming back isn't a conflict
+        claim.answers[0].name = "precursor-a1b2c3-2.local".to_string();
+        claim.answers[0].data = RData::A(OUR_ADDR);
+        r.handle(&claim, PEER, 150);
+        assert_eq!(r.host(), "precursor-a1b2c3-2");
+
+        r.set_hostname("vault", 200);
+        assert_eq!(r.host_name(), "vault.local");
+    }
+
+    #[test]
+    fn instance_conflict() {
+        let mut r = running();
+        let claim = Packet::response(
+            vec![Record {
+                name: "precursor web._http._tcp.local".to_string(),
+                cache_flush: true,
+                ttl: 120,
+                data: RData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: 8080,
+                    target: "laptop.local".to_string(),
+                },
+            }],
+            Vec::new(),
+        );
+        r.handle(&claim, PEER, 6000);
+        assert_eq!(
+            r.services[0].instance_name(),
+            "Precursor Web (2)._http._tcp.local"
+        );
+        assert!(matches!(r.state, State::Probing { .. }));
+    }
+
+    #[test]
+    fn answers() {
+        let mut r = running();
+        r.handle(&question("PRECURSOR-a1b2c3.local", TYPE_A), PEER, 6000);
+        let (reply, dst) = r.take_outbox().remove(0);
+        assert_eq!(dst, GROUP);
+        assert_eq!(reply.answers[0].data, RData::A(OUR_ADDR));
+
+        r.handle(&question("_http._tcp.local", TYPE_PTR), PEER, 6000);
+        let (reply, _) = r.take_outbox().remove(0);
+        assert_eq!(reply.answers.len(), 1);
+        assert_eq!(
+            reply
+                .additional
+                .iter()
+                .map(|r| r.data.rtype())
+                .collect::<Vec<_>>(),
+            vec![TYPE_SRV, TYPE_TXT, TYPE_A]
+        );
+
+        r.handle(&question(SERVICES_META_QUERY, TYPE_PTR), PEER, 6000);
+        let (reply, _) = r.take_outbox().remove(0);
+        assert_eq!(
+            reply.answers[0].data,
+            RData::Ptr("_http._tcp.local".to_string())
+        );
+
+        // not ours, so no reply
+        r.handle(&question("_ipp._tcp.local", TYPE_PTR), PEER, 6000);
+        r.handle(&question("laptop.local", TYPE_A), PEER, 6000);
+        assert!(r.take_outbox().is_empty());
+
+        // the querier already knows the answer
+        let mut known = question("_http._tcp.local", TYPE_PTR);
+        known.answers.push(r.type_ptr(&r.services[0], 4000));
+        r.handle(&known, PEER, 6000);
+        assert!(r.take_outbox().is_empty());
+    }
+
+    #[test]
+    fn reply_routing() {
+        let mut r = running();
+        let mut qu = question("precursor-a1b2c3.local", TYPE_A);
+        qu.questions[0].unicast = true;
+        r.handle(&qu, PEER, 6000);
+        assert_eq!(r.take_outbox()[0].1, PEER);
+
+        let legacy = SocketAddrV4::new(*PEER.ip(), 50123);
+        let mut query = question("Precursor Web._http._tcp.local", TYPE_SRV);
+        query.id = 0x1234;
+        r.handle(&query, legacy, 6000);
+        let (reply, dst) = r.take_outbox().remove(0);
+        assert_eq!(dst, legacy);
+        assert_eq!(reply.id, 0x1234);
+        assert_eq!(reply.questions, query.questions);
+        assert!(reply
+            .records()
+            .all(|r| r.ttl == LEGACY_TTL && !r.cache_flush));
+    }
+
+    #[test]
+    fn goodbyes() {
+        let mut r = running();
+        r.unregister("precursor web", "_HTTP._tcp");
+        assert!(r.services.is_empty());
+        let (goodbye, dst) = r.take_outbox().remove(0);
+        assert_eq!(dst, GROUP);
+        assert_eq!(goodbye.answers.len(), 4);
+        assert!(goodbye.answers.iter().all(|r| r.ttl == 0));
+
+        r.quit();
+        let (goodbye, _) = r.take_outbox().remove(0);
+        assert_eq!(goodbye.answers[0].ttl, 0);
+        assert!(r.quit);
+    }
+
+    #[test]
+    fn registration_limits() {
+        let mut r = Responder::new();
+        assert_eq!(r.register(web(), 0), RegisterCode::Ok);
+        assert_eq!(r.register(web(), 0), RegisterCode::Duplicate);
+        for i in 1..MDNS_MAX_SERVICES {
+            let mut s = web();
+            s.name = format!("Web {}", i);
+            assert_eq!(r.register(s, 0), RegisterCode::Ok);
+        }
+        let mut s = web();
+        s.name = "One Too Many".to_string();
+        assert_eq!(r.register(s, 0), RegisterCode::TableFull);
+    }
+}
@@ -0,0 +1,450 @@
+//! Just enough of the DNS message format (RFC 1035, with the mDNS changes in RFC 6762 section 18)
+//! to answer and send mDNS queries. Names are written out without compression, which keeps the
+//! encoder simple at the cost of a few bytes per record; names are decompressed when parsing.
+
+use std::convert::{TryFrom, TryInto};
+use std::net::Ipv4Addr;
+
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// top bit of the class: "unicast response" in a question, "cache flush" in a record
+const CLASS_FLAG: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+/// Most compression pointers followed while reading a single name
+const MAX_NAME_POINTERS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    /// the QU bit: the querier would like a unicast response
+    pub unicast: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Ptr(String),
+    /// the character-strings of the record; for DNS-SD these are `key=value` pairs
+    Txt(Vec<String>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Other(u16),
+}
+impl RData {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            RData::A(_) => TYPE_A,
+            RData::Ptr(_) => TYPE_PTR,
+            RData::Txt(_) => TYPE_TXT,
+            RData::Srv { .. } => TYPE_SRV,
+            RData::Other(t) => *t,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub name: String,
+    /// set on records that only we own, so that caches replace rather than add to what they hold
+    pub cache_flush: bool,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Packet {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Packet {
+    pub fn query(questions: Vec<Question>) -> Packet {
+        Packet {
+            questions,
+            ..Default::default()
+        }
+    }
+    pub fn response(answers: Vec<Record>, additional: Vec<Record>) -> Packet {
+        Packet {
+            flags: FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+            answers,
+            additional,
+            ..Default::default()
+        }
+    }
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+    /// All the records in the packet, from every section.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers
+            .iter()
+            .chain(self.authority.iter())
+            .chain(self.additional.iter())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for field in [
+            self.id,
+            self.flags,
+            self.questions.len() as u16,
+            self.answers.len() as u16,
+            self.authority.len() as u16,
+            self.additional.len() as u16,
+        ]
+        .iter()
+        {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+        for q in self.questions.iter() {
+            write_name(&mut out, &q.name);
+            out.extend_from_slice(&q.qtype.to_be_bytes());
+            let class = if q.unicast {
+                CLASS_IN | CLASS_FLAG
+            } else {
+                CLASS_IN
+            };
+            out.extend_from_slice(&class.to_be_bytes());
+        }
+        for r in self.records() {
+            write_name(&mut out, &r.name);
+            out.extend_from_slice(&r.data.rtype().to_be_bytes());
+            let class = if r.cache_flush {
+                CLASS_IN | CLASS_FLAG
+            } else {
+                CLASS_IN
+            };
+            out.extend_from_slice(&class.to_be_bytes());
+            out.extend_from_slice(&r.ttl.to_be_bytes());
+            let len_at = out.len();
+            out.extend_from_slice(&[0, 0]);
+            match &r.data {
+                RData::A(addr) => out.extend_from_slice(&addr.octets()),
+                RData::Ptr(name) => write_name(&mut out, name),
+                RData::Txt(strings) => {
+                    // a TXT record can't be empty; an empty string stands in for "no data"
+                    if strings.is_empty() {
+                        out.push(0);
+                    }
+                    for s in strings.iter() {
+                        let s = &s.as_bytes()[..s.len().min(255)];
+                        out.push(s.len() as u8);
+                        out.extend_from_slice(s);
+                    }
+                }
+                RData::Srv {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                } => {
+                    out.extend_from_slice(&priority.to_be_bytes());
+                    out.extend_from_slice(&weight.to_be_bytes());
+                    out.extend_from_slice(&port.to_be_bytes());
+                    write_name(&mut out, target);
+                }
+                RData::Other(_) => {}
+            }
+            let rdlen = (out.len() - len_at - 2) as u16;
+            out[len_at..len_at + 2].copy_from_slice(&rdlen.to_be_bytes());
+        }
+        out
+    }
+
+    /// Parses a datagram, or returns `None` if it's malformed. Records of classes other than IN
+    /// are dropped.
+    pub fn parse(datagram: &[u8]) -> Option<Packet> {
+        let mut r = Reader {
+            data: datagram,
+            index: 12,
+        };
+        let header = |i: usize| r.u16_at(i);
+        let mut packet = Packet {
+            id: header(0)?,
+            flags: header(2)?,
+            ..Default::default()
+        };
+        let counts = [header(4)?, header(6)?, header(8)?, header(10)?];
+        for _ in 0..counts[0] {
+            let name = r.name()?;
+            let qtype = r.u16()?;
+            let class = r.u16()?;
+            if class & !CLASS_FLAG == CLASS_IN {
+                packet.questions.push(Question {
+                    name,
+                    qtype,
+                    unicast: class & CLASS_FLAG != 0,
+                });
+            }
+        }
+        for (section, &count) in counts[1..].iter().enumerate() {
+            for _ in 0..count {
+                let record = r.record()?;
+                if let Some(record) = record {
+                    match section {
+                        0 => packet.answers.push(record),
+                        1 => packet.authority.push(record),
+                        _ => packet.additional.push(record),
+                    }
+                }
+            }
+        }
+        Some(packet)
+    }
+}
+
+/// Compares names the way DNS does, ignoring ASCII case.
+pub fn name_eq(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// Writes `name` as a sequence of labels. Labels are cut at 63 bytes, the most a label can hold.
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() {
+            continue;
+        }
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+impl<'a> Reader<'a> {
+    fn u16_at(&self, index: usize) -> Option<u16> {
+        Some(u16::from_be_bytes(
+            self.data.get(index..index + 2)?.try_into().unwrap(),
+        ))
+    }
+    fn u16(&mut self) -> Option<u16> {
+        let v = self.u16_at(self.index)?;
+        self.index += 2;
+        Some(v)
+    }
+    fn u32(&mut self) -> Option<u32> {
+        let v = u32::from_be_bytes(
+            self.data
+                .get(self.index..self.index + 4)?
+                .try_into()
+                .unwrap(),
+        );
+        self.index += 4;
+        Some(v)
+    }
+    /// Reads a name at the current position, following compression pointers.
+    fn name(&mut self) -> Option<String> {
+        let mut name = String::new();
+        let mut index = self.index;
+        let mut end = None;
+        let mut pointers = 0;
+        loop {
+            let len = *self.data.get(index)? as usize;
+            if len == 0 {
+                self.index = end.unwrap_or(index + 1);
+                return Some(name);
+            } else if len & 0xc0 == 0xc0 {
+                if end.is_none() {
+                    end = Some(index + 2);
+                }
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    return None;
+                }
+                index = (self.u16_at(index)? & 0x3fff) as usize;
+            } else if len & 0xc0 != 0 {
+                return None;
+            } else {
+                let label = self.data.get(index + 1..index + 1 + len)?;
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                if name.len() > 255 {
+                    return None;
+                }
+                index += 1 + len;
+            }
+        }
+    }
+    /// Reads a resource record. Returns `Some(None)` for a well-formed record of another class.
+    fn record(&mut self) -> Option<Option<Record>> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let rdlen = self.u16()? as usize;
+        let start = self.index;
+        let rdata = self.data.get(start..start + rdlen)?;
+        let data = match rtype {
+            TYPE_A => RData::A(Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?)),
+            TYPE_PTR => RData::Ptr(self.name()?),
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                let mut rest = rdata;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let s = tail.get(..len as usize)?;
+                    if !s.is_empty() {
+                        strings.push(String::from_utf8_lossy(s).into_owned());
+                    }
+                    rest = &tail[len as usize..];
+                }
+                RData::Txt(strings)
+            }
+            TYPE_SRV => {
+                let priority = self.u16()?;
+                let weight = self.u16()?;
+                let port = self.u16()?;
+                RData::Srv {
+                    priority,
+                    weight,
+                    port,
+                    target: self.name()?,
+                }
+            }
+            other => RData::Other(other),
+        };
+        self.index = start + rdlen;
+        if class & !CLASS_FLAG != CLASS_IN {
+            return Some(None);
+        }
+        Some(Some(Record {
+            name,
+            cache_flush: class & CLASS_FLAG != 0,
+            ttl,
+            data,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a response to a PTR query for _http._tcp.local, laid out the way Avahi sends it: the SRV,
+    /// TXT and A records for the instance ride along in the additional section
+    const AVAHI_HTTP: [u8; 157] = [
+        0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x05, 0x5f, 0x68,
+        0x74, 0x74, 0x70, 0x04, 0x5f, 0x74, 0x63, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00,
+        0x00, 0x0c, 0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x0f, 0x0c, 0x50, 0x72, 0x69, 0x6e,
+        0x74, 0x65, 0x72, 0x20, 0x50, 0x61, 0x67, 0x65, 0xc0, 0x0c, 0xc0, 0x28, 0x00, 0x21, 0x80,
+        0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x07, 0x70,
+        0x72, 0x69, 0x6e, 0x74, 0x65, 0x72, 0xc0, 0x17, 0xc0, 0x28, 0x00, 0x10, 0x80, 0x01, 0x00,
+        0x00, 0x11, 0x94, 0x00, 0x2e, 0x09, 0x74, 0x78, 0x74, 0x76, 0x65, 0x72, 0x73, 0x3d, 0x31,
+        0x0b, 0x70, 0x61, 0x74, 0x68, 0x3d, 0x2f, 0x61, 0x64, 0x6d, 0x69, 0x6e, 0x00, 0x16, 0x6e,
+        0x6f, 0x74, 0x65, 0x3d, 0x53, 0x65, 0x63, 0x6f, 0x6e, 0x64, 0x20, 0x66, 0x6c, 0x6f, 0x6f,
+        0x72, 0x20, 0x68, 0x61, 0x6c, 0x6c, 0xc0, 0x49, 0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00,
+        0x78, 0x00, 0x04, 0xc0, 0xa8, 0x01, 0x17,
+    ];
+
+    #[test]
+    fn parse_dns_sd_response() {
+        let packet = Packet::parse(&AVAHI_HTTP).unwrap();
+        assert!(packet.is_response());
+        assert!(packet.questions.is_empty());
+        assert_eq!(
+            packet.answers,
+            vec![Record {
+                name: "_http._tcp.local".to_string(),
+                cache_flush: false,
+                ttl: 4500,
+                data: RData::Ptr("Printer Page._http._tcp.local".to_string()),
+            }]
+        );
+        assert_eq!(
+            packet.additional,
+            vec![
+                Record {
+                    name: "Printer Page._http._tcp.local".to_string(),
+                    cache_flush: true,
+                    ttl: 120,
+                    data: RData::Srv {
+                        priority: 0,
+                        weight: 0,
+                        port: 80,
+                        target: "printer.local".to_string(),
+                    },
+                },
+                Record {
+                    name: "Printer Page._http._tcp.local".to_string(),
+                    cache_flush: true,
+                    ttl: 4500,
+                    data: RData::Txt(vec![
+                        "txtvers=1".to_string(),
+                        "path=/admin".to_string(),
+                        "note=Second floor hall".to_string(),
+                    ]),
+                },
+                Record {
+                    name: "printer.local".to_string(),
+                    cache_flush: true,
+                    ttl: 120,
+                    data: RData::A(Ipv4Addr::new(192, 168, 1, 23)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut probe = Packet::query(vec![Question {
+            name: "precursor.local".to_string(),
+            qtype: TYPE_ANY,
+            unicast: true,
+        }]);
+        probe.authority.push(Record {
+            name: "precursor.local".to_string(),
+            cache_flush: false,
+            ttl: 120,
+            data: RData::A(Ipv4Addr::new(10, 0, 245, 7)),
+        });
+        assert_eq!(Packet::parse(&probe.to_bytes()), Some(probe));
+
+        let response = Packet::parse(&AVAHI_HTTP).unwrap();
+        assert_eq!(Packet::parse(&response.to_bytes()), Some(response));
+    }
+
+    #[test]
+    fn malformed() {
+        for len in 0..AVAHI_HTTP.len() {
+            assert_eq!(Packet::parse(&AVAHI_HTTP[..len]), None, "length {}", len);
+        }
+        // a name that points at itself
+        let mut looped = AVAHI_HTTP[..12].to_vec();
+        looped[7] = 1;
+        looped[11] = 0;
+        looped.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 0, 0, 4, 10, 0, 0, 1]);
+        assert_eq!(Packet::parse(&looped), None);
+    }
+
+    #[test]
+    fn names() {
+        assert!(name_eq("Precursor.local.", "precursor.LOCAL"));
+        assert!(!name_eq("precursor.local", "precursor-2.local"));
+    }
+}
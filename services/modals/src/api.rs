@@ -27,6 +27,8 @@ pub struct ManagedListItem {
 pub struct ManagedPromptWithTextResponse {
     pub token: [u32; 4],
     pub prompt: xous_ipc::String::<1024>,
+    /// hide the text as it is typed
+    pub password: bool,
 }
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
 pub struct ManagedNotification {
@@ -32,12 +32,30 @@ impl Modals {
         prompt: &str,
         maybe_validator: Option<fn(TextEntryPayload, u32) -> Option<ValidatorErr>>,
         maybe_validator_op: Option<u32>,
+    ) -> Result<TextEntryPayload, xous::Error> {
+        self.prompt_text(prompt, false, maybe_validator, maybe_validator_op)
+    }
+    /// Like `get_text()`, but the text is hidden as it is typed. Use this for passwords and other
+    /// secrets, instead of taking them on an app's input line where they'd be shown and recorded.
+    pub fn get_password(&self,
+        prompt: &str,
+        maybe_validator: Option<fn(TextEntryPayload, u32) -> Option<ValidatorErr>>,
+        maybe_validator_op: Option<u32>,
+    ) -> Result<TextEntryPayload, xous::Error> {
+        self.prompt_text(prompt, true, maybe_validator, maybe_validator_op)
+    }
+    fn prompt_text(&self,
+        prompt: &str,
+        password: bool,
+        maybe_validator: Option<fn(TextEntryPayload, u32) -> Option<ValidatorErr>>,
+        maybe_validator_op: Option<u32>,
     ) -> Result<TextEntryPayload, xous::Error> {
         self.lock();
 
         let mut spec = ManagedPromptWithTextResponse {
             token: self.token,
             prompt: xous_ipc::String::from_str(prompt),
+            password,
         };
         // question: do we want to add a retry limit?
         loop {
@@ -316,8 +316,13 @@ fn xmain() -> ! {
                         log::debug!("initiating text entry modal");
                         #[cfg(feature="tts")]
                         tts.tts_simple(config.prompt.as_str().unwrap()).unwrap();
+                        let mut entry = text_action;
+                        if config.password {
+                            // just hide the text: the inverted password styling is reserved for trusted contexts
+                            entry.visibility = TextEntryVisibility::Hidden;
+                        }
                         renderer_modal.modify(
-                            Some(ActionType::TextEntry(text_action)),
+                            Some(ActionType::TextEntry(entry)),
                             Some(config.prompt.as_str().unwrap()), false,
                             None, true, None
                         );
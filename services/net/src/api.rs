@@ -22,6 +22,12 @@ pub use smoltcp::wire::Icmpv4DstUnreachable;
 pub(crate) const SERVER_NAME_NET: &str = "_Middleware Network Server_";
 #[allow(dead_code)]
 pub const AP_DICT_NAME: &'static str = "wlan.networks";
+/// per-network settings for the APs in `AP_DICT_NAME`, one key per SSID
+#[allow(dead_code)]
+pub const AP_SETTINGS_DICT_NAME: &'static str = "wlan.settings";
+/// packet captures are saved as keys in this dictionary, one pcap file per key
+#[allow(dead_code)]
+pub const CAPTURE_DICT_NAME: &'static str = "net.capture";
 
 #[allow(dead_code)]
 /// minimum revision required for compatibility with Net crate
@@ -353,6 +359,30 @@ pub(crate) enum Opcode {
     StdTcpAccept = 45,
 
     StdTcpStreamShutdown = 46,
+
+    /// BlockingScalar call to join an IPv4 multicast group, so that UDP sockets receive datagrams
+    /// sent to it. arg1 is the group address as a big-endian u32. Memberships are kept per calling
+    /// process: joining twice is the same as joining once, and the group is left once every process
+    /// that joined it has left. Returns 0 on success, or a `NetError`.
+    JoinMulticastV4 = 47,
+
+    /// BlockingScalar call to leave an IPv4 multicast group; the arguments are as for `JoinMulticastV4`.
+    /// Returns `NetError::Invalid` if the calling process isn't a member.
+    LeaveMulticastV4 = 48,
+
+    /// BlockingScalar call to start capturing the frames that cross the network device. arg1 is a
+    /// `CaptureProtocol`, and arg2 is a TCP/UDP port to narrow it down to, or 0 for any port. A
+    /// capture that is already running is discarded. Returns 0 on success, or a `NetError`.
+    CaptureStart = 49,
+
+    /// Stops the capture and saves it as a pcap file in `CAPTURE_DICT_NAME`. A `Buffer` mutable lend
+    /// of a `CaptureSave`; the counts and `result` are filled in on return. An empty key is refused
+    /// with `LibraryError`, and leaves the capture running.
+    CaptureStop = 50,
+
+    /// BlockingScalar call that returns 1 if the Wi-Fi network the device is joined to is marked
+    /// metered in its saved settings, and 0 if it isn't, or if the device isn't joined to a saved network.
+    IsMetered = 51,
 }
 
 #[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone, Default)]
@@ -372,6 +402,35 @@ pub enum XousServerId {
     ServerName(xous_ipc::String<64>),
 }
 
+/// Narrows a packet capture down to one protocol. ICMP covers both ICMPv4 and ICMPv6.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CaptureProtocol {
+    Any = 0,
+    Arp = 1,
+    Icmp = 2,
+    Tcp = 3,
+    Udp = 4,
+}
+
+#[derive(Debug, Archive, Serialize, Deserialize, Copy, Clone)]
+pub(crate) struct CaptureSave {
+    /// the key to save the capture under
+    pub key: xous_ipc::String<64>,
+    /// frames saved
+    pub frames: u32,
+    /// frames that matched the filter, but were pushed out of the ring buffer by newer ones
+    pub dropped: u32,
+    /// `Invalid` if no capture was running, `AccessDenied` if the PDDB couldn't be written
+    pub result: NetMemResponse,
+}
+
+/// The outcome of a capture that was saved to the PDDB.
+#[derive(Debug, Copy, Clone)]
+pub struct CaptureSummary {
+    pub frames: u32,
+    pub dropped: u32,
+}
+
 /// These opcodes are reserved for private SIDs shared from a DNS server to
 /// reconfigure DNS on IP change/update.
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
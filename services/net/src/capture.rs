@@ -0,0 +1,271 @@
+//! Packet capture for debugging the link: every frame that crosses the `NetPhy` in either
+//! direction is offered to a `Capture`, which keeps the ones that match its filter as pcap records
+//! in a ring buffer until the capture is stopped and saved to the PDDB.
+//!
+//! Timestamps count from boot, because that's the only clock the network stack has.
+
+use crate::api::CaptureProtocol;
+use crate::pcap;
+use com::api::NET_MTU;
+use smoltcp::time::Instant;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// most bytes of pcap records held; the oldest records are dropped to make room for new ones
+pub const CAPTURE_RING_BYTES: usize = 64 * 1024;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IP_PROTO_ICMP: u8 = 1;
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+const IP_PROTO_ICMPV6: u8 = 58;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Filter {
+    pub protocol: CaptureProtocol,
+    /// a TCP or UDP port that must be either the source or the destination
+    pub port: Option<u16>,
+}
+impl Filter {
+    /// A port only makes sense for the protocols that have them.
+    pub fn is_valid(&self) -> bool {
+        self.port.is_none()
+            || match self.protocol {
+                CaptureProtocol::Any | CaptureProtocol::Tcp | CaptureProtocol::Udp => true,
+                CaptureProtocol::Arp | CaptureProtocol::Icmp => false,
+            }
+    }
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return false;
+        }
+        let payload = &frame[ETHERNET_HEADER_LEN..];
+        let (ip_proto, transport) = match u16::from_be_bytes([frame[12], frame[13]]) {
+            ETHERTYPE_IPV4 => {
+                if payload.len() < 20 {
+                    return false;
+                }
+                let header_len = (payload[0] & 0xf) as usize * 4;
+                // only the first fragment of a datagram carries the ports
+                let first_fragment = u16::from_be_bytes([payload[6], payload[7]]) & 0x1fff == 0;
+                (
+                    payload[9],
+                    payload.get(header_len..).filter(|_| first_fragment),
+                )
+            }
+            // extension headers aren't followed, so they hide the transport protocol
+            ETHERTYPE_IPV6 => match payload.get(6) {
+                Some(&next_header) => (next_header, payload.get(40..)),
+                None => return false,
+            },
+            ETHERTYPE_ARP => {
+                return self.port.is_none()
+                    && (self.protocol == CaptureProtocol::Any
+                        || self.protocol == CaptureProtocol::Arp)
+            }
+            _ => return self.port.is_none() && self.protocol == CaptureProtocol::Any,
+        };
+        let protocol_matches = match self.protocol {
+            CaptureProtocol::Any => true,
+            CaptureProtocol::Arp => false,
+            CaptureProtocol::Icmp => ip_proto == IP_PROTO_ICMP || ip_proto == IP_PROTO_ICMPV6,
+            CaptureProtocol::Tcp => ip_proto == IP_PROTO_TCP,
+            CaptureProtocol::Udp => ip_proto == IP_PROTO_UDP,
+        };
+        match self.port {
+            None => protocol_matches,
+            Some(port) => {
+                protocol_matches
+                    && (ip_proto == IP_PROTO_TCP || ip_proto == IP_PROTO_UDP)
+                    && match transport {
+                        Some(t) if t.len() >= 4 => {
+                            u16::from_be_bytes([t[0], t[1]]) == port
+                                || u16::from_be_bytes([t[2], t[3]]) == port
+                        }
+                        _ => false,
+                    }
+            }
+        }
+    }
+}
+
+/// A finished capture, as a complete pcap file.
+pub struct Saved {
+    pub pcap: Vec<u8>,
+    pub frames: u32,
+    pub dropped: u32,
+}
+
+#[derive(Default)]
+pub struct Capture {
+    /// `None` while no capture is running
+    filter: Option<Filter>,
+    /// each entry is a record header followed by the frame
+    records: VecDeque<Vec<u8>>,
+    bytes: usize,
+    dropped: u32,
+}
+impl Capture {
+    /// Starts capturing from scratch, discarding any capture already running.
+    pub fn start(&mut self, filter: Filter) {
+        self.records.clear();
+        self.bytes = 0;
+        self.dropped = 0;
+        self.filter = Some(filter);
+    }
+    /// Records `frame` if a capture is running and the frame matches its filter.
+    pub fn tee(&mut self, timestamp: Instant, frame: &[u8]) {
+        match self.filter {
+            Some(filter) if filter.matches(frame) => {}
+            _ => return,
+        }
+        let incl_len = frame.len().min(NET_MTU);
+        let mut record = Vec::with_capacity(pcap::RECORD_HEADER_LEN + incl_len);
+        record.extend_from_slice(&pcap::record_header(
+            timestamp.total_millis() as u64 * 1000,
+            incl_len as u32,
+            frame.len() as u32,
+        ));
+        record.extend_from_slice(&frame[..incl_len]);
+        while self.bytes + record.len() > CAPTURE_RING_BYTES {
+            match self.records.pop_front() {
+                Some(oldest) => {
+                    self.bytes -= oldest.len();
+                    self.dropped += 1;
+                }
+                None => break,
+            }
+        }
+        self.bytes += record.len();
+        self.records.push_back(record);
+    }
+    /// Stops the capture, returning what it recorded, or `None` if no capture was running.
+    pub fn stop(&mut self) -> Option<Saved> {
+        self.filter.take()?;
+        let mut pcap = Vec::with_capacity(pcap::FILE_HEADER_LEN + self.bytes);
+        pcap.extend_from_slice(&pcap::file_header(NET_MTU as u32));
+        let frames = self.records.len() as u32;
+        for record in self.records.drain(..) {
+            pcap.extend_from_slice(&record);
+        }
+        self.bytes = 0;
+        Some(Saved {
+            pcap,
+            frames,
+            dropped: self.dropped,
+        })
+    }
+}
+
+/// The capture shared between the server and the device. The device checks `capturing` before it
+/// takes the lock, so frames cost nothing extra while no capture is running.
+#[derive(Default)]
+pub struct CaptureTap {
+    capturing: AtomicBool,
+    capture: Mutex<Capture>,
+}
+impl CaptureTap {
+    pub fn start(&self, filter: Filter) {
+        let mut capture = self.capture.lock().unwrap();
+        capture.start(filter);
+        self.capturing.store(true, Ordering::SeqCst);
+    }
+    pub fn tee(&self, timestamp: Instant, frame: &[u8]) {
+        if self.capturing.load(Ordering::Relaxed) {
+            self.capture.lock().unwrap().tee(timestamp, frame);
+        }
+    }
+    pub fn stop(&self) -> Option<Saved> {
+        let mut capture = self.capture.lock().unwrap();
+        self.capturing.store(false, Ordering::SeqCst);
+        capture.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_frame(proto: u8, src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; ETHERNET_HEADER_LEN + 20 + 8];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame[14] = 0x45;
+        frame[14 + 9] = proto;
+        frame[34..36].copy_from_slice(&src_port.to_be_bytes());
+        frame[36..38].copy_from_slice(&dst_port.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn filters() {
+        let dns = ipv4_frame(IP_PROTO_UDP, 53, 49152);
+        let http = ipv4_frame(IP_PROTO_TCP, 49153, 80);
+        let ping = ipv4_frame(IP_PROTO_ICMP, 0, 0);
+        let mut arp = vec![0u8; 42];
+        arp[12..14].copy_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+        let mut mdns6 = vec![0u8; ETHERNET_HEADER_LEN + 40 + 8];
+        mdns6[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        mdns6[14 + 6] = IP_PROTO_UDP;
+        mdns6[54..56].copy_from_slice(&5353u16.to_be_bytes());
+        mdns6[56..58].copy_from_slice(&5353u16.to_be_bytes());
+
+        let filter = |protocol, port| Filter { protocol, port };
+        let all = [&dns, &http, &ping, &arp, &mdns6];
+        let matching = |f: Filter| all.iter().filter(|frame| f.matches(frame)).count();
+        assert_eq!(matching(filter(CaptureProtocol::Any, None)), 5);
+        assert_eq!(matching(filter(CaptureProtocol::Udp, None)), 2);
+        assert!(filter(CaptureProtocol::Udp, Some(53)).matches(&dns));
+        assert!(filter(CaptureProtocol::Any, Some(80)).matches(&http));
+        assert!(!filter(CaptureProtocol::Udp, Some(80)).matches(&http));
+        assert!(filter(CaptureProtocol::Udp, Some(5353)).matches(&mdns6));
+        assert!(filter(CaptureProtocol::Icmp, None).matches(&ping));
+        assert!(filter(CaptureProtocol::Arp, None).matches(&arp));
+        assert!(!filter(CaptureProtocol::Any, Some(0)).matches(&arp));
+        assert!(!filter(CaptureProtocol::Arp, Some(53)).is_valid());
+
+        // a later fragment has no ports to match on
+        let mut fragment = dns.clone();
+        fragment[14 + 7] = 0x10;
+        assert!(!filter(CaptureProtocol::Udp, Some(53)).matches(&fragment));
+        assert!(!filter(CaptureProtocol::Any, None).matches(&dns[..10]));
+    }
+
+    #[test]
+    fn ring() {
+        let mut capture = Capture::default();
+        let frame = ipv4_frame(IP_PROTO_UDP, 53, 49152);
+        capture.tee(Instant::from_millis(1), &frame);
+        assert!(capture.stop().is_none());
+
+        capture.start(Filter {
+            protocol: CaptureProtocol::Udp,
+            port: None,
+        });
+        let record_len = pcap::RECORD_HEADER_LEN + frame.len();
+        let fits = CAPTURE_RING_BYTES / record_len;
+        for i in 0..fits + 3 {
+            capture.tee(Instant::from_millis(i as i64), &frame);
+            capture.tee(
+                Instant::from_millis(i as i64),
+                &ipv4_frame(IP_PROTO_TCP, 1, 2),
+            );
+        }
+        let saved = capture.stop().unwrap();
+        assert_eq!(saved.frames as usize, fits);
+        assert_eq!(saved.dropped, 3);
+        assert!(capture.stop().is_none());
+
+        // the oldest frames were the ones dropped
+        let mut reader = pcap::Reader::new(&saved.pcap[..]).unwrap();
+        assert_eq!(reader.next_frame().unwrap(), Some((3_000, frame.clone())));
+        let mut count = 1;
+        while reader.next_frame().unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, fits);
+    }
+}
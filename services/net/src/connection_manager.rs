@@ -4,10 +4,10 @@ use core::sync::atomic::{AtomicU32, AtomicBool, Ordering};
 use com::{WlanStatus, WlanStatusIpc, SsidRecord};
 use com_rs_ref::{ConnectResult, LinkState};
 use net::MIN_EC_REV;
+use net::saved_networks::{join_order, SavedNetwork, SavedNetworks};
 use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, send_message, try_send_message, Message};
 use xous_ipc::Buffer;
 use num_traits::*;
-use std::io::Read;
 use std::collections::{HashMap, HashSet};
 use crate::ComIntSources;
 #[cfg(any(target_os = "none", target_os = "xous"))]
@@ -29,6 +29,7 @@ pub(crate) enum ConnectionManagerOpcode {
     SubscribeWifiStats,
     UnsubWifiStats,
     FetchSsidList,
+    IsMetered,
     ComInt,
     SuspendResume,
     Quit,
@@ -62,7 +63,7 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
     let xns = xous_names::XousNames::new().unwrap();
     let mut com = com::Com::new(&xns).unwrap();
     let netmgr = net::NetManager::new();
-    let mut pddb = pddb::Pddb::new();
+    let mut saved_networks = SavedNetworks::new();
     let self_cid = xous::connect(sid).unwrap();
     // give the system some time to boot before trying to run a check on the EC minimum version, as it is in reset on boot
     tt.sleep_ms(POLL_INTERVAL_MS).unwrap();
@@ -299,6 +300,14 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                                 buf.send(sub, WifiStateCallback::Update.to_u32().unwrap()).or(Err(xous::Error::InternalError)).unwrap();
                             }
                             if wifi_stats_cache.ipv4.dhcp == com_rs_ref::DhcpState::Bound {
+                                if wifi_state != WifiState::Connected {
+                                    if let Some(ssid) = wifi_stats_cache.ssid.as_ref() {
+                                        // networks joined by hand needn't be saved, so failing here is normal
+                                        if let Err(e) = saved_networks.mark_connected(ssid.name.as_str().unwrap_or("")) {
+                                            log::debug!("couldn't note the connection time for {}: {:?}", ssid.name, e);
+                                        }
+                                    }
+                                }
                                 wifi_state = WifiState::Connected;
                             } else {
                                 wifi_state = WifiState::WaitDhcp;
@@ -331,26 +340,22 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                             }
                         }
 
-                        if let Ok(ap_list_vec) = pddb.list_keys(AP_DICT_NAME, None) {
-                            let mut ap_list = HashSet::<String>::new();
-                            for ap in ap_list_vec {
-                                ap_list.insert(ap);
-                            }
+                        if let Ok(saved) = saved_networks.list() {
                             match wifi_state {
                                 WifiState::Unknown | WifiState::Disconnected | WifiState::InvalidAp | WifiState::InvalidAuth => {
                                     if (scan_state == SsidScanState::Idle) || scan_count > SCAN_COUNT_MAX {
                                         scan_count = 0;
                                         // wait until we're done scanning before trying to connect
-                                        if let Some(ssid) = get_next_ssid(&mut ssid_list, &mut ssid_attempted, ap_list) {
-                                            let mut wpa_pw_file = pddb.get(AP_DICT_NAME, &ssid, None, false, false, None, Some(||{})).expect("couldn't retrieve AP password");
-                                            let mut wp_pw_raw = [0u8; com::api::WF200_PASS_MAX_LEN];
-                                            if let Ok(readlen) = wpa_pw_file.read(&mut wp_pw_raw) {
-                                                let pw = std::str::from_utf8(&wp_pw_raw[..readlen]).expect("password was not valid utf-8");
-                                                log::info!("Attempting wifi connection: {}", ssid);
-                                                com.wlan_set_ssid(&ssid).expect("couldn't set SSID");
-                                                com.wlan_set_pass(pw).expect("couldn't set password");
-                                                com.wlan_join().expect("couldn't issue join command");
-                                                wifi_state = WifiState::Connecting;
+                                        if let Some(ssid) = get_next_ssid(&ssid_list, &mut ssid_attempted, &saved) {
+                                            match saved_networks.password(&ssid) {
+                                                Ok(pw) => {
+                                                    log::info!("Attempting wifi connection: {}", ssid);
+                                                    com.wlan_set_ssid(&ssid).expect("couldn't set SSID");
+                                                    com.wlan_set_pass(&pw).expect("couldn't set password");
+                                                    com.wlan_join().expect("couldn't issue join command");
+                                                    wifi_state = WifiState::Connecting;
+                                                }
+                                                Err(e) => log::error!("couldn't retrieve AP password for {}: {:?}", ssid, e),
                                             }
                                         } else {
                                             // no SSIDs available, scan again
@@ -465,6 +470,17 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
                 }
                 buffer.replace(ret_list).expect("couldn't return config");
             },
+            Some(ConnectionManagerOpcode::IsMetered) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                // read the settings on every call, so a change made in the menu counts right away
+                let metered = match (wifi_state, wifi_stats_cache.ssid.as_ref()) {
+                    (WifiState::Connected, Some(ssid)) => saved_networks
+                        .get(ssid.name.as_str().unwrap_or(""))
+                        .map(|network| network.metered)
+                        .unwrap_or(false),
+                    _ => false,
+                };
+                xous::return_scalar(msg.sender, if metered { 1 } else { 0 }).unwrap();
+            }),
             Some(ConnectionManagerOpcode::Run) => msg_scalar_unpack!(msg, _, _, _, _, {
                 if !run.swap(true, Ordering::SeqCst) {
                     if !pumping.load(Ordering::SeqCst) { // avoid having multiple pump messages being sent if a user tries to rapidly toggle the run/stop switch
@@ -491,57 +507,32 @@ pub(crate) fn connection_manager(sid: xous::SID, activity_interval: Arc<AtomicU3
     xous::destroy_server(sid).unwrap();
 }
 
-fn get_next_ssid(ssid_list_map: &mut HashMap<String, u8>, ssid_attempted: &mut HashSet<String>, ap_list: HashSet::<String>) -> Option<String> {
-    log::trace!("ap_list: {:?}", ap_list);
+fn get_next_ssid(ssid_list_map: &HashMap<String, u8>, ssid_attempted: &mut HashSet<String>, saved: &[SavedNetwork]) -> Option<String> {
+    log::trace!("saved networks: {:?}", saved);
     log::trace!("ssid_list: {:?}", ssid_list_map);
-    // 0. convert the HashMap of ssid_list into a HashSet
-    let mut ssid_list = HashSet::<String>::new();
-    for ssid in ssid_list_map.keys() {
-        ssid_list.insert(ssid.to_string());
-    }
-    // 1. find the intersection of ap_list and ssid_list to create a candidate_list
-    let all_candidate_list_ref = ap_list.intersection(&ssid_list).collect::<HashSet<_>>();
-    // this copy is required to perform the next set computation
-    let mut all_candidate_list = HashSet::<String>::new();
-    for c in all_candidate_list_ref {
-        all_candidate_list.insert(String::from(c));
-    }
-    log::trace!("intersection: {:?}", all_candidate_list);
+    // 1. rank the saved networks that are in range (or hidden) and allowed to auto-join
+    let candidate_list = join_order(saved, ssid_list_map);
+    log::trace!("candidates: {:?}", candidate_list);
 
     log::trace!("ssids already attempted: {:?}", ssid_attempted);
-    // 2. find the complement of ssid_attempted and candidate_list
-    let untried_candidate_list_ref = all_candidate_list.difference(ssid_attempted).collect::<HashSet<_>>();
-    // this copy breaks the mutability issue with changing ssid_attempted after the difference is computed
-    let mut untried_candidate_list = HashSet::<String>::new();
-    for c in untried_candidate_list_ref {
-        untried_candidate_list.insert(String::from(c));
-    }
-    log::trace!("untried_candidates: {:?}", untried_candidate_list);
-
-    if untried_candidate_list.len() > 0 {
-        if let Some(candidate) = untried_candidate_list.into_iter().next() {
-            ssid_attempted.insert(candidate.to_string());
-            log::debug!("SSID connect attempt: {:?}", candidate);
-            Some(candidate.to_string())
-        } else {
-            log::error!("We should have had at least one item in the candidate list, but found none.");
-            None
-        }
+    // 2. take the best candidate that hasn't been tried yet
+    if let Some(candidate) = candidate_list.iter().find(|&c| !ssid_attempted.contains(c)) {
+        ssid_attempted.insert(candidate.to_string());
+        log::debug!("SSID connect attempt: {:?}", candidate);
+        Some(candidate.to_string())
     } else {
         // clear the ssid_attempted list and start from scratch
         log::debug!("Exhausted all candidates, starting over again...");
         ssid_attempted.clear();
-        if let Some(candidate) = all_candidate_list.iter().next() {
+        if let Some(candidate) = candidate_list.first() {
             ssid_attempted.insert(candidate.to_string());
             log::debug!("SSID connect attempt: {:?}", candidate);
             Some(candidate.to_string())
         } else {
             log::info!("No SSID candidates visible. Debug dump:");
-            log::info!("ap_list: {:?}", ap_list);
+            log::info!("saved networks: {:?}", saved);
             log::info!("ssid_list: {:?}", ssid_list_map);
-            log::info!("candidate list: {:?}", all_candidate_list);
-            log::info!("untried candidate list: {:?}", untried_candidate_list);
             None
         }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,7 @@
 use com::Com;
 use com::api::NET_MTU;
+use crate::capture::CaptureTap;
+use std::sync::Arc;
 
 use smoltcp::Result;
 use smoltcp::phy::{self, DeviceCapabilities, Medium};
@@ -13,15 +15,17 @@ pub struct NetPhy {
     tx_buffer: [u8; NET_MTU],
     com: Com,
     rx_avail: Option<u16>,
+    capture: Arc<CaptureTap>,
 }
 
 impl<'a> NetPhy {
-    pub fn new(xns: &xous_names::XousNames) -> NetPhy {
+    pub fn new(xns: &xous_names::XousNames, capture: Arc<CaptureTap>) -> NetPhy {
         NetPhy {
             rx_buffer: [0; NET_MTU],
             tx_buffer: [0; NET_MTU],
             com: Com::new(&xns).unwrap(),
             rx_avail: None,
+            capture,
         }
     }
     // returns None if there was a slot to put the availability into
@@ -44,15 +48,15 @@ impl<'a> phy::Device<'a> for NetPhy {
         if let Some(rx_len) = self.rx_avail.take() {
             self.com.wlan_fetch_packet(&mut self.rx_buffer[..rx_len as usize]).expect("Couldn't call wlan_fetch_packet in device adapter");
 
-            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize]},
-            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com}))
+            Some((NetPhyRxToken{buf: &mut self.rx_buffer[..rx_len as usize], capture: &self.capture},
+            NetPhyTxToken{buf: &mut self.tx_buffer[..], com: & self.com, capture: &self.capture}))
         } else {
             None
         }
     }
 
     fn transmit(&'a mut self) -> Option<Self::TxToken> {
-        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com})
+        Some(NetPhyTxToken{buf: &mut self.tx_buffer[..], com: &self.com, capture: &self.capture})
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
@@ -66,12 +70,14 @@ impl<'a> phy::Device<'a> for NetPhy {
 
 pub struct NetPhyRxToken<'a> {
     buf: &'a mut [u8],
+    capture: &'a CaptureTap,
 }
 
 impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
-    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
+        self.capture.tee(timestamp, self.buf);
         let result = f(&mut self.buf);
         //log::info!("rx: {:x?}", self.buf);
         result
@@ -81,16 +87,18 @@ impl<'a, 'c> phy::RxToken for NetPhyRxToken<'a> {
 pub struct NetPhyTxToken<'a> {
     buf: &'a mut [u8],
     com: &'a Com,
+    capture: &'a CaptureTap,
 }
 
 impl<'a> phy::TxToken for NetPhyTxToken<'a> {
-    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
         where F: FnOnce(&mut [u8]) -> Result<R>
     {
         let result = f(&mut self.buf[..len]);
         //log::info!("txlen: {}", len);
 
         if result.is_ok() {
+            self.capture.tee(timestamp, &self.buf[..len]);
             self.com.wlan_send_packet(&self.buf[..len]).map_err(|_| smoltcp::Error::Dropped)?;
         }
         result
@@ -0,0 +1,544 @@
+//! A `NetPhy` for hosted mode, so that the smoltcp stack and everything built on it can be
+//! exercised on a workstation. There's no WF200 in hosted mode, so frames are exchanged with
+//! whatever the environment of the net process asks for:
+//!
+//!   `XOUS_NET_TAP=<ifname>`: a Linux TAP interface, which must already exist and be up, e.g.
+//!       `sudo ip tuntap add dev xous0 mode tap user $USER && sudo ip link set xous0 up`
+//!   `XOUS_NET_PCAP=<file>`: replays the frames of an Ethernet pcap capture as received
+//!       traffic, spaced as they were captured, after `XOUS_NET_PCAP_DELAY_MS` (default 0)
+//!   `XOUS_NET_PCAP_OUT=<file>`: writes every transmitted frame to a pcap capture, with either
+//!       of the above or on its own
+//!
+//! Without a DHCP lease from the EC, the interface is configured from
+//! `XOUS_NET_IPV4=<addr>,<gateway>[,<dns>]` and, optionally, `XOUS_NET_MAC=<aa:bb:cc:dd:ee:ff>`.
+//! A capture being replayed has to have been taken against the same addresses.
+
+use com::api::NET_MTU;
+use com::Ipv4Conf;
+use num_traits::*;
+
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+use smoltcp::Result;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::api::{Opcode, SERVER_NAME_NET};
+use crate::capture::CaptureTap;
+use crate::pcap;
+
+/// frames received but not yet taken by smoltcp; past this, the oldest are dropped
+const RX_QUEUE_DEPTH: usize = 64;
+const DEFAULT_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+lazy_static::lazy_static! {
+    // the interface is rebuilt on every config change, but the TAP device and the replay have to
+    // outlive all of them, so every `NetPhy` shares one backend
+    static ref BACKEND: Arc<Backend> = Backend::start();
+}
+
+struct Backend {
+    rx: Mutex<VecDeque<Vec<u8>>>,
+    tap: Option<File>,
+    capture: Option<Mutex<File>>,
+    /// connection back to the net server, to have it poll once a frame has arrived
+    pump: Option<xous::CID>,
+}
+impl Backend {
+    fn start() -> Arc<Backend> {
+        let xns = xous_names::XousNames::new().unwrap();
+        Backend::from_env(Some(
+            xns.request_connection_blocking(SERVER_NAME_NET)
+                .expect("can't connect to the net server"),
+        ))
+    }
+
+    /// Opens whatever the environment asks for. Without a `pump`, received frames just wait in
+    /// the queue until the interface is next polled.
+    fn from_env(pump: Option<xous::CID>) -> Arc<Backend> {
+        let tap = std::env::var("XOUS_NET_TAP")
+            .ok()
+            .and_then(|name| match open_tap(&name) {
+                Ok(tap) => {
+                    log::info!("hosted network device on TAP interface {}", name);
+                    Some(tap)
+                }
+                Err(e) => {
+                    log::error!("couldn't open TAP interface {}: {:?}", name, e);
+                    None
+                }
+            });
+        let capture =
+            std::env::var("XOUS_NET_PCAP_OUT").ok().and_then(|path| {
+                match File::create(&path).and_then(|mut f| {
+                    f.write_all(&pcap::file_header(NET_MTU as u32))?;
+                    Ok(f)
+                }) {
+                    Ok(f) => {
+                        log::info!("writing transmitted frames to {}", path);
+                        Some(Mutex::new(f))
+                    }
+                    Err(e) => {
+                        log::error!("couldn't create {}: {:?}", path, e);
+                        None
+                    }
+                }
+            });
+        let backend = Arc::new(Backend {
+            rx: Mutex::new(VecDeque::new()),
+            tap,
+            capture,
+            pump,
+        });
+
+        if let Some(tap) = backend.tap.as_ref() {
+            let mut tap = tap.try_clone().expect("couldn't clone the TAP handle");
+            thread::spawn({
+                let backend = backend.clone();
+                move || {
+                    let mut frame = [0u8; 2048];
+                    loop {
+                        match tap.read(&mut frame) {
+                            Ok(len) if len <= NET_MTU => backend.deliver(frame[..len].to_vec()),
+                            Ok(len) => {
+                                log::warn!("dropping {}-byte frame, larger than the MTU", len)
+                            }
+                            Err(e) => {
+                                log::error!("TAP read failed, giving up on it: {:?}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+        if let Ok(path) = std::env::var("XOUS_NET_PCAP") {
+            let delay = std::env::var("XOUS_NET_PCAP_DELAY_MS")
+                .ok()
+                .and_then(|ms| ms.parse::<u64>().ok())
+                .unwrap_or(0);
+            thread::spawn({
+                let backend = backend.clone();
+                move || {
+                    thread::sleep(Duration::from_millis(delay));
+                    match backend.replay(&path) {
+                        Ok(count) => log::info!("replayed {} frames from {}", count, path),
+                        Err(e) => log::error!("replay of {} failed: {:?}", path, e),
+                    }
+                }
+            });
+        }
+        backend
+    }
+
+    /// Queues a received frame, and has the net server poll the interface.
+    fn deliver(&self, frame: Vec<u8>) {
+        {
+            let mut rx = self.rx.lock().unwrap();
+            if rx.len() >= RX_QUEUE_DEPTH {
+                log::warn!("hosted rx queue full, dropping the oldest frame");
+                rx.pop_front();
+            }
+            rx.push_back(frame);
+        }
+        if let Some(pump) = self.pump {
+            match xous::try_send_message(
+                pump,
+                xous::Message::new_scalar(Opcode::NetPump.to_usize().unwrap(), 0, 0, 0, 0),
+            ) {
+                Ok(_) | Err(xous::Error::ServerQueueFull) => {}
+                Err(e) => log::error!("Unhandled error sending NetPump: {:?}", e),
+            }
+        }
+    }
+
+    /// Feeds the frames of a capture to `deliver()` with their original spacing, returning how
+    /// many there were.
+    fn replay(&self, path: &str) -> io::Result<usize> {
+        let mut reader = pcap::Reader::new(BufReader::new(File::open(path)?))?;
+        let start = std::time::Instant::now();
+        let mut first = None;
+        let mut count = 0;
+        while let Some((timestamp, frame)) = reader.next_frame()? {
+            let first = *first.get_or_insert(timestamp);
+            let due = start + Duration::from_micros(timestamp.saturating_sub(first));
+            if let Some(wait) = due.checked_duration_since(std::time::Instant::now()) {
+                thread::sleep(wait);
+            }
+            if frame.len() > NET_MTU {
+                log::warn!("skipping {}-byte frame, larger than the MTU", frame.len());
+                continue;
+            }
+            self.deliver(frame);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn transmit(&self, frame: &[u8]) -> io::Result<()> {
+        if let Some(capture) = self.capture.as_ref() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as u64)
+                .unwrap_or(0);
+            let mut capture = capture.lock().unwrap();
+            capture.write_all(&pcap::record_header(
+                now,
+                frame.len() as u32,
+                frame.len() as u32,
+            ))?;
+            capture.write_all(frame)?;
+        }
+        if let Some(mut tap) = self.tap.as_ref() {
+            tap.write_all(frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_tap(name: &str) -> io::Result<File> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+    const IFF_TAP: libc::c_short = 0x0002;
+    const IFF_NO_PI: libc::c_short = 0x1000;
+    #[repr(C)]
+    struct IfReq {
+        name: [u8; libc::IFNAMSIZ],
+        flags: libc::c_short,
+        _pad: [u8; 22],
+    }
+    if name.is_empty() || name.len() >= libc::IFNAMSIZ {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "bad interface name",
+        ));
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")?;
+    let mut req = IfReq {
+        name: [0; libc::IFNAMSIZ],
+        flags: IFF_TAP | IFF_NO_PI,
+        _pad: [0; 22],
+    };
+    req.name[..name.len()].copy_from_slice(name.as_bytes());
+    if unsafe { libc::ioctl(file.as_raw_fd(), TUNSETIFF as _, &mut req as *mut IfReq) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+#[cfg(not(target_os = "linux"))]
+fn open_tap(_name: &str) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "TAP interfaces are only supported on Linux",
+    ))
+}
+
+/// The interface config given by `XOUS_NET_IPV4` and `XOUS_NET_MAC`, if there is one.
+pub fn static_config() -> Option<Ipv4Conf> {
+    let spec = std::env::var("XOUS_NET_IPV4").ok()?;
+    let addrs = match spec
+        .split(',')
+        .map(|a| a.trim().parse::<Ipv4Addr>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+    {
+        Ok(addrs) if addrs.len() == 2 || addrs.len() == 3 => addrs,
+        _ => {
+            log::error!(
+                "XOUS_NET_IPV4 should be <addr>,<gateway>[,<dns>], not {}",
+                spec
+            );
+            return None;
+        }
+    };
+    let mac = match std::env::var("XOUS_NET_MAC") {
+        Ok(mac) => match parse_mac(&mac) {
+            Some(mac) => mac,
+            None => {
+                log::error!(
+                    "XOUS_NET_MAC should be six hex bytes separated by colons, not {}",
+                    mac
+                );
+                return None;
+            }
+        },
+        Err(_) => DEFAULT_MAC,
+    };
+    let mut config = Ipv4Conf::default();
+    config.mac = mac;
+    config.addr = addrs[0].octets();
+    config.gtwy = addrs[1].octets();
+    config.dns1 = addrs.get(2).unwrap_or(&addrs[1]).octets();
+    Some(config)
+}
+
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.split(':');
+    for byte in bytes.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(bytes)
+}
+
+pub struct NetPhy {
+    backend: Arc<Backend>,
+    capture: Arc<CaptureTap>,
+}
+
+impl NetPhy {
+    pub fn new(_xns: &xous_names::XousNames, capture: Arc<CaptureTap>) -> NetPhy {
+        NetPhy {
+            backend: BACKEND.clone(),
+            capture,
+        }
+    }
+    // frames find their own way to the queue in hosted mode, so there's never a length to hold
+    pub fn push_rx_avail(&mut self, _len: u16) -> Option<u16> {
+        None
+    }
+}
+
+impl<'a> phy::Device<'a> for NetPhy {
+    type RxToken = NetPhyRxToken<'a>;
+    type TxToken = NetPhyTxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = self.backend.rx.lock().unwrap().pop_front()?;
+        Some((
+            NetPhyRxToken {
+                buf: frame,
+                capture: &self.capture,
+            },
+            NetPhyTxToken {
+                backend: &self.backend,
+                capture: &self.capture,
+            },
+        ))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(NetPhyTxToken {
+            backend: &self.backend,
+            capture: &self.capture,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = NET_MTU;
+        caps.max_burst_size = Some(1);
+        caps.medium = Medium::Ethernet;
+        caps
+    }
+}
+
+pub struct NetPhyRxToken<'a> {
+    buf: Vec<u8>,
+    capture: &'a CaptureTap,
+}
+
+impl<'a> phy::RxToken for NetPhyRxToken<'a> {
+    fn consume<R, F>(mut self, timestamp: Instant, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> Result<R>,
+    {
+        self.capture.tee(timestamp, &self.buf);
+        f(&mut self.buf)
+    }
+}
+
+pub struct NetPhyTxToken<'a> {
+    backend: &'a Backend,
+    capture: &'a CaptureTap,
+}
+
+impl<'a> phy::TxToken for NetPhyTxToken<'a> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> Result<R>,
+    {
+        let mut buf = vec![0u8; len];
+        let result = f(&mut buf);
+        if result.is_ok() {
+            self.capture.tee(timestamp, &buf);
+            self.backend.transmit(&buf).map_err(|e| {
+                log::warn!("hosted transmit failed: {:?}", e);
+                smoltcp::Error::Dropped
+            })?;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::iface::{InterfaceBuilder, NeighborCache};
+    use smoltcp::phy::{ChecksumCapabilities, Device};
+    use smoltcp::socket::SocketSet;
+    use smoltcp::wire::{
+        ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+        EthernetRepr, Icmpv4Packet, Icmpv4Repr, IpCidr, IpProtocol, Ipv4Address, Ipv4Packet,
+        Ipv4Repr,
+    };
+    use std::collections::BTreeMap;
+
+    const OUR_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x01]);
+    const OUR_IP: Ipv4Address = Ipv4Address([10, 0, 2, 15]);
+    const PEER_MAC: EthernetAddress = EthernetAddress([0x02, 0, 0, 0, 0, 0x02]);
+    const PEER_IP: Ipv4Address = Ipv4Address([10, 0, 2, 2]);
+
+    fn ethernet_frame(
+        dst_addr: EthernetAddress,
+        ethertype: EthernetProtocol,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let repr = EthernetRepr {
+            src_addr: PEER_MAC,
+            dst_addr,
+            ethertype,
+        };
+        let mut buf = vec![0u8; EthernetFrame::<&[u8]>::buffer_len(payload.len())];
+        let mut frame = EthernetFrame::new_unchecked(&mut buf);
+        repr.emit(&mut frame);
+        frame.payload_mut().copy_from_slice(payload);
+        buf
+    }
+
+    fn arp_request() -> Vec<u8> {
+        let repr = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: PEER_MAC,
+            source_protocol_addr: PEER_IP,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: OUR_IP,
+        };
+        let mut payload = vec![0u8; repr.buffer_len()];
+        repr.emit(&mut ArpPacket::new_unchecked(&mut payload));
+        ethernet_frame(EthernetAddress::BROADCAST, EthernetProtocol::Arp, &payload)
+    }
+
+    fn echo_request(data: &[u8]) -> Vec<u8> {
+        let caps = ChecksumCapabilities::default();
+        let icmp = Icmpv4Repr::EchoRequest {
+            ident: 0x1234,
+            seq_no: 1,
+            data,
+        };
+        let ip = Ipv4Repr {
+            src_addr: PEER_IP,
+            dst_addr: OUR_IP,
+            protocol: IpProtocol::Icmp,
+            payload_len: icmp.buffer_len(),
+            hop_limit: 64,
+        };
+        let mut payload = vec![0u8; ip.buffer_len() + icmp.buffer_len()];
+        let mut packet = Ipv4Packet::new_unchecked(&mut payload);
+        ip.emit(&mut packet, &caps);
+        icmp.emit(
+            &mut Icmpv4Packet::new_unchecked(packet.payload_mut()),
+            &caps,
+        );
+        ethernet_frame(OUR_MAC, EthernetProtocol::Ipv4, &payload)
+    }
+
+    /// Replays an ARP request and a ping through `XOUS_NET_PCAP`, and checks that the replies
+    /// smoltcp sends come out in `XOUS_NET_PCAP_OUT`.
+    #[test]
+    fn replay_arp_and_ping() {
+        let dir = std::env::temp_dir().join(format!("xous-net-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.pcap");
+        let output = dir.join("out.pcap");
+        let mut capture = pcap::file_header(NET_MTU as u32).to_vec();
+        for (timestamp, frame) in [
+            (1_000_000, arp_request()),
+            (1_001_000, echo_request(b"xous")),
+        ]
+        .iter()
+        {
+            let len = frame.len() as u32;
+            capture.extend_from_slice(&pcap::record_header(*timestamp, len, len));
+            capture.extend_from_slice(frame);
+        }
+        std::fs::write(&input, &capture).unwrap();
+        std::env::set_var("XOUS_NET_PCAP", &input);
+        std::env::set_var("XOUS_NET_PCAP_OUT", &output);
+
+        // there's no net server to pump, so the interface is polled until the replies are out
+        let device = NetPhy {
+            backend: Backend::from_env(None),
+            capture: Arc::new(CaptureTap::default()),
+        };
+        assert_eq!(device.capabilities().medium, Medium::Ethernet);
+        let mut iface = InterfaceBuilder::new(device)
+            .ethernet_addr(OUR_MAC)
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs([IpCidr::new(OUR_IP.into(), 24)])
+            .finalize();
+        let mut sockets = SocketSet::new(vec![]);
+        let start = std::time::Instant::now();
+        let mut sent = Vec::new();
+        while sent.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            iface
+                .poll(
+                    &mut sockets,
+                    Instant::from_millis(start.elapsed().as_millis() as i64),
+                )
+                .ok();
+            thread::sleep(Duration::from_millis(5));
+            let mut reader = pcap::Reader::new(File::open(&output).unwrap()).unwrap();
+            sent.clear();
+            while let Some((_, frame)) = reader.next_frame().unwrap() {
+                sent.push(frame);
+            }
+        }
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(sent.len(), 2, "expected an ARP reply and an echo reply");
+
+        let frame = EthernetFrame::new_checked(&sent[0][..]).unwrap();
+        assert_eq!(frame.dst_addr(), PEER_MAC);
+        assert_eq!(frame.ethertype(), EthernetProtocol::Arp);
+        assert_eq!(
+            ArpRepr::parse(&ArpPacket::new_checked(frame.payload()).unwrap()).unwrap(),
+            ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Reply,
+                source_hardware_addr: OUR_MAC,
+                source_protocol_addr: OUR_IP,
+                target_hardware_addr: PEER_MAC,
+                target_protocol_addr: PEER_IP,
+            }
+        );
+
+        let caps = ChecksumCapabilities::default();
+        let frame = EthernetFrame::new_checked(&sent[1][..]).unwrap();
+        assert_eq!(frame.dst_addr(), PEER_MAC);
+        assert_eq!(frame.ethertype(), EthernetProtocol::Ipv4);
+        let packet = Ipv4Packet::new_checked(frame.payload()).unwrap();
+        let ip = Ipv4Repr::parse(&packet, &caps).unwrap();
+        assert_eq!((ip.src_addr, ip.dst_addr), (OUR_IP, PEER_IP));
+        assert_eq!(
+            Icmpv4Repr::parse(&Icmpv4Packet::new_checked(packet.payload()).unwrap(), &caps)
+                .unwrap(),
+            Icmpv4Repr::EchoReply {
+                ident: 0x1234,
+                seq_no: 1,
+                data: b"xous",
+            }
+        );
+    }
+}
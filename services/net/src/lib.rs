@@ -8,6 +8,8 @@ use num_traits::*;
 
 pub mod protocols;
 pub use protocols::*;
+pub mod saved_networks;
+pub use saved_networks::{SavedNetwork, SavedNetworks};
 pub use smoltcp::time::Duration;
 pub use api::*;
 pub use smoltcp::wire::IpEndpoint;
@@ -160,6 +162,81 @@ impl NetManager {
         }
         Ok(ret)
     }
+    /// Joins the IPv4 multicast group `group`, so that UDP sockets bound to its port receive
+    /// datagrams sent to it. The membership belongs to the calling process, lasts until it calls
+    /// `leave_multicast_v4()`, and is kept across link resets and DHCP renewals. Joining a group
+    /// the process is already in does nothing.
+    pub fn join_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        self.multicast_op(Opcode::JoinMulticastV4, group)
+    }
+    pub fn leave_multicast_v4(&self, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        self.multicast_op(Opcode::LeaveMulticastV4, group)
+    }
+    fn multicast_op(&self, op: Opcode, group: std::net::Ipv4Addr) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(op.to_usize().unwrap(), u32::from(group) as usize, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(0) => Ok(()),
+            // not a multicast address
+            xous::Result::Scalar1(code) if code == NetError::Unaddressable as usize => Err(xous::Error::BadAddress),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Starts capturing the frames that cross the network device, keeping those of `protocol`, to or
+    /// from `port` if one is given, in a ring buffer until `capture_stop()`. Any capture that is
+    /// already running is discarded.
+    pub fn capture_start(&self, protocol: CaptureProtocol, port: Option<u16>) -> Result<(), xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::CaptureStart.to_usize().unwrap(),
+                protocol.to_usize().unwrap(),
+                port.unwrap_or(0) as usize,
+                0, 0)
+        )? {
+            xous::Result::Scalar1(0) => Ok(()),
+            // a port was given for a protocol that doesn't have them
+            xous::Result::Scalar1(code) if code == NetError::Invalid as usize => Err(xous::Error::InvalidSyscall),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Stops the capture, and saves it as a pcap file under `key` in the `CAPTURE_DICT_NAME` dictionary,
+    /// replacing whatever was there. Returns `InvalidString` for an empty key, in which case the capture
+    /// keeps running, `UseBeforeInit` if no capture was running, and `AccessDenied` if the PDDB couldn't
+    /// be written, in which case the capture is lost but whatever was under `key` is kept.
+    pub fn capture_stop(&self, key: &str) -> Result<CaptureSummary, xous::Error> {
+        if key.is_empty() {
+            return Err(xous::Error::InvalidString);
+        }
+        let save = CaptureSave {
+            key: xous_ipc::String::from_str(key),
+            frames: 0,
+            dropped: 0,
+            result: NetMemResponse::Ok,
+        };
+        let mut buf = Buffer::into_buf(save).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.netconn.conn(), Opcode::CaptureStop.to_u32().unwrap())?;
+        let save = buf.to_original::<CaptureSave, _>().or(Err(xous::Error::InternalError))?;
+        match save.result {
+            NetMemResponse::Ok => Ok(CaptureSummary {
+                frames: save.frames,
+                dropped: save.dropped,
+            }),
+            NetMemResponse::Invalid => Err(xous::Error::UseBeforeInit),
+            NetMemResponse::LibraryError => Err(xous::Error::InvalidString),
+            NetMemResponse::AccessDenied => Err(xous::Error::AccessDenied),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
+    /// Whether the Wi-Fi network the device is joined to is marked metered in its saved settings,
+    /// in which case bulk transfers should be put off until a cheaper network comes along. This is
+    /// `false` when the device isn't joined to a saved network.
+    pub fn is_metered(&self) -> Result<bool, xous::Error> {
+        match send_message(self.netconn.conn(),
+            Message::new_blocking_scalar(Opcode::IsMetered.to_usize().unwrap(), 0, 0, 0, 0)
+        )? {
+            xous::Result::Scalar1(metered) => Ok(metered != 0),
+            _ => Err(xous::Error::InternalError),
+        }
+    }
     pub fn connection_manager_stop(&self) -> Result<(), xous::Error> {
         send_message(self.netconn.conn(),
             Message::new_scalar(Opcode::ConnMgrStartStop.to_usize().unwrap(), 0, 0,0, 0)
@@ -15,10 +15,18 @@ use std_tcplistener::*;
 use com::api::{ComIntSources, Ipv4Conf, NET_MTU};
 use num_traits::*;
 
+mod capture;
 mod connection_manager;
+#[cfg(any(target_os = "none", target_os = "xous"))]
 mod device;
-
-use std::collections::{BTreeMap, HashMap};
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+mod device_hosted;
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+use device_hosted as device;
+mod pcap;
+mod wifi_menu;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryInto;
 use xous::{msg_blocking_scalar_unpack, msg_scalar_unpack, send_message, Message, CID, SID};
 use xous_ipc::Buffer;
@@ -55,6 +63,90 @@ where
     });
 }
 
+/// Joins the multicast groups that clients have asked for on a freshly built interface.
+fn rejoin_multicast_groups<DeviceT>(
+    iface: &mut Interface<'_, DeviceT>,
+    groups: &BTreeMap<Ipv4Address, BTreeSet<Option<xous::PID>>>,
+    timestamp: Instant,
+) where
+    DeviceT: for<'d> Device<'d>,
+{
+    for &group in groups.keys() {
+        if let Err(e) = iface.join_multicast_group(group, timestamp) {
+            log::warn!("couldn't rejoin multicast group {}: {:?}", group, e);
+        }
+    }
+}
+
+/// Writes a finished capture to `CAPTURE_DICT_NAME`, replacing whatever was stored under `key`.
+/// This goes through a transaction, so a capture that can't be written leaves the old one intact.
+fn save_capture(key: &str, pcap: &[u8]) -> std::io::Result<()> {
+    let mut pddb = pddb::Pddb::new();
+    let mut txn = pddb.transaction(None)?;
+    if let Err(e) = pddb.txn_write(&mut txn, CAPTURE_DICT_NAME, key, pcap) {
+        pddb.abort(txn).ok();
+        return Err(e);
+    }
+    pddb.commit(txn)
+}
+
+/// Builds a fresh interface with the address, default route and MAC of `config`, joined to the
+/// multicast groups in `groups`.
+fn ipv4_iface<'a>(
+    xns: &xous_names::XousNames,
+    config: &Ipv4Conf,
+    groups: &BTreeMap<Ipv4Address, BTreeSet<Option<xous::PID>>>,
+    capture: &Arc<capture::CaptureTap>,
+    timestamp: Instant,
+) -> Interface<'a, device::NetPhy> {
+    let mac = EthernetAddress::from_bytes(&config.mac);
+
+    // we need to clear the ARP cache in case we've migrated base stations (e.g. in a wireless network
+    // that is coverd by multiple AP), as the host AP's MAC address would have changed, and we wouldn't
+    // be able to route responses back. I can't seem to find a function in smoltcp 0.7.5 that allows us
+    // to neatly clear the ARP cache as the BTreeMap that underlies it is moved into the container and
+    // no "clear" API is exposed, so let's just rebuild the whole interface if we get a DHCP renewal.
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
+    let routes = Routes::new(BTreeMap::new());
+    let device = device::NetPhy::new(xns, capture.clone());
+    let medium = device.capabilities().medium;
+    let mut builder = InterfaceBuilder::new(device)
+        .ip_addrs(ip_addrs)
+        .routes(routes)
+        .ipv4_multicast_groups(BTreeMap::new());
+    if medium == Medium::Ethernet {
+        builder = builder.ethernet_addr(mac).neighbor_cache(neighbor_cache);
+    }
+    let mut iface = builder.finalize();
+
+    let ip_addr = Ipv4Cidr::new(
+        Ipv4Address::new(
+            config.addr[0],
+            config.addr[1],
+            config.addr[2],
+            config.addr[3],
+        ),
+        24,
+    );
+    set_ipv4_addr(&mut iface, ip_addr);
+    let default_v4_gw = Ipv4Address::new(
+        config.gtwy[0],
+        config.gtwy[1],
+        config.gtwy[2],
+        config.gtwy[3],
+    );
+
+    // reset the default route, in case it has changed
+    iface.routes_mut().remove_default_ipv4_route();
+    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
+        Ok(route) => log::info!("routing table updated successfully [{:?}]", route),
+        Err(e) => log::error!("routing table update error: {}", e),
+    }
+    rejoin_multicast_groups(&mut iface, groups, timestamp);
+    iface
+}
+
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 enum WaitOp {
     WaitMs,
@@ -185,6 +277,12 @@ fn xmain() -> ! {
     // incoming UDP socket data.
     let mut udp_rx_waiting: Vec<Option<UdpStdState>> = Vec::new();
 
+    // IPv4 multicast groups joined on behalf of clients, with the processes that joined each. A
+    // process can only give up its own membership, so one client leaving a group can't drop it
+    // from under another. The interface forgets its groups whenever it is rebuilt, so they are
+    // joined again from here.
+    let mut multicast_groups = BTreeMap::<Ipv4Address, BTreeSet<Option<xous::PID>>>::new();
+
     // ------------- native variant -----------
     // ping storage
     // up to four concurrent pings in the queue
@@ -222,19 +320,35 @@ fn xmain() -> ! {
     let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
     let routes = Routes::new(BTreeMap::new());
 
-    let device = device::NetPhy::new(&xns);
+    // frames crossing the device are teed into this while a capture is running
+    let capture = Arc::new(capture::CaptureTap::default());
+    let device = device::NetPhy::new(&xns, capture.clone());
     // needed by ICMP to determine if we should compute checksums
     let device_caps = device.capabilities();
     let medium = device.capabilities().medium;
     let mut builder = InterfaceBuilder::new(device)
         .ip_addrs(ip_addrs)
-        .routes(routes);
+        .routes(routes)
+        .ipv4_multicast_groups(BTreeMap::new());
     if medium == Medium::Ethernet {
         builder = builder
             .ethernet_addr(EthernetAddress::from_bytes(&[0; 6]))
             .neighbor_cache(neighbor_cache);
     }
     let mut iface = builder.finalize();
+    // there's no EC to hand out a DHCP lease in hosted mode, so the address comes from the environment
+    #[cfg(not(any(target_os = "none", target_os = "xous")))]
+    if let Some(config) = device::static_config() {
+        log::info!("Network config from the environment: {:?}", config);
+        net_config = Some(config);
+        iface = ipv4_iface(
+            &xns,
+            &config,
+            &multicast_groups,
+            &capture,
+            Instant::from_millis(timer.elapsed_ms() as i64),
+        );
+    }
 
     // DNS hooks - the DNS server can ask the Net crate to tickle it when IP configs change using these hooks
     // Currently, we assume there is only one DNS server in Xous. I suppose you could
@@ -268,6 +382,13 @@ fn xmain() -> ! {
         }
     });
 
+    // the saved network menu is registered with the GAM at boot, so it runs even without a connection manager
+    let menu_sid = xous::create_server().expect("couldn't create wifi menu server");
+    let menu_cid = xous::connect(menu_sid).unwrap();
+    thread::spawn(move || {
+        wifi_menu::wifi_menu(menu_sid);
+    });
+
     let mut cid_to_disconnect: Option<CID> = None;
     loop {
         let mut msg = xous::receive_message(net_sid).unwrap();
@@ -463,6 +584,100 @@ fn xmain() -> ! {
                 dns_allclear_hook.clear();
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack unhook");
             }),
+            Some(Opcode::JoinMulticastV4) => msg_blocking_scalar_unpack!(msg, group, _, _, _, {
+                let group = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                if !group.is_multicast() {
+                    xous::return_scalar(msg.sender, NetError::Unaddressable as usize).unwrap();
+                } else {
+                    let members = multicast_groups.entry(group).or_default();
+                    // joining again from the same process changes nothing
+                    if members.insert(msg.sender.pid()) && members.len() == 1 {
+                        // this fails while the link is down, but the group is joined again once we
+                        // have an address
+                        let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                        if let Err(e) = iface.join_multicast_group(group, timestamp) {
+                            log::warn!("couldn't join multicast group {}: {:?}", group, e);
+                        }
+                    }
+                    xous::return_scalar(msg.sender, 0).unwrap();
+                }
+            }),
+            Some(Opcode::LeaveMulticastV4) => msg_blocking_scalar_unpack!(msg, group, _, _, _, {
+                let group = Ipv4Address::from_bytes(&(group as u32).to_be_bytes());
+                match multicast_groups.get_mut(&group) {
+                    Some(members) if members.remove(&msg.sender.pid()) => {
+                        if members.is_empty() {
+                            multicast_groups.remove(&group);
+                            let timestamp = Instant::from_millis(timer.elapsed_ms() as i64);
+                            if let Err(e) = iface.leave_multicast_group(group, timestamp) {
+                                log::warn!("couldn't leave multicast group {}: {:?}", group, e);
+                            }
+                        }
+                        xous::return_scalar(msg.sender, 0).unwrap();
+                    }
+                    _ => {
+                        // not a member, or the group was joined by some other process
+                        xous::return_scalar(msg.sender, NetError::Invalid as usize).unwrap();
+                    }
+                }
+            }),
+            Some(Opcode::CaptureStart) => msg_blocking_scalar_unpack!(msg, protocol, port, _, _, {
+                let filter = FromPrimitive::from_usize(protocol).map(|protocol| capture::Filter {
+                    protocol,
+                    port: if port == 0 { None } else { Some(port as u16) },
+                });
+                match filter {
+                    Some(filter) if filter.is_valid() => {
+                        log::info!("starting packet capture: {:?}", filter);
+                        capture.start(filter);
+                        xous::return_scalar(msg.sender, 0).unwrap();
+                    }
+                    _ => {
+                        xous::return_scalar(msg.sender, NetError::Invalid as usize).unwrap();
+                    }
+                }
+            }),
+            Some(Opcode::CaptureStop) => {
+                let mut buf = unsafe {
+                    Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap())
+                };
+                let mut save = buf.to_original::<CaptureSave, _>().unwrap();
+                // check the key before stopping, so a bad one doesn't cost the capture
+                let key = match save.key.as_str() {
+                    Ok(key) if !key.is_empty() => key.to_string(),
+                    _ => {
+                        save.result = NetMemResponse::LibraryError;
+                        buf.replace(save).unwrap();
+                        continue;
+                    }
+                };
+                let saved = capture.stop();
+                save.result = match saved {
+                    Some(saved) => {
+                        save.frames = saved.frames;
+                        save.dropped = saved.dropped;
+                        // this holds up the stack for as long as the PDDB takes, but captures are
+                        // only ever taken while debugging
+                        match save_capture(&key, &saved.pcap) {
+                            Ok(()) => {
+                                log::info!(
+                                    "saved {} captured frames to {}:{}",
+                                    saved.frames,
+                                    CAPTURE_DICT_NAME,
+                                    save.key
+                                );
+                                NetMemResponse::Ok
+                            }
+                            Err(e) => {
+                                log::error!("couldn't save capture: {:?}", e);
+                                NetMemResponse::AccessDenied
+                            }
+                        }
+                    }
+                    None => NetMemResponse::Invalid,
+                };
+                buf.replace(save).unwrap();
+            }
 
             Some(Opcode::StdTcpConnect) => {
                 // Pick a random local port using the system's TRNG
@@ -950,55 +1165,13 @@ fn xmain() -> ! {
                                         .expect("couldn't retrieve updated ipv4 config");
                                     log::info!("Network config acquired: {:?}", config);
                                     net_config = Some(config);
-                                    let mac = EthernetAddress::from_bytes(&config.mac);
-
-                                    // we need to clear the ARP cache in case we've migrated base stations (e.g. in a wireless network
-                                    // that is coverd by multiple AP), as the host AP's MAC address would have changed, and we wouldn't
-                                    // be able to route responses back. I can't seem to find a function in smoltcp 0.7.5 that allows us
-                                    // to neatly clear the ARP cache as the BTreeMap that underlies it is moved into the container and
-                                    // no "clear" API is exposed, so let's just rebuild the whole interface if we get a DHCP renewal.
-                                    let neighbor_cache = NeighborCache::new(BTreeMap::new());
-                                    let ip_addrs =
-                                        [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
-                                    let routes = Routes::new(BTreeMap::new());
-                                    let device = device::NetPhy::new(&xns);
-                                    let medium = device.capabilities().medium;
-                                    let mut builder = InterfaceBuilder::new(device)
-                                        .ip_addrs(ip_addrs)
-                                        .routes(routes);
-                                    if medium == Medium::Ethernet {
-                                        builder = builder
-                                            .ethernet_addr(mac)
-                                            .neighbor_cache(neighbor_cache);
-                                    }
-                                    iface = builder.finalize();
-
-                                    let ip_addr = Ipv4Cidr::new(
-                                        Ipv4Address::new(
-                                            config.addr[0],
-                                            config.addr[1],
-                                            config.addr[2],
-                                            config.addr[3],
-                                        ),
-                                        24,
-                                    );
-                                    set_ipv4_addr(&mut iface, ip_addr);
-                                    let default_v4_gw = Ipv4Address::new(
-                                        config.gtwy[0],
-                                        config.gtwy[1],
-                                        config.gtwy[2],
-                                        config.gtwy[3],
+                                    iface = ipv4_iface(
+                                        &xns,
+                                        &config,
+                                        &multicast_groups,
+                                        &capture,
+                                        Instant::from_millis(timer.elapsed_ms() as i64),
                                     );
-
-                                    // reset the default route, in case it has changed
-                                    iface.routes_mut().remove_default_ipv4_route();
-                                    match iface.routes_mut().add_default_ipv4_route(default_v4_gw) {
-                                        Ok(route) => log::info!(
-                                            "routing table updated successfully [{:?}]",
-                                            route
-                                        ),
-                                        Err(e) => log::error!("routing table update error: {}", e),
-                                    }
                                     dns_allclear_hook.notify();
                                     dns_ipv4_hook.notify_custom_args([
                                         Some(u32::from_be_bytes(config.dns1)),
@@ -1692,6 +1865,24 @@ fn xmain() -> ! {
                     .expect("couldn't restore original");
                 buffer.replace(ret_list).expect("couldn't return config");
             }
+            Some(Opcode::IsMetered) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
+                let metered = match send_message(
+                    cm_cid,
+                    Message::new_blocking_scalar(
+                        connection_manager::ConnectionManagerOpcode::IsMetered
+                            .to_usize()
+                            .unwrap(),
+                        0,
+                        0,
+                        0,
+                        0,
+                    ),
+                ) {
+                    Ok(xous::Result::Scalar1(metered)) => metered,
+                    _ => 0,
+                };
+                xous::return_scalar(msg.sender, metered).unwrap();
+            }),
             Some(Opcode::ConnMgrStartStop) => msg_scalar_unpack!(msg, code, _, _, _, {
                 if code == 0 {
                     // 0 is stop, 1 is start
@@ -1731,11 +1922,12 @@ fn xmain() -> ! {
                 let neighbor_cache = NeighborCache::new(BTreeMap::new());
                 let ip_addrs = [IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0)];
                 let routes = Routes::new(BTreeMap::new());
-                let device = device::NetPhy::new(&xns);
+                let device = device::NetPhy::new(&xns, capture.clone());
                 let medium = device.capabilities().medium;
                 let mut builder = InterfaceBuilder::new(device)
                     .ip_addrs(ip_addrs)
-                    .routes(routes);
+                    .routes(routes)
+                    .ipv4_multicast_groups(BTreeMap::new());
                 if medium == Medium::Ethernet {
                     builder = builder
                         .ethernet_addr(EthernetAddress::from_bytes(&[0; 6]))
@@ -1791,6 +1983,12 @@ fn xmain() -> ! {
     )
     .expect("couldn't quit connection manager server");
     unsafe { xous::disconnect(cm_cid).ok() };
+    xous::send_message(
+        menu_cid,
+        Message::new_blocking_scalar(wifi_menu::WifiMenuOpcode::Quit.to_usize().unwrap(), 0, 0, 0, 0),
+    )
+    .expect("couldn't quit wifi menu server");
+    unsafe { xous::disconnect(menu_cid).ok() };
     xns.unregister_server(net_sid).unwrap();
     xous::destroy_server(net_sid).unwrap();
     log::trace!("quitting");
@@ -0,0 +1,159 @@
+//! The classic libpcap file format, as read and written by Wireshark and tcpdump: a 24-byte file
+//! header followed by one 16-byte record header per frame. Only Ethernet captures are written.
+#![allow(dead_code)]
+
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Read, Result};
+
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const FILE_HEADER_LEN: usize = 24;
+pub const RECORD_HEADER_LEN: usize = 16;
+
+/// The header that starts every capture. Frames longer than `snaplen` are cut short when recorded.
+pub fn file_header(snaplen: u32) -> [u8; FILE_HEADER_LEN] {
+    let mut header = [0u8; FILE_HEADER_LEN];
+    header[0..4].copy_from_slice(&MAGIC_MICROS.to_le_bytes());
+    header[4..6].copy_from_slice(&2u16.to_le_bytes()); // version 2.4
+    header[6..8].copy_from_slice(&4u16.to_le_bytes());
+    // thiszone and sigfigs are always zero
+    header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// The header for one frame of `orig_len` bytes, of which `incl_len` are recorded, seen at
+/// `timestamp_us` microseconds.
+pub fn record_header(timestamp_us: u64, incl_len: u32, orig_len: u32) -> [u8; RECORD_HEADER_LEN] {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    header[0..4].copy_from_slice(&((timestamp_us / 1_000_000) as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&((timestamp_us % 1_000_000) as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&incl_len.to_le_bytes());
+    header[12..16].copy_from_slice(&orig_len.to_le_bytes());
+    header
+}
+
+/// Reads the frames of an Ethernet capture, in either byte order and with either microsecond or
+/// nanosecond timestamps.
+pub struct Reader<R: Read> {
+    inner: R,
+    big_endian: bool,
+    nanos: bool,
+    snaplen: u32,
+}
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> Result<Reader<R>> {
+        let mut header = [0u8; FILE_HEADER_LEN];
+        inner.read_exact(&mut header)?;
+        let magic_le = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let magic_be = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, nanos) = match (magic_le, magic_be) {
+            (MAGIC_MICROS, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC_MICROS) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "not a pcap file")),
+        };
+        let mut reader = Reader {
+            inner,
+            big_endian,
+            nanos,
+            snaplen: 0,
+        };
+        reader.snaplen = reader.u32_at(&header, 16);
+        if reader.u32_at(&header, 20) != LINKTYPE_ETHERNET {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "not an Ethernet capture",
+            ));
+        }
+        Ok(reader)
+    }
+    fn u32_at(&self, buf: &[u8], at: usize) -> u32 {
+        let bytes = buf[at..at + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+    /// The next frame and its timestamp in microseconds, or `None` at the end of the capture.
+    pub fn next_frame(&mut self) -> Result<Option<(u64, Vec<u8>)>> {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        // a clean end of file only counts between records
+        let mut got = 0;
+        while got < header.len() {
+            match self.inner.read(&mut header[got..])? {
+                0 if got == 0 => return Ok(None),
+                0 => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "truncated record header",
+                    ))
+                }
+                n => got += n,
+            }
+        }
+        let secs = self.u32_at(&header, 0) as u64;
+        let frac = self.u32_at(&header, 4) as u64;
+        let incl_len = self.u32_at(&header, 8);
+        if incl_len > self.snaplen.max(65535) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "record longer than the snap length",
+            ));
+        }
+        let mut frame = vec![0u8; incl_len as usize];
+        self.inner.read_exact(&mut frame)?;
+        let micros = if self.nanos { frac / 1000 } else { frac };
+        Ok(Some((secs * 1_000_000 + micros, frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut capture = file_header(1514).to_vec();
+        let frames: [(u64, &[u8]); 2] = [
+            (1_650_000_000_123_456, &[0xff; 60]),
+            (1_650_000_001_000_001, &[0x5a; 1514]),
+        ];
+        for (ts, frame) in frames.iter() {
+            capture.extend_from_slice(&record_header(*ts, frame.len() as u32, frame.len() as u32));
+            capture.extend_from_slice(frame);
+        }
+        let mut reader = Reader::new(&capture[..]).unwrap();
+        for (ts, frame) in frames.iter() {
+            assert_eq!(reader.next_frame().unwrap(), Some((*ts, frame.to_vec())));
+        }
+        assert_eq!(reader.next_frame().unwrap(), None);
+
+        // cut off partway through the last frame
+        let mut reader = Reader::new(&capture[..capture.len() - 1]).unwrap();
+        assert!(reader.next_frame().unwrap().is_some());
+        assert!(reader.next_frame().is_err());
+    }
+
+    #[test]
+    fn big_endian_nanos() {
+        let mut capture = vec![
+            0xa1, 0xb2, 0x3c, 0x4d, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0,
+            1,
+        ];
+        capture.extend_from_slice(&[
+            0, 0, 0, 2, 0x3b, 0x9a, 0xc9, 0xff, 0, 0, 0, 3, 0, 0, 0, 3, 1, 2, 3,
+        ]);
+        let mut reader = Reader::new(&capture[..]).unwrap();
+        assert_eq!(
+            reader.next_frame().unwrap(),
+            Some((2_999_999, vec![1, 2, 3]))
+        );
+
+        capture[23] = 105; // 802.11
+        assert!(Reader::new(&capture[..]).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! The Wi-Fi networks the user has saved. Each one is a key in `AP_DICT_NAME`, named for its SSID
+//! and holding its password. Everything else about a network is kept under the same name in
+//! `AP_SETTINGS_DICT_NAME`, so networks saved before the settings existed simply take the defaults.
+
+use crate::api::{AP_DICT_NAME, AP_SETTINGS_DICT_NAME};
+use com::api::WF200_PASS_MAX_LEN;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// the priority of a network that has never had one set
+pub const DEFAULT_PRIORITY: u8 = 128;
+
+const SETTINGS_VERSION: u8 = 1;
+const SETTINGS_LEN: usize = 12;
+const FLAG_AUTO_JOIN: u8 = 0x1;
+const FLAG_HIDDEN: u8 = 0x2;
+const FLAG_METERED: u8 = 0x4;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SavedNetwork {
+    pub ssid: String,
+    /// networks with a higher priority are joined first; signal strength breaks ties
+    pub priority: u8,
+    /// if false, the connection manager never joins the network on its own
+    pub auto_join: bool,
+    /// the AP doesn't broadcast its SSID, so it's tried even when it doesn't show up in a scan
+    pub hidden: bool,
+    /// the link is paid for by the byte, so bulk transfers should wait for another network;
+    /// applications check the network they're on with `NetManager::is_metered()`
+    pub metered: bool,
+    /// when the network was last joined, in seconds since the UNIX epoch
+    pub last_connected: Option<u64>,
+}
+impl SavedNetwork {
+    /// A network with the default settings.
+    pub fn new(ssid: &str) -> SavedNetwork {
+        SavedNetwork {
+            ssid: String::from(ssid),
+            priority: DEFAULT_PRIORITY,
+            auto_join: true,
+            hidden: false,
+            metered: false,
+            last_connected: None,
+        }
+    }
+    fn settings_to_bytes(&self) -> [u8; SETTINGS_LEN] {
+        let mut bytes = [0u8; SETTINGS_LEN];
+        bytes[0] = SETTINGS_VERSION;
+        bytes[1] = self.priority;
+        if self.auto_join {
+            bytes[2] |= FLAG_AUTO_JOIN;
+        }
+        if self.hidden {
+            bytes[2] |= FLAG_HIDDEN;
+        }
+        if self.metered {
+            bytes[2] |= FLAG_METERED;
+        }
+        // zero stands for "never", which would otherwise be 1970
+        bytes[4..12].copy_from_slice(&self.last_connected.unwrap_or(0).to_le_bytes());
+        bytes
+    }
+    /// Settings that are missing, or from some other version, are replaced by the defaults.
+    fn settings_from_bytes(ssid: &str, bytes: &[u8]) -> SavedNetwork {
+        let mut network = SavedNetwork::new(ssid);
+        if bytes.len() == SETTINGS_LEN && bytes[0] == SETTINGS_VERSION {
+            network.priority = bytes[1];
+            network.auto_join = bytes[2] & FLAG_AUTO_JOIN != 0;
+            network.hidden = bytes[2] & FLAG_HIDDEN != 0;
+            network.metered = bytes[2] & FLAG_METERED != 0;
+            let mut last = [0u8; 8];
+            last.copy_from_slice(&bytes[4..12]);
+            network.last_connected = match u64::from_le_bytes(last) {
+                0 => None,
+                secs => Some(secs),
+            };
+        }
+        network
+    }
+}
+
+/// The SSIDs worth trying to join, best first. A network qualifies if it allows auto-join and was
+/// seen in the last scan, or is hidden and so couldn't have been. They're ordered by priority, then
+/// by signal strength; `visible` maps SSIDs to their RSSI, in -dBm.
+pub fn join_order(saved: &[SavedNetwork], visible: &HashMap<String, u8>) -> Vec<String> {
+    let mut candidates: Vec<(u8, u8, &str)> = saved
+        .iter()
+        .filter(|network| network.auto_join)
+        .filter_map(|network| match visible.get(&network.ssid) {
+            Some(&rssi) => Some((network.priority, rssi, network.ssid.as_str())),
+            // there's no telling how strong a hidden network is, so it comes after the ones in view
+            None if network.hidden => Some((network.priority, u8::MAX, network.ssid.as_str())),
+            None => None,
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(b.2)));
+    candidates
+        .into_iter()
+        .map(|(_, _, ssid)| String::from(ssid))
+        .collect()
+}
+
+/// The saved networks, as stored in the PDDB.
+pub struct SavedNetworks {
+    pddb: pddb::Pddb,
+}
+impl SavedNetworks {
+    pub fn new() -> SavedNetworks {
+        SavedNetworks {
+            pddb: pddb::Pddb::new(),
+        }
+    }
+    /// Every saved network, highest priority first.
+    pub fn list(&mut self) -> Result<Vec<SavedNetwork>> {
+        let mut networks = Vec::new();
+        for ssid in self.pddb.list_keys(AP_DICT_NAME, None)? {
+            networks.push(self.settings(&ssid));
+        }
+        networks.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.ssid.cmp(&b.ssid)));
+        Ok(networks)
+    }
+    /// The saved network named `ssid`.
+    pub fn get(&mut self, ssid: &str) -> Result<SavedNetwork> {
+        if !self
+            .pddb
+            .list_keys(AP_DICT_NAME, None)?
+            .iter()
+            .any(|s| s == ssid)
+        {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "no network of that name is saved",
+            ));
+        }
+        Ok(self.settings(ssid))
+    }
+    fn settings(&mut self, ssid: &str) -> SavedNetwork {
+        let mut bytes = [0u8; SETTINGS_LEN];
+        let len = match self.pddb.get(
+            AP_SETTINGS_DICT_NAME,
+            ssid,
+            None,
+            false,
+            false,
+            None,
+            None::<fn()>,
+        ) {
+            Ok(mut key) => key.read(&mut bytes).unwrap_or(0),
+            Err(_) => 0,
+        };
+        SavedNetwork::settings_from_bytes(ssid, &bytes[..len])
+    }
+    pub fn password(&mut self, ssid: &str) -> Result<String> {
+        let mut key = self
+            .pddb
+            .get(AP_DICT_NAME, ssid, None, false, false, None, None::<fn()>)?;
+        let mut pw = [0u8; WF200_PASS_MAX_LEN];
+        let len = key.read(&mut pw)?;
+        String::from_utf8(pw[..len].to_vec()).or(Err(Error::new(
+            ErrorKind::InvalidData,
+            "password was not valid utf-8",
+        )))
+    }
+    /// Saves a network, or changes the password of one that's already saved. The settings of a
+    /// network that's already saved are kept; a new one gets the defaults.
+    pub fn save(&mut self, ssid: &str, password: &str) -> Result<()> {
+        // a transaction replaces the whole key, so a shorter password doesn't leave the tail of the
+        // old one behind, and the old password survives if the new one can't be written
+        let mut txn = self.pddb.transaction(None)?;
+        if let Err(e) = self
+            .pddb
+            .txn_write(&mut txn, AP_DICT_NAME, ssid, password.as_bytes())
+        {
+            self.pddb.abort(txn).ok();
+            return Err(e);
+        }
+        self.pddb.commit(txn)
+    }
+    /// Changes the settings of a saved network.
+    pub fn update(&mut self, network: &SavedNetwork) -> Result<()> {
+        if !self
+            .pddb
+            .list_keys(AP_DICT_NAME, None)?
+            .contains(&network.ssid)
+        {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "no network of that name is saved",
+            ));
+        }
+        let mut key = self.pddb.get(
+            AP_SETTINGS_DICT_NAME,
+            &network.ssid,
+            None,
+            true,
+            true,
+            Some(SETTINGS_LEN),
+            None::<fn()>,
+        )?;
+        key.write_all(&network.settings_to_bytes())?;
+        self.pddb.sync()
+    }
+    /// Forgets a network, along with its settings.
+    pub fn remove(&mut self, ssid: &str) -> Result<()> {
+        self.pddb.delete_key(AP_DICT_NAME, ssid, None)?;
+        self.pddb.delete_key(AP_SETTINGS_DICT_NAME, ssid, None).ok();
+        self.pddb.sync()
+    }
+    /// Notes that the network was just joined.
+    pub fn mark_connected(&mut self, ssid: &str) -> Result<()> {
+        let mut network = self.settings(ssid);
+        network.last_connected = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|now| now.as_secs());
+        self.update(&network)
+    }
+}
+impl Default for SavedNetworks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let mut network = SavedNetwork::new("coffee shop");
+        assert_eq!(
+            SavedNetwork::settings_from_bytes("coffee shop", &network.settings_to_bytes()),
+            network
+        );
+        network.priority = 7;
+        network.auto_join = false;
+        network.metered = true;
+        network.last_connected = Some(1_650_000_000);
+        assert_eq!(
+            SavedNetwork::settings_from_bytes("coffee shop", &network.settings_to_bytes()),
+            network
+        );
+
+        // networks saved before there were settings, or by some later version
+        assert_eq!(
+            SavedNetwork::settings_from_bytes("home", &[]),
+            SavedNetwork::new("home")
+        );
+        let mut future = network.settings_to_bytes();
+        future[0] = SETTINGS_VERSION + 1;
+        assert_eq!(
+            SavedNetwork::settings_from_bytes("coffee shop", &future),
+            SavedNetwork::new("coffee shop")
+        );
+    }
+
+    #[test]
+    fn priorities() {
+        let network = |ssid: &str, priority, auto_join, hidden| SavedNetwork {
+            priority,
+            auto_join,
+            hidden,
+            ..SavedNetwork::new(ssid)
+        };
+        let saved = [
+            network("home", DEFAULT_PRIORITY, true, false),
+            network("office", DEFAULT_PRIORITY, true, false),
+            network("phone", 200, true, false),
+            network("closet", 200, true, true),
+            network("neighbour", 255, false, false),
+            network("cafe", 10, true, false),
+        ];
+        let mut visible = HashMap::new();
+        for (ssid, rssi) in [
+            ("home", 70),
+            ("office", 40),
+            ("phone", 80),
+            ("neighbour", 30),
+            ("cafe", 20),
+            ("stranger", 10),
+        ]
+        .iter()
+        {
+            visible.insert(String::from(*ssid), *rssi);
+        }
+        // priority first, then the strongest signal, with hidden networks after the visible ones
+        assert_eq!(
+            join_order(&saved, &visible),
+            vec!["phone", "closet", "office", "home", "cafe"]
+        );
+
+        visible.clear();
+        assert_eq!(join_order(&saved, &visible), vec!["closet"]);
+    }
+}
@@ -0,0 +1,236 @@
+use gam::modal::*;
+use gam::*;
+use locales::t;
+use net::saved_networks::{SavedNetwork, SavedNetworks};
+use num_traits::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xous_ipc::String;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+pub(crate) enum WifiMenuOpcode {
+    SavedNetworks,
+    AddNetwork,
+    Quit,
+}
+
+/// Runs the saved network menu. The modals block until the user is done with them, so this gets a
+/// thread of its own rather than holding up the connection manager.
+pub(crate) fn wifi_menu(sid: xous::SID) {
+    let xns = xous_names::XousNames::new().unwrap();
+    let modals = modals::Modals::new(&xns).unwrap();
+    let mut saved_networks = SavedNetworks::new();
+    create_wifi_menu(xous::connect(sid).unwrap());
+
+    loop {
+        let msg = xous::receive_message(sid).unwrap();
+        match FromPrimitive::from_usize(msg.body.id()) {
+            Some(WifiMenuOpcode::SavedNetworks) => {
+                let networks = saved_networks.list().unwrap_or(Vec::new());
+                if networks.len() == 0 {
+                    notify(&modals, t!("net.wifi.none_saved", xous::LANG));
+                    continue;
+                }
+                let ssids: Vec<&str> = networks.iter().map(|n| n.ssid.as_str()).collect();
+                let ssid = match pick(&modals, t!("net.wifi.pick", xous::LANG), &ssids) {
+                    Some(ssid) => ssid,
+                    None => continue,
+                };
+                if let Some(mut network) = networks.into_iter().find(|n| n.ssid == ssid) {
+                    if let Err(e) = edit_network(&modals, &mut saved_networks, &mut network) {
+                        notify_error(&modals, e);
+                    }
+                }
+            }
+            Some(WifiMenuOpcode::AddNetwork) => {
+                let ssid = match modals.get_text(t!("net.wifi.ssid_prompt", xous::LANG), Some(ssid_validator), None) {
+                    Ok(ssid) => ssid.as_str().to_string(),
+                    _ => {
+                        log::error!("couldn't get SSID");
+                        continue;
+                    }
+                };
+                let mut pass = match modals.get_password(t!("net.wifi.pass_prompt", xous::LANG), Some(pass_validator), None) {
+                    Ok(pass) => pass,
+                    _ => {
+                        log::error!("couldn't get password");
+                        continue;
+                    }
+                };
+                let hidden = match pick(&modals, t!("net.wifi.hidden_prompt", xous::LANG),
+                    &[t!("pddb.yes", xous::LANG), t!("pddb.no", xous::LANG)]
+                ) {
+                    Some(response) => response.as_str() == t!("pddb.yes", xous::LANG),
+                    None => {
+                        pass.volatile_clear();
+                        continue;
+                    }
+                };
+                let result = saved_networks.save(&ssid, pass.as_str());
+                pass.volatile_clear();
+                let result = result.and_then(|_| {
+                    // re-saving a network keeps its settings, so only the hidden flag is touched
+                    let mut network = saved_networks.get(&ssid)?;
+                    network.hidden = hidden;
+                    saved_networks.update(&network)
+                });
+                match result {
+                    Ok(()) => notify(&modals, t!("net.wifi.saved", xous::LANG)),
+                    Err(e) => notify_error(&modals, e),
+                }
+            }
+            Some(WifiMenuOpcode::Quit) => {
+                xous::return_scalar(msg.sender, 1).unwrap();
+                break;
+            }
+            None => {
+                log::error!("couldn't convert opcode: {:?}", msg);
+            }
+        }
+    }
+    xous::destroy_server(sid).unwrap();
+}
+
+fn create_wifi_menu(conn: xous::CID) {
+    let mut menu_items = Vec::<MenuItem>::new();
+
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("net.wifi.menu.saved", xous::LANG)),
+        action_conn: Some(conn),
+        action_opcode: WifiMenuOpcode::SavedNetworks.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("net.wifi.menu.add", xous::LANG)),
+        action_conn: Some(conn),
+        action_opcode: WifiMenuOpcode::AddNetwork.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("mainmenu.closemenu", xous::LANG)),
+        action_conn: None,
+        action_opcode: 0,
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+
+    menu_matic(menu_items, WIFI_MENU_NAME, None);
+}
+
+/// Shows the settings of one network, and applies the changes picked from them until the user is
+/// done or forgets the network.
+fn edit_network(modals: &modals::Modals, saved_networks: &mut SavedNetworks, network: &mut SavedNetwork) -> std::io::Result<()> {
+    let on_off = |flag: bool| if flag { t!("net.wifi.on", xous::LANG) } else { t!("net.wifi.off", xous::LANG) };
+    loop {
+        let last_connected = match network.last_connected {
+            Some(secs) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(secs);
+                match now.saturating_sub(secs) / (24 * 3600) {
+                    0 => std::string::String::from(t!("net.wifi.today", xous::LANG)),
+                    days => format!("{} {}", days, t!("net.wifi.days_ago", xous::LANG)),
+                }
+            }
+            None => std::string::String::from(t!("net.wifi.never", xous::LANG)),
+        };
+        let prompt = format!("{}\n{}{}\n{}{}",
+            network.ssid,
+            t!("net.wifi.priority", xous::LANG), network.priority,
+            t!("net.wifi.last_connected", xous::LANG), last_connected,
+        );
+        let set_priority = t!("net.wifi.set_priority", xous::LANG);
+        let auto_join = format!("{}{}", t!("net.wifi.auto_join", xous::LANG), on_off(network.auto_join));
+        let hidden = format!("{}{}", t!("net.wifi.hidden", xous::LANG), on_off(network.hidden));
+        let metered = format!("{}{}", t!("net.wifi.metered", xous::LANG), on_off(network.metered));
+        let forget = t!("net.wifi.forget", xous::LANG);
+        let done = t!("net.wifi.done", xous::LANG);
+        let action = match pick(modals, &prompt,
+            &[set_priority, auto_join.as_str(), hidden.as_str(), metered.as_str(), forget, done]
+        ) {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+        if action == set_priority {
+            match modals.get_text(t!("net.wifi.priority_prompt", xous::LANG), Some(priority_validator), None)
+                .map(|priority| priority.as_str().parse::<u8>())
+            {
+                Ok(Ok(priority)) => network.priority = priority,
+                _ => {
+                    log::error!("couldn't get priority");
+                    continue;
+                }
+            }
+        } else if action == auto_join {
+            network.auto_join = !network.auto_join;
+        } else if action == hidden {
+            network.hidden = !network.hidden;
+        } else if action == metered {
+            network.metered = !network.metered;
+        } else if action == forget {
+            if let Some(response) = pick(modals, t!("net.wifi.forget_confirm", xous::LANG),
+                &[t!("pddb.yes", xous::LANG), t!("pddb.no", xous::LANG)]
+            ) {
+                if response.as_str() == t!("pddb.yes", xous::LANG) {
+                    return saved_networks.remove(&network.ssid);
+                }
+            }
+            continue;
+        } else {
+            return Ok(());
+        }
+        saved_networks.update(network)?;
+    }
+}
+
+/// Asks the user to pick one of `items`. Returns `None` if the modal failed, which has been logged.
+fn pick(modals: &modals::Modals, prompt: &str, items: &[&str]) -> Option<std::string::String> {
+    for item in items.iter() {
+        if let Err(e) = modals.add_list_item(item) {
+            log::error!("couldn't build radio item list: {:?}", e);
+            return None;
+        }
+    }
+    match modals.get_radiobutton(prompt) {
+        Ok(choice) => Some(choice),
+        Err(e) => {
+            log::error!("get_radiobutton failed: {:?}", e);
+            None
+        }
+    }
+}
+
+fn notify(modals: &modals::Modals, note: &str) {
+    if let Err(e) = modals.show_notification(note, false) {
+        log::error!("couldn't show notification: {:?}", e);
+    }
+}
+
+fn notify_error(modals: &modals::Modals, e: std::io::Error) {
+    let mut note = std::string::String::from(t!("net.wifi.error", xous::LANG));
+    note.push_str(&format!("{:?}", e));
+    notify(modals, &note);
+}
+
+fn priority_validator(input: TextEntryPayload, _opcode: u32) -> Option<ValidatorErr> {
+    match input.as_str().parse::<u8>() {
+        Ok(_) => None,
+        _ => Some(ValidatorErr::from_str(t!("net.wifi.priority_err", xous::LANG))),
+    }
+}
+
+fn ssid_validator(input: TextEntryPayload, _opcode: u32) -> Option<ValidatorErr> {
+    let len = input.as_str().len();
+    if len == 0 || len > com::api::WF200_SSID_MAX_LEN {
+        Some(ValidatorErr::from_str(t!("net.wifi.ssid_err", xous::LANG)))
+    } else {
+        None
+    }
+}
+
+fn pass_validator(input: TextEntryPayload, _opcode: u32) -> Option<ValidatorErr> {
+    if input.as_str().len() > com::api::WF200_PASS_MAX_LEN {
+        Some(ValidatorErr::from_str(t!("net.wifi.pass_err", xous::LANG)))
+    } else {
+        None
+    }
+}
@@ -73,6 +73,14 @@ pub(crate) const FSCB_FILL_COEFFICIENT: f32 = 0.5;
 #[allow(dead_code)]
 pub(crate) const FSCB_FILL_UNCERTAINTY: f32 = 0.1;
 
+/// How often the server checks if it has been idle long enough to compact its free space.
+#[allow(dead_code)]
+pub(crate) const IDLE_COMPACT_INTERVAL_MS: usize = 5 * 60 * 1000;
+/// Repacking a small pool rewrites every page it touches, so when compacting opportunistically,
+/// a dictionary is only repacked if that frees at least this many pages. This limits FLASH wear.
+#[allow(dead_code)]
+pub(crate) const IDLE_COMPACT_MIN_PAGES: usize = 4;
+
 #[allow(dead_code)]
 pub const PDDB_DEFAULT_SYSTEM_BASIS: &'static str = ".System";
 // this isn't an "official" basis, but it is used for the AAD for encrypting the FastSpace structure
@@ -96,6 +104,8 @@ pub(crate) enum Opcode {
     CloseBasis,
     /// warning, the Delete routines have not been well tested
     DeleteBasis,
+    /// re-keys an open secret basis under a new password, prompting for both passwords
+    ChangeBasisPassword,
     DeleteKey,
     DeleteDict,
     KeyAttributes,
@@ -105,6 +115,8 @@ pub(crate) enum Opcode {
     GetKeyNameAtIndex,
     DictCountInBasis,
     GetDictNameAtIndex,
+    /// returns the next batch of key names in a dictionary, optionally filtered by prefix and with attributes
+    ListKeyBatch,
 
     /// primary method for accessing the database
     KeyRequest,
@@ -117,8 +129,23 @@ pub(crate) enum Opcode {
     /// drops any connection state associated with a given key
     KeyDrop,
 
+    /// registers a callback for changes to a key, or to every key in a dictionary
+    Subscribe,
+    /// drops a change subscription
+    Unsubscribe,
+
+    /// defragments the free space of one or all open basis
+    Compact,
+    /// periodic tick used to compact opportunistically while the PDDB is idle
+    CompactIdle,
+    /// checks, and optionally repairs, the consistency of one or all open basis
+    Fsck,
+    /// atomically applies the writes and deletes staged by a transaction
+    TxnCommit,
+
     /// Menu opcodes
     MenuListBasis,
+    MenuChangeBasisPassword,
 
     /// Suspend/resume callback
     SuspendResume,
@@ -148,6 +175,7 @@ pub enum PddbRequestCode {
     Open,
     Close,
     Delete,
+    ChangePassword,
     NoErr,
     NotMounted,
     NoFreeSpace,
@@ -189,6 +217,160 @@ pub struct PddbDictRequest {
     pub code: PddbRequestCode,
 }
 
+/// Most key names returned by a single `ListKeyBatch` call.
+pub(crate) const KEY_LIST_BATCH: usize = 28;
+#[derive(Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbKeyListEntry {
+    pub name: xous_ipc::String::<KEY_NAME_LEN>,
+    pub len: u64,
+    pub age: u32,
+    pub flags: u32,
+}
+/// Key names are returned in sorted order. The server keeps no listing state: each batch starts
+/// right after `cursor`, which the client sets to the last name of the previous batch.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbKeyListRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    /// only names starting with this are returned; empty matches every name
+    pub prefix: xous_ipc::String::<KEY_NAME_LEN>,
+    pub cursor_valid: bool,
+    pub cursor: xous_ipc::String::<KEY_NAME_LEN>,
+    /// if false, `len`, `age` and `flags` of the entries are left at 0, which saves a lookup per key
+    pub with_attributes: bool,
+    /// number of valid `entries`
+    pub count: u32,
+    /// total number of names matching `prefix`, across all batches
+    pub total: u32,
+    /// set if there are names after the last one returned
+    pub more: bool,
+    pub entries: [PddbKeyListEntry; KEY_LIST_BATCH],
+    pub code: PddbRequestCode,
+}
+
+/// Length of the text summary of the findings returned by a consistency check. Longer summaries are clipped.
+pub(crate) const FSCK_SUMMARY_LEN: usize = 2048;
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbFsckRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub repair: bool,
+    pub pages_checked: u32,
+    pub keys_checked: u32,
+    pub issues: u32,
+    pub repaired: u32,
+    pub quarantined: u32,
+    /// one line per finding
+    pub summary: xous_ipc::String::<FSCK_SUMMARY_LEN>,
+    pub code: PddbRequestCode,
+}
+
+/// Dictionary holding the data staged by transactions, and the intent records of transactions being committed.
+pub(crate) const PDDB_TXN_DICT: &'static str = "pddb.txn";
+/// Most writes and deletes a single transaction can carry.
+pub(crate) const PDDB_TXN_MAX_OPS: usize = 4096;
+/// Most ops that fit in a commit request; longer op lists are staged in `txn_ops_key()` instead.
+pub(crate) const PDDB_TXN_INLINE_OPS: usize = 16;
+/// Name of the key in `PDDB_TXN_DICT` holding the data staged for op `index` of transaction `id`.
+pub(crate) fn txn_staged_key(id: u64, index: usize) -> String {
+    format!("{:016x}.{}", id, index)
+}
+/// Name of the key in `PDDB_TXN_DICT` holding the op list of transaction `id`, if it was too long for the commit request.
+pub(crate) fn txn_ops_key(id: u64) -> String {
+    format!("{:016x}.ops", id)
+}
+/// Serializes an op list for `txn_ops_key()`: per op, a flags byte, then the dictionary and key names,
+/// each prefixed with its length.
+#[allow(dead_code)]
+pub(crate) fn txn_ops_encode(ops: &[PddbTxnOp]) -> Vec::<u8> {
+    let mut data = Vec::<u8>::new();
+    for op in ops.iter() {
+        data.push(if op.delete { 1 } else { 0 });
+        for name in [op.dict.as_str().unwrap_or(""), op.key.as_str().unwrap_or("")].iter() {
+            data.push(name.len() as u8);
+            data.extend_from_slice(name.as_bytes());
+        }
+    }
+    data
+}
+/// Parses an op list written by `txn_ops_encode()` into (dict, key, delete) triples. Returns `None` if it's damaged.
+#[allow(dead_code)]
+pub(crate) fn txn_ops_decode(data: &[u8]) -> Option<Vec::<(String, String, bool)>> {
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    let field = |pos: &mut usize| -> Option<String> {
+        let len = *data.get(*pos)? as usize;
+        let s = std::str::from_utf8(data.get(*pos + 1..*pos + 1 + len)?).ok()?.to_string();
+        *pos += 1 + len;
+        Some(s)
+    };
+    while pos < data.len() {
+        let delete = data[pos] != 0;
+        pos += 1;
+        let dict = field(&mut pos)?;
+        let key = field(&mut pos)?;
+        ops.push((dict, key, delete));
+    }
+    Some(ops)
+}
+/// Name of the key in `PDDB_TXN_DICT` holding the intent record of transaction `id`.
+#[allow(dead_code)]
+pub(crate) fn txn_record_key(id: u64) -> String {
+    format!("{:016x}", id)
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone)]
+pub struct PddbTxnOp {
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    /// if false, the key is replaced with the data staged for this op
+    pub delete: bool,
+}
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbTxnRequest {
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub id: u64,
+    /// number of ops in the transaction
+    pub count: u32,
+    /// the ops, if there are no more than `PDDB_TXN_INLINE_OPS`; otherwise they are staged in `txn_ops_key(id)`
+    pub ops: [PddbTxnOp; PDDB_TXN_INLINE_OPS],
+    pub code: PddbRequestCode,
+}
+
+/// What happened to a key that a subscriber is watching
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PddbChangeKind {
+    /// the key was created, possibly empty
+    Created,
+    /// new data was written to the key
+    Written,
+    /// the key was deleted, either on its own or with its dictionary
+    Deleted,
+}
+/// Registers `cb_sid` to receive a `PddbChangeEvent` when a key in `dict` changes. If `key_specified`
+/// is false, every key in the dictionary is watched.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbSubscribeRequest {
+    pub basis_specified: bool,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key_specified: bool,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+    pub cb_sid: [u32; 4],
+    pub token: Option<ApiToken>,
+    pub code: PddbRequestCode,
+}
+/// Sent to a subscriber's callback server with `CbOp::Event`. `basis` is empty if the change was made
+/// to the union of all open basis, and the server can't tell which one held the key.
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct PddbChangeEvent {
+    pub token: ApiToken,
+    pub kind: PddbChangeKind,
+    pub basis: xous_ipc::String::<BASIS_NAME_LEN>,
+    pub dict: xous_ipc::String::<DICT_NAME_LEN>,
+    pub key: xous_ipc::String::<KEY_NAME_LEN>,
+}
+
 /// A structure for requesting a token to access a particular key/value pair
 #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct PddbKeyRequest {
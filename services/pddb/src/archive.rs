@@ -0,0 +1,276 @@
+//! Encrypted archives of a basis, for backup and restore.
+//!
+//! An archive starts with a plaintext header, which carries the format version and the parameters
+//! needed to derive the archive key from a passphrase. The header is followed by a sequence of
+//! records, each of which is sealed on its own with AES-GCM-SIV:
+//!
+//! ```text
+//! header:  magic[8] | version: u32 | bcrypt cost: u32 | salt[16] | nonce base[8]
+//! record:  length: u32 | ciphertext[length]
+//! ```
+//!
+//! The nonce of each record is the nonce base followed by the record's sequence number, and the
+//! header is the AAD of every record. Thus records can't be reordered, dropped, or spliced in from
+//! another archive without failing authentication. The last record is an end marker carrying the
+//! number of dictionaries and keys, so a truncated archive is detected as well.
+//!
+//! Keys always follow the dictionary record they belong to. All integers are little-endian.
+
+use std::io::{Read, Write, Seek, SeekFrom, Result, Error, ErrorKind};
+use std::convert::TryInto;
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Key};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use zeroize::Zeroize;
+use crate::bcrypt;
+
+const ARCHIVE_MAGIC: [u8; 8] = *b"PDDBARCH";
+/// Bumped whenever the header or record layout changes.
+pub(crate) const ARCHIVE_VERSION: u32 = 1;
+const ARCHIVE_HEADER_LEN: usize = 40;
+pub(crate) const ARCHIVE_SALT_LEN: usize = 16;
+pub(crate) const ARCHIVE_NONCE_BASE_LEN: usize = 8;
+/// Bounds on the bcrypt cost accepted from an archive header. The upper bound keeps a damaged
+/// header from tying up the CPU for hours.
+const ARCHIVE_MIN_COST: u32 = 4;
+const ARCHIVE_MAX_COST: u32 = 16;
+/// Largest record accepted on restore; anything bigger is taken to be a damaged length field.
+const ARCHIVE_MAX_RECORD: usize = 16 * 1024 * 1024;
+
+const RECORD_DICT: u8 = 1;
+const RECORD_KEY: u8 = 2;
+const RECORD_END: u8 = 3;
+
+/// Counts of what went into, or came out of, an archive.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub dicts: usize,
+    pub keys: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum ArchiveEntry {
+    Dict(String),
+    /// name, data. The key belongs to the most recent `Dict` entry.
+    Key(String, Vec<u8>),
+}
+
+fn archive_cipher(passphrase: &str, cost: u32, salt: &[u8; ARCHIVE_SALT_LEN]) -> Aes256GcmSiv {
+    let mut hashed_passphrase: [u8; 24] = [0; 24];
+    bcrypt(cost, salt, passphrase, &mut hashed_passphrase);
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(Some(&salt[..]), &hashed_passphrase);
+    let mut okm = [0u8; 32];
+    hk.expand(b"pddb archive key", &mut okm).expect("invalid length specified for HKDF");
+    let cipher = Aes256GcmSiv::new(Key::from_slice(&okm));
+    hashed_passphrase.zeroize();
+    okm.zeroize();
+    cipher
+}
+
+fn record_nonce(nonce_base: &[u8; ARCHIVE_NONCE_BASE_LEN], seq: u32) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..ARCHIVE_NONCE_BASE_LEN].copy_from_slice(nonce_base);
+    nonce[ARCHIVE_NONCE_BASE_LEN..].copy_from_slice(&seq.to_le_bytes());
+    nonce
+}
+
+pub(crate) struct ArchiveWriter<W: Write> {
+    w: W,
+    cipher: Aes256GcmSiv,
+    header: [u8; ARCHIVE_HEADER_LEN],
+    nonce_base: [u8; ARCHIVE_NONCE_BASE_LEN],
+    seq: u32,
+    stats: ArchiveStats,
+    in_dict: bool,
+}
+impl<W: Write> ArchiveWriter<W> {
+    /// Writes the archive header. `salt` and `nonce_base` must be fresh random values for every archive.
+    pub(crate) fn new(mut w: W, passphrase: &str, cost: u32,
+        salt: [u8; ARCHIVE_SALT_LEN], nonce_base: [u8; ARCHIVE_NONCE_BASE_LEN]
+    ) -> Result<Self> {
+        let mut header = [0u8; ARCHIVE_HEADER_LEN];
+        header[..8].copy_from_slice(&ARCHIVE_MAGIC);
+        header[8..12].copy_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&cost.to_le_bytes());
+        header[16..32].copy_from_slice(&salt);
+        header[32..40].copy_from_slice(&nonce_base);
+        w.write_all(&header)?;
+        Ok(ArchiveWriter {
+            w,
+            cipher: archive_cipher(passphrase, cost, &salt),
+            header,
+            nonce_base,
+            seq: 0,
+            stats: ArchiveStats::default(),
+            in_dict: false,
+        })
+    }
+    fn record(&mut self, plaintext: &[u8]) -> Result<()> {
+        let nonce = record_nonce(&self.nonce_base, self.seq);
+        let ciphertext = self.cipher.encrypt(Nonce::from_slice(&nonce),
+            Payload { msg: plaintext, aad: &self.header }
+        ).or(Err(Error::new(ErrorKind::Other, "archive record encryption failed")))?;
+        if ciphertext.len() > ARCHIVE_MAX_RECORD {
+            return Err(Error::new(ErrorKind::InvalidInput, "key is too large to archive"));
+        }
+        self.w.write_all(&(ciphertext.len() as u32).to_le_bytes())?;
+        self.w.write_all(&ciphertext)?;
+        self.seq += 1;
+        Ok(())
+    }
+    /// Starts a dictionary; the keys that follow belong to it.
+    pub(crate) fn dict(&mut self, name: &str) -> Result<()> {
+        let mut plaintext = Vec::with_capacity(1 + name.len());
+        plaintext.push(RECORD_DICT);
+        plaintext.extend_from_slice(name.as_bytes());
+        self.record(&plaintext)?;
+        self.stats.dicts += 1;
+        self.in_dict = true;
+        Ok(())
+    }
+    pub(crate) fn key(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        if !self.in_dict {
+            return Err(Error::new(ErrorKind::InvalidInput, "key archived outside of a dictionary"));
+        }
+        let mut plaintext = Vec::with_capacity(3 + name.len() + data.len());
+        plaintext.push(RECORD_KEY);
+        plaintext.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        plaintext.extend_from_slice(name.as_bytes());
+        plaintext.extend_from_slice(data);
+        let result = self.record(&plaintext);
+        // key data may be secret, so don't leave copies of it lying around on the heap
+        plaintext.zeroize();
+        result?;
+        self.stats.keys += 1;
+        Ok(())
+    }
+    /// Writes the end marker, and hands back the underlying writer.
+    pub(crate) fn finish(mut self) -> Result<(W, ArchiveStats)> {
+        let mut plaintext = [0u8; 9];
+        plaintext[0] = RECORD_END;
+        plaintext[1..5].copy_from_slice(&(self.stats.dicts as u32).to_le_bytes());
+        plaintext[5..9].copy_from_slice(&(self.stats.keys as u32).to_le_bytes());
+        self.record(&plaintext)?;
+        self.w.flush()?;
+        Ok((self.w, self.stats))
+    }
+}
+
+pub(crate) struct ArchiveReader<R: Read> {
+    r: R,
+    cipher: Aes256GcmSiv,
+    header: [u8; ARCHIVE_HEADER_LEN],
+    nonce_base: [u8; ARCHIVE_NONCE_BASE_LEN],
+    seq: u32,
+    stats: ArchiveStats,
+    done: bool,
+}
+impl<R: Read> ArchiveReader<R> {
+    /// Reads and checks the archive header. The passphrase is only checked once the first record is read.
+    pub(crate) fn new(mut r: R, passphrase: &str) -> Result<Self> {
+        let mut header = [0u8; ARCHIVE_HEADER_LEN];
+        r.read_exact(&mut header)?;
+        if header[..8] != ARCHIVE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a PDDB archive"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != ARCHIVE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported PDDB archive version"));
+        }
+        let cost = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if cost < ARCHIVE_MIN_COST || cost > ARCHIVE_MAX_COST {
+            return Err(Error::new(ErrorKind::InvalidData, "archive key derivation cost is out of range"));
+        }
+        let salt: [u8; ARCHIVE_SALT_LEN] = header[16..32].try_into().unwrap();
+        let nonce_base: [u8; ARCHIVE_NONCE_BASE_LEN] = header[32..40].try_into().unwrap();
+        Ok(ArchiveReader {
+            r,
+            cipher: archive_cipher(passphrase, cost, &salt),
+            header,
+            nonce_base,
+            seq: 0,
+            stats: ArchiveStats::default(),
+            done: false,
+        })
+    }
+    fn record(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.r.read_exact(&mut len).or(Err(Error::new(ErrorKind::UnexpectedEof, "archive is truncated")))?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > ARCHIVE_MAX_RECORD {
+            return Err(Error::new(ErrorKind::InvalidData, "archive record length is out of range"));
+        }
+        let mut ciphertext = vec![0u8; len];
+        self.r.read_exact(&mut ciphertext).or(Err(Error::new(ErrorKind::UnexpectedEof, "archive is truncated")))?;
+        let nonce = record_nonce(&self.nonce_base, self.seq);
+        let plaintext = self.cipher.decrypt(Nonce::from_slice(&nonce),
+            Payload { msg: &ciphertext, aad: &self.header }
+        ).or_else(|_| if self.seq == 0 {
+            // the first record is where a wrong passphrase shows up
+            Err(Error::new(ErrorKind::PermissionDenied, "wrong passphrase, or the archive is damaged"))
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, "archive record failed authentication"))
+        })?;
+        self.seq += 1;
+        Ok(plaintext)
+    }
+    /// Returns the next entry in the archive, or `None` once the end marker has been read and checked.
+    pub(crate) fn next_entry(&mut self) -> Result<Option<ArchiveEntry>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut plaintext = self.record()?;
+        let entry = match plaintext.first() {
+            Some(&RECORD_DICT) => {
+                let name = String::from_utf8(plaintext[1..].to_vec())
+                    .or(Err(Error::new(ErrorKind::InvalidData, "archived dictionary name is not utf-8")))?;
+                self.stats.dicts += 1;
+                Some(ArchiveEntry::Dict(name))
+            }
+            Some(&RECORD_KEY) => {
+                if self.stats.dicts == 0 || plaintext.len() < 3 {
+                    return Err(Error::new(ErrorKind::InvalidData, "malformed archived key"));
+                }
+                let name_len = u16::from_le_bytes([plaintext[1], plaintext[2]]) as usize;
+                if plaintext.len() < 3 + name_len {
+                    return Err(Error::new(ErrorKind::InvalidData, "malformed archived key"));
+                }
+                let name = String::from_utf8(plaintext[3..3 + name_len].to_vec())
+                    .or(Err(Error::new(ErrorKind::InvalidData, "archived key name is not utf-8")))?;
+                let data = plaintext[3 + name_len..].to_vec();
+                plaintext.zeroize();
+                self.stats.keys += 1;
+                Some(ArchiveEntry::Key(name, data))
+            }
+            Some(&RECORD_END) => {
+                if plaintext.len() != 9 {
+                    return Err(Error::new(ErrorKind::InvalidData, "malformed archive end marker"));
+                }
+                let dicts = u32::from_le_bytes(plaintext[1..5].try_into().unwrap()) as usize;
+                let keys = u32::from_le_bytes(plaintext[5..9].try_into().unwrap()) as usize;
+                if dicts != self.stats.dicts || keys != self.stats.keys {
+                    return Err(Error::new(ErrorKind::InvalidData, "archive contents don't match its end marker"));
+                }
+                self.done = true;
+                None
+            }
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown archive record type")),
+        };
+        Ok(entry)
+    }
+    /// Reads the rest of the archive, checking every record without keeping any of them.
+    pub(crate) fn verify(&mut self) -> Result<ArchiveStats> {
+        while self.next_entry()?.is_some() {}
+        Ok(self.stats)
+    }
+}
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Goes back to the first record, so the archive can be read again without re-deriving the key.
+    /// The archive must start at the beginning of the stream.
+    pub(crate) fn rewind(&mut self) -> Result<()> {
+        self.r.seek(SeekFrom::Start(ARCHIVE_HEADER_LEN as u64))?;
+        self.seq = 0;
+        self.stats = ArchiveStats::default();
+        self.done = false;
+        Ok(())
+    }
+}
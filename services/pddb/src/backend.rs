@@ -10,8 +10,16 @@ mod fastspace;
 pub use fastspace::*;
 mod types;
 pub use types::*;
+mod layout;
+pub use layout::*;
+mod pagecodec;
+pub(crate) use pagecodec::*;
 mod bcrypt;
 pub use bcrypt::*;
+mod fsck;
+pub use fsck::*;
+mod txn;
+pub use txn::*;
 
 // local to the backend
 mod murmur3;
@@ -133,9 +133,8 @@
 use crate::api::*;
 use super::*;
 
-use core::ops::{Deref, DerefMut};
+use core::ops::Deref;
 use core::mem::size_of;
-use std::convert::TryInto;
 use aes_gcm_siv::{Aes256GcmSiv, Key};
 use aes_gcm_siv::aead::NewAead;
 use aes::Aes256;
@@ -145,96 +144,28 @@ use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::io::{Result, Error, ErrorKind};
 use std::cmp::Reverse;
 use core::num::NonZeroU32;
+use subtle::ConstantTimeEq;
 
-pub(crate) const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
-pub(crate) const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
-pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
-/// we don't want this bigger than VPAGE_SIZE, because a key goal of the small pool is to
-/// reduce # of writes to the disk of small data. While we could get some gain in memory efficiency
-/// if we made this larger than a VPAGE_SIZE, we don't get much gain in terms of write reduction,
-/// and it greatly complicates the implementation. So, SMALL_CAPACITY should be less than VPAGE_SIZE.
-pub(crate) const SMALL_CAPACITY: usize = VPAGE_SIZE;
-pub(crate) const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
-pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
-/// This is a size limit on the biggest file you can create. It's currently 32GiB. No, this is not
-/// web scale, but it's big enough to hold a typical blu-ray movie as a single file. You can adjust
-/// this constant up or down, and the trade-off is, you get more or less total number of large files
-/// allocated over the life of the filesystem. We simply "increment a pointer" when a new large file
-/// is added to create the next virtual memory spot for the large file. So at 32GiB, you can create
-/// a lifetime total of about 200 million files (this includes files you've previously deleted, until
-/// we create a mechanism for sweeping through the memory space and tracking de-allocations). Note that
-/// a "large" file includes anything over 4kiB, so if you create a 5kiB file, it can potentially grow to
-/// 32 GiB without bumping into the next large file. This is a very "lazy" way to deal with large files.
-/// Given that the PDDB is designed for a 32-bit device with only 128MiB of memory and a read/write lifetime
-/// of 100k cycles for the FLASH, 200 million file allocations is probably greater than the lifetime of
-/// the device itself. If the PDDB migrates to a larger handphone-style application, I think it'll probably
-/// still hold up OK with 200 million total large file allocations over the device lifetime and a limit
-/// of 32GiB. That's about 73k files created per day for 10 years, or about 50 files per minute -- roughly
-/// one new file per second for 10 years straight before the PDDB runs out of virtual memory space.
-/// A web server creating a >4k temporary log file for every client that hit and then deleting it
-/// would probably crush this limit in months. So don't use the PDDB to back a high volume web server.
-/// But it's probably OK for a consumer electronics device with a typical lifetime of less than 10 years.
-/// If you really think you want larger files and also more write life, you'd need to implement an in-memory
-/// "free" file allocator, but honestly, this is not something I think we need to burn resources on for
-/// the initial target of the PDDB (that is, a 100MiB device with 100k read/write endurance lifetime).
-/// Anyways, the code is written so you can just slide this constant up or down and change the behavior
-/// of the system; it's recommended you reformat when you do that but I /think/ it should actually be OK
-/// if you made a change "on the fly".
-///
-/// Also note that in practice, a file size is limited to 4GiB on a 32-bit Precursor device anyways
-/// because the usize type isn't big enough. Recompiling for a 64-bit target, however, should give
-/// you access to the 32GiB file size limit.
-pub(crate) const LARGE_FILE_MAX_SIZE: u64 = 0x0000_0008_0000_0000;
-
-/// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
-/// This is chosen such that 32 of these entries fit evenly into a VPAGE.
-pub(crate) const DK_STRIDE: usize = 127;
-//// DK_STRIDES per VPAGE
-pub(crate) const DK_PER_VPAGE: usize = VPAGE_SIZE / DK_STRIDE; // should be 32 - use this for computing modulus on dictionary indices
-/// size of a dictionary region in virtual memory
-pub(crate) const DICT_VSIZE: u64 = 0xFE_0000;
-/// maximum number of dictionaries in a system
-pub(crate) const DICT_MAXCOUNT: usize = 16383;
-
-/// This is the format of the Basis as stored on disk
-#[derive(PartialEq, Debug, Default)]
-#[repr(C, align(8))]
-pub(crate) struct BasisRoot {
-    pub(crate) magic: [u8; 4],
-    pub(crate) version: u32,
-    /// increments every time the BasisRoot is modified. This field must saturate, not roll over.
-    pub(crate) age: u32,
-    /// number of dictionaries.
-    pub(crate) num_dictionaries: u32,
-    /* at this point, we are aligned to a 64-bit boundary. All data must stay aligned to this boundary from here out! */
-    /// 64-byte name; aligns to 64-bits
-    pub(crate) name: BasisRootName,
-}
-impl BasisRoot {
-    pub(crate) fn aad(&self, dna: u64) -> Vec::<u8> {
-        let mut aad = Vec::<u8>::new();
-        aad.extend_from_slice(&self.name.data[..self.name.len as usize]);
-        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
-        aad.extend_from_slice(&dna.to_le_bytes());
-        aad
-    }
-}
-impl Deref for BasisRoot {
-    type Target = [u8];
-    fn deref(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(self as *const BasisRoot as *const u8, core::mem::size_of::<BasisRoot>())
-                as &[u8]
-        }
-    }
+/// Pages given back to the fast space by a compaction.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct CompactStats {
+    pub(crate) small_pages: usize,
+    pub(crate) large_pages: usize,
 }
-impl DerefMut for BasisRoot {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(self as *mut BasisRoot as *mut u8, core::mem::size_of::<BasisRoot>())
-                as &mut [u8]
-        }
-    }
+
+/// How many sorted listings `key_list_batch` keeps around, so a few walks can be interleaved.
+const KEY_INDEX_CACHE: usize = 4;
+/// The state of a dictionary in each basis a listing was built from: the dictionary index, age and key count,
+/// or `None` if the basis doesn't have the dictionary. Any change to a key of the dictionary changes its age.
+type KeyIndexStamp = Vec::<(String, Option<(u32, u32, u32)>)>;
+/// The sorted names of a dictionary that start with `prefix`, so that a listing can be walked a batch at a
+/// time without listing and sorting the whole dictionary again for every batch.
+struct KeyIndex {
+    basis: Option<String>,
+    dict: String,
+    prefix: String,
+    stamp: KeyIndexStamp,
+    names: Vec::<String>,
 }
 
 /// A list of open Basis that we can use to search and operate upon. Sort of the "root" data structure of the PDDB.
@@ -245,10 +176,12 @@ impl DerefMut for BasisRoot {
 pub(crate) struct BasisCache {
     /// the cache entries themselves
     cache: Vec::<BasisCacheEntry>,
+    /// sorted listings kept for `key_list_batch`, most recently built last
+    key_indices: Vec::<KeyIndex>,
 }
 impl BasisCache {
     pub(crate) fn new() -> Self {
-        BasisCache { cache: Vec::new() }
+        BasisCache { cache: Vec::new(), key_indices: Vec::new() }
     }
     fn select_basis(&mut self, basis_name: Option<&str>) -> Option<usize> {
         if self.cache.len() == 0 {
@@ -385,6 +318,58 @@ impl BasisCache {
         }
     }
 
+    /// Returns up to `max` key names of `dict` that start with `prefix`, in sorted order, beginning right
+    /// after `after` (or at the first match if `None`). Also returns how many names match `prefix` in
+    /// total, and whether any come after the last one returned. The sorted listing is kept until the
+    /// dictionary changes, so walking a dictionary a batch at a time only lists and sorts it once.
+    pub(crate) fn key_list_batch(&mut self, hw: &mut PddbOs, dict: &str, basis_name: Option<&str>,
+        prefix: &str, after: Option<&str>, max: usize
+    ) -> Result<(Vec::<String>, usize, bool)> {
+        let basis = basis_name.map(|b| b.to_string());
+        let stamp = self.key_index_stamp(dict, basis_name);
+        let position = self.key_indices.iter().position(|index|
+            index.basis == basis && index.dict == dict && index.prefix == prefix && index.stamp == stamp
+        );
+        let position = match position {
+            Some(position) => position,
+            None => {
+                let mut names: Vec::<String> = self.key_list(hw, dict, basis_name)?
+                    .into_iter()
+                    .filter(|name| name.starts_with(prefix))
+                    .collect();
+                names.sort_unstable();
+                // listing can load the dictionary into the cache, so the stamp is taken again
+                let stamp = self.key_index_stamp(dict, basis_name);
+                self.key_indices.retain(|index| !(index.basis == basis && index.dict == dict && index.prefix == prefix));
+                if self.key_indices.len() >= KEY_INDEX_CACHE {
+                    self.key_indices.remove(0);
+                }
+                self.key_indices.push(KeyIndex { basis, dict: dict.to_string(), prefix: prefix.to_string(), stamp, names });
+                self.key_indices.len() - 1
+            }
+        };
+        let names = &self.key_indices[position].names;
+        let total = names.len();
+        let start = match after {
+            Some(after) => names.partition_point(|name| name.as_str() <= after),
+            None => 0,
+        };
+        let end = (start + max).min(total);
+        Ok((names[start..end].to_vec(), total, end < total))
+    }
+    fn key_index_stamp(&mut self, dict: &str, basis_name: Option<&str>) -> KeyIndexStamp {
+        let targets: Vec::<usize> = if basis_name.is_some() {
+            self.select_basis(basis_name).into_iter().collect()
+        } else {
+            (0..self.cache.len()).collect()
+        };
+        targets.into_iter().map(|basis_index| {
+            let basis = &self.cache[basis_index];
+            let state = basis.dicts.get(dict).filter(|d| d.flags.valid()).map(|d| (d.index.get(), d.age, d.key_count));
+            (basis.name.clone(), state)
+        }).collect()
+    }
+
     /// This version of the call only removes one instance of a dictionary from the specified basis.
     /// Perhaps there also needs to be a `dict_remove_all` call which iterates through every basis
     /// makes sure the dictionary is removed from all the possible known basis. Anyways, that function
@@ -795,25 +780,12 @@ impl BasisCache {
                     Some(data) => data,
                     None => {log::error!("Could not find basis {} root", name); return None;},
                 };
-                let mut basis_root = BasisRoot::default();
-                for (&src, dst) in vpage[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
-                    *dst = src;
-                }
-                if basis_root.magic != PDDB_MAGIC {
-                    log::error!("Basis root did not deserialize correctly, unrecoverable error.");
-                    return None;
-                }
-                if basis_root.version != PDDB_VERSION {
-                    log::error!("PDDB version mismatch in system basis root. Unrecoverable error.");
-                    return None;
-                }
-                let basis_name = std::str::from_utf8(&basis_root.name.data[..basis_root.name.len as usize]).expect("basis name is not valid utf-8");
-                if basis_name != name {
-                    log::error!("PDDB mount requested {}, but got {}; aborting.", name, basis_name);
+                if let Err(e) = basis_root_decode(&vpage, name) {
+                    log::error!("PDDB mount requested {}, but its root is not usable: {:?}; aborting.", name, e);
                     return None;
                 }
                 log::debug!("Basis {} record found, generating cache entry", name);
-                BasisCacheEntry::mount(hw, &basis_name, &basis_key, false, policy)
+                BasisCacheEntry::mount(hw, name, &basis_key, false, policy)
             } else {
                 None
             }
@@ -900,6 +872,8 @@ impl BasisCache {
             let basis = &mut self.cache[basis_index];
             basis.sync(hw)?;
             self.cache.retain(|x| x.name != basis_name);
+            // the names of a locked basis shouldn't linger in RAM
+            self.key_indices.clear();
             Ok(())
         } else {
             Err(Error::new(ErrorKind::NotFound, "Basis not found"))
@@ -907,8 +881,7 @@ impl BasisCache {
     }
 
     /// note: you can "delete" a basis simply by forgetting its password, but this is more thorough.
-    /// there might also need to be a variant to make which is a "change my password" function, but that is actually
-    /// surprisingly hard.
+    /// To change the password instead, see `basis_change_password`.
     pub(crate) fn basis_delete(&mut self, hw: &mut PddbOs, basis_name: &str) -> Result<()> {
         if let Some(basis_index) = self.select_basis(Some(basis_name)) {
             let basis = &mut self.cache[basis_index];
@@ -926,6 +899,105 @@ impl BasisCache {
         }
     }
 
+    /// Changes the password of the open secret basis `basis_name`. Basis keys are derived straight from the
+    /// password, so there's nothing to re-wrap: every page of the basis is copied to a fresh page under the
+    /// new keys, and then the old pages are wiped. The new root is written last and the old root is erased
+    /// first, so a power loss leaves either the old password working, or -- in the short window between
+    /// the two -- both passwords opening identical copies. The pages copied before an early power loss are
+    /// not reachable by either password, and stay allocated.
+    ///
+    /// Not supported for the system basis, which returns `ErrorKind::Unsupported`. Its key is random and wrapped
+    /// by the root keys, so its password is the device unlock password; changing that is the rootkey password
+    /// change on the 1.0 roadmap (`RELEASE-v0.9.md`), and leaves the system basis keys as they are.
+    pub(crate) fn basis_change_password(&mut self, hw: &mut PddbOs, basis_name: &str, old_password: &str, new_password: &str) -> Result<()> {
+        if basis_name == PDDB_DEFAULT_SYSTEM_BASIS {
+            return Err(Error::new(ErrorKind::Unsupported, "The system basis follows the device unlock password, which is changed through the root keys"));
+        }
+        let basis_index = match self.select_basis(Some(basis_name)) {
+            Some(index) => index,
+            None => return Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+        };
+        let old_key = hw.basis_derive_key(basis_name, old_password);
+        if !bool::from(old_key.data[..].ct_eq(&self.cache[basis_index].key[..])) {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Old password is incorrect"));
+        }
+        self.cache[basis_index].sync(hw)?;
+        let pages = self.cache[basis_index].v2p_map.len();
+        if !hw.fast_space_ensure_next_log() || !hw.ensure_fast_space_alloc(pages, &self.cache) {
+            return Err(Error::new(ErrorKind::OutOfMemory, "Not enough free space to re-key the basis"));
+        }
+        let new_key = hw.basis_derive_key(basis_name, new_password);
+        let new_cipher = Aes256GcmSiv::new(Key::from_slice(&new_key.data));
+        let new_cipher_ecb = Aes256::new(GenericArray::from_slice(&new_key.pt));
+
+        let basis = &mut self.cache[basis_index];
+        let root_va = VirtAddr::new(VPAGE_SIZE as u64).unwrap();
+        let mut order: Vec::<VirtAddr> = basis.v2p_map.keys().filter(|&&va| va != root_va).cloned().collect();
+        order.push(root_va);
+        let mut new_map = HashMap::<VirtAddr, PhysPage>::new();
+        let mut failure: Option<Error> = None;
+        for va in order {
+            let old_pp = match basis.v2p_map.get(&va) {
+                Some(pp) => *pp,
+                None => continue,
+            };
+            let mut new_pp = match hw.try_fast_space_alloc() {
+                Some(pp) => pp,
+                None => {
+                    failure = Some(Error::new(ErrorKind::OutOfMemory, "Ran out of free space while re-keying the basis"));
+                    break;
+                }
+            };
+            new_pp.set_valid(true);
+            new_pp.set_clean(true);
+            // track the page before anything can fail, so it's released on the abort path
+            new_map.insert(va, new_pp);
+            if va == root_va {
+                // the root needs a key commitment, see `data_encrypt_and_patch_page_with_commit`
+                match hw.data_decrypt_page_with_commit(&basis.key, &basis.aad, &old_pp) {
+                    Some(vpage) => {
+                        let mut block = [0 as u8; KCOM_CT_LEN];
+                        for (&src, dst) in vpage.iter().zip(block.iter_mut()) {
+                            *dst = src;
+                        }
+                        hw.data_encrypt_and_patch_page_with_commit(&new_key.data, &basis.aad, &mut block, &new_pp);
+                    }
+                    None => {
+                        failure = Some(Error::new(ErrorKind::InvalidData, "Basis root did not decrypt"));
+                        break;
+                    }
+                }
+            } else {
+                match hw.data_decrypt_page(&basis.cipher, &basis.aad, &old_pp) {
+                    Some(mut vpage) => hw.data_encrypt_and_patch_page(&new_cipher, &basis.aad, &mut vpage, &new_pp),
+                    None => {
+                        log::error!("page at va {:x?} of {} did not decrypt; run fsck first", va, basis_name);
+                        failure = Some(Error::new(ErrorKind::InvalidData, "Basis page did not decrypt"));
+                        break;
+                    }
+                }
+            }
+            hw.pt_patch_mapping(va, new_pp.page_number(), &new_cipher_ecb);
+        }
+        if let Some(e) = failure {
+            // the old pages are still the only complete basis, so it's the partial copy that goes
+            retire_pages(hw, &mut new_map, root_va);
+            return Err(e);
+        }
+        retire_pages(hw, &mut basis.v2p_map, root_va);
+        basis.v2p_map = new_map;
+        basis.cipher = new_cipher;
+        basis.cipher_ecb = new_cipher_ecb;
+        basis.key = GenericArray::clone_from_slice(&new_key.data);
+        // the dictionary caches refer to the old pages, so rebuild them against the new map
+        basis.dicts.clear();
+        basis.free_dict_offset = None;
+        basis.large_alloc_ptr = None;
+        basis.populate_caches(hw);
+        log::info!("basis {} re-keyed ({} pages)", basis_name, pages);
+        Ok(())
+    }
+
     pub(crate) fn sync(&mut self, hw: &mut PddbOs, basis_name: Option<&str>) -> Result<()> {
         if basis_name.is_some() {
             if let Some(basis_index) = self.select_basis(basis_name) {
@@ -939,6 +1011,87 @@ impl BasisCache {
         Ok(())
     }
 
+    /// Compacts the named basis, or every open basis if `basis_name` is None. See `BasisCacheEntry::compact`.
+    pub(crate) fn compact(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, min_gain: usize) -> Result<CompactStats> {
+        let targets: Vec::<usize> = if basis_name.is_some() {
+            if let Some(basis_index) = self.select_basis(basis_name) {
+                vec![basis_index]
+            } else {
+                return Err(Error::new(ErrorKind::NotFound, "Basis not found"));
+            }
+        } else {
+            (0..self.cache.len()).collect()
+        };
+        let mut stats = CompactStats::default();
+        for basis_index in targets {
+            self.cache[basis_index].populate_caches(hw);
+            if !hw.ensure_fast_space_alloc(self.cache[basis_index].compact_estimate(), &self.cache) {
+                return Err(Error::new(ErrorKind::OutOfMemory, "No free space to compact the basis"));
+            }
+            let basis_stats = self.cache[basis_index].compact(hw, min_gain)?;
+            stats.small_pages += basis_stats.small_pages;
+            stats.large_pages += basis_stats.large_pages;
+        }
+        Ok(stats)
+    }
+
+    /// Checks the named basis, or all the open basis if none is named. With `repair`, broken keys are
+    /// copied into the `FSCK_QUARANTINE_DICT` of their basis and dropped, and bookkeeping errors are fixed.
+    pub(crate) fn fsck(&mut self, hw: &mut PddbOs, basis_name: Option<&str>, repair: bool) -> Result<FsckReport> {
+        let targets: Vec::<usize> = if basis_name.is_some() {
+            if let Some(basis_index) = self.select_basis(basis_name) {
+                vec![basis_index]
+            } else {
+                return Err(Error::new(ErrorKind::NotFound, "Basis not found"));
+            }
+        } else {
+            (0..self.cache.len()).collect()
+        };
+        let mut report = FsckReport::default();
+        // pages of the basis that aren't checked still count as owned, so aliases into them are caught
+        let mut owners = HashMap::<u32, String>::new();
+        for (basis_index, basis) in self.cache.iter().enumerate() {
+            if !targets.contains(&basis_index) {
+                for pp in basis.v2p_map.values() {
+                    if pp.valid() {
+                        owners.insert(pp.page_number() as u32, basis.name.clone());
+                    }
+                }
+            }
+        }
+        let mut scans = Vec::<(usize, FsckScan)>::new();
+        for &basis_index in targets.iter() {
+            self.cache[basis_index].populate_caches(hw);
+            let scan = self.cache[basis_index].fsck_scan(hw, &mut owners, &mut report);
+            scans.push((basis_index, scan));
+        }
+        fsck_fast_space(hw, &mut report);
+        if !repair {
+            return Ok(report);
+        }
+        for (basis_index, scan) in scans {
+            let name = self.cache[basis_index].name.clone();
+            // salvage before detaching, so a full basis loses nothing
+            for broken in scan.broken.iter() {
+                match self.dict_add(hw, FSCK_QUARANTINE_DICT, Some(name.as_str())) {
+                    Ok(_) => (),
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                    Err(e) => return Err(e),
+                }
+                self.key_update(hw, FSCK_QUARANTINE_DICT, &fsck_quarantine_name(&broken.dict, &broken.key),
+                    &broken.data, None, None, Some(name.as_str()), true)?;
+                report.findings[broken.finding].action = FsckAction::Quarantined;
+            }
+            if !hw.ensure_fast_space_alloc(2, &self.cache) {
+                return Err(Error::new(ErrorKind::OutOfMemory, "No free space to repair the basis"));
+            }
+            self.cache[basis_index].fsck_repair(hw, scan, &mut report)?;
+        }
+        log::info!("fsck: {} findings, {} repaired, {} quarantined", report.findings.len(),
+            report.count(FsckAction::Repaired), report.count(FsckAction::Quarantined));
+        Ok(report)
+    }
+
     pub(crate) fn suspend(&mut self, hw: &mut PddbOs) {
         self.sync(hw, None).expect("couldn't sync on suspend");
         let mut lock_list = Vec::<String>::new();
@@ -966,6 +1119,21 @@ impl BasisCache {
     }
 }
 
+/// Wipes and frees every page in `map`, erasing the PTE of the root at `root_va` before any other, so that
+/// a power loss part way through never leaves a root pointing at missing pages.
+fn retire_pages(hw: &mut PddbOs, map: &mut HashMap<VirtAddr, PhysPage>, root_va: VirtAddr) {
+    if let Some(root) = map.get(&root_va) {
+        hw.pt_erase(root.page_number());
+    }
+    let mut temp: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+    for page in map.values_mut() {
+        hw.pt_erase(page.page_number());
+        hw.trng_slice(&mut temp);
+        hw.patch_data(&temp, page.page_number() * PAGE_SIZE as u32);
+        hw.fast_space_free(page);
+    }
+}
+
 /// This is the RAM cached copy of a basis as maintained in the PDDB.
 pub(crate) struct BasisCacheEntry {
     /// the name of this basis
@@ -1018,27 +1186,15 @@ impl BasisCacheEntry {
                     Some(data) => data,
                     None => {log::error!("System basis decryption did not authenticate. Unrecoverable error."); return None;},
                 };
-                // if the below assertion fails, you will need to re-code this to decrypt more than one VPAGE and stripe into a basis root struct
-                assert!(size_of::<BasisRoot>() <= VPAGE_SIZE, "BasisRoot has grown past a single VPAGE, this routine needs to be re-coded to accommodate the extra bulk");
-                let mut basis_root = BasisRoot::default();
-                for (&src, dst) in vpage[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
-                    *dst = src;
-                }
-                if basis_root.magic != PDDB_MAGIC {
-                    log::error!("Basis root did not deserialize correctly, unrecoverable error.");
-                    return None;
-                }
-                if basis_root.version != PDDB_VERSION {
-                    log::error!("PDDB version mismatch in system basis root. Unrecoverable error.");
-                    return None;
-                }
-                let basis_name = std::str::from_utf8(&basis_root.name.data[..basis_root.name.len as usize]).expect("basis name is not valid utf-8");
-                if basis_name != String::from(name) {
-                    log::error!("Discovered basis name does not match the requested name: {}; aborting mount operation.", basis_name);
-                    return None;
-                }
+                let basis_root = match basis_root_decode(&vpage, name) {
+                    Ok(root) => root,
+                    Err(e) => {
+                        log::error!("Basis {} root is not usable: {:?}; aborting mount operation.", name, e);
+                        return None;
+                    }
+                };
                 let mut bcache = BasisCacheEntry {
-                    name: basis_name.to_string(),
+                    name: name.to_string(),
                     clean: true,
                     last_sync: Some(hw.timestamp_now()),
                     num_dicts: basis_root.num_dictionaries,
@@ -1050,7 +1206,7 @@ impl BasisCacheEntry {
                     age: basis_root.age,
                     free_dict_offset: None,
                     v2p_map: basis_map,
-                    journal: u32::from_le_bytes(vpage[..size_of::<JournalType>()].try_into().unwrap()),
+                    journal: journal_of(&vpage),
                     large_alloc_ptr: None,
                     policy,
                     policy_state: policy.derive_init_state(),
@@ -1287,15 +1443,7 @@ impl BasisCacheEntry {
     /// The `pp` must be the resolved physical page storing the top of the given
     /// dictionary index for this to work.
     pub(crate) fn dict_decrypt(&self, hw: &mut PddbOs, pp: &PhysPage) -> Option<Dictionary> {
-        if let Some(data) = hw.data_decrypt_page(&self.cipher, &self.aad, &pp) {
-            let mut dict = Dictionary::default();
-            for (&src, dst) in data[size_of::<JournalType>()..].iter().zip(dict.deref_mut().iter_mut()) {
-                *dst = src;
-            }
-            Some(dict)
-        } else {
-            None
-        }
+        hw.data_decrypt_page(&self.cipher, &self.aad, &pp).map(|data| dict_decode(&data))
     }
 
     /// Looks for dirty entries in the page table, and flushes them to disk.
@@ -1395,6 +1543,17 @@ impl BasisCacheEntry {
                     // make, but for now, let's do it with a dumb O(N) scan through the KeyCacheEntry, running under
                     // the assumption that the KeyCacheEntry doesn't ever get to a very large N.
                     let next_vpage = VirtAddr::new(cur_vpage.get() + VPAGE_SIZE as u64).unwrap();
+                    // deleted keys have their descriptor blanked, so they can't come back on the next mount. If the
+                    // slot was already handed out again, the new key is merged in below and takes precedence.
+                    for key in dict.keys.values_mut() {
+                        if !key.clean && !key.flags.valid() {
+                            if key.descriptor_vaddr(dict_offset) >= cur_vpage &&
+                            key.descriptor_vaddr(dict_offset) < next_vpage {
+                                dk_vpage.elements[key.descriptor_index.get() as usize % DK_PER_VPAGE] = Some(DictKeyEntry::default());
+                                key.clean = true;
+                            }
+                        }
+                    }
                     for (key_name, key) in dict.keys.iter_mut() {
                         /*if key_name.contains("dict2|key6|len2347") {
                             log::warn!("TRACING: {}", key_name);
@@ -1455,7 +1614,10 @@ impl BasisCacheEntry {
                     // exit the loop
                     let mut found_next = false;
                     for key in dict.keys.values() {
-                        if !key.clean && key.flags.valid() {
+                        // a deleted key whose descriptor page was never written has nothing to blank
+                        let blank_pending = !key.clean && !key.flags.valid() && self.v2p_map.contains_key(
+                            &VirtAddr::new(dict_offset.get() + (key.descriptor_vpage_num() * VPAGE_SIZE) as u64).unwrap());
+                        if !key.clean && (key.flags.valid() || blank_pending) {
                             found_next = true;
                             // note: we don't care *which* vpage we do next -- so we just break after finding the first one
                             vpage_num = key.descriptor_vpage_num();
@@ -1477,12 +1639,139 @@ impl BasisCacheEntry {
         }
     }
 
+    /// Estimates the fast space needed by `compact`. Repacked small pools are written to fresh pages
+    /// before the old ones are freed, so in the worst case every occupied pool needs a new page.
+    pub(crate) fn compact_estimate(&self) -> usize {
+        let mut pages = 0;
+        for dict in self.dicts.values() {
+            if dict.flags.valid() {
+                pages += dict.small_pool.iter().filter(|ksp| ksp.contents.len() > 0).count();
+            }
+        }
+        pages
+    }
+
     /// Runs through the dictionary listing in a basis and compacts them. Call when the
     /// the dictionary space becomes sufficiently fragmented that accesses are becoming
-    /// inefficient.
-    #[allow(dead_code)]
-    pub(crate) fn dict_compact(&self, _basis_name: Option<&str>) -> Result<()> {
-        unimplemented!();
+    /// inefficient. Small keys are repacked into as few pool pages as possible, and large
+    /// keys give back the pages reserved beyond their length. Repacking a dictionary's small pool
+    /// shuffles its keys around, so it is skipped unless it frees at least `min_gain` pages. Run `populate_caches` and
+    /// make sure there is `compact_estimate()` worth of fast space before calling this.
+    ///
+    /// Repacked pools are written to freshly allocated pages and their page table entries are synced;
+    /// only then are the key descriptors committed, and only after that are the old pages wiped and
+    /// freed. A power loss at any point leaves the descriptors on disk pointing at intact data.
+    pub(crate) fn compact(&mut self, hw: &mut PddbOs, min_gain: usize) -> Result<CompactStats> {
+        let mut stats = CompactStats::default();
+        let mut dictnames = Vec::<String>::new();
+        for (dict, entry) in self.dicts.iter() {
+            if entry.flags.valid() {
+                dictnames.push(dict.to_string());
+            }
+        }
+        let mut stale = Vec::<VirtAddr>::new();
+        for name in dictnames.iter() {
+            let dict = self.dicts.get_mut(name).expect("dict was just listed");
+            if let Some((freed, gain)) = dict.small_pool_compact(&self.v2p_map, min_gain) {
+                if !dict.sync_small_pool(hw, &mut self.v2p_map, &self.cipher) {
+                    return Err(Error::new(ErrorKind::OutOfMemory, "No free space to compact the small pool"));
+                }
+                stats.small_pages += gain;
+                stale.extend(freed);
+            }
+        }
+        stats.large_pages = self.large_pool_trim(&mut stale);
+        // the new pool pages must be reachable before any descriptor points at them
+        self.pt_sync(hw);
+        for name in dictnames.iter() {
+            self.dict_sync(hw, name)?;
+        }
+        for vaddr in stale {
+            if let Some(pp) = self.v2p_map.get_mut(&vaddr) {
+                assert!(pp.valid(), "v2p returned an invalid page");
+                { // always nuke old data
+                    let mut random = [0u8; PAGE_SIZE];
+                    hw.trng_slice(&mut random);
+                    hw.patch_data(&random, pp.page_number() * PAGE_SIZE as u32);
+                }
+                log::trace!("fast_space_free compact {} before", pp.journal());
+                hw.fast_space_free(pp);
+                assert!(pp.valid() == false, "pp is still marked as valid!");
+            }
+        }
+        self.pt_sync(hw);
+        log::info!("compacted {}: {} small pool pages, {} large pool pages freed", self.name, stats.small_pages, stats.large_pages);
+        Ok(stats)
+    }
+
+    /// Shrinks the reservation of every large key down to the pages its data actually covers, and
+    /// looks for large pool pages that no key refers to any more (e.g. left over from a power loss in
+    /// the middle of an update). The pages to free are added to `stale`; returns how many were added.
+    fn large_pool_trim(&mut self, stale: &mut Vec::<VirtAddr>) -> usize {
+        let mut freed = 0;
+        let mut live = Vec::<(u64, u64)>::new();
+        // the orphan search is only sound if every key of every dictionary is in cache
+        let mut complete = self.dicts.values().filter(|d| d.flags.valid()).count() as u32 == self.num_dicts;
+        for dict in self.dicts.values_mut() {
+            if !dict.flags.valid() {
+                continue;
+            }
+            let mut valid_keys = 0;
+            for kcache in dict.keys.values_mut() {
+                if !kcache.flags.valid() {
+                    continue;
+                }
+                valid_keys += 1;
+                if kcache.start < LARGE_POOL_START {
+                    continue;
+                }
+                // keep at least one page, like a freshly created large key
+                let keep = ((kcache.len + VPAGE_SIZE as u64 - 1) / VPAGE_SIZE as u64).max(1) * VPAGE_SIZE as u64;
+                if keep < kcache.reserved {
+                    for vpage in (kcache.start + keep..kcache.start + kcache.reserved).step_by(VPAGE_SIZE) {
+                        let vaddr = VirtAddr::new(vpage).unwrap();
+                        if self.v2p_map.get(&vaddr).map(|pp| pp.valid()).unwrap_or(false) {
+                            stale.push(vaddr);
+                            freed += 1;
+                        }
+                    }
+                    kcache.reserved = keep;
+                    kcache.age = kcache.age.saturating_add(1);
+                    kcache.clean = false;
+                    dict.age = dict.age.saturating_add(1);
+                    dict.clean = false;
+                }
+                live.push((kcache.start, kcache.start + kcache.reserved));
+            }
+            if valid_keys != dict.key_count {
+                complete = false;
+            }
+        }
+        if complete {
+            live.sort();
+            for (&vaddr, pp) in self.v2p_map.iter() {
+                if vaddr.get() >= LARGE_POOL_START && pp.valid() {
+                    let v = vaddr.get();
+                    let owned = live.binary_search_by(|&(start, end)|
+                        if end <= v {
+                            std::cmp::Ordering::Less
+                        } else if start > v {
+                            std::cmp::Ordering::Greater
+                        } else {
+                            std::cmp::Ordering::Equal
+                        }
+                    ).is_ok();
+                    if !owned {
+                        log::warn!("large pool page 0x{:x} has no owner, reclaiming", v);
+                        stale.push(vaddr);
+                        freed += 1;
+                    }
+                }
+            }
+        } else {
+            log::info!("not all keys of {} are in cache, skipping the large pool orphan search", self.name);
+        }
+        freed
     }
 
     /// Syncs *only* the basis header to disk.
@@ -1578,39 +1867,3 @@ impl BasisCacheEntry {
     }*/
 
 }
-
-// ****
-// Beginning of serializers for the data structures in this file.
-// ****
-
-/// Newtype for BasisRootName so we can give it a default initializer.
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
-pub struct BasisRootName {
-    pub len: u8,
-    pub data: [u8; BASIS_NAME_LEN - 1],
-}
-impl BasisRootName {
-    pub fn try_from_str(name: &str) -> Result<BasisRootName> {
-        let mut alloc = [0u8; BASIS_NAME_LEN - 1];
-        let bytes = name.as_bytes();
-        if bytes.len() > (BASIS_NAME_LEN - 1) {
-            Err(Error::new(ErrorKind::InvalidInput, "basis name is too long")) // FileNameTooLong is still nightly :-/
-        } else {
-            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
-                *dst = src;
-            }
-            Ok(BasisRootName {
-                len: bytes.len() as u8, // this as checked above to be short enough
-                data: alloc,
-            })
-        }
-    }
-}
-impl Default for BasisRootName {
-    fn default() -> BasisRootName {
-        BasisRootName{
-            len: 0,
-            data: [0; BASIS_NAME_LEN - 1]
-        }
-    }
-}
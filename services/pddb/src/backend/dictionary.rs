@@ -2,21 +2,12 @@ use crate::api::*;
 use super::*;
 
 use std::num::NonZeroU32;
-use core::ops::{Deref, DerefMut};
 use core::mem::size_of;
 use aes_gcm_siv::Aes256GcmSiv;
 use std::collections::{HashMap, BinaryHeap, HashSet};
 use std::io::{Result, Error, ErrorKind};
-use bitfield::bitfield;
 use std::cmp::{Ordering, Reverse};
 
-bitfield! {
-    #[derive(Copy, Clone, PartialEq, Eq)]
-    pub struct DictFlags(u32);
-    impl Debug;
-    pub valid, set_valid: 0;
-}
-
 /// RAM based copy of the dictionary structures on disk. Most of the methods on this function operate on
 /// keys within the Dictionary. Operations on the Dictionary itself originate from the containing Basis
 /// structure.
@@ -141,11 +132,7 @@ impl DictCacheEntry {
                     assert!(pp.valid(), "v2p returned an invalid page");
                     assert!(cache_pp.page_number() == pp.page_number(), "cache inconsistency error");
                     let cache = index_cache.data.as_ref().expect("Cache should be full, it was already checked...");
-                    let mut keydesc = KeyDescriptor::default();
-                    let start = size_of::<JournalType>() + (try_entry % DK_PER_VPAGE) * DK_STRIDE;
-                    for (&src, dst) in cache[start..start + DK_STRIDE].iter().zip(keydesc.deref_mut().iter_mut()) {
-                        *dst = src;
-                    }
+                    let keydesc = key_descriptor_decode(cache, try_entry);
                     if keydesc.flags.valid() {
                         let kcache = KeyCacheEntry {
                             start: keydesc.start,
@@ -241,11 +228,7 @@ impl DictCacheEntry {
                     let pp = v2p_map.get(&VirtAddr::new(req_vaddr).unwrap()).expect("dictionary PP should be in existence");
                     assert!(pp.valid(), "v2p returned an invalid page");
                     assert!(cache_pp.page_number() == pp.page_number(), "cache inconsistency error");
-                    let mut keydesc = KeyDescriptor::default();
-                    let start = size_of::<JournalType>() + (try_entry % DK_PER_VPAGE) * DK_STRIDE;
-                    for (&src, dst) in cache[start..start + DK_STRIDE].iter().zip(keydesc.deref_mut().iter_mut()) {
-                        *dst = src;
-                    }
+                    let keydesc = key_descriptor_decode(cache, try_entry);
                     let kname = std::str::from_utf8(&keydesc.name.data[..keydesc.name.len as usize]).expect("key is not valid utf-8");
                     if keydesc.flags.valid() {
                         if kname == name_str {
@@ -671,7 +654,7 @@ impl DictCacheEntry {
         self.keys.contains_key(&String::from(name))
     }
 
-    fn rebuild_free_pool(&mut self) {
+    pub(crate) fn rebuild_free_pool(&mut self) {
         self.small_pool_free.clear();
         for (index, ksp) in self.small_pool.iter().enumerate() {
             self.small_pool_free.push(KeySmallPoolOrd{index, avail: ksp.avail})
@@ -856,6 +839,139 @@ impl DictCacheEntry {
         true
     }
 
+    /// Repacks the small pool so its keys occupy as few pages as possible. Call `fill` first, so that every
+    /// small key is in cache. Returns `None` if the repack would not free at least `min_gain` pages (and at
+    /// least one); otherwise, returns the virtual addresses of the pool pages that are no longer used, and
+    /// how many pages are reclaimed once the moved pools have been given their new pages.
+    ///
+    /// Pools whose membership doesn't change keep their page. Every other pool is moved to a slot that has
+    /// no page mapped, so `sync_small_pool` writes it to a freshly allocated page and the old pages keep
+    /// their data until the new descriptors are on disk. The caller must run `sync_small_pool`, `pt_sync`
+    /// and `dict_sync`, in that order, *before* wiping and freeing the returned pages.
+    pub(crate) fn small_pool_compact(&mut self, v2p_map: &HashMap::<VirtAddr, PhysPage>, min_gain: usize) -> Option<(Vec::<VirtAddr>, usize)> {
+        let mut sizes = Vec::<(u64, String)>::new();
+        let mut occupied = 0;
+        for ksp in self.small_pool.iter() {
+            if ksp.contents.len() > 0 {
+                occupied += 1;
+            }
+            for name in ksp.contents.iter() {
+                let kcache = self.keys.get(name).expect("data allocated but no index entry");
+                if !matches!(kcache.data, Some(KeyCacheData::Small(_))) {
+                    log::warn!("small key {} is not in cache, skipping compaction", name);
+                    return None;
+                }
+                sizes.push((kcache.reserved, name.to_string()));
+            }
+        }
+        // first-fit decreasing; the name is a tie-breaker so the result does not depend on hash order
+        sizes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let mut packed = Vec::<KeySmallPool>::new();
+        for (reserved, name) in sizes {
+            let slot = match packed.iter().position(|ksp| ksp.avail as u64 >= reserved) {
+                Some(slot) => slot,
+                None => {
+                    packed.push(KeySmallPool::new());
+                    packed.len() - 1
+                }
+            };
+            packed[slot].contents.push(name);
+            packed[slot].avail -= reserved as u16;
+        }
+        if packed.len() > occupied {
+            // first-fit decreasing is not optimal, and can lose to an allocation history in rare cases
+            packed = Vec::new();
+            for ksp in self.small_pool.iter().filter(|ksp| ksp.contents.len() > 0) {
+                let mut kept = KeySmallPool::new();
+                kept.contents = ksp.contents.clone();
+                kept.avail = ksp.avail;
+                packed.push(kept);
+            }
+        }
+
+        // pools whose membership did not change stay where they are
+        let mut old_pools = HashMap::<Vec::<String>, usize>::new();
+        for (index, ksp) in self.small_pool.iter().enumerate() {
+            if ksp.clean && ksp.contents.len() > 0 {
+                let mut names = ksp.contents.clone();
+                names.sort();
+                old_pools.insert(names, index);
+            }
+        }
+        let mut targets = Vec::<(usize, bool)>::new(); // (slot, moved)
+        for ksp in packed.iter() {
+            let mut names = ksp.contents.clone();
+            names.sort();
+            targets.push(match old_pools.get(&names) {
+                Some(&index) => (index, false),
+                None => (usize::MAX, true),
+            });
+        }
+        let kept: HashSet::<usize> = targets.iter().filter(|t| !t.1).map(|t| t.0).collect();
+        // everything else moves to slots that have no page mapped, so their old pages stay intact
+        let max_slots = (SMALL_POOL_STRIDE / SMALL_CAPACITY as u64) as usize;
+        let mut next_slot = 0;
+        for target in targets.iter_mut().filter(|t| t.1) {
+            while next_slot < max_slots && (
+                kept.contains(&next_slot)
+                || v2p_map.contains_key(&VirtAddr::new(small_storage_base_vaddr_from_indices(self.index, next_slot)).unwrap())
+            ) {
+                next_slot += 1;
+            }
+            if next_slot >= max_slots {
+                log::warn!("no unmapped small pool slots left, skipping compaction");
+                return None;
+            }
+            target.0 = next_slot;
+            next_slot += 1;
+        }
+        let moved = targets.iter().filter(|t| t.1).count();
+
+        let pool_base = small_storage_base_vaddr_from_indices(self.index, 0);
+        let mut freed = Vec::<VirtAddr>::new();
+        for (vaddr, pp) in v2p_map.iter() {
+            if vaddr.get() >= pool_base && vaddr.get() < pool_base + SMALL_POOL_STRIDE && pp.valid() {
+                let slot = ((vaddr.get() - pool_base) / SMALL_CAPACITY as u64) as usize;
+                if !targets.iter().any(|t| t.0 == slot) {
+                    freed.push(*vaddr);
+                }
+            }
+        }
+        // the moved pools need new pages, so only the difference is actually reclaimed
+        if freed.len() <= moved || freed.len() - moved < min_gain {
+            return None;
+        }
+        freed.sort();
+
+        let slots = targets.iter().map(|t| t.0).max().map(|m| m + 1).unwrap_or(0);
+        let mut pool = Vec::<KeySmallPool>::new();
+        for _ in 0..slots {
+            // placeholders have nothing to write until a new key is allocated into them
+            let mut ksp = KeySmallPool::new();
+            ksp.clean = true;
+            pool.push(ksp);
+        }
+        for (mut ksp, (slot, moved)) in packed.into_iter().zip(targets.into_iter()) {
+            if moved {
+                let pool_vaddr = small_storage_base_vaddr_from_indices(self.index, slot);
+                for name in ksp.contents.iter() {
+                    let kcache = self.keys.get_mut(name).expect("data allocated but no index entry");
+                    // the exact offset within the pool is assigned by `sync_small_pool`
+                    kcache.start = pool_vaddr;
+                    kcache.clean = false;
+                }
+            }
+            ksp.clean = !moved;
+            pool[slot] = ksp;
+        }
+        self.small_pool = pool;
+        self.rebuild_free_pool();
+        self.age = self.age.saturating_add(1);
+        self.clean = false;
+        let gain = freed.len() - moved;
+        Some((freed, gain))
+    }
+
     /// No data cache to flush yet...large pool caches not implemented!
     pub(crate) fn sync_large_pool(&self) {
     }
@@ -997,81 +1113,6 @@ pub(crate) fn small_storage_base_vaddr_from_indices(dict_index: NonZeroU32, base
     SMALL_POOL_START + (dict_index.get()-1) as u64 * DICT_VSIZE + base_index as u64 * SMALL_CAPACITY as u64
 }
 
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
-#[repr(C, align(8))]
-pub struct DictName {
-    pub len: u8,
-    pub data: [u8; DICT_NAME_LEN - 1],
-}
-impl DictName {
-    pub fn try_from_str(name: &str) -> Result<DictName> {
-        let mut alloc = [0u8; DICT_NAME_LEN - 1];
-        let bytes = name.as_bytes();
-        if bytes.len() > (DICT_NAME_LEN - 1) {
-            Err(Error::new(ErrorKind::InvalidInput, "dict name is too long"))
-        } else {
-            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
-                *dst = src;
-            }
-            Ok(DictName {
-                len: bytes.len() as u8, // this as checked above to be short enough
-                data: alloc,
-            })
-        }
-    }
-}
-impl Default for DictName {
-    fn default() -> DictName {
-        DictName {
-            len: 0,
-            data: [0; DICT_NAME_LEN - 1]
-        }
-    }
-}
-
-#[derive(Debug)]
-/// On-disk representation of the dictionary header. This structure is mainly for archival/unarchival
-/// purposes. To "functionalize" a stored disk entry, it needs to be deserialized into a DictionaryCacheEntry.
-#[repr(C, align(8))]
-pub(crate) struct Dictionary {
-    /// Reserved for flags on the record entry
-    pub(crate) flags: DictFlags,
-    /// Access count to the dicitionary
-    pub(crate) age: u32,
-    /// Number of keys in the dictionary
-    pub(crate) num_keys: u32,
-    /// Free index starting space. While this is a derived parameter, its value is recorded to avoid
-    /// an expensive, long search operation during the creation of a dictionary cache record. 0 is an invalid index,
-    /// as this is where the header goes. Maybe this should be a NonZeroU32.
-    pub(crate) free_key_index: u32,
-    /// Name. Length should pad out the record to exactly 127 bytes.
-    pub(crate) name: DictName,
-}
-impl Default for Dictionary {
-    fn default() -> Dictionary {
-        let mut flags = DictFlags(0);
-        flags.set_valid(true);
-        Dictionary { flags, age: 0, num_keys: 0, free_key_index: 1, name: DictName::default() }
-    }
-}
-impl Deref for Dictionary {
-    type Target = [u8];
-    fn deref(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(self as *const Dictionary as *const u8, core::mem::size_of::<Dictionary>())
-                as &[u8]
-        }
-    }
-}
-impl DerefMut for Dictionary {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        unsafe {
-            core::slice::from_raw_parts_mut(self as *mut Dictionary as *mut u8, core::mem::size_of::<Dictionary>())
-                as &mut [u8]
-        }
-    }
-}
-
 #[allow(dead_code)]
 #[derive(Debug)]
 pub(crate) struct DictAttributes {
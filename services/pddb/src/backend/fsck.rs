@@ -0,0 +1,439 @@
+use crate::api::*;
+use super::*;
+
+use core::fmt;
+use core::mem::size_of;
+use std::collections::{HashMap, HashSet};
+use std::io::{Result, Error, ErrorKind};
+
+/// A repairing check copies broken keys into this dictionary of their own basis, with as
+/// much of their data as could be read back, before dropping them from their dictionary.
+pub(crate) const FSCK_QUARANTINE_DICT: &'static str = "fsck.quarantine";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FsckProblem {
+    /// a page table entry points outside of the data area
+    PageOutOfRange,
+    /// a physical page is mapped by more than one virtual page
+    PageAliased,
+    /// a mapped page is also listed as free in the fast space
+    PageMarkedFree,
+    /// a mapped page that no dictionary or key refers to
+    PageOrphaned,
+    /// a fast space entry points outside of the data area
+    FastSpaceEntry,
+    /// the basis root disagrees with the number of dictionaries found
+    DictCount,
+    /// a dictionary header page is not mapped
+    DictDescriptor,
+    /// a dictionary disagrees with the number of keys found
+    KeyCount,
+    /// a key descriptor is out of bounds, or collides with another key
+    KeyDescriptor,
+    /// some of a key's data is missing or does not authenticate
+    KeyData,
+    /// a small pool's free space does not add up, or its keys overlap
+    SmallPool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FsckAction {
+    None,
+    Repaired,
+    Quarantined,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FsckFinding {
+    pub(crate) basis: String,
+    /// where the problem is: a dictionary, a `dict:key` pair, a virtual address or a physical page
+    pub(crate) location: String,
+    pub(crate) problem: FsckProblem,
+    pub(crate) action: FsckAction,
+}
+impl fmt::Display for FsckFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {} {}", self.problem, self.basis, self.location)?;
+        match self.action {
+            FsckAction::None => Ok(()),
+            FsckAction::Repaired => write!(f, " (repaired)"),
+            FsckAction::Quarantined => write!(f, " (quarantined)"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FsckReport {
+    pub(crate) pages_checked: usize,
+    pub(crate) keys_checked: usize,
+    pub(crate) findings: Vec::<FsckFinding>,
+}
+impl FsckReport {
+    fn add(&mut self, basis: &str, location: String, problem: FsckProblem) -> usize {
+        log::warn!("fsck: {:?} in {} at {}", problem, basis, location);
+        self.findings.push(FsckFinding {
+            basis: basis.to_string(),
+            location,
+            problem,
+            action: FsckAction::None,
+        });
+        self.findings.len() - 1
+    }
+    pub(crate) fn count(&self, action: FsckAction) -> usize {
+        self.findings.iter().filter(|f| f.action == action).count()
+    }
+}
+
+/// A key that failed its checks, with whatever data could be read back.
+pub(crate) struct FsckBrokenKey {
+    pub(crate) dict: String,
+    pub(crate) key: String,
+    pub(crate) data: Vec::<u8>,
+    /// index of the finding in the report
+    pub(crate) finding: usize,
+}
+
+/// The problems found in one basis that a repairing check knows how to fix.
+#[derive(Default)]
+pub(crate) struct FsckScan {
+    pub(crate) broken: Vec::<FsckBrokenKey>,
+    dict_count: Option<usize>,
+    key_counts: Vec::<(String, usize)>,
+    pools: Vec::<(String, usize, usize)>,
+    marked_free: Vec::<(u32, usize)>,
+    orphans: Vec::<(VirtAddr, usize)>,
+}
+
+/// Name of the quarantine copy of `dict:key`, clipped to fit a key name.
+pub(crate) fn fsck_quarantine_name(dict: &str, key: &str) -> String {
+    let mut name = format!("{}:{}", dict, key);
+    while name.len() > KEY_NAME_LEN - 1 {
+        name.pop();
+    }
+    name
+}
+
+/// Checks the fast space, as replayed from its journal, which is shared by every basis. Pages it lists
+/// as free while a basis maps them are caught by `fsck_scan`.
+pub(crate) fn fsck_fast_space(hw: &PddbOs, report: &mut FsckReport) {
+    let data_pages = hw.data_page_count();
+    for pp in hw.fast_space_snapshot() {
+        // entries replayed from the journal as used stay in the cache, so only the range can be checked here
+        if pp.page_number() as u32 >= data_pages {
+            report.add(PDDB_FAST_SPACE_SYSTEM_BASIS, format!("page {} {:?}", pp.page_number(), pp.space_state()), FsckProblem::FastSpaceEntry);
+        }
+    }
+}
+
+impl BasisCacheEntry {
+    /// Checks the page table, dictionaries and keys of this basis, and reads back the data of every
+    /// key to confirm it authenticates. Call `populate_caches` first. `owners` maps physical pages to
+    /// the basis mapping them, and should be shared across all the open basis to catch aliased pages.
+    /// Nothing is changed; the returned scan is what `fsck_repair` needs to fix the problems found.
+    pub(crate) fn fsck_scan(&self, hw: &mut PddbOs, owners: &mut HashMap::<u32, String>, report: &mut FsckReport) -> FsckScan {
+        let mut scan = FsckScan::default();
+        let data_pages = hw.data_page_count();
+        let mut free = HashSet::<u32>::new();
+        for pp in hw.fast_space_snapshot() {
+            if pp.space_state() == SpaceState::Free || pp.space_state() == SpaceState::Dirty {
+                free.insert(pp.page_number() as u32);
+            }
+        }
+        for (vaddr, pp) in self.v2p_map.iter() {
+            if !pp.valid() {
+                continue; // freed, and waiting for the next pt_sync
+            }
+            report.pages_checked += 1;
+            let page = pp.page_number() as u32;
+            if page >= data_pages {
+                report.add(&self.name, format!("0x{:x}->page {}", vaddr.get(), page), FsckProblem::PageOutOfRange);
+            }
+            if let Some(other) = owners.insert(page, self.name.clone()) {
+                report.add(&self.name, format!("page {} (also in {})", page, other), FsckProblem::PageAliased);
+            }
+            if free.contains(&page) {
+                let finding = report.add(&self.name, format!("page {}", page), FsckProblem::PageMarkedFree);
+                scan.marked_free.push((page, finding));
+            }
+        }
+
+        let mut complete = true;
+        let mut valid_dicts = 0;
+        let mut dict_indices = HashSet::<u32>::new();
+        let mut large_ranges = Vec::<(u64, u64, String)>::new();
+        for (name, dict) in self.dicts.iter() {
+            if !dict.flags.valid() {
+                continue;
+            }
+            valid_dicts += 1;
+            dict_indices.insert(dict.index.get());
+            if !self.v2p_map.contains_key(&VirtAddr::new(dict.index.get() as u64 * DICT_VSIZE).unwrap()) && dict.clean {
+                report.add(&self.name, name.to_string(), FsckProblem::DictDescriptor);
+            }
+            let mut valid_keys = 0;
+            let mut descriptors = HashSet::<u32>::new();
+            let mut pool_use = vec![0u64; dict.small_pool.len()];
+            let mut pool_extents = vec![Vec::<(u64, u64)>::new(); dict.small_pool.len()];
+            for (key_name, kcache) in dict.keys.iter() {
+                if !kcache.flags.valid() {
+                    continue;
+                }
+                valid_keys += 1;
+                report.keys_checked += 1;
+                let mut problem = None;
+                if !descriptors.insert(kcache.descriptor_index.get()) || kcache.len > kcache.reserved {
+                    problem = Some(FsckProblem::KeyDescriptor);
+                } else if kcache.start >= SMALL_POOL_START && kcache.start < SMALL_POOL_END {
+                    match small_storage_index_from_key(kcache, dict.index) {
+                        Some(index) if index < dict.small_pool.len()
+                        && kcache.reserved <= SMALL_CAPACITY as u64
+                        && dict.small_pool[index].contents.contains(key_name) => {
+                            pool_use[index] += kcache.reserved;
+                            pool_extents[index].push((kcache.start, kcache.start + kcache.reserved));
+                            if !matches!(kcache.data, Some(KeyCacheData::Small(_))) {
+                                problem = Some(FsckProblem::KeyData);
+                            }
+                        }
+                        _ => problem = Some(FsckProblem::KeyDescriptor),
+                    }
+                } else if kcache.start >= LARGE_POOL_START {
+                    if kcache.start % VPAGE_SIZE as u64 != 0 || kcache.reserved % VPAGE_SIZE as u64 != 0
+                    || kcache.reserved > LARGE_FILE_MAX_SIZE {
+                        problem = Some(FsckProblem::KeyDescriptor);
+                    } else {
+                        large_ranges.push((kcache.start, kcache.start + kcache.reserved, format!("{}:{}", name, key_name)));
+                        for vpage in (kcache.start..kcache.start + kcache.len).step_by(VPAGE_SIZE) {
+                            let readable = match self.v2p_map.get(&VirtAddr::new(vpage).unwrap()) {
+                                Some(pp) => pp.valid() && hw.data_decrypt_page(&self.cipher, &self.aad, pp).is_some(),
+                                None => false,
+                            };
+                            if !readable {
+                                problem = Some(FsckProblem::KeyData);
+                                break;
+                            }
+                        }
+                    }
+                } else {
+                    problem = Some(FsckProblem::KeyDescriptor);
+                }
+                if let Some(problem) = problem {
+                    let finding = report.add(&self.name, format!("{}:{}", name, key_name), problem);
+                    scan.broken.push(FsckBrokenKey {
+                        dict: name.to_string(),
+                        key: key_name.to_string(),
+                        data: self.fsck_salvage(hw, kcache),
+                        finding,
+                    });
+                }
+            }
+            for (index, ksp) in dict.small_pool.iter().enumerate() {
+                pool_extents[index].sort();
+                // dirty pools haven't been laid out yet, so only the clean ones can overlap
+                let overlap = ksp.clean && pool_extents[index].windows(2).any(|w| w[0].1 > w[1].0);
+                if overlap || pool_use[index] + ksp.avail as u64 != SMALL_CAPACITY as u64 {
+                    let finding = report.add(&self.name, format!("{} pool {}", name, index), FsckProblem::SmallPool);
+                    scan.pools.push((name.to_string(), index, finding));
+                }
+            }
+            if valid_keys != dict.key_count {
+                complete = false;
+                let finding = report.add(&self.name, name.to_string(), FsckProblem::KeyCount);
+                scan.key_counts.push((name.to_string(), finding));
+            }
+        }
+        if valid_dicts != self.num_dicts {
+            complete = false;
+            scan.dict_count = Some(report.add(&self.name, format!("{} vs {}", valid_dicts, self.num_dicts), FsckProblem::DictCount));
+        }
+
+        large_ranges.sort();
+        for pair in large_ranges.windows(2) {
+            if pair[0].1 > pair[1].0 {
+                // can't tell which of the two owns the shared pages, so leave them be
+                report.add(&self.name, format!("{} overlaps {}", pair[1].2, pair[0].2), FsckProblem::KeyDescriptor);
+            }
+        }
+        // pages can only be called orphans if every dictionary and key is accounted for
+        if complete {
+            for (&vaddr, pp) in self.v2p_map.iter() {
+                if pp.valid() && !fsck_page_owned(vaddr.get(), &dict_indices, &large_ranges) {
+                    let finding = report.add(&self.name, format!("0x{:x}", vaddr.get()), FsckProblem::PageOrphaned);
+                    scan.orphans.push((vaddr, finding));
+                }
+            }
+        }
+        scan
+    }
+
+    /// Reads back as much of a key as possible. Pages that are missing or don't authenticate read as zeros.
+    fn fsck_salvage(&self, hw: &mut PddbOs, kcache: &KeyCacheEntry) -> Vec::<u8> {
+        if let Some(KeyCacheData::Small(small)) = &kcache.data {
+            let mut data = small.data.clone();
+            data.truncate(kcache.len as usize);
+            return data;
+        }
+        if kcache.start < LARGE_POOL_START || kcache.start % VPAGE_SIZE as u64 != 0 {
+            return Vec::new();
+        }
+        // don't trust the length of a broken descriptor further than the pages actually mapped for it
+        let claimed_end = kcache.start.saturating_add(kcache.len.min(LARGE_FILE_MAX_SIZE));
+        let mut mapped_end = kcache.start;
+        for &vaddr in self.v2p_map.keys() {
+            if vaddr.get() >= kcache.start && vaddr.get() < claimed_end {
+                mapped_end = mapped_end.max(vaddr.get() + VPAGE_SIZE as u64);
+            }
+        }
+        let len = (claimed_end.min(mapped_end) - kcache.start) as usize;
+        let mut data = vec![0u8; len];
+        for (index, chunk) in data.chunks_mut(VPAGE_SIZE).enumerate() {
+            let vaddr = VirtAddr::new(kcache.start + (index * VPAGE_SIZE) as u64).unwrap();
+            if let Some(pp) = self.v2p_map.get(&vaddr) {
+                if pp.valid() {
+                    if let Some(page) = hw.data_decrypt_page(&self.cipher, &self.aad, pp) {
+                        let len = chunk.len();
+                        chunk.copy_from_slice(&page[size_of::<JournalType>()..size_of::<JournalType>() + len]);
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    /// Fixes what `fsck_scan` found. Broken keys should already have been copied into the quarantine
+    /// dictionary; here they are dropped from their own dictionary. Counts and small pool bookkeeping
+    /// are rebuilt from the keys that remain, pages that the fast space wrongly lists as free are
+    /// taken out of it, and orphaned pages are wiped and freed. Make sure there is some fast space
+    /// before calling this, as the repaired structures are synced to disk.
+    pub(crate) fn fsck_repair(&mut self, hw: &mut PddbOs, scan: FsckScan, report: &mut FsckReport) -> Result<()> {
+        for broken in scan.broken.iter() {
+            self.fsck_detach_key(&broken.dict, &broken.key);
+        }
+        for (name, finding) in scan.key_counts.iter() {
+            if let Some(dict) = self.dicts.get_mut(name) {
+                dict.key_count = dict.keys.values().filter(|k| k.flags.valid()).count() as u32;
+                dict.age = dict.age.saturating_add(1);
+                dict.clean = false;
+                report.findings[*finding].action = FsckAction::Repaired;
+            }
+        }
+        for (name, index, finding) in scan.pools.iter() {
+            if let Some(dict) = self.dicts.get_mut(name) {
+                let keys = &dict.keys;
+                let ksp = &mut dict.small_pool[*index];
+                ksp.contents.retain(|k| keys.get(k).map(|kc| kc.flags.valid()).unwrap_or(false));
+                let used: u64 = ksp.contents.iter().map(|k| keys[k].reserved).sum();
+                if used <= SMALL_CAPACITY as u64 {
+                    // rewriting the pool lays its keys out again, one after the other
+                    ksp.avail = (SMALL_CAPACITY as u64 - used) as u16;
+                    ksp.clean = false;
+                    report.findings[*finding].action = FsckAction::Repaired;
+                }
+                dict.rebuild_free_pool();
+            }
+        }
+        if let Some(finding) = scan.dict_count {
+            self.num_dicts = self.dicts.values().filter(|d| d.flags.valid()).count() as u32;
+            self.age = self.age.saturating_add(1);
+            self.clean = false;
+            report.findings[finding].action = FsckAction::Repaired;
+        }
+        for (page, finding) in scan.marked_free.iter() {
+            if hw.fast_space_claim(*page) {
+                report.findings[*finding].action = FsckAction::Repaired;
+            }
+        }
+
+        let mut dictnames = Vec::<String>::new();
+        for (name, dict) in self.dicts.iter() {
+            if dict.flags.valid() {
+                dictnames.push(name.to_string());
+            }
+        }
+        for name in dictnames.iter() {
+            let dict = self.dicts.get_mut(name).expect("dict was just listed");
+            if !dict.sync_small_pool(hw, &mut self.v2p_map, &self.cipher) {
+                return Err(Error::new(ErrorKind::OutOfMemory, "No free space to repair the small pool"));
+            }
+        }
+        self.sync(hw)?;
+
+        for (vaddr, finding) in scan.orphans.iter() {
+            if let Some(pp) = self.v2p_map.get_mut(vaddr) {
+                if pp.valid() {
+                    let mut random = [0u8; PAGE_SIZE];
+                    hw.trng_slice(&mut random);
+                    hw.patch_data(&random, pp.page_number() * PAGE_SIZE as u32);
+                    hw.fast_space_free(pp);
+                    report.findings[*finding].action = FsckAction::Repaired;
+                }
+            }
+        }
+        self.pt_sync(hw);
+        Ok(())
+    }
+
+    /// Drops a key from its dictionary without touching its data pages: for a broken descriptor, they
+    /// might belong to another key. Pages nobody owns are picked up as orphans by the next check.
+    fn fsck_detach_key(&mut self, dict_name: &str, key_name: &str) {
+        if let Some(dict) = self.dicts.get_mut(dict_name) {
+            let (descriptor, reserved) = if let Some(kcache) = dict.keys.get(key_name) {
+                (kcache.descriptor_index.get(), kcache.reserved)
+            } else {
+                return;
+            };
+            for ksp in dict.small_pool.iter_mut() {
+                if let Some(position) = ksp.contents.iter().position(|k| k == key_name) {
+                    ksp.contents.swap_remove(position);
+                    // give the space back like `key_remove` does; a damaged size can't push the pool past its capacity
+                    ksp.avail = (ksp.avail as u64 + reserved).min(SMALL_CAPACITY as u64) as u16;
+                    ksp.clean = false;
+                }
+            }
+            let shared = dict.keys.iter().any(|(k, kc)|
+                k != key_name && kc.flags.valid() && kc.descriptor_index.get() == descriptor);
+            if shared {
+                // the descriptor slot belongs to another key too: forget this one, and have the other one rewritten
+                dict.keys.remove(key_name);
+                for kcache in dict.keys.values_mut() {
+                    if kcache.descriptor_index.get() == descriptor {
+                        kcache.clean = false;
+                    }
+                }
+            } else {
+                let kcache = dict.keys.get_mut(key_name).expect("key was just found");
+                kcache.flags.set_valid(false);
+                kcache.age = kcache.age.saturating_add(1);
+                kcache.clean = false;
+                dict.put_free_key_index(descriptor);
+            }
+            dict.key_count = dict.key_count.saturating_sub(1);
+            dict.age = dict.age.saturating_add(1);
+            dict.clean = false;
+            dict.rebuild_free_pool();
+        }
+    }
+}
+
+/// Decides if a virtual page of a basis belongs to anything, given the indices of its
+/// valid dictionaries and the extents of its large keys (sorted by start).
+fn fsck_page_owned(vaddr: u64, dict_indices: &HashSet::<u32>, large_ranges: &Vec::<(u64, u64, String)>) -> bool {
+    if vaddr < DICT_VSIZE {
+        true // the basis root region
+    } else if vaddr < SMALL_POOL_START {
+        dict_indices.contains(&((vaddr / DICT_VSIZE) as u32))
+    } else if vaddr >= SMALL_POOL_START && vaddr < SMALL_POOL_END {
+        dict_indices.contains(&(((vaddr - SMALL_POOL_START) / SMALL_POOL_STRIDE) as u32 + 1))
+    } else if vaddr >= LARGE_POOL_START {
+        large_ranges.binary_search_by(|(start, end, _)|
+            if *end <= vaddr {
+                std::cmp::Ordering::Less
+            } else if *start > vaddr {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        ).is_ok()
+    } else {
+        false
+    }
+}
@@ -7,7 +7,6 @@ use aes::{Aes256, Block, BLOCK_SIZE};
 use aes::cipher::{BlockDecrypt, BlockEncrypt, NewBlockCipher, generic_array::GenericArray};
 use root_keys::api::AesRootkeyType;
 use spinor::SPINOR_BULK_ERASE_SIZE;
-use subtle::ConstantTimeEq;
 use core::ops::{Deref, DerefMut};
 use core::mem::size_of;
 
@@ -30,19 +29,8 @@ use zeroize::Zeroize;
 #[cfg(feature="migration1")]
 use crate::backend::migration1to2::*;
 
-/// Implementation-specific PDDB structures: for Precursor/Xous OS pair
-pub(crate) const MBBB_PAGES: usize = 10;
-pub(crate) const FSCB_PAGES: usize = 16;
-
 /// size of a physical page
 pub const PAGE_SIZE: usize = spinor::SPINOR_ERASE_SIZE as usize;
-/// size of a virtual page -- after the AES encryption and journaling overhead is subtracted
-pub const VPAGE_SIZE: usize = PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<JournalType>();
-
-/// length of the ciphertext in an AES-GCM-SIV page with key commitments
-/// equal to the total plaintext to be encrypted, including the journal number
-/// does not include the MAC overhead
-pub const KCOM_CT_LEN: usize = 4004;
 
 pub(crate) const WRAPPED_AES_KEYSIZE: usize = AES_KEYSIZE + 8;
 const SCD_VERSION: u32 = 2;
@@ -429,62 +417,16 @@ impl PddbOs {
     /// into the page table, overwriting the impostor entry in the paget able. On the next mount, this turns into the "trivial conflict"
     /// case, where one page will validate and the other will not.
     pub(crate) fn pt_scan_key(&self, key: &[u8; AES_KEYSIZE], basis_name: &str) -> Option<HashMap::<VirtAddr, PhysPage>> {
-        let cipher = Aes256::new(&GenericArray::from_slice(key));
-        let pt = self.pt_as_slice();
-        let mut map = HashMap::<VirtAddr, PhysPage>::new();
-        let blank = [0xffu8; aes::BLOCK_SIZE];
-        for (page_index, pt_page) in pt.chunks(PAGE_SIZE).enumerate() {
-            let clean_page = if pt_page[..aes::BLOCK_SIZE] == blank {
-                if let Some(page) = self.mbbb_retrieve() {
-                    page
-                } else {
-                    log::debug!("Blank page in PT found, but no MBBB entry exists. PT is either corrupted or not initialized!");
-                    pt_page
-                }
-            } else {
-                pt_page
-            };
-            for (index, candidate) in clean_page.chunks(aes::BLOCK_SIZE).enumerate() {
-                // encryption is in-place, but the candidates are read-only, so we have to copy them to a new location
-                let mut block = Block::clone_from_slice(candidate);
-                cipher.decrypt_block(&mut block);
-                if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
-                    let mut pp = PhysPage(0);
-                    pp.set_page_number(((page_index * PAGE_SIZE / aes::BLOCK_SIZE) + index) as PhysAddr);
-                    // the state is clean because this entry is, by definition, synchronized with the disk
-                    pp.set_clean(true);
-                    pp.set_valid(true);
-                    pp.set_space_state(SpaceState::Used);
-                    // handle conflicting journal versions here
-                    if let Some(prev_page) = map.get(&pte.vaddr()) {
-                        let cipher = Aes256GcmSiv::new(Key::from_slice(key));
-                        let aad = self.data_aad(basis_name);
-                        let prev_data = self.data_decrypt_page(&cipher, &aad, prev_page);
-                        let new_data = self.data_decrypt_page(&cipher, &aad, &pp);
-                        if let Some(new_d) = new_data {
-                            if let Some(prev_d) = prev_data {
-                                let prev_j = JournalType::from_le_bytes(prev_d[..size_of::<JournalType>()].try_into().unwrap());
-                                let new_j = JournalType::from_le_bytes(new_d[..size_of::<JournalType>()].try_into().unwrap());
-                                if new_j > prev_j {
-                                    map.insert(pte.vaddr(), pp);
-                                } else if new_j == prev_j {
-                                    log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
-                                }
-                            } else {
-                                self.resolve_pp_journal(&mut pp);
-                                // prev data was bogus anyways, replace with the new entry
-                                map.insert(pte.vaddr(), pp);
-                            }
-                        } else {
-                            // new data is bogus, ignore it
-                        }
-                    } else {
-                        self.resolve_pp_journal(&mut pp);
-                        map.insert(pte.vaddr(), pp);
-                    }
-                }
-            }
-        }
+        let cipher_ecb = Aes256::new(&GenericArray::from_slice(key));
+        let cipher = Aes256GcmSiv::new(Key::from_slice(key));
+        let aad = self.data_aad(basis_name);
+        let map = pt_scan(
+            self.pt_as_slice(),
+            self.mbbb_retrieve(),
+            &cipher_ecb,
+            |pp| self.data_decrypt_page(&cipher, &aad, pp),
+            |pp| self.resolve_pp_journal(pp),
+        );
         if map.len() > 0 {
             Some(map)
         } else {
@@ -1117,6 +1059,46 @@ impl PddbOs {
         // mark the page as invalid, so that it will be deleted on the next PT sync
         pp.set_valid(false);
     }
+    /// Returns a copy of the fast space cache, for the consistency checker.
+    pub(crate) fn fast_space_snapshot(&self) -> Vec::<PhysPage> {
+        self.fspace_cache.iter().cloned().collect()
+    }
+    /// Takes a page out of the fast space without handing it out, and journals it as Used. This repairs
+    /// a page that is mapped by a basis, but that the fast space also thinks is free. Returns false if
+    /// the page was not in the fast space, or there was no room to journal the change.
+    pub(crate) fn fast_space_claim(&mut self, page_number: u32) -> bool {
+        let mut target = PhysPage(0);
+        target.set_page_number(page_number as PhysAddr);
+        let mut pp = if let Some(pp) = self.fspace_cache.get(&target) {
+            pp.clone()
+        } else {
+            return false
+        };
+        if pp.journal() >= PHYS_PAGE_JOURNAL_MAX || !self.fast_space_ensure_next_log() {
+            return false
+        }
+        self.fspace_cache.remove(&pp);
+        pp.set_space_state(SpaceState::Used);
+        pp.set_journal(pp.journal() + 1);
+
+        self.syskey_ensure();
+        let cipher = self.cipher_ecb.as_ref().expect("Inconsistent internal state - syskey_ensure() failed");
+        let mut update = SpaceUpdate::new(self.entropy.borrow_mut().get_u64(), pp);
+        let mut block = Block::from_mut_slice(update.deref_mut());
+        cipher.encrypt_block(&mut block);
+        let log_addr = self.fspace_log_next_addr.take().unwrap() as PhysAddr;
+        self.patch_fscb(&block, log_addr);
+        self.fspace_log_len += 1;
+        let next_addr = log_addr + aes::BLOCK_SIZE as PhysAddr;
+        if (next_addr & (PAGE_SIZE as PhysAddr - 1)) != 0 {
+            self.fspace_log_next_addr = Some(next_addr as PhysAddr);
+        }
+        true
+    }
+    /// Number of physical pages in the data area.
+    pub(crate) fn data_page_count(&self) -> u32 {
+        ((PDDB_A_LEN - self.data_phys_base.as_usize()) / PAGE_SIZE) as u32
+    }
     /// This is a "look before you leap" function that will potentially pause all system operations
     /// and do a deep scan for space if the required amount is not available.
     pub fn ensure_fast_space_alloc(&mut self, pages: usize, cache: &Vec::<BasisCacheEntry>) -> bool {
@@ -1190,108 +1172,25 @@ impl PddbOs {
     }
 
     pub(crate) fn data_aad(&self, name: &str) -> Vec::<u8> {
-        let mut aad = Vec::<u8>::new();
-        aad.extend_from_slice(&name.as_bytes());
-        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
-        aad.extend_from_slice(&self.dna.to_le_bytes());
-        aad
+        data_aad(name, self.dna)
     }
 
-    /// returns a decrypted page that still includes the journal number at the very beginning
-    /// We don't clip it off because it would require re-allocating a vector, and it's cheaper (although less elegant) to later
-    /// just index past it.
+    /// returns a decrypted page that still includes the journal number at the very beginning; see `decrypt_page()`
     pub(crate) fn data_decrypt_page(&self, cipher: &Aes256GcmSiv, aad: &[u8], page: &PhysPage) -> Option<Vec::<u8>> {
-        let ct_slice = &self.pddb_mr.as_slice()[
-            self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
-            self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
-        let nonce = &ct_slice[..size_of::<Nonce>()];
-        let ct = &ct_slice[size_of::<Nonce>()..];
-        match cipher.decrypt(
-            Nonce::from_slice(nonce),
-            Payload {
-                aad,
-                msg: ct,
-            }
-        ) {
-            Ok(data) => {
-                assert!(data.len() == VPAGE_SIZE + size_of::<JournalType>(), "authentication successful, but wrong amount of data was recovered");
-                Some(data)
-            },
-            Err(e) => {
-                log::trace!("Error decrypting page: {:?}", e); // sometimes this is totally "normal", like when we're testing for valid data.
-                None
-            }
-        }
+        decrypt_page(cipher, aad, self.data_page_as_slice(page))
     }
 
-    /// returns a decrypted page that also encodes a key commitments. In this case, a raw key is passed,
-    /// instead of the generic AES-GCM-SIV cipher, because we need to derive the key commitment.
-    /// Key commitments are a patch to work-around the salamander problem in AES-GCM-SIV see https://eprint.iacr.org/2020/1456.pdf
-    ///
-    /// The structure of a page with commit key storage is as follows:
-    /// - Nonce - 12 bytes
-    /// - ciphertext - 4004 bytes (includes the journal number)
-    ///   - kcomm_nonce - 32 bytes
-    ///   - kcomm - 32 bytes
-    /// - MAC - 16 bytes
-    /// We stripe the MAC at the end just in case the MAC has some arithmetic property that can betray the existence
-    /// of a basis root record with key commitment. The committed key and the nonce both should be indistinguishable
-    /// from ciphertext.
+    /// returns a decrypted page that also encodes a key commitment; see `decrypt_page_with_commit()` for the layout.
     pub(crate) fn data_decrypt_page_with_commit(&self, key: &[u8], aad: &[u8], page: &PhysPage) -> Option<Vec::<u8>> {
-        const KCOM_NONCE_LEN: usize = 32;
-        const KCOM_LEN: usize = 32;
-        const MAC_LEN: usize = 16;
-        let ct_slice = &self.pddb_mr.as_slice()[
-            self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
-            self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE];
         log::debug!("commit data at 0x{:x}", self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE);
-        let nonce = &ct_slice[..size_of::<Nonce>()];
-        let ct_total = &ct_slice[size_of::<Nonce>()..];
-
-        // extract the regions of the stored data and place them into their respective buffers
-        let mut ct_plus_mac = [0u8; KCOM_CT_LEN + MAC_LEN];
-        let mut nonce_comm = [0u8; KCOM_NONCE_LEN];
-        let mut key_comm_stored = [0u8; KCOM_LEN];
-        let mut ct_pos = 0;
-
-        for (&src, dst) in ct_total[ct_pos..].iter().zip(ct_plus_mac[..KCOM_CT_LEN].iter_mut()) {
-            *dst = src;
-            ct_pos += 1;
-        }
-        for (&src, dst) in ct_total[ct_pos..].iter().zip(nonce_comm.iter_mut()) {
-            *dst = src;
-            ct_pos += 1;
-        }
-        for (&src, dst) in ct_total[ct_pos..].iter().zip(key_comm_stored.iter_mut()) {
-            *dst = src;
-            ct_pos += 1;
-        }
-        for (&src, dst) in ct_total[ct_pos..].iter().zip(ct_plus_mac[KCOM_CT_LEN..].iter_mut()) {
-            *dst = src;
-            ct_pos += 1;
-        }
-        assert!(ct_pos == PAGE_SIZE - size_of::<Nonce>(), "struct sizing error in unpacking page with key commit");
-        log::debug!("found nonce of {:x?}", nonce);
-        log::debug!("found kcom_nonce of {:x?}", nonce_comm);
-
-        let (kenc, kcom) = self.kcom_func(key.try_into().unwrap(), &nonce_comm);
-        let cipher = Aes256GcmSiv::new(Key::from_slice(&kenc));
-
-        // Attempt decryption. This is None on failure
-        let plaintext = cipher.decrypt(
-            Nonce::from_slice(nonce),
-            Payload {
-                aad,
-                msg: &ct_plus_mac,
-            }
-        ).ok();
+        decrypt_page_with_commit(key, aad, self.data_page_as_slice(page))
+    }
 
-        // Only return the plaintext if the stored key commitment agrees with the computed one
-        if kcom.ct_eq(&key_comm_stored).into() {
-            plaintext
-        } else {
-            None
-        }
+    /// maps the ciphertext of a physical page out of the data area
+    fn data_page_as_slice(&self, page: &PhysPage) -> &[u8] {
+        &self.pddb_mr.as_slice()[
+            self.data_phys_base.as_usize() + page.page_number() as usize * PAGE_SIZE ..
+            self.data_phys_base.as_usize() + (page.page_number() as usize + 1) * PAGE_SIZE]
     }
 
     /// `data` includes the journal entry on top. The data passed in must be exactly one vpage plus the journal entry
@@ -1321,10 +1220,10 @@ impl PddbOs {
         // gets the AES-GCM-SIV nonce
         let nonce = self.nonce_gen();
         // makes a nonce for the key commit
-        let mut kcom_nonce = [0u8; 32];
+        let mut kcom_nonce = [0u8; KCOM_NONCE_LEN];
         self.trng_slice(&mut kcom_nonce);
         // generates the encryption and commit keys
-        let (kenc, kcom) = self.kcom_func(key.try_into().unwrap(), &kcom_nonce);
+        let (kenc, kcom) = kcom_func(key.try_into().unwrap(), &kcom_nonce);
         let cipher = Aes256GcmSiv::new(Key::from_slice(&kenc));
         let ciphertext = cipher.encrypt(
             &nonce,
@@ -1352,32 +1251,6 @@ impl PddbOs {
         self.patch_data(&dest_page, pp.page_number() * PAGE_SIZE as u32);
     }
 
-    /// Derive a key commitment. This takes in a base `key`, which is 256 bits;
-    /// a `nonce` which is the 96-bit nonce used in the AES-GCM-SIV for a given block;
-    /// and `nonce_com` which is the commitment nonce, set at 256 bits.
-    /// The result is two tuples, (kenc, kcom).
-    fn kcom_func(&self,
-        key: &[u8; 32],
-        nonce_com: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
-        use sha2::{FallbackStrategy, Sha512Trunc256};
-        use digest::Digest;
-
-        let mut h_enc = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
-        h_enc.update(key);
-        // per https://eprint.iacr.org/2020/1456.pdf Table 4 on page 13 Type I Lenc
-        h_enc.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x01]);
-        h_enc.update(nonce_com);
-        let k_enc = h_enc.finalize();
-
-        let mut h_com = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
-        h_com.update(key);
-        // per https://eprint.iacr.org/2020/1456.pdf Table 4 on page 13 Type I Lcom. Note one-bit difference in last byte.
-        h_com.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x02]);
-        h_com.update(nonce_com);
-        let k_com = h_com.finalize();
-        (k_enc.into(), k_com.into())
-    }
-
     /// Meant to be called on boot. This will read the FastSpace record, and then attempt to load
     /// in the system basis.
     pub(crate) fn pddb_mount(&mut self) -> Option<BasisCacheEntry> {
@@ -1392,27 +1265,12 @@ impl PddbOs {
                         Some(data) => data,
                         None => {log::error!("System basis decryption did not authenticate. Unrecoverable error."); return None;},
                     };
-                    // if the below assertion fails, you will need to re-code this to decrypt more than one VPAGE and stripe into a basis root struct
-                    assert!(size_of::<BasisRoot>() <= VPAGE_SIZE, "BasisRoot has grown past a single VPAGE, this routine needs to be re-coded to accommodate the extra bulk");
-                    let mut basis_root = BasisRoot::default();
-                    for (&src, dst) in vpage[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
-                        *dst = src;
-                    }
-                    if basis_root.magic != PDDB_MAGIC {
-                        log::error!("Basis root did not deserialize correctly, unrecoverable error.");
-                        return None;
-                    }
-                    if basis_root.version != PDDB_VERSION {
-                        log::error!("PDDB version mismatch in system basis root. Unrecoverable error.");
-                        return None;
-                    }
-                    let basis_name = std::str::from_utf8(&basis_root.name.data[..basis_root.name.len as usize]).expect("basis is not valid utf-8");
-                    if basis_name != PDDB_DEFAULT_SYSTEM_BASIS {
-                        log::error!("PDDB system basis name is incorrect: {}; aborting mount operation.", basis_name);
+                    if let Err(e) = basis_root_decode(&vpage, PDDB_DEFAULT_SYSTEM_BASIS) {
+                        log::error!("System basis root is not usable: {:?}; aborting mount operation.", e);
                         return None;
                     }
                     log::info!("System BasisRoot record found, generating cache entry");
-                    let bce = BasisCacheEntry::mount(self, &basis_name, &syskey, false, BasisRetentionPolicy::Persist);
+                    let bce = BasisCacheEntry::mount(self, PDDB_DEFAULT_SYSTEM_BASIS, &syskey, false, BasisRetentionPolicy::Persist);
                     self.system_basis_key = Some(syskey);
                     bce
                 } else {
@@ -2009,10 +1867,7 @@ impl PddbOs {
                         if let Some(vpage) = migrating_data {
                             log::info!("migration: found root block!");
                             found_basis = true;
-                            let mut basis_root = BasisRoot::default();
-                            for (&src, dst) in (&vpage)[size_of::<JournalType>()..].iter().zip(basis_root.deref_mut().iter_mut()) {
-                                *dst = src;
-                            }
+                            let mut basis_root = basis_root_decode_unchecked(&vpage);
                             let (previous, _) = PDDB_MIGRATE_1;
                             if basis_root.version != previous {
                                 log::warn!("Root basis record did not match expected version during migration. Ignoring and attempting to move on...");
@@ -0,0 +1,229 @@
+// On-disk layout of the PDDB: region sizes, the basis virtual memory map, and the records
+// stored in it. Nothing in here touches the hardware, so host-side tools can include this file
+// (along with `types.rs`, `pagetable.rs` and `key.rs`) to read images with the same definitions
+// the PDDB itself uses. See `basis.rs` for a description of the overall organization.
+use crate::api::*;
+use super::*;
+
+use core::ops::{Deref, DerefMut};
+use core::mem::size_of;
+use aes_gcm_siv::{Nonce, Tag};
+use bitfield::bitfield;
+use std::io::{Result, Error, ErrorKind};
+
+/// Implementation-specific PDDB structures: for Precursor/Xous OS pair
+pub(crate) const MBBB_PAGES: usize = 10;
+pub(crate) const FSCB_PAGES: usize = 16;
+
+/// size of a virtual page -- after the AES encryption and journaling overhead is subtracted
+pub const VPAGE_SIZE: usize = PAGE_SIZE - size_of::<Nonce>() - size_of::<Tag>() - size_of::<JournalType>();
+
+/// length of the ciphertext in an AES-GCM-SIV page with key commitments
+/// equal to the total plaintext to be encrypted, including the journal number
+/// does not include the MAC overhead
+pub const KCOM_CT_LEN: usize = 4004;
+
+pub(crate) const SMALL_POOL_START: u64 = 0x0000_003F_8000_0000;
+pub(crate) const SMALL_POOL_END: u64 = 0x0000_007E_FF02_0000;
+pub(crate) const SMALL_POOL_STRIDE: u64 = 0xFE_0000;
+/// we don't want this bigger than VPAGE_SIZE, because a key goal of the small pool is to
+/// reduce # of writes to the disk of small data. While we could get some gain in memory efficiency
+/// if we made this larger than a VPAGE_SIZE, we don't get much gain in terms of write reduction,
+/// and it greatly complicates the implementation. So, SMALL_CAPACITY should be less than VPAGE_SIZE.
+pub(crate) const SMALL_CAPACITY: usize = VPAGE_SIZE;
+pub(crate) const LARGE_POOL_START: u64 = 0x0000_FE00_0000_0000;
+pub(crate) const KEY_MAXCOUNT: usize = 131_071; // 2^17 - 1
+/// This is a size limit on the biggest file you can create. It's currently 32GiB. No, this is not
+/// web scale, but it's big enough to hold a typical blu-ray movie as a single file. You can adjust
+/// this constant up or down, and the trade-off is, you get more or less total number of large files
+/// allocated over the life of the filesystem. We simply "increment a pointer" when a new large file
+/// is added to create the next virtual memory spot for the large file. So at 32GiB, you can create
+/// a lifetime total of about 200 million files (this includes files you've previously deleted, until
+/// we create a mechanism for sweeping through the memory space and tracking de-allocations). Note that
+/// a "large" file includes anything over 4kiB, so if you create a 5kiB file, it can potentially grow to
+/// 32 GiB without bumping into the next large file. This is a very "lazy" way to deal with large files.
+/// Given that the PDDB is designed for a 32-bit device with only 128MiB of memory and a read/write lifetime
+/// of 100k cycles for the FLASH, 200 million file allocations is probably greater than the lifetime of
+/// the device itself. If the PDDB migrates to a larger handphone-style application, I think it'll probably
+/// still hold up OK with 200 million total large file allocations over the device lifetime and a limit
+/// of 32GiB. That's about 73k files created per day for 10 years, or about 50 files per minute -- roughly
+/// one new file per second for 10 years straight before the PDDB runs out of virtual memory space.
+/// A web server creating a >4k temporary log file for every client that hit and then deleting it
+/// would probably crush this limit in months. So don't use the PDDB to back a high volume web server.
+/// But it's probably OK for a consumer electronics device with a typical lifetime of less than 10 years.
+/// If you really think you want larger files and also more write life, you'd need to implement an in-memory
+/// "free" file allocator, but honestly, this is not something I think we need to burn resources on for
+/// the initial target of the PDDB (that is, a 100MiB device with 100k read/write endurance lifetime).
+/// Anyways, the code is written so you can just slide this constant up or down and change the behavior
+/// of the system; it's recommended you reformat when you do that but I /think/ it should actually be OK
+/// if you made a change "on the fly".
+///
+/// Also note that in practice, a file size is limited to 4GiB on a 32-bit Precursor device anyways
+/// because the usize type isn't big enough. Recompiling for a 64-bit target, however, should give
+/// you access to the 32GiB file size limit.
+pub(crate) const LARGE_FILE_MAX_SIZE: u64 = 0x0000_0008_0000_0000;
+
+/// The chosen "stride" of a dict/key entry. Drives a lot of key parameters in the database's characteristics.
+/// This is chosen such that 32 of these entries fit evenly into a VPAGE.
+pub(crate) const DK_STRIDE: usize = 127;
+//// DK_STRIDES per VPAGE
+pub(crate) const DK_PER_VPAGE: usize = VPAGE_SIZE / DK_STRIDE; // should be 32 - use this for computing modulus on dictionary indices
+/// size of a dictionary region in virtual memory
+pub(crate) const DICT_VSIZE: u64 = 0xFE_0000;
+/// maximum number of dictionaries in a system
+pub(crate) const DICT_MAXCOUNT: usize = 16383;
+
+/// This is the format of the Basis as stored on disk
+#[derive(PartialEq, Debug, Default)]
+#[repr(C, align(8))]
+pub(crate) struct BasisRoot {
+    pub(crate) magic: [u8; 4],
+    pub(crate) version: u32,
+    /// increments every time the BasisRoot is modified. This field must saturate, not roll over.
+    pub(crate) age: u32,
+    /// number of dictionaries.
+    pub(crate) num_dictionaries: u32,
+    /* at this point, we are aligned to a 64-bit boundary. All data must stay aligned to this boundary from here out! */
+    /// 64-byte name; aligns to 64-bits
+    pub(crate) name: BasisRootName,
+}
+impl BasisRoot {
+    pub(crate) fn aad(&self, dna: u64) -> Vec::<u8> {
+        let mut aad = Vec::<u8>::new();
+        aad.extend_from_slice(&self.name.data[..self.name.len as usize]);
+        aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+        aad.extend_from_slice(&dna.to_le_bytes());
+        aad
+    }
+}
+impl Deref for BasisRoot {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const BasisRoot as *const u8, core::mem::size_of::<BasisRoot>())
+                as &[u8]
+        }
+    }
+}
+impl DerefMut for BasisRoot {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self as *mut BasisRoot as *mut u8, core::mem::size_of::<BasisRoot>())
+                as &mut [u8]
+        }
+    }
+}
+
+bitfield! {
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    pub struct DictFlags(u32);
+    impl Debug;
+    pub valid, set_valid: 0;
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+#[repr(C, align(8))]
+pub struct DictName {
+    pub len: u8,
+    pub data: [u8; DICT_NAME_LEN - 1],
+}
+impl DictName {
+    pub fn try_from_str(name: &str) -> Result<DictName> {
+        let mut alloc = [0u8; DICT_NAME_LEN - 1];
+        let bytes = name.as_bytes();
+        if bytes.len() > (DICT_NAME_LEN - 1) {
+            Err(Error::new(ErrorKind::InvalidInput, "dict name is too long"))
+        } else {
+            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
+                *dst = src;
+            }
+            Ok(DictName {
+                len: bytes.len() as u8, // this as checked above to be short enough
+                data: alloc,
+            })
+        }
+    }
+}
+impl Default for DictName {
+    fn default() -> DictName {
+        DictName {
+            len: 0,
+            data: [0; DICT_NAME_LEN - 1]
+        }
+    }
+}
+
+#[derive(Debug)]
+/// On-disk representation of the dictionary header. This structure is mainly for archival/unarchival
+/// purposes. To "functionalize" a stored disk entry, it needs to be deserialized into a DictionaryCacheEntry.
+#[repr(C, align(8))]
+pub(crate) struct Dictionary {
+    /// Reserved for flags on the record entry
+    pub(crate) flags: DictFlags,
+    /// Access count to the dicitionary
+    pub(crate) age: u32,
+    /// Number of keys in the dictionary
+    pub(crate) num_keys: u32,
+    /// Free index starting space. While this is a derived parameter, its value is recorded to avoid
+    /// an expensive, long search operation during the creation of a dictionary cache record. 0 is an invalid index,
+    /// as this is where the header goes. Maybe this should be a NonZeroU32.
+    pub(crate) free_key_index: u32,
+    /// Name. Length should pad out the record to exactly 127 bytes.
+    pub(crate) name: DictName,
+}
+impl Default for Dictionary {
+    fn default() -> Dictionary {
+        let mut flags = DictFlags(0);
+        flags.set_valid(true);
+        Dictionary { flags, age: 0, num_keys: 0, free_key_index: 1, name: DictName::default() }
+    }
+}
+impl Deref for Dictionary {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self as *const Dictionary as *const u8, core::mem::size_of::<Dictionary>())
+                as &[u8]
+        }
+    }
+}
+impl DerefMut for Dictionary {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self as *mut Dictionary as *mut u8, core::mem::size_of::<Dictionary>())
+                as &mut [u8]
+        }
+    }
+}
+
+/// Newtype for BasisRootName so we can give it a default initializer.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct BasisRootName {
+    pub len: u8,
+    pub data: [u8; BASIS_NAME_LEN - 1],
+}
+impl BasisRootName {
+    pub fn try_from_str(name: &str) -> Result<BasisRootName> {
+        let mut alloc = [0u8; BASIS_NAME_LEN - 1];
+        let bytes = name.as_bytes();
+        if bytes.len() > (BASIS_NAME_LEN - 1) {
+            Err(Error::new(ErrorKind::InvalidInput, "basis name is too long")) // FileNameTooLong is still nightly :-/
+        } else {
+            for (&src, dst) in bytes.iter().zip(alloc.iter_mut()) {
+                *dst = src;
+            }
+            Ok(BasisRootName {
+                len: bytes.len() as u8, // this as checked above to be short enough
+                data: alloc,
+            })
+        }
+    }
+}
+impl Default for BasisRootName {
+    fn default() -> BasisRootName {
+        BasisRootName{
+            len: 0,
+            data: [0; BASIS_NAME_LEN - 1]
+        }
+    }
+}
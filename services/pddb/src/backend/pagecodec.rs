@@ -0,0 +1,267 @@
+// Page-level decoding of the PDDB: decrypting data pages, deriving and checking key commitments,
+// scanning the page table, and unpacking the records stored in vpages. Like `layout.rs`, nothing
+// in here touches the hardware -- `PddbOs` hands over the bytes it maps out of FLASH -- so
+// host-side tools include this file to read images with the same code the PDDB mounts them with.
+use crate::api::*;
+use super::*;
+
+use core::ops::DerefMut;
+use core::mem::size_of;
+use std::convert::TryInto;
+use std::collections::HashMap;
+use std::io::{Result, Error, ErrorKind};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce, Key, Tag};
+use aes_gcm_siv::aead::{Aead, NewAead, Payload};
+use aes::{Aes256, Block, BLOCK_SIZE};
+use aes::cipher::BlockDecrypt;
+use subtle::ConstantTimeEq;
+
+/// length of the nonce used to derive a key commitment
+pub(crate) const KCOM_NONCE_LEN: usize = 32;
+/// length of a stored key commitment
+pub(crate) const KCOM_LEN: usize = 32;
+
+/// The AAD that binds a data page to its basis, the PDDB version, and the device it was written on.
+pub(crate) fn data_aad(name: &str, dna: u64) -> Vec::<u8> {
+    let mut aad = Vec::<u8>::new();
+    aad.extend_from_slice(&name.as_bytes());
+    aad.extend_from_slice(&PDDB_VERSION.to_le_bytes());
+    aad.extend_from_slice(&dna.to_le_bytes());
+    aad
+}
+
+/// The journal number at the top of a decrypted page.
+pub(crate) fn journal_of(page: &[u8]) -> JournalType {
+    JournalType::from_le_bytes(page[..size_of::<JournalType>()].try_into().unwrap())
+}
+
+/// Decrypts one physical page of ciphertext, as stored: the nonce, followed by the ciphertext and MAC.
+/// The result still includes the journal number at the very beginning. We don't clip it off because
+/// it would require re-allocating a vector, and it's cheaper (although less elegant) to later just index past it.
+pub(crate) fn decrypt_page(cipher: &Aes256GcmSiv, aad: &[u8], ct_slice: &[u8]) -> Option<Vec::<u8>> {
+    let nonce = &ct_slice[..size_of::<Nonce>()];
+    let ct = &ct_slice[size_of::<Nonce>()..];
+    match cipher.decrypt(
+        Nonce::from_slice(nonce),
+        Payload {
+            aad,
+            msg: ct,
+        }
+    ) {
+        Ok(data) => {
+            assert!(data.len() == VPAGE_SIZE + size_of::<JournalType>(), "authentication successful, but wrong amount of data was recovered");
+            Some(data)
+        },
+        Err(e) => {
+            log::trace!("Error decrypting page: {:?}", e); // sometimes this is totally "normal", like when we're testing for valid data.
+            None
+        }
+    }
+}
+
+/// Decrypts one physical page that also encodes a key commitment. In this case, a raw key is passed,
+/// instead of the generic AES-GCM-SIV cipher, because we need to derive the key commitment.
+/// Key commitments are a patch to work-around the salamander problem in AES-GCM-SIV see https://eprint.iacr.org/2020/1456.pdf
+///
+/// The structure of a page with commit key storage is as follows:
+/// - Nonce - 12 bytes
+/// - ciphertext - 4004 bytes (includes the journal number)
+///   - kcomm_nonce - 32 bytes
+///   - kcomm - 32 bytes
+/// - MAC - 16 bytes
+/// We stripe the MAC at the end just in case the MAC has some arithmetic property that can betray the existence
+/// of a basis root record with key commitment. The committed key and the nonce both should be indistinguishable
+/// from ciphertext.
+pub(crate) fn decrypt_page_with_commit(key: &[u8], aad: &[u8], ct_slice: &[u8]) -> Option<Vec::<u8>> {
+    let nonce = &ct_slice[..size_of::<Nonce>()];
+    let ct_total = &ct_slice[size_of::<Nonce>()..];
+
+    // extract the regions of the stored data and place them into their respective buffers
+    let mut ct_plus_mac = [0u8; KCOM_CT_LEN + size_of::<Tag>()];
+    let mut nonce_comm = [0u8; KCOM_NONCE_LEN];
+    let mut key_comm_stored = [0u8; KCOM_LEN];
+    let mut ct_pos = 0;
+
+    for (&src, dst) in ct_total[ct_pos..].iter().zip(ct_plus_mac[..KCOM_CT_LEN].iter_mut()) {
+        *dst = src;
+        ct_pos += 1;
+    }
+    for (&src, dst) in ct_total[ct_pos..].iter().zip(nonce_comm.iter_mut()) {
+        *dst = src;
+        ct_pos += 1;
+    }
+    for (&src, dst) in ct_total[ct_pos..].iter().zip(key_comm_stored.iter_mut()) {
+        *dst = src;
+        ct_pos += 1;
+    }
+    for (&src, dst) in ct_total[ct_pos..].iter().zip(ct_plus_mac[KCOM_CT_LEN..].iter_mut()) {
+        *dst = src;
+        ct_pos += 1;
+    }
+    assert!(ct_pos == PAGE_SIZE - size_of::<Nonce>(), "struct sizing error in unpacking page with key commit");
+    log::debug!("found nonce of {:x?}", nonce);
+    log::debug!("found kcom_nonce of {:x?}", nonce_comm);
+
+    let (kenc, kcom) = kcom_func(key.try_into().unwrap(), &nonce_comm);
+    let cipher = Aes256GcmSiv::new(Key::from_slice(&kenc));
+
+    // Attempt decryption. This is None on failure
+    let plaintext = cipher.decrypt(
+        Nonce::from_slice(nonce),
+        Payload {
+            aad,
+            msg: &ct_plus_mac,
+        }
+    ).ok();
+
+    // Only return the plaintext if the stored key commitment agrees with the computed one
+    if kcom.ct_eq(&key_comm_stored).into() {
+        plaintext
+    } else {
+        None
+    }
+}
+
+/// Derive a key commitment. This takes in a base `key`, which is 256 bits,
+/// and `nonce_com` which is the commitment nonce, set at 256 bits.
+/// The result is two tuples, (kenc, kcom).
+///
+/// The hash is always done in software: the inputs are small, and it keeps the derivation
+/// identical wherever this file is built.
+pub(crate) fn kcom_func(
+    key: &[u8; 32],
+    nonce_com: &[u8; KCOM_NONCE_LEN]) -> ([u8; 32], [u8; KCOM_LEN]) {
+    use sha2::{FallbackStrategy, Sha512Trunc256};
+    use digest::Digest;
+
+    let mut h_enc = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    h_enc.update(key);
+    // per https://eprint.iacr.org/2020/1456.pdf Table 4 on page 13 Type I Lenc
+    h_enc.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x01]);
+    h_enc.update(nonce_com);
+    let k_enc = h_enc.finalize();
+
+    let mut h_com = Sha512Trunc256::new_with_strategy(FallbackStrategy::SoftwareOnly);
+    h_com.update(key);
+    // per https://eprint.iacr.org/2020/1456.pdf Table 4 on page 13 Type I Lcom. Note one-bit difference in last byte.
+    h_com.update([0x43, 0x6f, 0x6, 0xd6, 0xd, 0x69, 0x74, 0x01, 0x02]);
+    h_com.update(nonce_com);
+    let k_com = h_com.finalize();
+    (k_enc.into(), k_com.into())
+}
+
+/// Scans the page table `pt` for entries that decrypt with `cipher_ecb`. See `PddbOs::pt_scan_key()`
+/// for how conflicting and bogus entries are handled.
+///
+/// `mbbb` is the make-before-break buffer page that stands in for a blank page table page, if there is one.
+/// `decrypt` returns the decrypted data page behind a physical page, and is only called to resolve conflicts.
+/// `resolve_journal` is given every entry that is newly placed in the map, so the caller can fix up its journal number.
+pub(crate) fn pt_scan(
+    pt: &[u8],
+    mbbb: Option<&[u8]>,
+    cipher_ecb: &Aes256,
+    decrypt: impl Fn(&PhysPage) -> Option<Vec::<u8>>,
+    mut resolve_journal: impl FnMut(&mut PhysPage),
+) -> HashMap::<VirtAddr, PhysPage> {
+    let mut map = HashMap::<VirtAddr, PhysPage>::new();
+    let blank = [0xffu8; BLOCK_SIZE];
+    for (page_index, pt_page) in pt.chunks(PAGE_SIZE).enumerate() {
+        let clean_page = if pt_page[..BLOCK_SIZE] == blank {
+            if let Some(page) = mbbb {
+                page
+            } else {
+                log::debug!("Blank page in PT found, but no MBBB entry exists. PT is either corrupted or not initialized!");
+                pt_page
+            }
+        } else {
+            pt_page
+        };
+        for (index, candidate) in clean_page.chunks(BLOCK_SIZE).enumerate() {
+            // encryption is in-place, but the candidates are read-only, so we have to copy them to a new location
+            let mut block = Block::clone_from_slice(candidate);
+            cipher_ecb.decrypt_block(&mut block);
+            if let Some(pte) = Pte::try_from_slice(block.as_slice()) {
+                let mut pp = PhysPage(0);
+                pp.set_page_number(((page_index * PAGE_SIZE / BLOCK_SIZE) + index) as PhysAddr);
+                // the state is clean because this entry is, by definition, synchronized with the disk
+                pp.set_clean(true);
+                pp.set_valid(true);
+                pp.set_space_state(SpaceState::Used);
+                // handle conflicting journal versions here
+                if let Some(prev_page) = map.get(&pte.vaddr()) {
+                    let prev_data = decrypt(prev_page);
+                    let new_data = decrypt(&pp);
+                    if let Some(new_d) = new_data {
+                        if let Some(prev_d) = prev_data {
+                            let prev_j = journal_of(&prev_d);
+                            let new_j = journal_of(&new_d);
+                            if new_j > prev_j {
+                                map.insert(pte.vaddr(), pp);
+                            } else if new_j == prev_j {
+                                log::error!("Found duplicate blocks with same journal age, picking arbitrary block and moving on...");
+                            }
+                        } else {
+                            resolve_journal(&mut pp);
+                            // prev data was bogus anyways, replace with the new entry
+                            map.insert(pte.vaddr(), pp);
+                        }
+                    } else {
+                        // new data is bogus, ignore it
+                    }
+                } else {
+                    resolve_journal(&mut pp);
+                    map.insert(pte.vaddr(), pp);
+                }
+            }
+        }
+    }
+    map
+}
+
+/// Copies a record out of the bytes that store it. Records are plain `repr(C)` structures, so
+/// this is the whole of their deserialization.
+fn record_decode<T: Default + DerefMut<Target = [u8]>>(src: &[u8]) -> T {
+    let mut record = T::default();
+    for (&src, dst) in src.iter().zip(record.deref_mut().iter_mut()) {
+        *dst = src;
+    }
+    record
+}
+
+/// Unpacks a basis root from its decrypted vpage, without checking it. Only the migration code
+/// should need this; everything else wants `basis_root_decode()`.
+pub(crate) fn basis_root_decode_unchecked(vpage: &[u8]) -> BasisRoot {
+    // if the below assertion fails, you will need to re-code this to decrypt more than one VPAGE and stripe into a basis root struct
+    assert!(size_of::<BasisRoot>() <= VPAGE_SIZE, "BasisRoot has grown past a single VPAGE, this routine needs to be re-coded to accommodate the extra bulk");
+    record_decode(&vpage[size_of::<JournalType>()..])
+}
+
+/// Unpacks a basis root from its decrypted vpage, and checks that it is the root of the basis `name`,
+/// written by this version of the PDDB.
+pub(crate) fn basis_root_decode(vpage: &[u8], name: &str) -> Result<BasisRoot> {
+    let basis_root = basis_root_decode_unchecked(vpage);
+    if basis_root.magic != PDDB_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "Basis root did not deserialize correctly"));
+    }
+    if basis_root.version != PDDB_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData,
+            format!("Basis root is version {:x}, expected {:x}", basis_root.version, PDDB_VERSION)));
+    }
+    let basis_name = std::str::from_utf8(&basis_root.name.data[..basis_root.name.len as usize])
+        .or(Err(Error::new(ErrorKind::InvalidData, "Basis name is not valid utf-8")))?;
+    if basis_name != name {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Basis root is named {}", basis_name)));
+    }
+    Ok(basis_root)
+}
+
+/// Unpacks the dictionary header at the top of a dictionary's first vpage.
+pub(crate) fn dict_decode(vpage: &[u8]) -> Dictionary {
+    record_decode(&vpage[size_of::<JournalType>()..])
+}
+
+/// Unpacks the key descriptor with index `entry` from the decrypted descriptor vpage that holds it.
+pub(crate) fn key_descriptor_decode(vpage: &[u8], entry: usize) -> KeyDescriptor {
+    let start = size_of::<JournalType>() + (entry % DK_PER_VPAGE) * DK_STRIDE;
+    record_decode(&vpage[start..start + DK_STRIDE])
+}
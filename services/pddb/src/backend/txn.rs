@@ -0,0 +1,235 @@
+use crate::api::*;
+use super::*;
+
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind};
+
+// Transactions are a redo log kept in `PDDB_TXN_DICT` of the basis they modify:
+//   1. the client stages the new data of every written key as `txn_staged_key(id, index)`
+//   2. on commit, the staged keys are synced, then an intent record listing the ops is written
+//      as `txn_record_key(id)` and synced. A complete record on disk is the commit point.
+//   3. the ops are applied to their keys and synced
+//   4. the record, and then the staged keys, are removed
+// An op list too long for the commit request is staged too, as `txn_ops_key(id)`. A commit that
+// fails before step 2 completes is rolled back on the spot, by removing everything staged for it.
+// If power is lost, `txn_recover` finishes any transaction whose record is complete, and throws
+// away everything else, so the keys involved end up either all old, or all new.
+
+const TXN_RECORD_MAGIC: [u8; 4] = *b"PDtx";
+/// version 1 records hold up to 255 ops, version 2 up to 65535
+const TXN_RECORD_VERSION: u8 = 2;
+const TXN_OP_DELETE: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TxnOp {
+    pub(crate) dict: String,
+    pub(crate) key: String,
+    pub(crate) delete: bool,
+}
+
+/// Serializes an intent record. It's padded to a word boundary and ends with a murmur3 of
+/// everything before it, so a record torn by a power loss is never mistaken for a commit.
+pub(crate) fn txn_record_encode(ops: &[TxnOp]) -> Vec::<u8> {
+    let mut record = Vec::<u8>::new();
+    record.extend_from_slice(&TXN_RECORD_MAGIC);
+    record.push(TXN_RECORD_VERSION);
+    record.extend_from_slice(&(ops.len() as u16).to_le_bytes());
+    for op in ops.iter() {
+        record.push(if op.delete { TXN_OP_DELETE } else { 0 });
+        record.push(op.dict.len() as u8);
+        record.extend_from_slice(op.dict.as_bytes());
+        record.push(op.key.len() as u8);
+        record.extend_from_slice(op.key.as_bytes());
+    }
+    while record.len() % 4 != 0 {
+        record.push(0);
+    }
+    let check = murmur3_32(&record, 0);
+    record.extend_from_slice(&check.to_le_bytes());
+    record
+}
+
+/// Parses an intent record, returning `None` if it is torn or otherwise damaged.
+pub(crate) fn txn_record_decode(record: &[u8]) -> Option<Vec::<TxnOp>> {
+    if record.len() < 10 || record.len() % 4 != 0 {
+        return None;
+    }
+    let (body, check) = record.split_at(record.len() - 4);
+    if murmur3_32(body, 0) != u32::from_le_bytes(check.try_into().unwrap()) || body[..4] != TXN_RECORD_MAGIC {
+        return None;
+    }
+    let (count, mut pos) = match body[4] {
+        1 => (body[5] as usize, 6),
+        2 if body.len() >= 7 => (u16::from_le_bytes(body[5..7].try_into().unwrap()) as usize, 7),
+        _ => return None,
+    };
+    let mut ops = Vec::<TxnOp>::new();
+    let field = |pos: &mut usize| -> Option<String> {
+        let len = *body.get(*pos)? as usize;
+        let s = std::str::from_utf8(body.get(*pos + 1..*pos + 1 + len)?).ok()?.to_string();
+        *pos += 1 + len;
+        Some(s)
+    };
+    for _ in 0..count {
+        let flags = *body.get(pos)?;
+        pos += 1;
+        let dict = field(&mut pos)?;
+        let key = field(&mut pos)?;
+        ops.push(TxnOp { dict, key, delete: flags & TXN_OP_DELETE != 0 });
+    }
+    if body[pos..].iter().any(|&b| b != 0) {
+        return None;
+    }
+    Some(ops)
+}
+
+impl BasisCache {
+    /// Commits transaction `id` in `basis_name`. Every write in `ops` must have its data staged already.
+    pub(crate) fn txn_commit(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, ops: &[TxnOp]) -> Result<()> {
+        if let Err(e) = self.txn_prepare(hw, basis_name, id, ops) {
+            // not committed, so roll back: nothing that was staged for it is kept
+            if let Err(cleanup) = self.txn_cleanup(hw, basis_name, id, ops.len()) {
+                log::error!("transaction {:016x}: couldn't discard staged data: {:?}", id, cleanup);
+            }
+            return Err(e);
+        }
+        // past the commit point, a failure leaves the record in place for `txn_recover` to finish on the next mount
+        self.txn_apply(hw, basis_name, id, ops)?;
+        self.txn_cleanup(hw, basis_name, id, ops.len())
+    }
+
+    /// Checks that the data of every write is staged, then syncs it and writes the intent record.
+    fn txn_prepare(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, ops: &[TxnOp]) -> Result<()> {
+        for (index, op) in ops.iter().enumerate() {
+            if !op.delete {
+                self.key_attributes(hw, PDDB_TXN_DICT, &txn_staged_key(id, index), Some(basis_name))?;
+            }
+        }
+        // the staged data has to be on disk before the record that points at it
+        self.sync(hw, Some(basis_name))?;
+        self.txn_record(hw, basis_name, id, ops)
+    }
+
+    /// Reads back the op list of transaction `id` that the client staged because it had more than
+    /// `PDDB_TXN_INLINE_OPS` ops. It has to hold exactly `count` ops.
+    pub(crate) fn txn_staged_ops(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, count: usize) -> Result<Vec::<TxnOp>> {
+        let len = self.key_attributes(hw, PDDB_TXN_DICT, &txn_ops_key(id), Some(basis_name))?.len;
+        let mut list = vec![0u8; len];
+        self.key_read(hw, PDDB_TXN_DICT, &txn_ops_key(id), &mut list, None, Some(basis_name))?;
+        match txn_ops_decode(&list) {
+            Some(ops) if ops.len() == count && count <= PDDB_TXN_MAX_OPS => {
+                Ok(ops.into_iter().map(|(dict, key, delete)| TxnOp { dict, key, delete }).collect())
+            }
+            _ => Err(Error::new(ErrorKind::InvalidData, "staged transaction op list is damaged")),
+        }
+    }
+
+    /// Writes and syncs the intent record of transaction `id`. Once this returns, the transaction is committed.
+    pub(crate) fn txn_record(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, ops: &[TxnOp]) -> Result<()> {
+        self.key_update(hw, PDDB_TXN_DICT, &txn_record_key(id), &txn_record_encode(ops), None, None, Some(basis_name), true)?;
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Applies the ops of transaction `id` to their keys, and syncs. Applying the same ops again gives the same result,
+    /// so an apply that was cut short can simply be started over.
+    pub(crate) fn txn_apply(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, ops: &[TxnOp]) -> Result<()> {
+        for (index, op) in ops.iter().enumerate() {
+            if op.delete {
+                match self.key_remove(hw, &op.dict, &op.key, Some(basis_name), false) {
+                    Ok(_) => (),
+                    Err(e) if e.kind() == ErrorKind::NotFound => (),
+                    Err(e) => return Err(e),
+                }
+                continue;
+            }
+            let staged = txn_staged_key(id, index);
+            let len = match self.key_attributes(hw, PDDB_TXN_DICT, &staged, Some(basis_name)) {
+                Ok(attr) => attr.len,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    log::error!("transaction {:016x}: staged data for {}:{} is missing, skipping", id, op.dict, op.key);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let mut data = vec![0u8; len];
+            self.key_read(hw, PDDB_TXN_DICT, &staged, &mut data, None, Some(basis_name))?;
+            match self.dict_add(hw, &op.dict, Some(basis_name)) {
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(e),
+            }
+            self.key_update(hw, &op.dict, &op.key, &data, None, None, Some(basis_name), true)?;
+        }
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Removes the record of transaction `id`, then its `count` staged keys. The record goes first: without it,
+    /// leftover staged keys are just thrown away by `txn_recover`.
+    pub(crate) fn txn_cleanup(&mut self, hw: &mut PddbOs, basis_name: &str, id: u64, count: usize) -> Result<()> {
+        match self.key_remove(hw, PDDB_TXN_DICT, &txn_record_key(id), Some(basis_name), false) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        self.sync(hw, Some(basis_name))?;
+        match self.key_remove(hw, PDDB_TXN_DICT, &txn_ops_key(id), Some(basis_name), false) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        for index in 0..count {
+            match self.key_remove(hw, PDDB_TXN_DICT, &txn_staged_key(id, index), Some(basis_name), false) {
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.sync(hw, Some(basis_name))
+    }
+
+    /// Finishes the transactions of a freshly mounted basis that were committed but not fully applied when
+    /// power was lost, and discards the data staged for transactions that never committed. Call this right
+    /// after a basis is added; `None` picks the most recently added one. Returns how many were finished.
+    pub(crate) fn txn_recover(&mut self, hw: &mut PddbOs, basis_name: Option<&str>) -> Result<usize> {
+        let name = match basis_name {
+            Some(name) => name.to_string(),
+            None => match self.basis_latest() {
+                Some(name) => name,
+                None => return Ok(0),
+            }
+        };
+        let keys = match self.key_list(hw, PDDB_TXN_DICT, Some(&name)) {
+            Ok(keys) => keys,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut finished = 0;
+        for record_key in keys.iter().filter(|k| !k.contains('.')) {
+            let id = u64::from_str_radix(record_key, 16).ok();
+            let len = self.key_attributes(hw, PDDB_TXN_DICT, record_key, Some(&name))?.len;
+            let mut record = vec![0u8; len];
+            self.key_read(hw, PDDB_TXN_DICT, record_key, &mut record, None, Some(&name))?;
+            match (id, txn_record_decode(&record)) {
+                (Some(id), Some(ops)) => {
+                    log::warn!("finishing transaction {:016x} in {} ({} ops)", id, name, ops.len());
+                    self.txn_apply(hw, &name, id, &ops)?;
+                    self.txn_cleanup(hw, &name, id, ops.len())?;
+                    finished += 1;
+                }
+                _ => {
+                    log::warn!("discarding incomplete transaction record {} in {}", record_key, name);
+                    self.key_remove(hw, PDDB_TXN_DICT, record_key, Some(&name), false)?;
+                }
+            }
+        }
+        // whatever is left was staged for transactions that never committed
+        for key in self.key_list(hw, PDDB_TXN_DICT, Some(&name))?.iter() {
+            self.key_remove(hw, PDDB_TXN_DICT, key, Some(&name), false)?;
+        }
+        self.sync(hw, Some(&name))?;
+        Ok(finished)
+    }
+}
@@ -0,0 +1,175 @@
+use crate::*;
+use crate::murmur3::murmur3_32;
+use serde::{Serialize, de::DeserializeOwned};
+use std::convert::TryInto;
+use std::io::{Result, Error, ErrorKind, Read, Write, Seek, SeekFrom};
+
+// A typed value is stored as a small envelope followed by the value encoded with bincode:
+//   magic "PDty" | envelope version (u8) | reserved (u8) | schema version (u16) | payload length (u32) | murmur3 of payload (u32)
+// All numbers are little-endian. The length lets a value shrink in place without truncating the key,
+// and the hash catches a value torn by a power loss part way through a multi-page write.
+const TYPED_MAGIC: [u8; 4] = *b"PDty";
+const TYPED_ENVELOPE_VERSION: u8 = 1;
+const TYPED_HEADER_LEN: usize = 16;
+
+/// A value that can be stored with `Pddb::set_typed` and read back with `Pddb::get_typed`.
+///
+/// `SCHEMA_VERSION` is written next to every stored value; bump it whenever the serialized form of
+/// the type changes, and teach `migrate` how to read the versions that came before. A value read
+/// back with an older schema version is handed to `migrate`, and the result is written back in the
+/// current version, so each migration runs once per key. Schema version 0 stands for the bare bytes
+/// a service wrote into the key before it used this API, which lets hand-rolled formats be adopted.
+pub trait PddbTyped: Serialize + DeserializeOwned {
+    const SCHEMA_VERSION: u16;
+    /// Converts `payload`, stored under the older `from_version`, into the current type. For versions
+    /// other than 0, `payload` is exactly what `bincode` produced for the type as it was back then.
+    fn migrate(from_version: u16, _payload: &[u8]) -> Result<Self> {
+        log::warn!("no migration from schema version {} to {}", from_version, Self::SCHEMA_VERSION);
+        Err(Error::new(ErrorKind::InvalidData, "No migration from the stored schema version"))
+    }
+}
+
+/// Encodes `value` into a typed envelope, ready to be written to a key.
+pub fn typed_encode<T: PddbTyped>(value: &T) -> Result<Vec::<u8>> {
+    let payload = bincode::serialize(value)
+        .or(Err(Error::new(ErrorKind::InvalidInput, "Value could not be serialized")))?;
+    let mut record = Vec::<u8>::with_capacity(TYPED_HEADER_LEN + payload.len());
+    record.extend_from_slice(&TYPED_MAGIC);
+    record.push(TYPED_ENVELOPE_VERSION);
+    record.push(0);
+    record.extend_from_slice(&T::SCHEMA_VERSION.to_le_bytes());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&murmur3_32(&payload, 0).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Decodes the contents of a key written by `typed_encode`, running `T::migrate` if it was stored with
+/// an older schema version, or was never an envelope at all. The flag is true if a migration ran.
+pub fn typed_decode<T: PddbTyped>(data: &[u8]) -> Result<(T, bool)> {
+    if data.len() < TYPED_HEADER_LEN || data[..4] != TYPED_MAGIC {
+        return T::migrate(0, data).map(|v| (v, true));
+    }
+    if data[4] != TYPED_ENVELOPE_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Unknown typed envelope version"));
+    }
+    let schema = u16::from_le_bytes(data[6..8].try_into().unwrap());
+    let len = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+    let check = u32::from_le_bytes(data[12..16].try_into().unwrap());
+    let payload = match data.get(TYPED_HEADER_LEN..TYPED_HEADER_LEN + len) {
+        Some(payload) => payload,
+        None => return Err(Error::new(ErrorKind::UnexpectedEof, "Typed value is shorter than its envelope says")),
+    };
+    if murmur3_32(payload, 0) != check {
+        return Err(Error::new(ErrorKind::InvalidData, "Typed value failed its integrity check"));
+    }
+    if schema > T::SCHEMA_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, "Typed value was stored by a newer schema version"));
+    }
+    if schema < T::SCHEMA_VERSION {
+        return T::migrate(schema, payload).map(|v| (v, true));
+    }
+    bincode::deserialize::<T>(payload)
+        .map(|v| (v, false))
+        .or(Err(Error::new(ErrorKind::InvalidData, "Typed value could not be deserialized")))
+}
+
+impl Pddb {
+    /// Reads `dict_name:key_name` as a `T`. If the value was stored by an older schema version, it's
+    /// migrated and the migrated value is written back. Returns `NotFound` if the key doesn't exist.
+    pub fn get_typed<T: PddbTyped>(&mut self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<T> {
+        let mut data = Vec::<u8>::new();
+        {
+            let mut key = self.get(dict_name, key_name, basis_name, false, false, None, None::<fn()>)?;
+            key.read_to_end(&mut data)?;
+        }
+        let (value, migrated) = typed_decode::<T>(&data)?;
+        if migrated {
+            log::info!("migrated {}:{} to schema version {}", dict_name, key_name, T::SCHEMA_VERSION);
+            if let Err(e) = self.set_typed(dict_name, key_name, basis_name, &value) {
+                // the value is still good; the migration just runs again next time
+                log::warn!("couldn't write back migrated {}:{}: {:?}", dict_name, key_name, e);
+            }
+        }
+        Ok(value)
+    }
+    /// Stores `value` as `dict_name:key_name`, creating the dictionary and key as needed, and flushes it to disk.
+    pub fn set_typed<T: PddbTyped>(&mut self, dict_name: &str, key_name: &str, basis_name: Option<&str>, value: &T) -> Result<()> {
+        let record = typed_encode(value)?;
+        let mut key = self.get(dict_name, key_name, basis_name, true, true, Some(record.len()), None::<fn()>)?;
+        key.seek(SeekFrom::Start(0))?;
+        key.write_all(&record)?;
+        key.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct PrefsV1 {
+        volume: u8,
+    }
+    impl PddbTyped for PrefsV1 {
+        const SCHEMA_VERSION: u16 = 1;
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Prefs {
+        volume: u8,
+        voice: String,
+    }
+    impl PddbTyped for Prefs {
+        const SCHEMA_VERSION: u16 = 2;
+        fn migrate(from_version: u16, payload: &[u8]) -> Result<Self> {
+            match from_version {
+                // the service used to store the volume as a single bare byte
+                0 if payload.len() == 1 => Ok(Prefs { volume: payload[0], voice: String::from("default") }),
+                1 => {
+                    let v1: PrefsV1 = bincode::deserialize(payload).or(Err(Error::new(ErrorKind::InvalidData, "bad v1")))?;
+                    Ok(Prefs { volume: v1.volume, voice: String::from("default") })
+                }
+                _ => Err(Error::new(ErrorKind::InvalidData, "unknown prefs version")),
+            }
+        }
+    }
+
+    #[test]
+    fn test_typed_round_trip() {
+        let prefs = Prefs { volume: 7, voice: String::from("alto") };
+        let record = typed_encode(&prefs).unwrap();
+        assert!(typed_decode::<Prefs>(&record).unwrap() == (prefs, false));
+    }
+    #[test]
+    fn test_typed_ignores_stale_tail() {
+        // a value that shrank in place leaves the end of the old one behind
+        let mut record = typed_encode(&Prefs { volume: 1, voice: String::from("a") }).unwrap();
+        record.extend_from_slice(&[0xAA; 40]);
+        assert!(typed_decode::<Prefs>(&record).unwrap().0.voice == "a");
+    }
+    #[test]
+    fn test_typed_migrates() {
+        let record = typed_encode(&PrefsV1 { volume: 3 }).unwrap();
+        let (prefs, migrated) = typed_decode::<Prefs>(&record).unwrap();
+        assert!(migrated && prefs == Prefs { volume: 3, voice: String::from("default") });
+        let (prefs, migrated) = typed_decode::<Prefs>(&[9]).unwrap();
+        assert!(migrated && prefs.volume == 9);
+    }
+    #[test]
+    fn test_typed_rejects_bad_records() {
+        let record = typed_encode(&Prefs { volume: 7, voice: String::from("alto") }).unwrap();
+        // newer schema than the reader knows
+        assert!(typed_decode::<PrefsV1>(&record).is_err());
+        // torn write
+        let mut torn = record.clone();
+        let last = torn.len() - 1;
+        torn[last] ^= 0xFF;
+        assert!(typed_decode::<Prefs>(&torn).unwrap_err().kind() == ErrorKind::InvalidData);
+        // truncated
+        assert!(typed_decode::<Prefs>(&record[..record.len() - 2]).unwrap_err().kind() == ErrorKind::UnexpectedEof);
+        // no migration from bare bytes
+        assert!(typed_decode::<PrefsV1>(&[1, 2, 3]).is_err());
+    }
+}
@@ -4,9 +4,19 @@ pub mod api;
 pub use api::*;
 pub mod frontend;
 pub use frontend::*;
+mod archive;
+use archive::*;
+pub use archive::ArchiveStats;
+// archive passphrases are stretched the same way as basis passwords
+#[path = "backend/bcrypt.rs"]
+mod bcrypt;
+use bcrypt::bcrypt;
+// typed values are checksummed with the same hash the backend uses
+#[path = "backend/murmur3.rs"]
+mod murmur3;
 
 use num_traits::*;
-use std::io::{Result, Error, ErrorKind};
+use std::io::{Result, Error, ErrorKind, Read, Write, Seek};
 use xous::{CID, SID, msg_scalar_unpack, send_message, Message};
 use xous_ipc::Buffer;
 
@@ -22,6 +32,8 @@ pub(crate) static POLLER_REFCOUNT: AtomicU32 = AtomicU32::new(0);
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub enum CbOp {
     Change,
+    /// a key under a subscription changed; carries a `PddbChangeEvent`
+    Event,
     Quit
 }
 
@@ -63,6 +75,180 @@ impl Drop for PddbMountPoller {
     }
 }
 
+/// Outcome of a consistency check, see `Pddb::fsck`.
+#[derive(Debug, Default, Clone)]
+pub struct FsckSummary {
+    pub pages_checked: usize,
+    pub keys_checked: usize,
+    /// number of problems found, including those that were fixed
+    pub issues: usize,
+    pub repaired: usize,
+    /// broken keys copied to the `fsck.quarantine` dictionary of their basis, and removed from their own
+    pub quarantined: usize,
+    /// one line per problem found; may be clipped if there are many
+    pub details: String,
+}
+
+/// A set of writes and deletes against one basis that take effect together, or not at all, even if
+/// power is lost half-way through. Build it with `Pddb::transaction`, `txn_write` and `txn_delete`,
+/// then hand it to `Pddb::commit`. Written data is staged in the basis right away, but the keys
+/// themselves don't change until the commit.
+pub struct PddbTransaction {
+    basis: String,
+    id: u64,
+    ops: Vec::<PddbTxnOp>,
+}
+impl PddbTransaction {
+    pub fn basis(&self) -> &str {
+        &self.basis
+    }
+    /// number of writes and deletes staged so far
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+/// A change to a key that was subscribed to with `Pddb::subscribe`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PddbChange {
+    pub kind: PddbChangeKind,
+    /// basis that held the key; empty if the change was made to the union of all open basis
+    pub basis: String,
+    pub dict: String,
+    pub key: String,
+}
+/// Handle to an active change subscription. Pass it to `Pddb::unsubscribe` to stop the callbacks;
+/// subscriptions are also dropped when the `Pddb` object that made them goes out of scope.
+#[derive(Debug)]
+pub struct PddbSubscription {
+    token: ApiToken,
+}
+
+/// A key name returned by `Pddb::list_keys_batch` or `Pddb::key_iter`. `len`, `age` and `flags`
+/// are only filled in if attributes were asked for, and are 0 otherwise.
+#[derive(Debug, Clone)]
+pub struct PddbKeyEntry {
+    pub name: String,
+    pub len: usize,
+    /// access count
+    pub age: usize,
+    pub flags: KeyFlags,
+}
+/// One batch of a key listing. Pass `next` back as `after` to get the batch that follows.
+#[derive(Debug, Clone)]
+pub struct PddbKeyBatch {
+    pub entries: Vec::<PddbKeyEntry>,
+    /// last name of this batch, if more names follow it
+    pub next: Option<String>,
+    /// number of names matching the prefix, across all batches
+    pub total: usize,
+}
+/// Walks the keys of a dictionary in sorted order, fetching them a batch at a time through a single
+/// buffer. Made with `Pddb::key_iter`. Keys added or removed during the walk may or may not show up.
+pub struct PddbKeyIter {
+    conn: CID,
+    buf: Buffer<'static>,
+    batch: std::vec::IntoIter::<PddbKeyEntry>,
+    total: usize,
+    done: bool,
+}
+impl PddbKeyIter {
+    /// number of names matching the prefix, as of the last batch fetched
+    pub fn total(&self) -> usize {
+        self.total
+    }
+    /// fetches the batch for the request sitting in `buf`, then leaves the request for the batch after it there
+    fn fetch(&mut self) -> Result<()> {
+        let mut response = key_list_batch_send(self.conn, &mut self.buf)?;
+        let batch = key_list_batch_unpack(&response);
+        self.total = batch.total;
+        match batch.next {
+            Some(next) => {
+                response.cursor_valid = true;
+                response.cursor = xous_ipc::String::<KEY_NAME_LEN>::from_str(&next);
+                response.code = PddbRequestCode::Uninit;
+                self.buf.rewrite(response).or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+            }
+            None => self.done = true,
+        }
+        self.batch = batch.entries.into_iter();
+        Ok(())
+    }
+}
+impl Iterator for PddbKeyIter {
+    type Item = Result<PddbKeyEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.batch.next() {
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+fn key_list_request(dict_name: &str, basis_name: Option<&str>, prefix: Option<&str>, after: Option<&str>, with_attributes: bool)
+-> Result<PddbKeyListRequest> {
+    if dict_name.len() > (DICT_NAME_LEN - 1) {
+        return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+    }
+    if basis_name.unwrap_or("").len() > (BASIS_NAME_LEN - 1) {
+        return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+    }
+    if prefix.unwrap_or("").len() > (KEY_NAME_LEN - 1) || after.unwrap_or("").len() > (KEY_NAME_LEN - 1) {
+        return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+    }
+    Ok(PddbKeyListRequest {
+        basis_specified: basis_name.is_some(),
+        basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+        dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+        prefix: xous_ipc::String::<KEY_NAME_LEN>::from_str(prefix.unwrap_or("")),
+        cursor_valid: after.is_some(),
+        cursor: xous_ipc::String::<KEY_NAME_LEN>::from_str(after.unwrap_or("")),
+        with_attributes,
+        count: 0,
+        total: 0,
+        more: false,
+        entries: [PddbKeyListEntry {
+            name: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            len: 0,
+            age: 0,
+            flags: 0,
+        }; KEY_LIST_BATCH],
+        code: PddbRequestCode::Uninit,
+    })
+}
+fn key_list_batch_send(conn: CID, buf: &mut Buffer) -> Result<PddbKeyListRequest> {
+    buf.lend_mut(conn, Opcode::ListKeyBatch.to_u32().unwrap())
+        .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+    let response = buf.to_original::<PddbKeyListRequest, _>().unwrap();
+    match response.code {
+        PddbRequestCode::NoErr => Ok(response),
+        PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "dictionary not found")),
+        _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+    }
+}
+fn key_list_batch_unpack(response: &PddbKeyListRequest) -> PddbKeyBatch {
+    let entries: Vec::<PddbKeyEntry> = response.entries[..response.count as usize].iter().map(|e|
+        PddbKeyEntry {
+            name: String::from(e.name.as_str().expect("utf-8 parse error in key name")),
+            len: e.len as usize,
+            age: e.age as usize,
+            flags: KeyFlags(e.flags),
+        }
+    ).collect();
+    let next = if response.more {
+        entries.last().map(|e| e.name.clone())
+    } else {
+        None
+    };
+    PddbKeyBatch { entries, next, total: response.total as usize }
+}
 
 /// The intention is that one Pddb management object is made per process, and this serves
 /// as the gateway for parcelling out PddbKey objects, which are the equivalent of a File
@@ -85,6 +271,9 @@ pub struct Pddb {
     /// in the case of a basis change. Basis changes are thought to be rare; so, big changes
     /// like this are probably OK.
     keys: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn() + 'static + Send> >>>,
+    /// Handle change subscriptions. The same caveats apply as for `keys`; the closure is run on
+    /// the callback thread, so it should do little more than forward the change as a message.
+    subs: Arc<Mutex<HashMap<ApiToken, Box<dyn Fn(PddbChange) + 'static + Send> >>>,
     trng: trng::Trng,
 }
 impl Pddb {
@@ -97,6 +286,7 @@ impl Pddb {
             conn,
             cb: None,
             keys,
+            subs: Arc::new(Mutex::new(HashMap::new())),
             trng: trng::Trng::new(&xns).unwrap(),
         }
     }
@@ -105,6 +295,7 @@ impl Pddb {
             let sid = xous::create_server().unwrap();
             let handle = thread::spawn({
                 let keys = Arc::clone(&self.keys);
+                let subs = Arc::clone(&self.subs);
                 let sid = sid.clone();
                 move || {
                     loop {
@@ -118,6 +309,20 @@ impl Pddb {
                                     log::warn!("Key changed but no callback was hooked to receive it");
                                 }
                             }),
+                            Some(CbOp::Event) => {
+                                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                                let event = buffer.to_original::<PddbChangeEvent, _>().unwrap();
+                                if let Some(cb) = subs.lock().unwrap().get(&event.token) {
+                                    cb(PddbChange {
+                                        kind: event.kind,
+                                        basis: String::from(event.basis.as_str().unwrap_or("")),
+                                        dict: String::from(event.dict.as_str().unwrap_or("")),
+                                        key: String::from(event.key.as_str().unwrap_or("")),
+                                    });
+                                } else {
+                                    log::debug!("Change event arrived after its subscription was dropped");
+                                }
+                            },
                             Some(CbOp::Quit) => { // blocking scalar
                                 xous::return_scalar(msg.sender, 0).unwrap();
                                 break;
@@ -267,6 +472,34 @@ impl Pddb {
             }
         }
     }
+    /// Changes the password of the open secret basis `basis_name`. The user is prompted for the current password,
+    /// and twice for the new one. Every page of the basis is re-encrypted, so this takes a while on a large basis.
+    ///
+    /// The system basis has no password of its own: it is unlocked by the device unlock password, through the root
+    /// keys. Changing that isn't supported yet, so asking for the system basis returns `ErrorKind::Unsupported`.
+    pub fn change_basis_password(&self, basis_name: &str) -> Result<()> {
+        if basis_name.len() > BASIS_NAME_LEN - 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        if basis_name == PDDB_DEFAULT_SYSTEM_BASIS {
+            return Err(Error::new(ErrorKind::Unsupported, "the system basis follows the device unlock password"));
+        }
+        let mgmt = PddbBasisRequest {
+            name: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name),
+            code: PddbRequestCode::ChangePassword,
+            policy: None,
+        };
+        let mut buf = Buffer::into_buf(mgmt).expect("Couldn't convert to memory structure");
+        buf.lend_mut(self.conn, Opcode::ChangeBasisPassword.to_u32().unwrap()).expect("Couldn't execute ChangeBasisPassword opcode");
+        let ret = buf.to_original::<PddbBasisRequest, _>().expect("couldn't restore mgmt structure");
+        match ret.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::AccessDenied => Err(Error::new(ErrorKind::PermissionDenied, "Authentication error")),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Not enough free space to re-key the basis")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error changing basis password")),
+        }
+    }
     pub fn delete_basis(&self, basis_name: &str) -> Result<()> {
         if basis_name.len() > BASIS_NAME_LEN - 1 {
             return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
@@ -374,6 +607,60 @@ impl Pddb {
         }
     }
 
+    /// Calls `cb` whenever `key_name` in `dict_name` is created, written or deleted. If `key_name` is `None`,
+    /// every key in the dictionary is watched. If `basis_name` is `None`, changes in any basis are reported.
+    /// Writes are reported once they are flushed, or when the `PddbKey` used to make them is dropped. The
+    /// dictionary doesn't have to exist yet.
+    pub fn subscribe(&mut self, dict_name: &str, key_name: Option<&str>, basis_name: Option<&str>,
+        cb: impl Fn(PddbChange) + 'static + Send) -> Result<PddbSubscription> {
+        if key_name.unwrap_or("").len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if basis_name.unwrap_or("").len() > (BASIS_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+        }
+        self.ensure_async_responder();
+        let cb_sid = self.cb.as_ref().map(|(sid, _handle)| sid.to_array()).unwrap();
+        let request = PddbSubscribeRequest {
+            basis_specified: basis_name.is_some(),
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis_name.unwrap_or("")),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key_specified: key_name.is_some(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name.unwrap_or("")),
+            cb_sid,
+            token: None,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Subscribe.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbSubscribeRequest, _>().unwrap();
+        match (response.code, response.token) {
+            (PddbRequestCode::NoErr, Some(token)) => {
+                self.subs.lock().unwrap().insert(token, Box::new(cb));
+                Ok(PddbSubscription { token })
+            }
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Stops the callbacks of a subscription. Events that were already in flight are discarded.
+    pub fn unsubscribe(&mut self, sub: PddbSubscription) -> Result<()> {
+        self.subs.lock().unwrap().remove(&sub.token);
+        let response = send_message(self.conn, Message::new_blocking_scalar(Opcode::Unsubscribe.to_usize().unwrap(),
+            sub.token[0] as usize, sub.token[1] as usize, sub.token[2] as usize, 0))
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        match response {
+            xous::Result::Scalar1(1) => Ok(()),
+            xous::Result::Scalar1(_) => Err(Error::new(ErrorKind::NotFound, "Subscription not found")),
+            _ => Err(Error::new(ErrorKind::Other, "Xous internal error")),
+        }
+    }
+
     /// deletes a key within the dictionary
     pub fn delete_key(&mut self, dict_name: &str, key_name: &str, basis_name: Option<&str>) -> Result<()> {
         if key_name.len() > (KEY_NAME_LEN - 1) {
@@ -541,6 +828,35 @@ impl Pddb {
         }
         Ok(key_list)
     }
+    /// Returns one batch of the keys in `dict_name`, in sorted order, starting right after `after`,
+    /// or at the first key if it's `None`. Only names starting with `prefix` are returned. If
+    /// `with_attributes` is set, the length, access count and flags of each key come along too.
+    pub fn list_keys_batch(&self, dict_name: &str, basis_name: Option<&str>, prefix: Option<&str>,
+        after: Option<&str>, with_attributes: bool
+    ) -> Result<PddbKeyBatch> {
+        let request = key_list_request(dict_name, basis_name, prefix, after, with_attributes)?;
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let response = key_list_batch_send(self.conn, &mut buf)?;
+        Ok(key_list_batch_unpack(&response))
+    }
+    /// Iterates over the keys in `dict_name` that start with `prefix`, in sorted order. Names are
+    /// fetched a batch at a time, so this scales to dictionaries with thousands of keys. Fails up front
+    /// with `NotFound` if the dictionary doesn't exist.
+    pub fn key_iter(&self, dict_name: &str, basis_name: Option<&str>, prefix: Option<&str>, with_attributes: bool) -> Result<PddbKeyIter> {
+        let request = key_list_request(dict_name, basis_name, prefix, None, with_attributes)?;
+        let buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        let mut iter = PddbKeyIter {
+            conn: self.conn,
+            buf,
+            batch: Vec::new().into_iter(),
+            total: 0,
+            done: false,
+        };
+        iter.fetch()?;
+        Ok(iter)
+    }
 
 
     pub fn list_dict(&mut self, basis_name: Option<&str>) -> Result<Vec::<String>> {
@@ -599,6 +915,261 @@ impl Pddb {
         }
         Ok(dict_list)
     }
+    /// Defragments the free space of `basis_name`, or of every open basis if None: small keys are
+    /// repacked into as few pages as possible, and large keys give back pages they don't use.
+    /// The server also does this on its own when it is idle. Returns the number of pages reclaimed.
+    pub fn compact(&mut self, basis_name: Option<&str>) -> Result<usize> {
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        let request = PddbDictRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            dict: xous_ipc::String::<DICT_NAME_LEN>::new(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            index: 0,
+            code: PddbRequestCode::Uninit,
+            token: [0; 4],
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Compact.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbDictRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(response.index as usize),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Not enough free space to compact")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Starts a transaction against `basis_name`, or against the most recently unlocked basis if `None`.
+    pub fn transaction(&mut self, basis_name: Option<&str>) -> Result<PddbTransaction> {
+        let basis = match basis_name {
+            Some(name) => {
+                if name.len() > BASIS_NAME_LEN - 1 {
+                    return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+                }
+                name.to_string()
+            }
+            None => self.latest_basis().ok_or(Error::new(ErrorKind::ConnectionReset, "PDDB was unmounted"))?,
+        };
+        let id = self.trng.get_u64().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?;
+        Ok(PddbTransaction { basis, id, ops: Vec::new() })
+    }
+    fn txn_push(&mut self, txn: &mut PddbTransaction, dict_name: &str, key_name: &str, delete: bool) -> Result<usize> {
+        if key_name.len() > (KEY_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "key name too long"));
+        }
+        if dict_name.len() > (DICT_NAME_LEN - 1) {
+            return Err(Error::new(ErrorKind::InvalidInput, "dictionary name too long"));
+        }
+        if txn.ops.len() >= PDDB_TXN_MAX_OPS {
+            return Err(Error::new(ErrorKind::OutOfMemory, "too many operations in one transaction"));
+        }
+        txn.ops.push(PddbTxnOp {
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict_name),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key_name),
+            delete,
+        });
+        Ok(txn.ops.len() - 1)
+    }
+    /// Stages `data` as the new contents of `dict_name:key_name`. The key and dictionary are created on
+    /// commit if they don't exist yet.
+    pub fn txn_write(&mut self, txn: &mut PddbTransaction, dict_name: &str, key_name: &str, data: &[u8]) -> Result<()> {
+        let index = self.txn_push(txn, dict_name, key_name, false)?;
+        let basis = txn.basis.clone();
+        let staged = self.get(PDDB_TXN_DICT, &txn_staged_key(txn.id, index), Some(&basis),
+            true, true, Some(data.len()), None::<fn()>)
+            .and_then(|mut key| key.write_all(data));
+        if staged.is_err() {
+            txn.ops.pop();
+        }
+        staged
+    }
+    /// Stages the deletion of `dict_name:key_name`. It's not an error if the key is already gone at commit time.
+    pub fn txn_delete(&mut self, txn: &mut PddbTransaction, dict_name: &str, key_name: &str) -> Result<()> {
+        self.txn_push(txn, dict_name, key_name, true).map(|_| ())
+    }
+    /// Applies everything staged in `txn` in one step. If this returns an error, none of it was applied and
+    /// the staged data is gone, unless the failure came after the commit point, in which case the transaction
+    /// is finished on the next mount.
+    pub fn commit(&mut self, txn: PddbTransaction) -> Result<()> {
+        let mut ops = [PddbTxnOp {
+            dict: xous_ipc::String::<DICT_NAME_LEN>::new(),
+            key: xous_ipc::String::<KEY_NAME_LEN>::new(),
+            delete: false,
+        }; PDDB_TXN_INLINE_OPS];
+        if txn.ops.len() > PDDB_TXN_INLINE_OPS {
+            // too many to send along, so the op list is staged with the data
+            let list = txn_ops_encode(&txn.ops);
+            let basis = txn.basis.clone();
+            let staged = self.get(PDDB_TXN_DICT, &txn_ops_key(txn.id), Some(&basis), true, true, Some(list.len()), None::<fn()>)
+                .and_then(|mut key| key.write_all(&list));
+            if let Err(e) = staged {
+                self.abort(txn).ok();
+                return Err(e);
+            }
+        } else {
+            for (&src, dst) in txn.ops.iter().zip(ops.iter_mut()) {
+                *dst = src;
+            }
+        }
+        let request = PddbTxnRequest {
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(&txn.basis),
+            id: txn.id,
+            count: txn.ops.len() as u32,
+            ops,
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = match Buffer::into_buf(request) {
+            Ok(buf) => buf,
+            Err(_) => {
+                self.abort(txn).ok();
+                return Err(Error::new(ErrorKind::Other, "Xous internal error"));
+            }
+        };
+        if buf.lend_mut(self.conn, Opcode::TxnCommit.to_u32().unwrap()).is_err() {
+            self.abort(txn).ok();
+            return Err(Error::new(ErrorKind::Other, "Xous internal error"));
+        }
+
+        // any error from here on was already rolled back by the server, or is past the commit point
+        let response = buf.to_original::<PddbTxnRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(()),
+            PddbRequestCode::NotFound => Err(Error::new(ErrorKind::NotFound, "Basis or staged data not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "No more space on disk")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Throws away the data staged for `txn`.
+    pub fn abort(&mut self, txn: PddbTransaction) -> Result<()> {
+        let mut staged: Vec::<String> = txn.ops.iter().enumerate()
+            .filter(|(_, op)| !op.delete)
+            .map(|(index, _)| txn_staged_key(txn.id, index))
+            .collect();
+        if txn.ops.len() > PDDB_TXN_INLINE_OPS {
+            staged.push(txn_ops_key(txn.id));
+        }
+        for key in staged.iter() {
+            match self.delete_key(PDDB_TXN_DICT, key, Some(&txn.basis)) {
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.sync()
+    }
+    /// Checks the page table, dictionaries and keys of the named basis, or of all open basis if `None`,
+    /// along with the free space journal. With `repair`, bookkeeping errors are fixed, and keys whose
+    /// descriptor or data is damaged are salvaged into the `fsck.quarantine` dictionary of their basis.
+    pub fn fsck(&mut self, basis_name: Option<&str>, repair: bool) -> Result<FsckSummary> {
+        let bname = if let Some(bname) = basis_name {
+            if bname.len() > BASIS_NAME_LEN - 1 {
+                return Err(Error::new(ErrorKind::InvalidInput, "basis name too long"));
+            }
+            xous_ipc::String::<BASIS_NAME_LEN>::from_str(bname)
+        } else {
+            xous_ipc::String::<BASIS_NAME_LEN>::new()
+        };
+        let request = PddbFsckRequest {
+            basis_specified: basis_name.is_some(),
+            basis: bname,
+            repair,
+            pages_checked: 0,
+            keys_checked: 0,
+            issues: 0,
+            repaired: 0,
+            quarantined: 0,
+            summary: xous_ipc::String::<FSCK_SUMMARY_LEN>::new(),
+            code: PddbRequestCode::Uninit,
+        };
+        let mut buf = Buffer::into_buf(request)
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+        buf.lend_mut(self.conn, Opcode::Fsck.to_u32().unwrap())
+            .or(Err(Error::new(ErrorKind::Other, "Xous internal error")))?;
+
+        let response = buf.to_original::<PddbFsckRequest, _>().unwrap();
+        match response.code {
+            PddbRequestCode::NoErr => Ok(FsckSummary {
+                pages_checked: response.pages_checked as usize,
+                keys_checked: response.keys_checked as usize,
+                issues: response.issues as usize,
+                repaired: response.repaired as usize,
+                quarantined: response.quarantined as usize,
+                details: response.summary.as_str().unwrap_or("").to_string(),
+            }),
+            PddbRequestCode::NotMounted => Err(Error::new(ErrorKind::NotFound, "Basis not found")),
+            PddbRequestCode::NoFreeSpace => Err(Error::new(ErrorKind::OutOfMemory, "Not enough free space to repair")),
+            _ => Err(Error::new(ErrorKind::Other, "Internal error")),
+        }
+    }
+    /// Writes every dictionary and key of an open basis into `archive`, encrypted under `passphrase`.
+    /// The whole listing is taken before anything is written, but the archive should still not be
+    /// stored in the basis that is being exported, or a re-export would nest the old archive inside.
+    pub fn export_basis(&mut self, basis_name: &str, passphrase: &str, archive: impl Write) -> Result<ArchiveStats> {
+        let mut listing = Vec::<(String, Vec<String>)>::new();
+        for dict in self.list_dict(Some(basis_name))? {
+            let keys = self.list_keys(&dict, Some(basis_name))?;
+            listing.push((dict, keys));
+        }
+        let mut salt = [0u8; ARCHIVE_SALT_LEN];
+        for chunk in salt.chunks_mut(4) {
+            chunk.copy_from_slice(&self.trng.get_u32().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?.to_le_bytes());
+        }
+        let nonce_base = self.trng.get_u64().or(Err(Error::new(ErrorKind::Other, "TRNG error")))?.to_le_bytes();
+        let mut writer = ArchiveWriter::new(archive, passphrase, BCRYPT_COST, salt, nonce_base)?;
+        for (dict, keys) in listing.iter() {
+            writer.dict(dict)?;
+            for key in keys.iter() {
+                let mut data = Vec::<u8>::new();
+                self.get(dict, key, Some(basis_name), false, false, None, None::<fn()>)?
+                    .read_to_end(&mut data)?;
+                writer.key(key, &data)?;
+            }
+        }
+        let (_archive, stats) = writer.finish()?;
+        Ok(stats)
+    }
+    /// Recreates the dictionaries and keys stored in `archive` inside an open basis. Keys that already
+    /// exist are replaced; everything else in the basis is left alone. The whole archive is authenticated
+    /// before the basis is touched, and the keys are then restored in a single transaction, so a wrong
+    /// passphrase, a damaged archive, or a power loss part of the way through changes nothing.
+    /// Dictionaries that were empty when exported are not recreated.
+    pub fn restore_basis(&mut self, archive: impl Read + Seek, passphrase: &str, basis_name: &str) -> Result<ArchiveStats> {
+        let mut reader = ArchiveReader::new(archive, passphrase)?;
+        let stats = reader.verify()?;
+        if stats.keys > PDDB_TXN_MAX_OPS {
+            return Err(Error::new(ErrorKind::OutOfMemory, "archive has too many keys to restore in one transaction"));
+        }
+        reader.rewind()?;
+        let mut txn = self.transaction(Some(basis_name))?;
+        let mut dict = String::new();
+        loop {
+            let staged = match reader.next_entry() {
+                Ok(Some(ArchiveEntry::Dict(name))) => {
+                    dict = name;
+                    Ok(())
+                }
+                Ok(Some(ArchiveEntry::Key(name, data))) => self.txn_write(&mut txn, &dict, &name, &data),
+                Ok(None) => break,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = staged {
+                self.abort(txn).ok();
+                return Err(e);
+            }
+        }
+        self.commit(txn)?;
+        Ok(stats)
+    }
     /// Triggers a dump of the PDDB to host disk
     #[cfg(not(any(target_os = "none", target_os = "xous")))]
     pub fn dbg_dump(&self, name: &str) -> Result<()> {
@@ -628,6 +1199,11 @@ impl Pddb {
 
 impl Drop for Pddb {
     fn drop(&mut self) {
+        // the server would otherwise keep sending events to a callback server that's about to go away
+        let tokens: Vec::<ApiToken> = self.subs.lock().unwrap().keys().cloned().collect();
+        for token in tokens {
+            self.unsubscribe(PddbSubscription { token }).ok();
+        }
         if let Some((cb_sid, handle)) = self.cb.take() {
             let cid = xous::connect(cb_sid).unwrap();
             send_message(cid, Message::new_blocking_scalar(CbOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)).unwrap();
@@ -372,9 +372,15 @@ mod ux;
 use ux::*;
 mod menu;
 use menu::*;
+mod subscriptions;
+use subscriptions::*;
 
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 mod tests;
+// the archive format belongs to the client library; it's built in here so the CI tests can run it against the hosted backend
+#[cfg(not(any(target_os = "none", target_os = "xous")))]
+#[allow(dead_code)]
+mod archive;
 #[cfg(not(any(target_os = "none", target_os = "xous")))]
 #[allow(unused_imports)]
 use tests::*;
@@ -397,6 +403,15 @@ use locales::t;
 pub(crate) struct BasisRequestPassword {
     db_name: xous_ipc::String::<{crate::api::BASIS_NAME_LEN}>,
     plaintext_pw: Option<xous_ipc::String::<{crate::api::PASSWORD_LEN}>>,
+    prompt: PasswordPrompt,
+}
+/// Selects the title of the password modal
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) enum PasswordPrompt {
+    Unlock,
+    Current,
+    New,
+    Confirm,
 }
 #[derive(Debug, PartialEq, Eq)]
 pub(crate) enum PasswordState {
@@ -412,6 +427,7 @@ struct TokenRecord {
     pub basis: Option<String>,
     pub alloc_hint: Option<usize>,
     pub conn: Option<xous::CID>, // callback connection, if one was specified
+    pub written: bool, // set when data is written through the token, cleared once subscribers are told
 }
 
 #[xous::xous_main]
@@ -438,6 +454,8 @@ fn xmain() -> ! {
     let mut basis_cache = BasisCache::new();
     // storage for the token lookup: given an ApiToken, return a dict/key/basis set. Basis can be None or specified.
     let mut token_dict = HashMap::<ApiToken, TokenRecord>::new();
+    // storage for change subscriptions: given an ApiToken, return the dict/key/basis being watched, and who to tell.
+    let mut subscriptions = Subscriptions::new();
 
     // mount poller thread
     let is_mounted = Arc::new(AtomicBool::new(false));
@@ -509,6 +527,19 @@ fn xmain() -> ! {
             ).expect("couldn't send mount request");
         }
     });
+    // periodically poke the main loop, so it can compact the free space if nothing else is going on
+    let _ = thread::spawn({
+        let my_cid = my_cid.clone();
+        move || {
+            let tt = ticktimer_server::Ticktimer::new().unwrap();
+            loop {
+                tt.sleep_ms(IDLE_COMPACT_INTERVAL_MS).unwrap();
+                send_message(my_cid,
+                    Message::new_scalar(Opcode::CompactIdle.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't send idle compaction tick");
+            }
+        }
+    });
     // main server loop
     let mut key_list = Vec::<String>::new(); // storage for key lists
     let mut key_token: Option<[u32; 4]> = None;
@@ -525,8 +556,11 @@ fn xmain() -> ! {
     // register a suspend/resume listener
     let mut susres = susres::Susres::new(Some(susres::SuspendOrder::Early), &xns,
         Opcode::SuspendResume as u32, my_cid).expect("couldn't create suspend/resume object");
+    // count of messages since the last idle tick; the tick itself is the only message seen if we've been idle
+    let mut msgs_since_idle_tick = 0;
     loop {
         let mut msg = xous::receive_message(pddb_sid).unwrap();
+        msgs_since_idle_tick += 1;
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(Opcode::SuspendResume) => xous::msg_scalar_unpack!(msg, token, _, _, _, {
                 basis_cache.suspend(&mut pddb_os);
@@ -609,6 +643,7 @@ fn xmain() -> ! {
                         let request = BasisRequestPassword {
                             db_name: mgmt.name,
                             plaintext_pw: None,
+                            prompt: PasswordPrompt::Unlock,
                         };
                         let mut buf = Buffer::into_buf(request).unwrap();
                         buf.lend_mut(pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
@@ -638,6 +673,7 @@ fn xmain() -> ! {
                             let request = BasisRequestPassword {
                                 db_name: mgmt.name,
                                 plaintext_pw: None,
+                                prompt: PasswordPrompt::Unlock,
                             };
                             let mut buf = Buffer::into_buf(request).unwrap();
                             buf.lend_mut(pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
@@ -648,6 +684,9 @@ fn xmain() -> ! {
                                     mgmt.policy.unwrap_or(BasisRetentionPolicy::Persist)
                                 ) {
                                     basis_cache.basis_add(basis);
+                                    if let Err(e) = basis_cache.txn_recover(&mut pddb_os, None) {
+                                        log::error!("couldn't recover transactions: {:?}", e);
+                                    }
                                     finished = true;
                                     mgmt.code = PddbRequestCode::NoErr;
                                 } else {
@@ -681,6 +720,19 @@ fn xmain() -> ! {
                 }
                 buffer.replace(mgmt).unwrap();
             }
+            Some(Opcode::ChangeBasisPassword) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut mgmt = buffer.to_original::<PddbBasisRequest, _>().unwrap();
+                match mgmt.code {
+                    PddbRequestCode::ChangePassword => {
+                        mgmt.code = change_password_ux(&modals, &mut pddb_os, &mut basis_cache, pw_cid, mgmt.name);
+                    }
+                    _ => {
+                        mgmt.code = PddbRequestCode::InternalError;
+                    }
+                }
+                buffer.replace(mgmt).unwrap();
+            }
             Some(Opcode::CloseBasis) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut mgmt = buffer.to_original::<PddbBasisRequest, _>().unwrap();
@@ -759,7 +811,8 @@ fn xmain() -> ! {
                         match basis_cache.key_update(&mut pddb_os,
                             dict, key, &empty, None, alloc_hint, bname, true
                         ) {
-                            Ok(_) => {},
+                            Ok(_) => notify_key_change(&subscriptions, &mut pddb_os, &mut basis_cache,
+                                PddbChangeKind::Created, dict, key, bname),
                             Err(e) => {
                                 log::error!("Couldn't allocate key: {:?}", e);
                                 match e.kind() {
@@ -785,6 +838,7 @@ fn xmain() -> ! {
                     basis: if let Some(name) = bname {Some(String::from(name))} else {None},
                     conn: cid,
                     alloc_hint,
+                    written: false,
                 };
                 token_dict.insert(token, token_record);
                 req.token = Some(token);
@@ -794,21 +848,16 @@ fn xmain() -> ! {
             Some(Opcode::KeyDrop) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
                 let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
                 if let Some(rec) = token_dict.remove(&token) {
+                    // writes that were never flushed are reported when the key is let go
+                    if rec.written {
+                        notify_key_change(&subscriptions, &mut pddb_os, &mut basis_cache,
+                            PddbChangeKind::Written, &rec.dict, &rec.key, rec.basis.as_deref());
+                    }
                     // now check if we can safely disconnect and recycle our connection number.
                     // This is important because we can only have 32 outgoing connections...
                     if let Some(conn_to_remove) = rec.conn {
-                        let mut still_needs_cid = false;
-                        for r in token_dict.values() {
-                            // check through the remaining dictionary values to see if they have a connection that is the same as our number
-                            if let Some(existing_conn) = r.conn {
-                                if existing_conn == conn_to_remove {
-                                    still_needs_cid = true;
-                                    break;
-                                }
-                            }
-                        }
                         // if nobody else had my connection number, disconnect it.
-                        if !still_needs_cid {
+                        if !callback_conn_in_use(conn_to_remove, &token_dict, &subscriptions) {
                             unsafe{xous::disconnect(conn_to_remove).expect("couldn't disconnect from callback server")};
                         }
                     } else {
@@ -817,6 +866,41 @@ fn xmain() -> ! {
                 }
                 xous::return_scalar(msg.sender, 1).expect("couldn't ack KeyDrop");
             }),
+            Some(Opcode::Subscribe) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbSubscribeRequest, _>().unwrap();
+                let token: ApiToken = [pddb_os.trng_u32(), pddb_os.trng_u32(), pddb_os.trng_u32()];
+                match xous::connect(xous::SID::from_array(req.cb_sid)) {
+                    Ok(conn) => {
+                        let record = SubscriptionRecord {
+                            dict: String::from(req.dict.as_str().expect("dict utf-8 decode error")),
+                            key: if req.key_specified {Some(String::from(req.key.as_str().expect("key utf-8 decode error")))} else {None},
+                            basis: if req.basis_specified {Some(String::from(req.basis.as_str().unwrap()))} else {None},
+                            conn,
+                        };
+                        log::debug!("subscribing to {:?}", record);
+                        subscriptions.insert(token, record);
+                        req.token = Some(token);
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => {
+                        log::error!("couldn't connect to subscriber: {:?}", e);
+                        req.code = PddbRequestCode::InternalError;
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::Unsubscribe) => msg_blocking_scalar_unpack!(msg, t0, t1, t2, _, {
+                let token: ApiToken = [t0 as u32, t1 as u32, t2 as u32];
+                if let Some(rec) = subscriptions.remove(&token) {
+                    if !callback_conn_in_use(rec.conn, &token_dict, &subscriptions) {
+                        unsafe{xous::disconnect(rec.conn).expect("couldn't disconnect from callback server")};
+                    }
+                    xous::return_scalar(msg.sender, 1).expect("couldn't ack Unsubscribe");
+                } else {
+                    xous::return_scalar(msg.sender, 0).expect("couldn't ack Unsubscribe");
+                }
+            }),
             Some(Opcode::DeleteKey) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req: PddbKeyRequest = buffer.to_original::<PddbKeyRequest, _>().unwrap();
@@ -829,6 +913,7 @@ fn xmain() -> ! {
                 let key = req.key.as_str().expect("key utf-8 decode error");
                 match basis_cache.key_remove(&mut pddb_os, dict, key, bname, false) {
                     Ok(_) => {
+                        notify_subscribers(&subscriptions, PddbChangeKind::Deleted, bname.unwrap_or(""), dict, key);
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -877,8 +962,17 @@ fn xmain() -> ! {
                 };
                 let dict = req.dict.as_str().expect("dict utf-8 decode error");
                 log::debug!("attempting to remove dict {} basis {:?}", dict, bname);
+                // only list the keys going away if someone wants to hear about them
+                let doomed_keys = if subscriptions.watches(dict, None) {
+                    basis_cache.key_list(&mut pddb_os, dict, bname).unwrap_or(Vec::new())
+                } else {
+                    Vec::new()
+                };
                 match basis_cache.dict_remove(&mut pddb_os, dict, bname, false) {
                     Ok(_) => {
+                        for key in doomed_keys.iter() {
+                            notify_subscribers(&subscriptions, PddbChangeKind::Deleted, bname.unwrap_or(""), dict, key);
+                        }
                         let mut evict_list = Vec::<ApiToken>::new();
                         // check to see if we need to eliminate any ApiTokens as a result of this.
                         for (token, rec) in token_dict.iter() {
@@ -1010,6 +1104,54 @@ fn xmain() -> ! {
                 }
                 buffer.replace(req).unwrap();
             }
+            Some(Opcode::ListKeyBatch) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbKeyListRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                let dict = req.dict.as_str().expect("dict utf-8 decode error");
+                let prefix = req.prefix.as_str().expect("prefix utf-8 decode error");
+                let after = if req.cursor_valid {
+                    Some(req.cursor.as_str().expect("cursor utf-8 decode error"))
+                } else {
+                    None
+                };
+                match basis_cache.key_list_batch(&mut pddb_os, dict, bname, prefix, after, KEY_LIST_BATCH) {
+                    Ok((names, total, more)) => {
+                        log::debug!("listing {} of {} keys in {} after {:?}", names.len(), total, dict, after);
+                        for (name, entry) in names.iter().zip(req.entries.iter_mut()) {
+                            entry.name = xous_ipc::String::<KEY_NAME_LEN>::from_str(name);
+                            entry.len = 0;
+                            entry.age = 0;
+                            entry.flags = 0;
+                            if req.with_attributes {
+                                // a key can only go missing here if its dictionary is being torn down under us
+                                if let Ok(attr) = basis_cache.key_attributes(&mut pddb_os, dict, name, bname) {
+                                    entry.len = attr.len as u64;
+                                    entry.age = attr.age as u32;
+                                    entry.flags = attr.flags.0;
+                                }
+                            }
+                        }
+                        req.count = names.len() as u32;
+                        req.total = total as u32;
+                        req.more = more;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => {
+                        req.count = 0;
+                        req.more = false;
+                        match e.kind() {
+                            std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::DictCountInBasis) => {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let mut req = buffer.to_original::<PddbDictRequest, _>().unwrap();
@@ -1091,7 +1233,7 @@ fn xmain() -> ! {
                 let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
                 let pbuf = PddbBuf::from_slice_mut(buffer.as_mut()); // direct translation, no serialization necessary for performance
                 let token = pbuf.token;
-                if let Some(rec) = token_dict.get(&token) {
+                if let Some(rec) = token_dict.get_mut(&token) {
                     match basis_cache.key_update(&mut pddb_os,
                         &rec.dict, &rec.key,
                         &pbuf.data[..pbuf.len as usize], Some(pbuf.position as usize),
@@ -1099,6 +1241,8 @@ fn xmain() -> ! {
                         false
                     ) {
                         Ok(_) => {
+                            // subscribers hear about this on the next flush, or when the key is dropped
+                            rec.written = true;
                             pbuf.retcode = PddbRetcode::Ok;
                         }
                         Err(e) => match e.kind() {
@@ -1118,7 +1262,14 @@ fn xmain() -> ! {
             }
             Some(Opcode::WriteKeyFlush) => msg_blocking_scalar_unpack!(msg, _, _, _, _, {
                 match basis_cache.sync(&mut pddb_os, None) {
-                    Ok(_) => xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap(),
+                    Ok(_) => {
+                        for rec in token_dict.values_mut().filter(|r| r.written) {
+                            rec.written = false;
+                            notify_key_change(&subscriptions, &mut pddb_os, &mut basis_cache,
+                                PddbChangeKind::Written, &rec.dict, &rec.key, rec.basis.as_deref());
+                        }
+                        xous::return_scalar(msg.sender, PddbRetcode::Ok.to_usize().unwrap()).unwrap()
+                    },
                     Err(e) => match e.kind() {
                         std::io::ErrorKind::OutOfMemory => xous::return_scalar(msg.sender, PddbRetcode::DiskFull.to_usize().unwrap()).unwrap(),
                         std::io::ErrorKind::NotFound => xous::return_scalar(msg.sender, PddbRetcode::BasisLost.to_usize().unwrap()).unwrap(),
@@ -1126,6 +1277,118 @@ fn xmain() -> ! {
                     }
                 };
             }),
+            Some(Opcode::Compact) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbDictRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                match basis_cache.compact(&mut pddb_os, bname, 1) {
+                    Ok(stats) => {
+                        req.index = (stats.small_pages + stats.large_pages) as u32;
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                        std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotMounted,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::CompactIdle) => {
+                // repacked pools are written to fresh pages before their descriptors are, so losing
+                // power part way through leaves the old copies in place
+                if msgs_since_idle_tick == 1 && basis_cache.basis_count() > 0 {
+                    log::info!("pddb is idle, compacting");
+                    match basis_cache.compact(&mut pddb_os, None, IDLE_COMPACT_MIN_PAGES) {
+                        Ok(stats) => log::info!("idle compaction freed {:?}", stats),
+                        Err(e) => log::warn!("idle compaction failed: {:?}", e),
+                    }
+                }
+                msgs_since_idle_tick = 0;
+            }
+            Some(Opcode::Fsck) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbFsckRequest, _>().unwrap();
+                let bname = if req.basis_specified {
+                    Some(req.basis.as_str().unwrap())
+                } else {
+                    None
+                };
+                match basis_cache.fsck(&mut pddb_os, bname, req.repair) {
+                    Ok(report) => {
+                        req.pages_checked = report.pages_checked as u32;
+                        req.keys_checked = report.keys_checked as u32;
+                        req.issues = report.findings.len() as u32;
+                        req.repaired = report.count(FsckAction::Repaired) as u32;
+                        req.quarantined = report.count(FsckAction::Quarantined) as u32;
+                        req.summary.clear();
+                        for finding in report.findings.iter() {
+                            let line = format!("{}\n", finding);
+                            if req.summary.len() + line.len() > FSCK_SUMMARY_LEN - 1 {
+                                break;
+                            }
+                            req.summary.append(&line).ok();
+                        }
+                        req.code = PddbRequestCode::NoErr;
+                    }
+                    Err(e) => match e.kind() {
+                        std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                        std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotMounted,
+                        _ => req.code = PddbRequestCode::InternalError,
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
+            Some(Opcode::TxnCommit) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                let mut req = buffer.to_original::<PddbTxnRequest, _>().unwrap();
+                let count = req.count as usize;
+                let ops = if count > PDDB_TXN_INLINE_OPS {
+                    basis_cache.txn_staged_ops(&mut pddb_os, req.basis.as_str().unwrap_or(""), req.id, count)
+                } else {
+                    Ok(req.ops[..count].iter().map(|op|
+                        TxnOp {
+                            dict: op.dict.as_str().unwrap_or("").to_string(),
+                            key: op.key.as_str().unwrap_or("").to_string(),
+                            delete: op.delete,
+                        }
+                    ).collect())
+                };
+                let result = match ops {
+                    Ok(ops) => basis_cache.txn_commit(&mut pddb_os, req.basis.as_str().unwrap_or(""), req.id, &ops).map(|_| ops),
+                    Err(e) => {
+                        // the op list never made it, so throw away whatever was staged under this id
+                        if let Err(cleanup) = basis_cache.txn_cleanup(&mut pddb_os, req.basis.as_str().unwrap_or(""), req.id, count) {
+                            log::error!("transaction {:016x}: couldn't discard staged data: {:?}", req.id, cleanup);
+                        }
+                        Err(e)
+                    }
+                };
+                match result {
+                    Ok(ops) => {
+                        for op in ops.iter() {
+                            notify_subscribers(&subscriptions,
+                                if op.delete {PddbChangeKind::Deleted} else {PddbChangeKind::Written},
+                                req.basis.as_str().unwrap_or(""), &op.dict, &op.key
+                            );
+                        }
+                        req.code = PddbRequestCode::NoErr
+                    },
+                    Err(e) => {
+                        log::error!("transaction {:016x} failed: {:?}", req.id, e);
+                        match e.kind() {
+                            std::io::ErrorKind::OutOfMemory => req.code = PddbRequestCode::NoFreeSpace,
+                            std::io::ErrorKind::NotFound => req.code = PddbRequestCode::NotFound,
+                            _ => req.code = PddbRequestCode::InternalError,
+                        }
+                    }
+                }
+                buffer.replace(req).unwrap();
+            }
             Some(Opcode::MenuListBasis) => {
                 let bases = basis_cache.basis_list();
                 let mut note = String::from(t!("pddb.menu.listbasis_response", xous::LANG));
@@ -1134,6 +1397,24 @@ fn xmain() -> ! {
                 }
                 modals.show_notification(&note, false).expect("couldn't show basis list");
             },
+            Some(Opcode::MenuChangeBasisPassword) => {
+                let secret_bases: Vec::<String> = basis_cache.basis_list().into_iter()
+                    .filter(|b| b != PDDB_DEFAULT_SYSTEM_BASIS).collect();
+                if secret_bases.len() == 0 {
+                    modals.show_notification(t!("pddb.menu.changepass_none", xous::LANG), false).expect("couldn't show notification");
+                    continue;
+                }
+                for basis in secret_bases.iter() {
+                    modals.add_list_item(basis).expect("couldn't build radio item list");
+                }
+                match modals.get_radiobutton(t!("pddb.menu.changepass_which", xous::LANG)) {
+                    Ok(basis) => {
+                        change_password_ux(&modals, &mut pddb_os, &mut basis_cache, pw_cid,
+                            xous_ipc::String::<BASIS_NAME_LEN>::from_str(&basis));
+                    }
+                    _ => log::error!("get_radiobutton failed"),
+                }
+            },
             #[cfg(not(any(target_os = "none", target_os = "xous")))]
             Some(Opcode::DangerousDebug) => {
                 let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
@@ -1149,6 +1430,7 @@ fn xmain() -> ! {
                         if let Some(sys_basis) = pddb_os.pddb_mount() {
                             log::info!("remount successful");
                             basis_cache.basis_add(sys_basis);
+                            basis_cache.txn_recover(&mut pddb_os, None).ok();
                         } else {
                             log::info!("remount failed");
                         }
@@ -1178,6 +1460,76 @@ fn xmain() -> ! {
     xous::terminate_process(0)
 }
 
+fn request_password(pw_cid: xous::CID, name: xous_ipc::String::<BASIS_NAME_LEN>, prompt: PasswordPrompt) -> Option<xous_ipc::String::<PASSWORD_LEN>> {
+    let request = BasisRequestPassword {
+        db_name: name,
+        plaintext_pw: None,
+        prompt,
+    };
+    let mut buf = Buffer::into_buf(request).unwrap();
+    buf.lend_mut(pw_cid, PwManagerOpcode::RequestPassword.to_u32().unwrap()).unwrap();
+    buf.to_original::<BasisRequestPassword, _>().unwrap().plaintext_pw
+}
+
+/// Walks the user through changing the password of the open basis `name`: the current password, the new one
+/// twice, and then the re-key. Returns the code to hand back to whoever asked for the change.
+fn change_password_ux(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cache: &mut BasisCache, pw_cid: xous::CID,
+    name: xous_ipc::String::<BASIS_NAME_LEN>) -> PddbRequestCode {
+    let bname = String::from(name.as_str().expect("name is not valid utf-8"));
+    if bname == PDDB_DEFAULT_SYSTEM_BASIS {
+        // see `BasisCache::basis_change_password`: the client library refuses this before it gets here
+        log::warn!("refusing to change the password of the system basis");
+        return PddbRequestCode::AccessDenied;
+    }
+    if !basis_cache.basis_list().contains(&bname) {
+        return PddbRequestCode::NotFound;
+    }
+    loop {
+        let old_pw = request_password(pw_cid, name, PasswordPrompt::Current);
+        let new_pw = request_password(pw_cid, name, PasswordPrompt::New);
+        let confirm_pw = request_password(pw_cid, name, PasswordPrompt::Confirm);
+        let (old_pw, new_pw, confirm_pw) = match (old_pw, new_pw, confirm_pw) {
+            (Some(o), Some(n), Some(c)) => (o, n, c),
+            _ => {
+                log::error!("internal error in password change, aborting!");
+                return PddbRequestCode::InternalError;
+            }
+        };
+        let retry_prompt = if new_pw.as_str() != confirm_pw.as_str() {
+            t!("pddb.password_mismatch", xous::LANG)
+        } else {
+            modals.dynamic_notification(Some(t!("pddb.password_changing", xous::LANG)), None).ok();
+            let result = basis_cache.basis_change_password(pddb_os, &bname,
+                old_pw.as_str().expect("password was not valid utf-8"), new_pw.as_str().expect("password was not valid utf-8"));
+            modals.dynamic_notification_close().ok();
+            match result {
+                Ok(_) => {
+                    modals.show_notification(t!("pddb.password_changed", xous::LANG), false).expect("couldn't show notification");
+                    return PddbRequestCode::NoErr;
+                }
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => t!("pddb.badpass", xous::LANG),
+                Err(e) => {
+                    log::error!("couldn't change password of {}: {:?}", bname, e);
+                    modals.show_notification(t!("pddb.password_change_failed", xous::LANG), false).expect("couldn't show notification");
+                    return match e.kind() {
+                        ErrorKind::OutOfMemory => PddbRequestCode::NoFreeSpace,
+                        _ => PddbRequestCode::InternalError,
+                    };
+                }
+            }
+        };
+        modals.add_list_item(t!("pddb.yes", xous::LANG)).expect("couldn't build radio item list");
+        modals.add_list_item(t!("pddb.no", xous::LANG)).expect("couldn't build radio item list");
+        match modals.get_radiobutton(retry_prompt) {
+            Ok(response) if response.as_str() == t!("pddb.yes", xous::LANG) => {
+                xous::yield_slice(); // allow a redraw to happen before repeating the request
+            }
+            Ok(_) => return PddbRequestCode::AccessDenied,
+            _ => panic!("get_radiobutton failed"),
+        }
+    }
+}
+
 fn ensure_password(modals: &modals::Modals, pddb_os: &mut PddbOs, _pw_cid: xous::CID) -> PasswordState {
     log::info!("Requesting login password");
     loop {
@@ -1227,6 +1579,9 @@ fn try_mount_or_format(modals: &modals::Modals, pddb_os: &mut PddbOs, basis_cach
         if let Some(sys_basis) = pddb_os.pddb_mount() {
             log::info!("PDDB mount operation finished successfully");
             basis_cache.basis_add(sys_basis);
+            if let Err(e) = basis_cache.txn_recover(pddb_os, None) {
+                log::error!("couldn't recover transactions: {:?}", e);
+            }
             return true
         }
     }
@@ -1419,6 +1774,46 @@ pub(crate) fn hw_testcase(pddb_os: &mut PddbOs) {
     pddb_os.dbg_dump(Some("manual".to_string()), None);
 }
 
+/// Returns true if `conn` is still needed for a key callback or a change subscription.
+fn callback_conn_in_use(conn: xous::CID, token_dict: &HashMap::<ApiToken, TokenRecord>, subscriptions: &Subscriptions) -> bool {
+    token_dict.values().any(|r| r.conn == Some(conn))
+    || subscriptions.conn_in_use(conn)
+}
+
+/// Reports a change to a key, naming the basis the key resolves to (or the requested basis, if it can't be
+/// found). The lookup is skipped entirely if nobody watches the key.
+fn notify_key_change(subscriptions: &Subscriptions, pddb_os: &mut PddbOs, basis_cache: &mut BasisCache,
+    kind: PddbChangeKind, dict: &str, key: &str, basis: Option<&str>) {
+    if !subscriptions.watches(dict, Some(key)) {
+        return;
+    }
+    let basis = match basis_cache.key_attributes(pddb_os, dict, key, basis) {
+        Ok(attr) => attr.basis,
+        Err(_) => String::from(basis.unwrap_or("")),
+    };
+    notify_subscribers(subscriptions, kind, &basis, dict, key);
+}
+
+fn notify_subscribers(subscriptions: &Subscriptions, kind: PddbChangeKind, basis: &str, dict: &str, key: &str) {
+    for (token, conn) in subscriptions.recipients(basis, dict, key) {
+        let event = PddbChangeEvent {
+            token,
+            kind,
+            basis: xous_ipc::String::<BASIS_NAME_LEN>::from_str(basis),
+            dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(dict),
+            key: xous_ipc::String::<KEY_NAME_LEN>::from_str(key),
+        };
+        // the send is non-blocking, so a slow subscriber can't stall the PDDB
+        match Buffer::into_buf(event) {
+            Ok(buf) => match buf.send(conn, pddb::CbOp::Event.to_u32().unwrap()) {
+                Ok(_) => log::debug!("{:?} on {}:{} sent to subscriber", kind, dict, key),
+                Err(e) => log::warn!("{:?} on {}:{} couldn't be sent to subscriber: {:?}", kind, dict, key, e),
+            },
+            Err(_) => log::warn!("couldn't allocate change event for {}:{}", dict, key),
+        }
+    }
+}
+
 fn notify_of_disconnect(pddb_os: &mut PddbOs, token_dict: &HashMap::<ApiToken, TokenRecord>, basis_cache: &mut BasisCache) {
     // 1. search to see if any of the active tokens are are in our token_dict
     // 2. notify them of the disconnect, if there is a callback set.
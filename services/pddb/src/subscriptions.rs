@@ -0,0 +1,116 @@
+use crate::api::*;
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub(crate) struct SubscriptionRecord {
+    pub dict: String,
+    pub key: Option<String>, // None watches every key in the dictionary
+    pub basis: Option<String>,
+    pub conn: xous::CID,
+}
+impl SubscriptionRecord {
+    /// An empty `basis` is a change made to the union of open basis; it matches every subscriber to the dict/key.
+    fn matches(&self, basis: &str, dict: &str, key: &str) -> bool {
+        if !self.watches(dict, Some(key)) {
+            return false;
+        }
+        match &self.basis {
+            Some(b) => basis.is_empty() || b == basis,
+            None => true,
+        }
+    }
+    /// Like `matches`, but for any basis, and `None` stands for any key of the dictionary.
+    fn watches(&self, dict: &str, key: Option<&str>) -> bool {
+        if self.dict != dict {
+            return false;
+        }
+        match (&self.key, key) {
+            (Some(k), Some(key)) => k == key,
+            _ => true,
+        }
+    }
+}
+
+/// Change subscriptions: given an ApiToken, the dict/key/basis being watched, and who to tell.
+pub(crate) struct Subscriptions {
+    records: HashMap::<ApiToken, SubscriptionRecord>,
+}
+impl Subscriptions {
+    pub(crate) fn new() -> Self {
+        Subscriptions { records: HashMap::new() }
+    }
+    pub(crate) fn insert(&mut self, token: ApiToken, record: SubscriptionRecord) {
+        self.records.insert(token, record);
+    }
+    pub(crate) fn remove(&mut self, token: &ApiToken) -> Option<SubscriptionRecord> {
+        self.records.remove(token)
+    }
+    /// Returns true if `conn` is still needed to deliver change events.
+    pub(crate) fn conn_in_use(&self, conn: xous::CID) -> bool {
+        self.records.values().any(|s| s.conn == conn)
+    }
+    /// A cheap check to run before doing any work to describe a change: returns true if anyone watches
+    /// `key` of `dict`, in any basis. With `key` set to `None`, returns true if anyone watches any key of `dict`.
+    pub(crate) fn watches(&self, dict: &str, key: Option<&str>) -> bool {
+        self.records.values().any(|s| s.watches(dict, key))
+    }
+    /// Returns the token and connection of every subscriber to a change of `dict:key` in `basis`.
+    pub(crate) fn recipients(&self, basis: &str, dict: &str, key: &str) -> Vec::<(ApiToken, xous::CID)> {
+        self.records.iter()
+            .filter(|(_, s)| s.matches(basis, dict, key))
+            .map(|(token, s)| (*token, s.conn))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(dict: &str, key: Option<&str>, basis: Option<&str>, conn: xous::CID) -> SubscriptionRecord {
+        SubscriptionRecord {
+            dict: dict.to_string(),
+            key: key.map(|k| k.to_string()),
+            basis: basis.map(|b| b.to_string()),
+            conn,
+        }
+    }
+
+    #[test]
+    fn subscribe_notify_unsubscribe() {
+        let mut subs = Subscriptions::new();
+        assert!(!subs.watches("wlan.networks", None));
+
+        let dict_wide: ApiToken = [1, 2, 3];
+        let one_key: ApiToken = [4, 5, 6];
+        subs.insert(dict_wide, record("wlan.networks", None, None, 7));
+        subs.insert(one_key, record("wlan.networks", Some("home"), Some("Private"), 8));
+
+        assert!(subs.watches("wlan.networks", None));
+        assert!(subs.watches("wlan.networks", Some("office")));
+        assert!(!subs.watches("wlan.config", None));
+
+        let mut to_tell = subs.recipients("Private", "wlan.networks", "home");
+        to_tell.sort();
+        assert_eq!(to_tell, vec![(dict_wide, 7), (one_key, 8)]);
+        // a change in another basis only reaches the subscriber that didn't pick a basis
+        assert_eq!(subs.recipients(".System", "wlan.networks", "home"), vec![(dict_wide, 7)]);
+        // a change to the union of basis reaches everyone watching the key
+        assert_eq!(subs.recipients("", "wlan.networks", "home").len(), 2);
+        assert_eq!(subs.recipients("Private", "wlan.networks", "office"), vec![(dict_wide, 7)]);
+        assert!(subs.recipients("Private", "wlan.config", "home").is_empty());
+
+        let gone = subs.remove(&dict_wide).expect("subscription was not recorded");
+        assert_eq!(gone.conn, 7);
+        assert!(!subs.conn_in_use(7));
+        assert!(subs.conn_in_use(8));
+        assert!(!subs.watches("wlan.networks", Some("office")));
+        assert!(subs.recipients("Private", "wlan.networks", "office").is_empty());
+        assert_eq!(subs.recipients("Private", "wlan.networks", "home"), vec![(one_key, 8)]);
+
+        assert!(subs.remove(&dict_wide).is_none());
+        subs.remove(&one_key).expect("subscription was not recorded");
+        assert!(!subs.watches("wlan.networks", None));
+    }
+}
@@ -3,8 +3,9 @@ use rand_chacha::rand_core::RngCore;
 use rand_chacha::rand_core::SeedableRng;
 use crate::*;
 use core::sync::atomic::{AtomicU64, Ordering};
-use std::collections::{BTreeSet, HashSet};
-use std::io::Result;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Result, Cursor, ErrorKind};
+use crate::archive::*;
 
 const UPPER_BOUND: usize = 9000;
 const LOWER_BOUND: usize = 12; // needs to be big enough to compute murmur3 hash + hold checksum
@@ -378,6 +379,443 @@ pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
     }
 }
 
+/// Exports `from_basis` into an archive, checks that damaged archives and wrong passphrases are rejected,
+/// then restores the archive into `to_basis` and checks that every key came across intact.
+pub(crate) fn archive_round_trip(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    from_basis: &str, to_basis: &str, passphrase: &str
+) -> Result<()> {
+    let mut dict_list = BTreeSet::<String>::new();
+    for dict in basis_cache.dict_list(hw, Some(from_basis)) {
+        dict_list.insert(dict);
+    }
+    let mut writer = ArchiveWriter::new(Vec::<u8>::new(), passphrase, BCRYPT_COST,
+        [0x5a; ARCHIVE_SALT_LEN], [0xa5; ARCHIVE_NONCE_BASE_LEN])?;
+    let mut key_count = 0;
+    for dict in dict_list.iter() {
+        writer.dict(dict)?;
+        let mut key_list = BTreeSet::<String>::new();
+        for key in basis_cache.key_list(hw, dict, Some(from_basis))? {
+            key_list.insert(key);
+        }
+        for key in key_list.iter() {
+            let attrs = basis_cache.key_attributes(hw, dict, key, Some(from_basis))?;
+            let mut data = vec![0u8; attrs.len];
+            basis_cache.key_read(hw, dict, key, &mut data, None, Some(from_basis))?;
+            writer.key(key, &data)?;
+            key_count += 1;
+        }
+    }
+    let (archive, stats) = writer.finish()?;
+    assert!(stats == ArchiveStats { dicts: dict_list.len(), keys: key_count }, "archive stats don't match the basis");
+    log::info!("archived {} dicts and {} keys into {} bytes", stats.dicts, stats.keys, archive.len());
+
+    let mut reader = ArchiveReader::new(Cursor::new(&archive), "not the passphrase")?;
+    let e = reader.verify().expect_err("archive opened with the wrong passphrase");
+    assert!(e.kind() == ErrorKind::PermissionDenied, "wrong passphrase gave the wrong error: {:?}", e);
+
+    let mut damaged = archive.clone();
+    let mid = damaged.len() / 2;
+    damaged[mid] ^= 0x1;
+    assert!(ArchiveReader::new(Cursor::new(&damaged), passphrase)?.verify().is_err(), "damaged archive was accepted");
+    let truncated = &archive[..archive.len() - 1];
+    assert!(ArchiveReader::new(Cursor::new(truncated), passphrase)?.verify().is_err(), "truncated archive was accepted");
+
+    let mut reader = ArchiveReader::new(Cursor::new(&archive), passphrase)?;
+    assert!(reader.verify()? == stats, "archive read back different stats");
+    reader.rewind()?;
+    // restore the way `Pddb::restore_basis` does: every key is staged, and the archive is committed as one
+    // transaction, whose op list is too long for a commit request and so is staged as well
+    let id = 0x7265_7374_0000_0000;
+    match basis_cache.dict_add(hw, PDDB_TXN_DICT, Some(to_basis)) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    let mut ops = Vec::<PddbTxnOp>::new();
+    let mut dict = String::new();
+    while let Some(entry) = reader.next_entry()? {
+        match entry {
+            ArchiveEntry::Dict(name) => dict = name,
+            ArchiveEntry::Key(name, data) => {
+                basis_cache.key_update(hw, PDDB_TXN_DICT, &txn_staged_key(id, ops.len()), &data, None, None, Some(to_basis), true)?;
+                ops.push(PddbTxnOp {
+                    dict: xous_ipc::String::<DICT_NAME_LEN>::from_str(&dict),
+                    key: xous_ipc::String::<KEY_NAME_LEN>::from_str(&name),
+                    delete: false,
+                });
+            }
+        }
+    }
+    assert!(ops.len() > PDDB_TXN_INLINE_OPS, "archive is too small to need a staged op list");
+    basis_cache.key_update(hw, PDDB_TXN_DICT, &txn_ops_key(id), &txn_ops_encode(&ops), None, None, Some(to_basis), true)?;
+    let txn_ops = basis_cache.txn_staged_ops(hw, to_basis, id, ops.len())?;
+    assert!(basis_cache.txn_staged_ops(hw, to_basis, id, ops.len() + 1).is_err(), "staged op list with the wrong count was accepted");
+    basis_cache.txn_commit(hw, to_basis, id, &txn_ops)?;
+    assert!(basis_cache.key_list(hw, PDDB_TXN_DICT, Some(to_basis))?.is_empty(), "restore left staged data behind");
+
+    for dict in dict_list.iter() {
+        let from_keys = basis_cache.key_list(hw, dict, Some(from_basis))?;
+        // dictionaries that were empty in the archive aren't recreated
+        let to_keys = match basis_cache.key_list(hw, dict, Some(to_basis)) {
+            Ok(keys) => keys,
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        assert!(from_keys == to_keys, "restored dictionary {} has different keys", dict);
+        for key in from_keys.iter() {
+            let len = basis_cache.key_attributes(hw, dict, key, Some(from_basis))?.len;
+            assert!(basis_cache.key_attributes(hw, dict, key, Some(to_basis))?.len == len, "restored key {}:{} has the wrong length", dict, key);
+            let mut original = vec![0u8; len];
+            basis_cache.key_read(hw, dict, key, &mut original, None, Some(from_basis))?;
+            let mut restored = vec![0u8; len];
+            basis_cache.key_read(hw, dict, key, &mut restored, None, Some(to_basis))?;
+            assert!(original == restored, "restored key {}:{} has different data", dict, key);
+        }
+    }
+    Ok(())
+}
+
+/// Reads back every key of a basis, so the contents can be compared across a compaction.
+fn snapshot_basis(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str) -> Result<BTreeMap<(String, String), Vec::<u8>>> {
+    let mut snapshot = BTreeMap::<(String, String), Vec::<u8>>::new();
+    for dict in basis_cache.dict_list(hw, Some(basis)) {
+        for key in basis_cache.key_list(hw, &dict, Some(basis))? {
+            let attrs = basis_cache.key_attributes(hw, &dict, &key, Some(basis))?;
+            let mut data = vec![0u8; attrs.len];
+            basis_cache.key_read(hw, &dict, &key, &mut data, None, Some(basis))?;
+            snapshot.insert((dict.to_string(), key), data);
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Churns a dictionary of the system basis so its pools fragment, then compacts and checks that the
+/// expected pages were reclaimed, that no data changed, and that the result survives a remount.
+/// Leaves only the system basis mounted in `basis_cache`.
+pub(crate) fn compaction_churn(hw: &mut PddbOs, basis_cache: &mut BasisCache,
+    maybe_num_keys: Option<usize>, maybe_extra_reserved: Option<usize>,
+) -> Result<()> {
+    const CHURN_DICT: &'static str = "churn";
+    let basis = PDDB_DEFAULT_SYSTEM_BASIS;
+    let num_keys = maybe_num_keys.unwrap_or(96);
+    let extra_reserved = maybe_extra_reserved.unwrap_or(2 * VPAGE_SIZE);
+
+    basis_cache.dict_add(hw, CHURN_DICT, Some(basis))?;
+    let mut keys = Vec::<String>::new();
+    for keynum in 1..=num_keys {
+        let (keyname, keydata) = gen_key(CHURN_DICT, keynum, LOWER_BOUND, UPPER_BOUND - 4);
+        // over-reserve the large keys, so they have pages to give back
+        let alloc_hint = if keydata.len() >= VPAGE_SIZE {
+            Some(keydata.len() + extra_reserved)
+        } else {
+            None
+        };
+        basis_cache.key_update(hw, CHURN_DICT, &keyname, &keydata, None, alloc_hint, Some(basis), false)?;
+        keys.push(keyname);
+    }
+    basis_cache.sync(hw, Some(basis))?;
+    // keep one key out of four, so most small pools are left mostly empty
+    for (index, key) in keys.iter().enumerate() {
+        if index % 4 != 0 {
+            basis_cache.key_remove(hw, CHURN_DICT, key, Some(basis), false)?;
+        }
+    }
+    basis_cache.sync(hw, Some(basis))?;
+
+    let before = snapshot_basis(hw, basis_cache, basis)?;
+    let stats = basis_cache.compact(hw, Some(basis), 1)?;
+    log::info!("churn compaction: {:?}", stats);
+    assert!(stats.small_pages > 0, "churned small pool was not compacted");
+    assert!(stats.large_pages > 0, "over-reserved large keys were not trimmed");
+    assert!(before == snapshot_basis(hw, basis_cache, basis)?, "compaction changed the data");
+
+    let again = basis_cache.compact(hw, Some(basis), 1)?;
+    assert!(again.small_pages == 0 && again.large_pages == 0, "compaction is not idempotent: {:?}", again);
+
+    // the compacted pools and trimmed keys must still take new data
+    for keynum in num_keys + 1..=num_keys + num_keys / 4 {
+        let (keyname, keydata) = gen_key(CHURN_DICT, keynum, LOWER_BOUND, UPPER_BOUND - 4);
+        basis_cache.key_update(hw, CHURN_DICT, &keyname, &keydata, None, None, Some(basis), false)?;
+    }
+    for key in keys.iter().step_by(4) {
+        let len = basis_cache.key_attributes(hw, CHURN_DICT, key, Some(basis))?.len;
+        basis_cache.key_update(hw, CHURN_DICT, key, &[0x5a; 16], Some(len), None, Some(basis), false)?;
+    }
+    basis_cache.sync(hw, Some(basis))?;
+    let grown = snapshot_basis(hw, basis_cache, basis)?;
+    for ((dict, key), data) in before.iter() {
+        let now = grown.get(&(dict.to_string(), key.to_string())).expect("key lost after compaction");
+        assert!(now[..data.len()] == data[..], "{}:{} changed after compaction", dict, key);
+    }
+
+    *basis_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't remount system basis");
+    basis_cache.basis_add(sys_basis);
+    assert!(grown == snapshot_basis(hw, basis_cache, basis)?, "compacted basis did not survive a remount");
+    Ok(())
+}
+
+/// Damages a multi-page key on flash and a dictionary's key count in RAM, then checks that fsck reports both,
+/// that a repair quarantines the key with its readable pages intact, and that the basis then checks clean,
+/// including after a remount. Leaves `basis` mounted.
+pub(crate) fn fsck_corruption(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, password: &str) -> Result<()> {
+    const DAMAGE_DICT: &'static str = "damage";
+    const VICTIM: &'static str = "victim";
+    basis_cache.basis_create(hw, basis, password)?;
+    let entry = basis_cache.basis_unlock(hw, basis, password, BasisRetentionPolicy::Persist).expect("couldn't unlock fsck basis");
+    basis_cache.basis_add(entry);
+
+    basis_cache.dict_add(hw, DAMAGE_DICT, Some(basis))?;
+    for keynum in 1..=8 {
+        let (keyname, keydata) = gen_key(DAMAGE_DICT, keynum, LOWER_BOUND, UPPER_BOUND);
+        basis_cache.key_update(hw, DAMAGE_DICT, &keyname, &keydata, None, None, Some(basis), false)?;
+    }
+    let mut victim = vec![0u8; 3 * VPAGE_SIZE];
+    for (index, byte) in victim.iter_mut().enumerate() {
+        *byte = (index / VPAGE_SIZE + 1) as u8;
+    }
+    basis_cache.key_update(hw, DAMAGE_DICT, VICTIM, &victim, None, None, Some(basis), false)?;
+    basis_cache.sync(hw, Some(basis))?;
+    let report = basis_cache.fsck(hw, Some(basis), false)?;
+    assert!(report.findings.is_empty(), "healthy basis has findings: {:?}", report.findings);
+    assert!(report.keys_checked == 9, "expected 9 keys, checked {}", report.keys_checked);
+    let before = snapshot_basis(hw, basis_cache, basis)?;
+
+    // remount to get at the page table, then scramble the middle page of the victim
+    basis_cache.basis_unmount(hw, basis)?;
+    let mut entry = basis_cache.basis_unlock(hw, basis, password, BasisRetentionPolicy::Persist).expect("couldn't unlock fsck basis");
+    entry.populate_caches(hw);
+    let dict = entry.dicts.get_mut(DAMAGE_DICT).expect("damage dict missing");
+    let start = dict.keys.get(VICTIM).expect("victim key missing").start;
+    dict.key_count += 1;
+    let pp = entry.v2p_map.get(&VirtAddr::new(start + VPAGE_SIZE as u64).unwrap()).expect("victim page not mapped");
+    let mut noise = [0u8; PAGE_SIZE];
+    hw.trng_slice(&mut noise);
+    hw.patch_data(&noise, pp.page_number() * PAGE_SIZE as u32);
+    basis_cache.basis_add(entry);
+
+    let report = basis_cache.fsck(hw, Some(basis), false)?;
+    let victim_location = format!("{}:{}", DAMAGE_DICT, VICTIM);
+    assert!(report.findings.iter().any(|f| f.problem == FsckProblem::KeyData && f.location == victim_location),
+        "damaged key not found: {:?}", report.findings);
+    assert!(report.findings.iter().any(|f| f.problem == FsckProblem::KeyCount && f.location == DAMAGE_DICT),
+        "bad key count not found: {:?}", report.findings);
+    assert!(report.count(FsckAction::None) == report.findings.len(), "a check without repair changed something");
+
+    let report = basis_cache.fsck(hw, Some(basis), true)?;
+    assert!(report.count(FsckAction::None) == 0, "problems left unrepaired: {:?}", report.findings);
+    assert!(report.count(FsckAction::Quarantined) == 1, "expected one quarantined key: {:?}", report.findings);
+    assert!(!basis_cache.key_list(hw, DAMAGE_DICT, Some(basis))?.contains(VICTIM), "damaged key was not removed");
+    let mut salvaged = vec![0xffu8; victim.len()];
+    basis_cache.key_read(hw, FSCK_QUARANTINE_DICT, &fsck_quarantine_name(DAMAGE_DICT, VICTIM), &mut salvaged, None, Some(basis))?;
+    assert!(salvaged[..VPAGE_SIZE] == victim[..VPAGE_SIZE], "readable page was not salvaged");
+    assert!(salvaged[VPAGE_SIZE..2 * VPAGE_SIZE].iter().all(|&b| b == 0), "damaged page was not zeroed");
+    assert!(salvaged[2 * VPAGE_SIZE..] == victim[2 * VPAGE_SIZE..], "readable page was not salvaged");
+
+    // with the counts right again, the pages the damaged key left behind show up as orphans
+    let report = basis_cache.fsck(hw, Some(basis), true)?;
+    assert!(report.findings.iter().all(|f| f.problem == FsckProblem::PageOrphaned && f.action == FsckAction::Repaired),
+        "unexpected findings after repair: {:?}", report.findings);
+    assert!(report.findings.len() == 3, "expected the 3 victim pages to be orphaned: {:?}", report.findings);
+    let report = basis_cache.fsck(hw, Some(basis), false)?;
+    assert!(report.findings.is_empty(), "repaired basis has findings: {:?}", report.findings);
+
+    basis_cache.basis_unmount(hw, basis)?;
+    let entry = basis_cache.basis_unlock(hw, basis, password, BasisRetentionPolicy::Persist).expect("couldn't unlock fsck basis");
+    basis_cache.basis_add(entry);
+    let report = basis_cache.fsck(hw, Some(basis), false)?;
+    assert!(report.findings.is_empty(), "repaired basis has findings after a remount: {:?}", report.findings);
+    let after = snapshot_basis(hw, basis_cache, basis)?;
+    for ((dict, key), data) in before.iter() {
+        if key != VICTIM {
+            assert!(after.get(&(dict.to_string(), key.to_string())) == Some(data), "{}:{} changed across the repair", dict, key);
+        }
+    }
+    Ok(())
+}
+
+/// Deletes keys and checks that their descriptors are blanked on flash, so they don't come back after a
+/// remount, including when a deleted key's descriptor slot is handed to a new key before the next sync,
+/// and when a key is deleted before it ever reached flash. Leaves only the system basis mounted.
+pub(crate) fn delete_remount(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const DELETE_DICT: &'static str = "deleted";
+    let basis = PDDB_DEFAULT_SYSTEM_BASIS;
+    basis_cache.dict_add(hw, DELETE_DICT, Some(basis))?;
+    let mut expected = BTreeMap::<String, Vec::<u8>>::new();
+    for keynum in 1..=24 {
+        let (keyname, keydata) = gen_key(DELETE_DICT, keynum, LOWER_BOUND, UPPER_BOUND);
+        basis_cache.key_update(hw, DELETE_DICT, &keyname, &keydata, None, None, Some(basis), false)?;
+        expected.insert(keyname, keydata);
+    }
+    basis_cache.sync(hw, Some(basis))?;
+
+    // every third key goes away on its own
+    let doomed: Vec::<String> = expected.keys().step_by(3).cloned().collect();
+    for key in doomed.iter() {
+        basis_cache.key_remove(hw, DELETE_DICT, key, Some(basis), false)?;
+        expected.remove(key);
+    }
+    basis_cache.sync(hw, Some(basis))?;
+    power_cycle(hw, basis_cache)?;
+    check_dict(hw, basis_cache, basis, DELETE_DICT, &expected)?;
+
+    // a delete followed by a create in the same sync, so the new key can reuse the blanked slot
+    let reused: Vec::<String> = expected.keys().step_by(4).cloned().collect();
+    for (index, key) in reused.iter().enumerate() {
+        basis_cache.key_remove(hw, DELETE_DICT, key, Some(basis), false)?;
+        expected.remove(key);
+        let (keyname, keydata) = gen_key(DELETE_DICT, 100 + index, LOWER_BOUND, UPPER_BOUND);
+        basis_cache.key_update(hw, DELETE_DICT, &keyname, &keydata, None, None, Some(basis), false)?;
+        expected.insert(keyname, keydata);
+    }
+    // a key that is gone before its descriptor was ever written
+    let (transient, data) = gen_key(DELETE_DICT, 200, LOWER_BOUND, UPPER_BOUND);
+    basis_cache.key_update(hw, DELETE_DICT, &transient, &data, None, None, Some(basis), false)?;
+    basis_cache.key_remove(hw, DELETE_DICT, &transient, Some(basis), false)?;
+    basis_cache.sync(hw, Some(basis))?;
+    power_cycle(hw, basis_cache)?;
+    check_dict(hw, basis_cache, basis, DELETE_DICT, &expected)?;
+    Ok(())
+}
+
+/// Checks that `dict` holds exactly the keys in `expected`, with the expected contents.
+fn check_dict(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, dict: &str, expected: &BTreeMap::<String, Vec::<u8>>) -> Result<()> {
+    let mut keys = basis_cache.key_list(hw, dict, Some(basis))?;
+    for (key, data) in expected.iter() {
+        let mut readback = vec![0u8; data.len() + 1];
+        let len = basis_cache.key_read(hw, dict, key, &mut readback, None, Some(basis))?;
+        assert!(len == data.len() && readback[..len] == data[..], "{}:{} has the wrong contents", dict, key);
+        assert!(keys.remove(key), "{}:{} is missing", dict, key);
+    }
+    assert!(keys.is_empty(), "deleted keys came back in {}: {:?}", dict, keys);
+    assert!(basis_cache.dict_attributes(hw, dict, Some(basis))?.num_keys == expected.len() as u32,
+        "{} has the wrong key count", dict);
+    Ok(())
+}
+
+/// Simulates a power loss by dropping everything in RAM and remounting the system basis from flash,
+/// then runs the same transaction recovery as a real mount.
+fn power_cycle(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<usize> {
+    *basis_cache = BasisCache::new();
+    let sys_basis = hw.pddb_mount().expect("couldn't remount system basis");
+    basis_cache.basis_add(sys_basis);
+    basis_cache.txn_recover(hw, None)
+}
+
+/// Runs the same transaction over and over, cutting the power at every step of the commit, and checks
+/// that after recovery the keys are either all as they were before, or all as the transaction left them.
+/// Leaves only the system basis mounted in `basis_cache`.
+pub(crate) fn txn_power_loss(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    let basis = PDDB_DEFAULT_SYSTEM_BASIS;
+    let large = vec![0xa5u8; VPAGE_SIZE * 2 + 100];
+    let before: Vec::<(&str, Vec::<u8>)> = vec![
+        ("index", b"v1".to_vec()),
+        ("entry.a", b"a1".to_vec()),
+        ("entry.b", b"b1".to_vec()),
+        ("stale", b"s1".to_vec()),
+    ];
+    let after: Vec::<(&str, Vec::<u8>)> = vec![
+        ("index", large.clone()),
+        ("entry.a", b"a2".to_vec()),
+        ("entry.b", b"b1".to_vec()),
+        ("entry.c", b"c2".to_vec()),
+    ];
+    // crash points: 0 staged but not synced, 1 staged and synced, 2 torn record, 3 committed,
+    // 4 committed and half applied, 5 applied with the record removed, 6 no crash at all
+    for crash in 0..=6 {
+        let dict = format!("txn{}", crash);
+        let id = 0x7478_0000 + crash as u64;
+        basis_cache.dict_add(hw, &dict, Some(basis))?;
+        for (key, data) in before.iter() {
+            basis_cache.key_update(hw, &dict, key, data, None, None, Some(basis), true)?;
+        }
+        basis_cache.sync(hw, Some(basis))?;
+
+        let ops = vec![
+            TxnOp { dict: dict.to_string(), key: "index".to_string(), delete: false },
+            TxnOp { dict: dict.to_string(), key: "entry.a".to_string(), delete: false },
+            TxnOp { dict: dict.to_string(), key: "stale".to_string(), delete: true },
+            TxnOp { dict: dict.to_string(), key: "entry.c".to_string(), delete: false },
+        ];
+        let staged: [&[u8]; 4] = [&large, b"a2", b"", b"c2"];
+        match basis_cache.dict_add(hw, PDDB_TXN_DICT, Some(basis)) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e),
+        }
+        for (index, (op, data)) in ops.iter().zip(staged.iter()).enumerate() {
+            if !op.delete {
+                basis_cache.key_update(hw, PDDB_TXN_DICT, &txn_staged_key(id, index), data, None, None, Some(basis), true)?;
+            }
+        }
+        match crash {
+            0 => (),
+            1 => basis_cache.sync(hw, Some(basis))?,
+            2 => {
+                basis_cache.sync(hw, Some(basis))?;
+                let record = txn_record_encode(&ops);
+                basis_cache.key_update(hw, PDDB_TXN_DICT, &txn_record_key(id), &record[..record.len() - 6], None, None, Some(basis), true)?;
+                basis_cache.sync(hw, Some(basis))?;
+            }
+            3 | 4 | 5 => {
+                basis_cache.sync(hw, Some(basis))?;
+                basis_cache.txn_record(hw, basis, id, &ops)?;
+                if crash >= 4 {
+                    basis_cache.txn_apply(hw, basis, id, &ops[..2])?;
+                }
+                if crash == 5 {
+                    basis_cache.txn_apply(hw, basis, id, &ops)?;
+                    basis_cache.key_remove(hw, PDDB_TXN_DICT, &txn_record_key(id), Some(basis), false)?;
+                    basis_cache.sync(hw, Some(basis))?;
+                }
+            }
+            _ => basis_cache.txn_commit(hw, basis, id, &ops)?,
+        }
+
+        let finished = power_cycle(hw, basis_cache)?;
+        let expect_finished = if crash == 3 || crash == 4 { 1 } else { 0 };
+        assert!(finished == expect_finished, "crash point {}: {} transactions finished", crash, finished);
+        let expected = if crash >= 3 { &after } else { &before };
+        let mut keys = basis_cache.key_list(hw, &dict, Some(basis))?;
+        for (key, data) in expected.iter() {
+            let mut readback = vec![0u8; data.len() + 1];
+            let len = basis_cache.key_read(hw, &dict, key, &mut readback, None, Some(basis))?;
+            assert!(len == data.len() && readback[..len] == data[..], "crash point {}: {}:{} is torn", crash, dict, key);
+            assert!(keys.remove(*key), "crash point {}: {}:{} is missing", crash, dict, key);
+        }
+        assert!(keys.is_empty(), "crash point {}: unexpected keys {:?}", crash, keys);
+        match basis_cache.key_list(hw, PDDB_TXN_DICT, Some(basis)) {
+            Ok(leftover) => assert!(leftover.is_empty(), "crash point {}: leftover staging {:?}", crash, leftover),
+            Err(e) => assert!(e.kind() == ErrorKind::NotFound),
+        }
+    }
+
+    // a commit whose staged data is incomplete fails before the commit point, and is rolled back
+    let dict = "txn6";
+    let id = 0x7478_00ff;
+    let ops = vec![
+        TxnOp { dict: dict.to_string(), key: "index".to_string(), delete: false },
+        TxnOp { dict: dict.to_string(), key: "entry.b".to_string(), delete: false },
+    ];
+    match basis_cache.dict_add(hw, PDDB_TXN_DICT, Some(basis)) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+        Err(e) => return Err(e),
+    }
+    basis_cache.key_update(hw, PDDB_TXN_DICT, &txn_staged_key(id, 0), b"never", None, None, Some(basis), true)?;
+    let err = basis_cache.txn_commit(hw, basis, id, &ops).expect_err("commit with missing staged data succeeded");
+    assert!(err.kind() == ErrorKind::NotFound, "unexpected commit error {:?}", err);
+    match basis_cache.key_list(hw, PDDB_TXN_DICT, Some(basis)) {
+        Ok(leftover) => assert!(leftover.is_empty(), "failed commit left staging {:?}", leftover),
+        Err(e) => assert!(e.kind() == ErrorKind::NotFound),
+    }
+    let mut readback = vec![0u8; large.len() + 1];
+    let len = basis_cache.key_read(hw, dict, "index", &mut readback, None, Some(basis))?;
+    assert!(readback[..len] == large[..], "failed commit changed {}:index", dict);
+    assert!(power_cycle(hw, basis_cache)? == 0, "failed commit was finished on remount");
+    Ok(())
+}
+
 /* list of test cases:
     - [done] genenral integrity: allocate 4 dictionaries, each with 34 keys of various sizes ranging from 1k-9k.
     - [done] delete/add consistency: general integrity, delete a dictionary, then add a dictionary.
@@ -388,8 +826,116 @@ pub(crate) fn list_all(hw: &mut PddbOs, basis_cache: &mut BasisCache) {
         note: for faster stress-testing, we dialed the FSCB_PAGES to 4 and the FASTSPACE_PAGES to 1.
     - [done] basis search: create basis A, populate with general integrity. create basis B, add test entries.
         hide basis B, confirm original A; mount basis B, confirm B overlay.
+    - [done] archive round trip: export basis B, reject damaged archives and wrong passphrases, restore into basis C.
+    - [done] compaction churn: delete 3 of 4 keys in a dictionary, compact, confirm pages came back and data is intact across a remount.
+    - [done] fsck: damage a key's data and a key count, confirm both are found, then repaired and quarantined, and that the basis checks clean.
+    - [done] delete and remount: deleted keys stay deleted, including when their slot is reused before the next sync.
+    - [done] transactions: cut the power at every step of a commit, confirm the keys come back either all old or all new.
+    - [done] password change: re-key a basis, confirm the old password stops working and the data survives a power cycle.
 */
 
+/// Fills a new basis, changes its password, and checks that after a power cycle only the new password
+/// unlocks it, with every key intact and the pages all accounted for. Leaves only the system basis mounted.
+pub(crate) fn password_change(hw: &mut PddbOs, basis_cache: &mut BasisCache, basis: &str, old_pw: &str, new_pw: &str) -> Result<()> {
+    const PW_DICT: &'static str = "rekey";
+    basis_cache.basis_create(hw, basis, old_pw)?;
+    let entry = basis_cache.basis_unlock(hw, basis, old_pw, BasisRetentionPolicy::Persist).expect("couldn't unlock password basis");
+    basis_cache.basis_add(entry);
+    basis_cache.dict_add(hw, PW_DICT, Some(basis))?;
+    for keynum in 1..=8 {
+        let (keyname, keydata) = gen_key(PW_DICT, keynum, LOWER_BOUND, UPPER_BOUND);
+        basis_cache.key_update(hw, PW_DICT, &keyname, &keydata, None, None, Some(basis), false)?;
+    }
+    basis_cache.sync(hw, Some(basis))?;
+    let before = snapshot_basis(hw, basis_cache, basis)?;
+
+    let wrong = basis_cache.basis_change_password(hw, basis, new_pw, old_pw);
+    assert!(wrong.map_err(|e| e.kind()) == Err(ErrorKind::PermissionDenied), "re-key went ahead with the wrong password");
+    let system = basis_cache.basis_change_password(hw, PDDB_DEFAULT_SYSTEM_BASIS, old_pw, new_pw);
+    assert!(system.map_err(|e| e.kind()) == Err(ErrorKind::Unsupported), "the system basis can't be re-keyed with a basis password");
+
+    basis_cache.basis_change_password(hw, basis, old_pw, new_pw)?;
+    assert!(snapshot_basis(hw, basis_cache, basis)? == before, "data changed across the re-key");
+    // writes after the change have to land under the new keys too
+    basis_cache.key_update(hw, PW_DICT, "after", b"written after the change", None, None, Some(basis), false)?;
+    basis_cache.sync(hw, Some(basis))?;
+
+    power_cycle(hw, basis_cache)?;
+    assert!(basis_cache.basis_unlock(hw, basis, old_pw, BasisRetentionPolicy::Persist).is_none(), "old password still unlocks the basis");
+    let entry = basis_cache.basis_unlock(hw, basis, new_pw, BasisRetentionPolicy::Persist).expect("new password doesn't unlock the basis");
+    basis_cache.basis_add(entry);
+    let mut after = snapshot_basis(hw, basis_cache, basis)?;
+    let late = after.remove(&(PW_DICT.to_string(), "after".to_string()));
+    assert!(late.as_deref() == Some(&b"written after the change"[..]), "key written after the change was lost");
+    assert!(after == before, "data changed across the re-key and remount");
+    let report = basis_cache.fsck(hw, Some(basis), false)?;
+    assert!(report.findings.is_empty(), "re-keyed basis has findings: {:?}", report.findings);
+    basis_cache.basis_unmount(hw, basis)?;
+    Ok(())
+}
+
+/// Walks a dictionary in small batches, with and without a prefix, and checks that every matching key
+/// comes back exactly once, in order, with the right attributes.
+pub(crate) fn key_batches(hw: &mut PddbOs, basis_cache: &mut BasisCache) -> Result<()> {
+    const BATCH_DICT: &'static str = "batches";
+    basis_cache.dict_add(hw, BATCH_DICT, None)?;
+    let mut expected = Vec::<String>::new();
+    for i in (0..50).rev() {
+        let name = format!("msg.{:04}", i);
+        basis_cache.key_update(hw, BATCH_DICT, &name, &vec![i as u8; i + 1], None, None, None, false)?;
+        expected.push(name);
+    }
+    for other in ["aaa", "msg", "zzz"].iter() {
+        basis_cache.key_update(hw, BATCH_DICT, other, b"not a message", None, None, None, false)?;
+    }
+    basis_cache.sync(hw, None)?;
+    expected.sort();
+
+    let mut listed = Vec::<String>::new();
+    let mut after: Option<String> = None;
+    loop {
+        let (names, total, more) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "msg.", after.as_deref(), 8)?;
+        assert!(total == expected.len(), "prefix total is {}, expected {}", total, expected.len());
+        assert!(names.len() <= 8, "batch is larger than requested");
+        for name in names.iter() {
+            let attr = basis_cache.key_attributes(hw, BATCH_DICT, name, None)?;
+            assert!(attr.len == name[4..].parse::<usize>().unwrap() + 1, "wrong length listed for {}", name);
+        }
+        listed.extend_from_slice(&names);
+        if !more {
+            break;
+        }
+        after = listed.last().cloned();
+    }
+    assert!(listed == expected, "batched listing doesn't match: {:?}", listed);
+
+    // the cursor doesn't have to be a key that exists
+    let (names, _, _) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "msg.", Some("msg.0010a"), 2)?;
+    assert!(names == vec!["msg.0011".to_string(), "msg.0012".to_string()], "cursor between keys gave {:?}", names);
+    let (names, total, more) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "", Some("msg.0049"), 8)?;
+    assert!(names == vec!["zzz".to_string()] && total == expected.len() + 3 && !more, "unfiltered tail gave {:?}", names);
+    let missing = basis_cache.key_list_batch(hw, "no such dict", None, "", None, 8);
+    assert!(missing.map_err(|e| e.kind()) == Err(ErrorKind::NotFound), "listing a missing dictionary didn't fail");
+
+    // the sorted listing is kept between batches, but a change to the dictionary has to show up in the next one
+    let (first, _, _) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "msg.", None, 8)?;
+    basis_cache.key_update(hw, BATCH_DICT, "msg.0003a", b"late", None, None, None, false)?;
+    let (names, total, _) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "msg.", first.last().map(|n| n.as_str()), 8)?;
+    assert!(total == expected.len() + 1, "added key was not counted");
+    let mut late = expected.clone();
+    late.push("msg.0003a".to_string());
+    late.sort();
+    // the added key sorts before the cursor, so the batch after it is one further along in the new listing
+    assert!(names[..] == late[9..17], "batch after an add gave {:?}", names);
+    basis_cache.key_remove(hw, BATCH_DICT, "msg.0003a", None, false)?;
+    let (names, total, _) = basis_cache.key_list_batch(hw, BATCH_DICT, None, "msg.", None, 8)?;
+    assert!(total == expected.len() && names[..] == expected[..8], "removed key is still listed: {:?}", names);
+
+    basis_cache.dict_remove(hw, BATCH_DICT, None, false)?;
+    basis_cache.sync(hw, None)?;
+    Ok(())
+}
+
 #[allow(dead_code)]
 pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
     {
@@ -546,6 +1092,41 @@ pub(crate) fn ci_tests(pddb_os: &mut PddbOs) -> Result<()> {
         assert!(merge2_list.difference(&merge_list).count() == 0, "merged list is different from the original list after remount");
         list_all(pddb_os, &mut basis_cache);
 
+        log::info!("Archiving the second basis and restoring it into a third");
+        const ARCHIVE_BASIS: &'static str = "Basis3";
+        basis_cache.basis_create(pddb_os,
+            ARCHIVE_BASIS, EXTRA_BASIS_PW).expect("couldn't build archive basis");
+        if let Some(basis3) = basis_cache.basis_unlock(pddb_os,
+            ARCHIVE_BASIS, EXTRA_BASIS_PW, BasisRetentionPolicy::Persist) {
+            basis_cache.basis_add(basis3);
+        }
+        archive_round_trip(pddb_os, &mut basis_cache, EXTRA_BASIS, ARCHIVE_BASIS, "correct horse battery staple")?;
+        pddb_os.dbg_dump(Some("archive".to_string()), Some(&export));
+
+        log::info!("Doing compaction churn test");
+        compaction_churn(pddb_os, &mut basis_cache, None, None)?;
+        pddb_os.dbg_dump(Some("compacte".to_string()), Some(&export));
+
+        log::info!("Doing fsck corruption test");
+        fsck_corruption(pddb_os, &mut basis_cache, "FsckBasis", EXTRA_BASIS_PW)?;
+        pddb_os.dbg_dump(Some("fsck".to_string()), Some(&export));
+
+        log::info!("Doing delete and remount test");
+        delete_remount(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("deletee".to_string()), Some(&export));
+
+        log::info!("Doing transaction power loss test");
+        txn_power_loss(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("txn".to_string()), Some(&export));
+
+        log::info!("Doing basis password change test");
+        password_change(pddb_os, &mut basis_cache, "PwBasis", EXTRA_BASIS_PW, "a brand new password")?;
+        pddb_os.dbg_dump(Some("passworde".to_string()), Some(&export));
+
+        log::info!("Doing batched key listing test");
+        key_batches(pddb_os, &mut basis_cache)?;
+        pddb_os.dbg_dump(Some("batchese".to_string()), Some(&export));
+
         log::info!("CI done");
 
         /*
@@ -8,7 +8,7 @@ use num_traits::*;
 use gam::modal::*;
 
 use locales::t;
-use crate::BasisRequestPassword;
+use crate::{BasisRequestPassword, PasswordPrompt};
 /*
 Conclusions:
 
@@ -96,14 +96,20 @@ pub(crate) fn password_ux_manager(
         log::debug!("message: {:?}", msg);
         match FromPrimitive::from_usize(msg.body.id()) {
             Some(PwManagerOpcode::RequestPassword) => {
-                let db_name = {
+                let (db_name, prompt) = {
                     let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                     let request = buffer.to_original::<BasisRequestPassword, _>().unwrap();
-                    request.db_name
+                    (request.db_name, request.prompt)
+                };
+                let title = match prompt {
+                    PasswordPrompt::Unlock => t!("pddb.password", xous::LANG),
+                    PasswordPrompt::Current => t!("pddb.password_current", xous::LANG),
+                    PasswordPrompt::New => t!("pddb.password_new", xous::LANG),
+                    PasswordPrompt::Confirm => t!("pddb.password_confirm", xous::LANG),
                 };
                 pddb_modal.modify(
                     Some(ActionType::TextEntry(password_action)),
-                    Some(t!("pddb.password", xous::LANG)), false,
+                    Some(title), false,
                     Some(format!("{}'{}'", t!("pddb.password_for", xous::LANG), db_name.as_str().unwrap()).as_str()), false, None
                 );
                 pddb_modal.activate();
@@ -53,6 +53,7 @@ impl RootKeys {
     }
 
     /// this initiates an attempt to update passwords. User must unlock their device first, and can cancel out if not expected.
+    /// Not implemented yet (see the 1.0 roadmap in RELEASE-v0.9.md); the PDDB system basis password follows the boot password.
     pub fn try_update_password(&mut self, _which: PasswordType) -> Result<(), xous::Error> {
         unimplemented!();
     }
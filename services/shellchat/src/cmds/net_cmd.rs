@@ -53,10 +53,10 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(any(target_os = "none", target_os = "xous"))]
-        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [rx socket] [tx dest socket]] [ping [host] [count]] [tcpget host/path] [capture [start [proto] [port]] [stop [key]]]";
         // no ping in hosted mode -- why would you need it? we're using the host's network connection.
         #[cfg(not(any(target_os = "none", target_os = "xous")))]
-        let helpstring = "net [udp [port]] [count]] [tcpget host/path]";
+        let helpstring = "net [udp [port]] [count]] [tcpget host/path] [capture [start [proto] [port]] [stop [key]]]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -224,6 +224,55 @@ impl<'a> ShellCmdApi<'a> for NetCmd {
                         }
                     }
                 }
+                // captures are saved to the PDDB as pcap files, readable by Wireshark once copied off the device
+                "capture" => {
+                    let usage = "Usage: net capture start [tcp|udp|icmp|arp] [port], net capture stop [key]";
+                    match tokens.next() {
+                        Some("start") => {
+                            let mut protocol = net::CaptureProtocol::Any;
+                            let mut port: Option<u16> = None;
+                            let mut valid = true;
+                            for tok in tokens {
+                                match tok {
+                                    "" => {}
+                                    "tcp" => protocol = net::CaptureProtocol::Tcp,
+                                    "udp" => protocol = net::CaptureProtocol::Udp,
+                                    "icmp" => protocol = net::CaptureProtocol::Icmp,
+                                    "arp" => protocol = net::CaptureProtocol::Arp,
+                                    _ => match tok.parse::<u16>() {
+                                        Ok(p) if p != 0 => port = Some(p),
+                                        _ => valid = false,
+                                    }
+                                }
+                            }
+                            if !valid {
+                                write!(ret, "{}", usage).unwrap();
+                            } else {
+                                match env.netmgr.capture_start(protocol, port) {
+                                    Ok(()) => write!(ret, "Capturing {:?} frames{}", protocol,
+                                        port.map(|p| format!(" on port {}", p)).unwrap_or_default()).unwrap(),
+                                    Err(xous::Error::InvalidSyscall) => write!(ret, "{:?} has no ports to filter on", protocol).unwrap(),
+                                    Err(e) => write!(ret, "Couldn't start capture: {:?}", e).unwrap(),
+                                }
+                            }
+                        }
+                        Some("stop") => {
+                            let key = tokens.next().unwrap_or("capture.pcap");
+                            match env.netmgr.capture_stop(key) {
+                                Ok(summary) => {
+                                    write!(ret, "Saved {} frames to {}:{}", summary.frames, net::CAPTURE_DICT_NAME, key).unwrap();
+                                    if summary.dropped != 0 {
+                                        write!(ret, "; the oldest {} were dropped to make room", summary.dropped).unwrap();
+                                    }
+                                }
+                                Err(xous::Error::UseBeforeInit) => write!(ret, "No capture is running").unwrap(),
+                                Err(xous::Error::InvalidString) => write!(ret, "Invalid key; the capture is still running").unwrap(),
+                                Err(e) => write!(ret, "Couldn't save capture: {:?}", e).unwrap(),
+                            }
+                        }
+                        _ => write!(ret, "{}", usage).unwrap(),
+                    }
+                }
                 "tls" => {
 
                 }
@@ -17,13 +17,13 @@ impl PddbCmd {
 impl<'a> ShellCmdApi<'a> for PddbCmd {
     cmd_api!(pddb); // inserts boilerplate for command API
 
-    fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
+    fn process(&mut self, args: String::<1024>, env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         use core::fmt::Write;
         let mut ret = String::<1024>::new();
         #[cfg(not(feature="pddbtest"))]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]\n[export] [restore] [compact] [fsck]";
         #[cfg(feature="pddbtest")]
-        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]\n[test]";
+        let helpstring = "pddb [basislist] [basiscreate] [basisunlock] [basislock] [basisdelete] [default]\n[dictlist] [keylist] [query] [dictdelete] [keydelete]\n[export] [restore] [compact] [fsck] [test]";
 
         let mut tokens = args.as_str().unwrap().split(' ');
         if let Some(sub_cmd) = tokens.next() {
@@ -164,21 +164,23 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                 }
                 "keylist" => {
                     if let Some(dict) = tokens.next() {
-                        match self.pddb.list_keys(dict, None) {
-                            Ok(list) => {
-                                let checked_len = if list.len() > 6 {
-                                    write!(ret, "First 6 keys of {}:", list.len()).unwrap();
+                        // an optional second argument lists only the keys starting with it
+                        let prefix = tokens.next();
+                        match self.pddb.list_keys_batch(dict, None, prefix, None, true) {
+                            Ok(batch) => {
+                                let checked_len = if batch.total > 6 {
+                                    write!(ret, "First 6 keys of {}:", batch.total).unwrap();
                                     6
                                 } else {
-                                    list.len()
+                                    batch.entries.len()
                                 };
-                                for i in 0..checked_len {
+                                for (i, entry) in batch.entries.iter().take(checked_len).enumerate() {
                                     let sep = if i != checked_len - 1 {
                                         ", "
                                     } else {
                                         ""
                                     };
-                                    match write!(ret, "{}{}", list[i], sep) {
+                                    match write!(ret, "{} ({}b){}", entry.name, entry.len, sep) {
                                         Ok(_) => (),
                                         Err(_) => break, // overflowed return buffer
                                     }
@@ -214,6 +216,106 @@ impl<'a> ShellCmdApi<'a> for PddbCmd {
                         Err(_) => write!(ret, "Error encountered listing dictionaries").ok().unwrap_or(()),
                     }
                 }
+                "export" => {
+                    // archives go into the system basis, so they survive the exported basis being locked or deleted
+                    if let (Some(bname), Some(descriptor)) = (tokens.next(), tokens.next()) {
+                        if let Some((dict, keyname)) = descriptor.split_once(':') {
+                            // the passphrase is asked for in a modal, so it's never echoed or kept in the input history
+                            let modals = modals::Modals::new(&env.xns).unwrap();
+                            let passphrase = modals.get_password("Archive passphrase", None, None);
+                            let confirm = modals.get_password("Repeat the archive passphrase", None, None);
+                            match (passphrase, confirm) {
+                                (Ok(mut passphrase), Ok(mut confirm)) => {
+                                    if passphrase.as_str() != confirm.as_str() {
+                                        write!(ret, "passphrases don't match, nothing was exported").unwrap();
+                                    } else {
+                                        let mut archive = Vec::<u8>::new();
+                                        match self.pddb.export_basis(bname, passphrase.as_str(), &mut archive) {
+                                            Ok(stats) => {
+                                                // replace any earlier archive in one step, so a failed save doesn't lose it
+                                                let mut archive_pddb = pddb::Pddb::new();
+                                                let result = archive_pddb.transaction(Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS))
+                                                    .and_then(|mut txn| {
+                                                        match archive_pddb.txn_write(&mut txn, dict, keyname, &archive) {
+                                                            Ok(_) => archive_pddb.commit(txn),
+                                                            Err(e) => {
+                                                                archive_pddb.abort(txn).ok();
+                                                                Err(e)
+                                                            }
+                                                        }
+                                                    });
+                                                match result {
+                                                    Ok(_) => write!(ret, "exported {} dicts, {} keys from {} into {}:{} ({} bytes)",
+                                                        stats.dicts, stats.keys, bname, dict, keyname, archive.len()).unwrap(),
+                                                    Err(e) => write!(ret, "couldn't save archive to {}:{}: {:?}", dict, keyname, e).unwrap(),
+                                                }
+                                            }
+                                            Err(e) => write!(ret, "basis {} could not be exported: {:?}", bname, e).unwrap(),
+                                        }
+                                    }
+                                    passphrase.volatile_clear();
+                                    confirm.volatile_clear();
+                                }
+                                _ => write!(ret, "couldn't get the archive passphrase").unwrap(),
+                            }
+                        } else {
+                            write!(ret, "Archive is of form 'dict:key'").unwrap();
+                        }
+                    } else {
+                        write!(ret, "usage: pddb export [basis name] [dict:key]; the passphrase is asked for separately").unwrap()
+                    }
+                }
+                "restore" => {
+                    if let (Some(descriptor), Some(bname)) = (tokens.next(), tokens.next()) {
+                        if let Some((dict, keyname)) = descriptor.split_once(':') {
+                            let mut archive_pddb = pddb::Pddb::new();
+                            match archive_pddb.get(dict, keyname, Some(pddb::PDDB_DEFAULT_SYSTEM_BASIS),
+                                false, false, None, None::<fn()>) {
+                                Ok(archive) => {
+                                    let modals = modals::Modals::new(&env.xns).unwrap();
+                                    match modals.get_password("Archive passphrase", None, None) {
+                                        Ok(mut passphrase) => {
+                                            match self.pddb.restore_basis(archive, passphrase.as_str(), bname) {
+                                                Ok(stats) => write!(ret, "restored {} dicts, {} keys into {}", stats.dicts, stats.keys, bname).unwrap(),
+                                                Err(e) => write!(ret, "archive could not be restored into {}: {:?}", bname, e).unwrap(),
+                                            }
+                                            passphrase.volatile_clear();
+                                        }
+                                        Err(e) => write!(ret, "couldn't get the archive passphrase: {:?}", e).unwrap(),
+                                    }
+                                }
+                                Err(e) => write!(ret, "{}:{} not found or other error: {:?}", dict, keyname, e).unwrap(),
+                            }
+                        } else {
+                            write!(ret, "Archive is of form 'dict:key'").unwrap();
+                        }
+                    } else {
+                        write!(ret, "usage: pddb restore [dict:key] [basis name]; the passphrase is asked for separately").unwrap()
+                    }
+                }
+                "compact" => {
+                    let bname = tokens.next();
+                    match self.pddb.compact(bname) {
+                        Ok(pages) => write!(ret, "Compacted {}: {} pages reclaimed", bname.unwrap_or("all basis"), pages).unwrap(),
+                        Err(e) => write!(ret, "Couldn't compact: {:?}", e).unwrap(),
+                    }
+                }
+                "fsck" => {
+                    let mut bname = tokens.next();
+                    let repair = bname == Some("repair");
+                    if repair {
+                        bname = tokens.next();
+                    }
+                    match self.pddb.fsck(bname, repair) {
+                        Ok(summary) => {
+                            write!(ret, "Checked {} pages, {} keys: {} issues, {} repaired, {} quarantined\n",
+                                summary.pages_checked, summary.keys_checked, summary.issues,
+                                summary.repaired, summary.quarantined).unwrap();
+                            write!(ret, "{}", summary.details).unwrap();
+                        }
+                        Err(e) => write!(ret, "Couldn't check: {:?}", e).unwrap(),
+                    }
+                }
                 // note that this feature only works in hosted mode
                 #[cfg(feature="pddbtest")]
                 "test" => {
@@ -20,7 +20,7 @@ impl<'a> ShellCmdApi<'a> for Tts {
 
     fn process(&mut self, args: String::<1024>, _env: &mut CommonEnv) -> Result<Option<String::<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
-        let helpstring = "tts options: speak";
+        let helpstring = "tts options: speak, wpm [n], pitch [50-200], volume [0-100], voice [standard|low|high], punct [none|some|all], spell [on|off], prefs";
 
         let mut tokens = args.as_str().unwrap().split(' ');
 
@@ -31,6 +31,56 @@ impl<'a> ShellCmdApi<'a> for Tts {
                     join_tokens(&mut text, &mut tokens);
                     self.fe.tts_simple(text.as_str().expect("not valid utf-8")).unwrap();
                 }
+                "wpm" | "pitch" | "volume" => {
+                    if let Some(n) = tokens.next().and_then(|t| t.parse::<u32>().ok()) {
+                        match sub_cmd {
+                            "wpm" => self.fe.set_words_per_minute(n).unwrap(),
+                            "pitch" => self.fe.set_pitch(n).unwrap(),
+                            _ => self.fe.set_volume(n).unwrap(),
+                        }
+                    } else {
+                        write!(ret, "{}", helpstring).unwrap();
+                    }
+                }
+                "voice" => {
+                    let kind = match tokens.next() {
+                        Some("standard") => Some(VoiceKind::Standard),
+                        Some("low") => Some(VoiceKind::Low),
+                        Some("high") => Some(VoiceKind::High),
+                        _ => None,
+                    };
+                    if let Some(kind) = kind {
+                        let mut voice = self.fe.get_prefs().unwrap().voice;
+                        voice.kind = kind;
+                        self.fe.set_voice(voice).unwrap();
+                    } else {
+                        write!(ret, "{}", helpstring).unwrap();
+                    }
+                }
+                "punct" => {
+                    let level = match tokens.next() {
+                        Some("none") => Some(Punctuation::None),
+                        Some("some") => Some(Punctuation::Some),
+                        Some("all") => Some(Punctuation::All),
+                        _ => None,
+                    };
+                    if let Some(level) = level {
+                        self.fe.set_punctuation(level).unwrap();
+                    } else {
+                        write!(ret, "{}", helpstring).unwrap();
+                    }
+                }
+                "spell" => {
+                    match tokens.next() {
+                        Some("on") => self.fe.set_spell_out(true).unwrap(),
+                        Some("off") => self.fe.set_spell_out(false).unwrap(),
+                        _ => write!(ret, "{}", helpstring).unwrap(),
+                    }
+                }
+                "prefs" => {
+                    let prefs = self.fe.get_prefs().unwrap();
+                    write!(ret, "{:?}", prefs).unwrap();
+                }
                 _ => {
                     write!(ret, "{}", helpstring).unwrap();
                 }
@@ -1,6 +1,5 @@
 use crate::{CommonEnv, ShellCmdApi};
 use core::fmt::Write;
-use std::io::Write as PddbWrite;
 use xous_ipc::String;
 
 #[derive(Debug)]
@@ -19,7 +18,7 @@ wlan shell command:
 - on: if in off mode, reset WF200 and load firmware, otherwise NOP
 - off: disconnect from AP (if joined) and put WF200 in low power standby
 - setssid ...: set AP SSID to ... (... can include spaces)
-- setpass ...: set AP password to ... (... can include spaces)
+- setpass: prompt for the AP password; it isn't taken on the command line, so it's never shown or kept in the input history
 - join: if disconnected, connect by WPA2 personal with previously set SSID
         and password, otherwise NOP
 - leave: if joined, disconnect from AP
@@ -34,7 +33,7 @@ impl<'a> ShellCmdApi<'a> for Wlan {
         env: &mut CommonEnv,
     ) -> Result<Option<String<1024>>, xous::Error> {
         let mut ret = String::<1024>::new();
-        let helpstring = "wlan [on] [off] [setssid ...] [setpass ...] [join] [leave] [status] [save] [known]";
+        let helpstring = "wlan [on] [off] [setssid ...] [setpass] [join] [leave] [status] [save] [known]";
         let mut show_help = false;
 
         let mut tokens = args.as_str().unwrap().split(' ');
@@ -72,45 +71,38 @@ impl<'a> ShellCmdApi<'a> for Wlan {
                     }
                 }
                 "setpass" => {
+                    if tokens.next().is_some() {
+                        write!(ret, "wlan setpass takes no arguments, it asks for the password instead").unwrap();
+                        return Ok(Some(ret));
+                    }
                     env.netmgr.connection_manager_stop().unwrap();
-                    let mut val = String::<1024>::new();
-                    join_tokens(&mut val, &mut tokens);
-                    let _ = match env.com.wlan_set_pass(val.as_str().expect("not valid utf-8")) {
-                        Ok(_) => {
-                            self.current_pass = Some(std::string::String::from(val.as_str().unwrap()));
-                            write!(ret, "wlan setpass {}.\nConnection manager paused during configuration.", val).unwrap()
-                        },
-                        Err(_) => write!(ret, "Error: passphrase too long for WF200").unwrap(),
-                    };
+                    let modals = modals::Modals::new(&env.xns).unwrap();
+                    match modals.get_password("Wi-Fi password", None, None) {
+                        Ok(mut pass) => {
+                            let _ = match env.com.wlan_set_pass(pass.as_str()) {
+                                Ok(_) => {
+                                    self.current_pass = Some(std::string::String::from(pass.as_str()));
+                                    write!(ret, "wlan setpass done.\nConnection manager paused during configuration.").unwrap()
+                                },
+                                Err(_) => write!(ret, "Error: passphrase too long for WF200").unwrap(),
+                            };
+                            pass.volatile_clear();
+                        }
+                        Err(e) => write!(ret, "Error: couldn't get password: {:?}", e).unwrap(),
+                    }
                 }
                 "save" => {
-                    let mut pddb = pddb::Pddb::new();
+                    let mut saved_networks = net::SavedNetworks::new();
                     if let Some(ssid) = &self.current_ssid {
                         if let Some(pass) = &self.current_pass {
-                            match pddb.get(
-                                net::AP_DICT_NAME, &ssid, None,
-                                true, true, Some(com::api::WF200_PASS_MAX_LEN), Some(||{})) {
-                                Ok(mut entry) => {
-                                    match entry.write(&pass.as_bytes()) {
-                                        Ok(len) => {
-                                            if len != pass.len() {
-                                                write!(ret, "PDDB wrote only {} of {} bytes of password", len, pass.len()).unwrap();
-                                            } else {
-                                                // for now, we should always call flush at the end of a routine; perhaps in the
-                                                // future we'll have a timer that automatically syncs the pddb
-                                                entry.flush().expect("couldn't sync pddb cache");
-                                                write!(ret, "SSID/pass combo saved to PDDB.\nConnection manager started.").unwrap();
-                                                // restart the connection manager now that the key combo has been committed
-                                                env.netmgr.connection_manager_run().unwrap();
-                                            }
-                                        }
-                                        Err(e) => {
-                                            write!(ret, "PDDB error storing key: {:?}", e).unwrap();
-                                        }
-                                    }
-                                },
+                            match saved_networks.save(ssid, pass) {
+                                Ok(()) => {
+                                    write!(ret, "SSID/pass combo saved to PDDB.\nConnection manager started.").unwrap();
+                                    // restart the connection manager now that the key combo has been committed
+                                    env.netmgr.connection_manager_run().unwrap();
+                                }
                                 Err(e) => {
-                                    write!(ret, "PDDB error creating key: {:?}", e).unwrap();
+                                    write!(ret, "PDDB error storing key: {:?}", e).unwrap();
                                 }
                             }
                         } else {
@@ -121,12 +113,18 @@ impl<'a> ShellCmdApi<'a> for Wlan {
                     }
                 }
                 "known" => {
-                    let mut pddb = pddb::Pddb::new();
-                    match pddb.list_keys(net::AP_DICT_NAME, None) {
+                    let mut saved_networks = net::SavedNetworks::new();
+                    match saved_networks.list() {
                         Ok(list) => {
                             write!(ret, "Saved network configs:\n").unwrap();
-                            for item in list.iter() {
-                                write!(ret, "- {}", item).ok(); // whatever, maybe we have too many?
+                            for network in list.iter() {
+                                write!(ret, "- {} (priority {}{}{}{})\n",
+                                    network.ssid,
+                                    network.priority,
+                                    if network.auto_join { "" } else { ", no auto-join" },
+                                    if network.hidden { ", hidden" } else { "" },
+                                    if network.metered { ", metered" } else { "" },
+                                ).ok(); // whatever, maybe we have too many?
                             }
                         }
                         Err(e) => {
@@ -47,8 +47,6 @@ mod oqc_test;
 
 #[cfg(feature="tts")]
 use locales::t;
-#[cfg(feature="tts")]
-use tts_frontend::*;
 
 #[derive(Debug)]
 struct History {
@@ -85,8 +83,6 @@ struct Repl {
 
     // our security token for making changes to our record on the GAM
     token: [u32; 4],
-    #[cfg(feature="tts")]
-    tts: TtsFrontend,
 }
 impl Repl{
     fn new(xns: &xous_names::XousNames, sid: xous::SID) -> Self {
@@ -126,8 +122,6 @@ impl Repl{
             bubble_space: 4,
             env: CmdEnv::new(xns),
             token: token.unwrap(),
-            #[cfg(feature="tts")]
-            tts: TtsFrontend::new(xns).unwrap(),
         }
     }
 
@@ -179,7 +173,8 @@ impl Repl{
                 {
                     let mut output = t!("shellchat.output-tts", xous::LANG).to_string();
                     output.push_str(res.as_str().unwrap_or("UTF-8 error"));
-                    self.tts.tts_simple(&output).unwrap();
+                    // new chat bubbles are read out by the screen reader
+                    self.gam.announce(&output).unwrap();
                 }
                 let output_history = History {
                     text: String::from(res.as_str().unwrap_or("UTF-8 Error")),
@@ -196,7 +191,8 @@ impl Repl{
                 {
                     let mut output = t!("shellchat.output-tts", xous::LANG).to_string();
                     output.push_str(res.as_str().unwrap_or("UTF-8 error"));
-                    self.tts.tts_simple(&output).unwrap();
+                    // new chat bubbles are read out by the screen reader
+                    self.gam.announce(&output).unwrap();
                 }
                 let output_history = History {
                     text: String::from(res.as_str().unwrap_or("UTF-8 Error")),
@@ -321,9 +317,6 @@ fn xmain() -> ! {
     let shch_sid = xns.register_name(SERVER_NAME_SHELLCHAT, None).expect("can't register server");
     //log::trace!("registered with NS -- {:?}", shch_sid);
 
-    #[cfg(feature="tts")]
-    let tts = TtsFrontend::new(&xns).unwrap();
-
     let mut repl = Repl::new(&xns, shch_sid);
     let mut update_repl = false;
     let mut was_callback = false;
@@ -342,7 +335,7 @@ fn xmain() -> ! {
                 {
                     let mut input = t!("shellchat.input-tts", xous::LANG).to_string();
                     input.push_str(s.as_str());
-                    tts.tts_simple(&input).unwrap();
+                    repl.gam.announce(&input).unwrap();
                 }
                 repl.input(s.as_str()).expect("REPL couldn't accept input string");
                 update_repl = true; // set a flag, instead of calling here, so message can drop and calling server is released
@@ -0,0 +1,84 @@
+use gam::modal::*;
+use gam::*;
+use locales::t;
+use num_traits::*;
+use xous_ipc::String;
+
+use crate::StatusOpcode;
+
+/// The predictors offered by the input method menu, by server name, in menu order. `None` leaves each
+/// app with the predictor it registered, e.g. the command history for shellchat.
+pub(crate) const PREDICTORS: [Option<&'static str>; 4] = [
+    None,
+    Some(ime_plugin_dict::SERVER_NAME_IME_PLUGIN_DICT),
+    Some(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_PINYIN),
+    Some(ime_plugin_cjk::SERVER_NAME_IME_PLUGIN_KANA),
+];
+
+/// Most lines of input history that can be kept per app
+const MAX_HISTORY_LENGTH: usize = 256;
+
+pub fn create_ime_menu(status_conn: xous::CID) {
+    let names = [
+        t!("imemenu.app_default", xous::LANG),
+        t!("imemenu.dict", xous::LANG),
+        t!("imemenu.pinyin", xous::LANG),
+        t!("imemenu.kana", xous::LANG),
+    ];
+    let mut menu_items = Vec::<MenuItem>::new();
+    for (index, name) in names.iter().enumerate() {
+        menu_items.push(MenuItem {
+            name: String::from_str(name),
+            action_conn: Some(status_conn),
+            action_opcode: StatusOpcode::SetPredictor.to_u32().unwrap(),
+            action_payload: MenuPayload::Scalar([index as u32, 0, 0, 0]),
+            close_on_select: true,
+        });
+    }
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("imemenu.clear_history", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::ClearInputHistory.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("imemenu.history_length", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SetInputHistoryLength.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+    menu_items.push(MenuItem {
+        name: String::from_str(t!("mainmenu.closemenu", xous::LANG)),
+        action_conn: None,
+        action_opcode: 0,
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+
+    menu_matic(menu_items, gam::IME_MENU_NAME, None);
+}
+
+/// Asks how many lines of input history to keep for each app, and passes the answer on to the IMEF
+/// through the GAM. The modal blocks until the user answers, so this is run on a thread of its own.
+pub(crate) fn ask_history_length() {
+    let xns = xous_names::XousNames::new().unwrap();
+    let modals = modals::Modals::new(&xns).unwrap();
+    let gam = gam::Gam::new(&xns).unwrap();
+    match modals.get_text(t!("imemenu.history_length_prompt", xous::LANG), Some(history_length_validator), None) {
+        Ok(len) => {
+            let len = len.as_str().parse::<usize>().expect("pre-validated input failed to re-parse!");
+            gam.set_input_history_length(len).expect("couldn't set the IME history length");
+        }
+        _ => log::error!("couldn't get the input history length"),
+    }
+}
+
+fn history_length_validator(input: TextEntryPayload, _opcode: u32) -> Option<ValidatorErr> {
+    match input.as_str().parse::<usize>() {
+        Ok(len) if len <= MAX_HISTORY_LENGTH => None,
+        Ok(_) => Some(ValidatorErr::from_str(t!("rtc.range_err", xous::LANG))),
+        _ => Some(ValidatorErr::from_str(t!("rtc.integer_err", xous::LANG))),
+    }
+}
@@ -7,8 +7,12 @@ mod appmenu;
 use appmenu::*;
 mod kbdmenu;
 use kbdmenu::*;
+mod imemenu;
+use imemenu::*;
 mod app_autogen;
 mod time;
+#[cfg(feature="tts")]
+mod ttsprefs;
 
 use com::api::*;
 use core::fmt::Write;
@@ -47,6 +51,10 @@ pub(crate) enum StatusOpcode {
     SubmenuApp,
     /// Raise the Keyboard layout menu
     SubmenuKbd,
+    /// Raise the saved Wi-Fi network menu
+    SubmenuWifi,
+    /// Raise the input method menu
+    SubmenuIme,
 
     /// Raise the Shellchat app
     SwitchToShellchat,
@@ -55,6 +63,12 @@ pub(crate) enum StatusOpcode {
 
     /// Set the keyboard map
     SetKeyboard,
+    /// Set the IME predictor, by its index in `PREDICTORS`
+    SetPredictor,
+    /// Forget the IME input history of every app
+    ClearInputHistory,
+    /// Ask for the number of lines of IME input history to keep
+    SetInputHistoryLength,
 
     /// Suspend handler from the main menu
     TrySuspend,
@@ -62,6 +76,8 @@ pub(crate) enum StatusOpcode {
     BatteryDisconnect,
     /// for returning wifi stats
     WifiStats,
+    /// Turn the GAM's screen reader on or off
+    ToggleScreenReader,
     Quit,
 }
 
@@ -337,12 +353,15 @@ fn xmain() -> ! {
     create_app_menu(xous::connect(status_sid).unwrap());
     let kbd_mgr = xous::create_server().unwrap();
     let kbd_menumatic = create_kbd_menu(xous::connect(status_sid).unwrap(), kbd_mgr);
+    create_ime_menu(xous::connect(status_sid).unwrap());
     let kbd = keyboard::Keyboard::new(&xns).unwrap();
 
     log::debug!("subscribe to wifi updates");
     netmgr.wifi_state_subscribe(cb_cid, StatusOpcode::WifiStats.to_u32().unwrap()).unwrap();
     let mut wifi_status: WlanStatus = WlanStatus::from_ipc(WlanStatusIpc::default());
 
+    #[cfg(feature="tts")]
+    ttsprefs::start_tts_prefs();
     #[cfg(feature="tts")]
     thread::spawn({
         move || {
@@ -601,6 +620,28 @@ fn xmain() -> ! {
                 ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
                 gam.raise_menu(gam::APP_MENU_NAME).expect("couldn't raise App submenu");
             },
+            Some(StatusOpcode::SubmenuWifi) => {
+                ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
+                gam.raise_menu(gam::WIFI_MENU_NAME).expect("couldn't raise Wi-Fi submenu");
+            },
+            Some(StatusOpcode::ToggleScreenReader) => {
+                let enable = !gam.is_screen_reader_on().unwrap_or(false);
+                if enable {
+                    gam.set_screen_reader(true).unwrap();
+                    gam.announce(t!("mainmenu.screen_reader_on", xous::LANG)).unwrap();
+                } else {
+                    // say so before going quiet, as there may be nothing to see on the screen. The announcement
+                    // has to finish before the reader is turned off, so wait for it away from the main loop.
+                    gam.announce(t!("mainmenu.screen_reader_off", xous::LANG)).unwrap();
+                    thread::spawn(move || {
+                        let xns = xous_names::XousNames::new().unwrap();
+                        let gam = gam::Gam::new(&xns).unwrap();
+                        let tt = ticktimer_server::Ticktimer::new().unwrap();
+                        tt.sleep_ms(1500).ok();
+                        gam.set_screen_reader(false).unwrap();
+                    });
+                }
+            },
             Some(StatusOpcode::SubmenuKbd) => {
                 log::debug!("getting keyboard map");
                 let map = kbd.get_keymap().expect("couldn't get key mapping");
@@ -614,6 +655,22 @@ fn xmain() -> ! {
                 let map = keyboard::KeyMap::from(code);
                 kbd.set_keymap(map).expect("couldn't set keyboard mapping");
             }),
+            Some(StatusOpcode::SubmenuIme) => {
+                ticktimer.sleep_ms(100).ok(); // yield for a moment to allow the previous menu to close
+                gam.raise_menu(gam::IME_MENU_NAME).expect("couldn't raise input method submenu");
+            },
+            Some(StatusOpcode::SetPredictor) => msg_scalar_unpack!(msg, index, _, _, _, {
+                match PREDICTORS.get(index) {
+                    Some(predictor) => gam.set_predictor(*predictor).expect("couldn't set the IME predictor"),
+                    None => log::error!("no predictor at menu index {}", index),
+                }
+            }),
+            Some(StatusOpcode::ClearInputHistory) => {
+                gam.clear_all_input_history().expect("couldn't clear the IME input history");
+            },
+            Some(StatusOpcode::SetInputHistoryLength) => {
+                thread::spawn(ask_history_length);
+            },
             Some(StatusOpcode::SwitchToShellchat) => {
                 ticktimer.sleep_ms(100).ok();
                 sec_notes.lock().unwrap().remove(&"current_app".to_string());
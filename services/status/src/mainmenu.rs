@@ -106,6 +106,15 @@ pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, status_conn: xous::CID, com:
         close_on_select: true,
     });
 
+    #[cfg(feature="tts")]
+    menuitems.push(MenuItem {
+        name: String::from_str(t!("mainmenu.screen_reader", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::ToggleScreenReader.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+
     menuitems.push(MenuItem {
         name: String::from_str(t!("mainmenu.kbd", xous::LANG)),
         action_conn: Some(status_conn),
@@ -113,6 +122,20 @@ pub fn create_main_menu(keys: Arc<Mutex<RootKeys>>, status_conn: xous::CID, com:
         action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
         close_on_select: true,
     });
+    menuitems.push(MenuItem {
+        name: String::from_str(t!("mainmenu.ime", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SubmenuIme.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
+    menuitems.push(MenuItem {
+        name: String::from_str(t!("mainmenu.wifi", xous::LANG)),
+        action_conn: Some(status_conn),
+        action_opcode: StatusOpcode::SubmenuWifi.to_u32().unwrap(),
+        action_payload: MenuPayload::Scalar([0, 0, 0, 0]),
+        close_on_select: true,
+    });
     menuitems.push(MenuItem {
         name: String::from_str(t!("mainmenu.battery_disconnect", xous::LANG)),
         action_conn: Some(status_conn),
@@ -0,0 +1,97 @@
+//! Persists the text to speech settings in the PDDB.
+//!
+//! This can't live in the `tts` service itself, because the PDDB depends on it for spoken prompts.
+//! Instead, this thread restores the settings into the TTS server once the PDDB is mounted, and then
+//! writes them back every time the TTS server reports a change.
+
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::thread;
+use num_traits::*;
+use pddb::Pddb;
+use tts_frontend::*;
+
+/// Dictionary for TTS settings.
+const TTS_PREFS_DICT: &'static str = "sys.tts";
+const TTS_PREFS_KEY: &'static str = "prefs";
+/// Bumped whenever the layout written by `encode()` changes.
+const TTS_PREFS_VERSION: u8 = 1;
+const TTS_PREFS_LEN: usize = 17;
+
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
+enum TtsPrefsOp {
+    Changed,
+}
+
+fn encode(prefs: &TtsPrefs) -> [u8; TTS_PREFS_LEN] {
+    let mut buf = [0u8; TTS_PREFS_LEN];
+    buf[0] = TTS_PREFS_VERSION;
+    buf[1..5].copy_from_slice(&prefs.wpm.to_le_bytes());
+    buf[5..9].copy_from_slice(&prefs.pitch.to_le_bytes());
+    buf[9..13].copy_from_slice(&prefs.volume.to_le_bytes());
+    buf[13] = prefs.voice.language.to_u8().unwrap();
+    buf[14] = prefs.voice.kind.to_u8().unwrap();
+    buf[15] = prefs.punctuation.to_u8().unwrap();
+    buf[16] = if prefs.spell_out { 1 } else { 0 };
+    buf
+}
+
+fn decode(buf: &[u8]) -> Option<TtsPrefs> {
+    if buf.len() != TTS_PREFS_LEN || buf[0] != TTS_PREFS_VERSION {
+        return None;
+    }
+    Some(TtsPrefs {
+        wpm: u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]),
+        pitch: u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]),
+        volume: u32::from_le_bytes([buf[9], buf[10], buf[11], buf[12]]),
+        voice: Voice {
+            language: FromPrimitive::from_u8(buf[13])?,
+            kind: FromPrimitive::from_u8(buf[14])?,
+        },
+        punctuation: FromPrimitive::from_u8(buf[15])?,
+        spell_out: buf[16] != 0,
+    })
+}
+
+pub(crate) fn start_tts_prefs() {
+    thread::spawn({
+        move || {
+            let xns = xous_names::XousNames::new().unwrap();
+            let tts = TtsFrontend::new(&xns).unwrap();
+            let mut pddb = Pddb::new();
+            pddb.is_mounted_blocking();
+
+            if let Ok(mut key) = pddb.get(TTS_PREFS_DICT, TTS_PREFS_KEY, None, false, false, None, None::<fn()>) {
+                let mut buf = Vec::new();
+                key.read_to_end(&mut buf).ok();
+                if let Some(prefs) = decode(&buf) {
+                    log::debug!("restoring tts prefs: {:?}", prefs);
+                    tts.set_prefs(prefs).unwrap();
+                } else {
+                    log::warn!("tts prefs in the PDDB are unreadable, using defaults");
+                }
+            }
+
+            let sid = xous::create_server().unwrap();
+            tts.hook_prefs_changed(sid, TtsPrefsOp::Changed.to_u32().unwrap()).unwrap();
+            loop {
+                let msg = xous::receive_message(sid).unwrap();
+                match FromPrimitive::from_usize(msg.body.id()) {
+                    Some(TtsPrefsOp::Changed) => {
+                        let prefs = tts.get_prefs().unwrap();
+                        match pddb.get(TTS_PREFS_DICT, TTS_PREFS_KEY, None, true, true, Some(TTS_PREFS_LEN), None::<fn()>) {
+                            Ok(mut key) => {
+                                key.seek(SeekFrom::Start(0)).ok();
+                                if key.write_all(&encode(&prefs)).is_err() {
+                                    log::error!("couldn't save tts prefs");
+                                }
+                            }
+                            Err(e) => log::error!("couldn't open the tts prefs key: {:?}", e),
+                        }
+                        pddb.sync().ok();
+                    }
+                    None => log::error!("couldn't convert opcode: {:?}", msg),
+                }
+            }
+        }
+    });
+}
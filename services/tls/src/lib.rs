@@ -0,0 +1,315 @@
+//! TLS 1.3 client for Xous applications, layered on the libstd `TcpStream` that the `net` service
+//! provides.
+//!
+//! The trusted root certificates live in the PDDB, so every application shares one store that can
+//! be curated on the device. Hosts can additionally be pinned to the public key of their leaf
+//! certificate, either on top of the normal chain validation or in place of it for self-signed
+//! servers. The crypto is `ring`'s; on Xous its randomness comes from the TRNG server, see
+//! `random.rs`.
+//!
+//! ```ignore
+//! let mut tls = tls::Tls::new();
+//! let mut stream = tls.connect("example.com", 443)?;
+//! stream.write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n")?;
+//! ```
+
+#[cfg(not(any(windows, unix)))]
+mod random;
+mod verifier;
+pub use verifier::{spki_pin, HostPins, Pin, PinMode};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use rustls::{Certificate, ClientConfig, ClientConnection, RootCertStore, ServerName};
+
+/// Dictionary holding the trusted root certificates: key = a name for the certificate,
+/// value = the DER-encoded certificate.
+pub const TLS_TRUSTED_DICT: &str = "tls.trusted";
+/// Dictionary holding certificate pins: key = host name or IP address, value = `HostPins::to_bytes()`.
+pub const TLS_PINS_DICT: &str = "tls.pins";
+
+/// An established TLS connection. It implements `Read` and `Write` like the `TcpStream` it wraps.
+pub type TlsStream = rustls::StreamOwned<ClientConnection, TcpStream>;
+
+pub struct Tls {
+    pddb: pddb::Pddb,
+}
+impl Tls {
+    pub fn new() -> Self {
+        let pddb = pddb::Pddb::new();
+        pddb.is_mounted_blocking();
+        Tls { pddb }
+    }
+
+    /// Adds `der` to the trusted roots under `name`, replacing any certificate already stored
+    /// under that name. The certificate is checked to be usable as a trust anchor first.
+    pub fn trust(&mut self, name: &str, der: &[u8]) -> Result<()> {
+        RootCertStore::empty()
+            .add(&Certificate(der.to_vec()))
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "not a usable root certificate"))?;
+        self.store(TLS_TRUSTED_DICT, name, der)
+    }
+    pub fn untrust(&mut self, name: &str) -> Result<()> {
+        self.pddb.delete_key(TLS_TRUSTED_DICT, name, None)?;
+        self.pddb.sync()
+    }
+    /// Names of the trusted root certificates.
+    pub fn trusted(&mut self) -> Result<Vec<String>> {
+        self.list(TLS_TRUSTED_DICT)
+    }
+    /// Builds a root store from the PDDB. Certificates that no longer parse are skipped with a
+    /// warning rather than failing every connection.
+    pub fn root_store(&mut self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        for name in self.trusted()? {
+            let der = self.load(TLS_TRUSTED_DICT, &name)?;
+            if let Err(e) = roots.add(&Certificate(der)) {
+                log::warn!("skipping trusted certificate {}: {:?}", name, e);
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Pins `host` to the given pins, replacing any it had.
+    pub fn pin(&mut self, host: &str, pins: &HostPins) -> Result<()> {
+        if pins.pins.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "at least one pin is needed",
+            ));
+        }
+        self.store(TLS_PINS_DICT, host, &pins.to_bytes())
+    }
+    pub fn unpin(&mut self, host: &str) -> Result<()> {
+        self.pddb.delete_key(TLS_PINS_DICT, host, None)?;
+        self.pddb.sync()
+    }
+    /// The pins for `host`, if it has any.
+    pub fn pins(&mut self, host: &str) -> Result<Option<HostPins>> {
+        match self.load(TLS_PINS_DICT, host) {
+            Ok(data) => HostPins::from_bytes(&data)
+                .map(Some)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "corrupted pin record")),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    /// Every pinned host with its pins.
+    pub fn all_pins(&mut self) -> Result<HashMap<String, HostPins>> {
+        let mut ret = HashMap::new();
+        for host in self.list(TLS_PINS_DICT)? {
+            if let Some(pins) = self.pins(&host)? {
+                ret.insert(host, pins);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// A client configuration using the PDDB's roots and pins, as they are right now.
+    pub fn client_config(&mut self) -> Result<Arc<ClientConfig>> {
+        let roots = self.root_store()?;
+        let pins = self.all_pins()?;
+        Ok(client_config(roots, pins))
+    }
+    /// Opens a TCP connection to `host` and completes a TLS handshake over it.
+    pub fn connect(&mut self, host: &str, port: u16) -> Result<TlsStream> {
+        let config = self.client_config()?;
+        let sock = TcpStream::connect((host, port))?;
+        connect_stream(config, host, sock)
+    }
+
+    fn store(&mut self, dict: &str, name: &str, data: &[u8]) -> Result<()> {
+        // a replacement can be shorter than what it replaces, so start from an empty key
+        match self.pddb.delete_key(dict, name, None) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let mut key =
+            self.pddb
+                .get(dict, name, None, true, true, Some(data.len()), None::<fn()>)?;
+        key.write_all(data)?;
+        key.flush()?;
+        self.pddb.sync()
+    }
+    fn load(&mut self, dict: &str, name: &str) -> Result<Vec<u8>> {
+        let mut key = self
+            .pddb
+            .get(dict, name, None, false, false, None, None::<fn()>)?;
+        let mut data = Vec::new();
+        key.read_to_end(&mut data)?;
+        Ok(data)
+    }
+    fn list(&mut self, dict: &str) -> Result<Vec<String>> {
+        match self.pddb.list_keys(dict, None) {
+            Ok(list) => Ok(list),
+            // nothing has been stored yet
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Builds a TLS 1.3-only client configuration, verifying servers against `roots` and the
+/// per-host `pins`.
+pub fn client_config(roots: RootCertStore, pins: HashMap<String, HostPins>) -> Arc<ClientConfig> {
+    let verifier = verifier::PinningVerifier::new(roots, pins);
+    let config = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .expect("rustls supports TLS 1.3")
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// Runs a TLS handshake for `server_name` over an already-connected socket. The handshake is
+/// completed before returning, so certificate problems are reported here rather than on the
+/// first read or write.
+pub fn connect_stream(
+    config: Arc<ClientConfig>,
+    server_name: &str,
+    mut sock: TcpStream,
+) -> Result<TlsStream> {
+    let name = ServerName::try_from(server_name)
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid server name"))?;
+    let mut conn =
+        ClientConnection::new(config, name).map_err(|e| Error::new(ErrorKind::Other, e))?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut sock)?;
+    }
+    Ok(rustls::StreamOwned::new(conn, sock))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{PrivateKey, ServerConfig, ServerConnection};
+    use std::net::TcpListener;
+
+    // a test CA, a P-256 leaf for "localhost" that it issued with the leaf's PKCS#8 key, and an
+    // unrelated CA. The certificates are valid until 2126.
+    const CA: &[u8] = include_bytes!("../testdata/ca.der");
+    const OTHER_CA: &[u8] = include_bytes!("../testdata/other-ca.der");
+    const LEAF: &[u8] = include_bytes!("../testdata/localhost.der");
+    const LEAF_KEY: &[u8] = include_bytes!("../testdata/localhost.key");
+    // from `openssl x509 -pubkey -noout -in localhost.pem | openssl pkey -pubin -outform der | openssl dgst -sha256`
+    const LEAF_PIN: Pin = [
+        0x01, 0xb9, 0x65, 0xff, 0xea, 0x20, 0xc5, 0x81, 0x72, 0x08, 0xbf, 0x37, 0x90, 0x3f, 0x7a,
+        0x65, 0x68, 0xd0, 0x36, 0xb7, 0xbf, 0x1a, 0x76, 0x78, 0x73, 0x0a, 0xc9, 0x23, 0xc0, 0xee,
+        0xda, 0xd8,
+    ];
+
+    /// Starts a one-shot TLS server on the loopback that answers "ping" with "pong". Returns
+    /// the port it listens on.
+    fn serve_once() -> u16 {
+        let config = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(LEAF.to_vec())],
+                PrivateKey(LEAF_KEY.to_vec()),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(config).unwrap();
+            let mut stream = rustls::StreamOwned::new(conn, sock);
+            let mut buf = [0u8; 4];
+            // a client that rejects the certificate hangs up mid-handshake
+            if stream.read_exact(&mut buf).is_ok() && &buf == b"ping" {
+                stream.write_all(b"pong").ok();
+                stream.flush().ok();
+            }
+        });
+        port
+    }
+
+    fn roots(certs: &[&[u8]]) -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        for &der in certs {
+            roots.add(&Certificate(der.to_vec())).unwrap();
+        }
+        roots
+    }
+    fn ping(config: Arc<ClientConfig>) -> Result<()> {
+        let port = serve_once();
+        let sock = TcpStream::connect(("127.0.0.1", port))?;
+        let mut stream = connect_stream(config, "localhost", sock)?;
+        stream.write_all(b"ping")?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"pong");
+        Ok(())
+    }
+
+    #[test]
+    fn test_spki_pin() {
+        assert_eq!(spki_pin(LEAF), Some(LEAF_PIN));
+        assert_eq!(spki_pin(&LEAF[..100]), None);
+        let pins = HostPins {
+            mode: PinMode::Exclusive,
+            pins: vec![LEAF_PIN, [7; 32]],
+        };
+        assert_eq!(HostPins::from_bytes(&pins.to_bytes()), Some(pins));
+        assert_eq!(HostPins::from_bytes(&[0, 1, 2]), None);
+    }
+    #[test]
+    fn test_trusted_handshake() {
+        ping(client_config(roots(&[CA]), HashMap::new())).unwrap();
+    }
+    #[test]
+    fn test_untrusted_handshake() {
+        assert!(ping(client_config(roots(&[OTHER_CA]), HashMap::new())).is_err());
+        assert!(ping(client_config(RootCertStore::empty(), HashMap::new())).is_err());
+    }
+    #[test]
+    fn test_pinned_handshake() {
+        let mut pins = HashMap::new();
+        pins.insert(
+            "localhost".to_string(),
+            HostPins {
+                mode: PinMode::Additional,
+                pins: vec![LEAF_PIN],
+            },
+        );
+        ping(client_config(roots(&[CA]), pins.clone())).unwrap();
+        // an additional pin doesn't excuse an untrusted chain
+        assert!(ping(client_config(roots(&[OTHER_CA]), pins)).is_err());
+    }
+    #[test]
+    fn test_pin_mismatch() {
+        let mut pins = HashMap::new();
+        pins.insert(
+            "localhost".to_string(),
+            HostPins {
+                mode: PinMode::Additional,
+                pins: vec![[0x55; 32]],
+            },
+        );
+        assert!(ping(client_config(roots(&[CA]), pins)).is_err());
+    }
+    #[test]
+    fn test_exclusive_pin() {
+        let mut pins = HashMap::new();
+        pins.insert(
+            "localhost".to_string(),
+            HostPins {
+                mode: PinMode::Exclusive,
+                pins: vec![LEAF_PIN],
+            },
+        );
+        ping(client_config(RootCertStore::empty(), pins)).unwrap();
+    }
+}
@@ -0,0 +1,18 @@
+//! `ring` gets its randomness from `getrandom`, which doesn't know about Xous, so it's pointed at
+//! the TRNG server here. The connection is made the first time randomness is needed.
+
+use rand_core::RngCore;
+use std::sync::Mutex;
+
+static TRNG: Mutex<Option<trng::Trng>> = Mutex::new(None);
+
+fn trng_getrandom(buf: &mut [u8]) -> Result<(), getrandom::Error> {
+    let mut trng = TRNG.lock().map_err(|_| getrandom::Error::UNSUPPORTED)?;
+    if trng.is_none() {
+        let xns = xous_names::XousNames::new().map_err(|_| getrandom::Error::UNSUPPORTED)?;
+        *trng = Some(trng::Trng::new(&xns).map_err(|_| getrandom::Error::UNSUPPORTED)?);
+    }
+    trng.as_mut().unwrap().fill_bytes(buf);
+    Ok(())
+}
+getrandom::register_custom_getrandom!(trng_getrandom);
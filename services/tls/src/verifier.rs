@@ -0,0 +1,198 @@
+//! Server certificate verification: the usual webpki chain check against the PDDB root store,
+//! plus optional per-host pins on the leaf certificate's public key.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, Error, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+
+/// A pin is the SHA-256 of the leaf certificate's DER-encoded SubjectPublicKeyInfo, the same
+/// value as an HPKP `pin-sha256`. It can be computed off-device with
+/// `openssl x509 -pubkey -noout -in cert.pem | openssl pkey -pubin -outform der | openssl dgst -sha256`
+pub type Pin = [u8; 32];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PinMode {
+    /// The chain must validate against the trusted roots *and* the leaf must match a pin.
+    Additional,
+    /// A leaf matching a pin is accepted on its own, without consulting the root store or
+    /// checking the name. This is for servers with self-signed certificates, such as devices on
+    /// the local network.
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HostPins {
+    pub mode: PinMode,
+    pub pins: Vec<Pin>,
+}
+impl HostPins {
+    /// Serialized form, as stored in the PDDB: one mode byte, then the pins back to back.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(1 + self.pins.len() * 32);
+        ret.push(match self.mode {
+            PinMode::Additional => 0,
+            PinMode::Exclusive => 1,
+        });
+        for pin in self.pins.iter() {
+            ret.extend_from_slice(pin);
+        }
+        ret
+    }
+    pub fn from_bytes(data: &[u8]) -> Option<HostPins> {
+        let (&mode, pins) = data.split_first()?;
+        let mode = match mode {
+            0 => PinMode::Additional,
+            1 => PinMode::Exclusive,
+            _ => return None,
+        };
+        if pins.len() % 32 != 0 {
+            return None;
+        }
+        let pins = pins
+            .chunks_exact(32)
+            .map(|c| {
+                let mut pin = [0u8; 32];
+                pin.copy_from_slice(c);
+                pin
+            })
+            .collect();
+        Some(HostPins { mode, pins })
+    }
+    fn matches(&self, cert: &Certificate) -> bool {
+        match spki_pin(&cert.0) {
+            Some(pin) => self.pins.contains(&pin),
+            None => false,
+        }
+    }
+}
+
+/// Computes the pin of a DER-encoded certificate, or `None` if the certificate can't be parsed.
+pub fn spki_pin(cert: &[u8]) -> Option<Pin> {
+    let spki = spki_of(cert)?;
+    let mut pin = [0u8; 32];
+    pin.copy_from_slice(&Sha256::digest(spki));
+    Some(pin)
+}
+
+/// One DER TLV: (tag, whole element, contents, what follows it).
+type Tlv<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+/// Splits one DER TLV off the front of `input`.
+fn der_next(input: &[u8]) -> Option<Tlv<'_>> {
+    let tag = *input.first()?;
+    let first = *input.get(1)? as usize;
+    let (len, hdr) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let mut len = 0usize;
+        for &b in input.get(2..2 + n)? {
+            len = (len << 8) | b as usize;
+        }
+        (len, 2 + n)
+    };
+    let end = hdr.checked_add(len)?;
+    if end > input.len() {
+        return None;
+    }
+    Some((tag, &input[..end], &input[hdr..end], &input[end..]))
+}
+
+/// Finds the SubjectPublicKeyInfo in a DER certificate. This only walks far enough to find it;
+/// the certificate has been (or will be) fully parsed by webpki.
+fn spki_of(cert: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    let (tag, _, cert, _) = der_next(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    let (tag, _, tbs, _) = der_next(cert)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    // optional [0] version, then serial, signature, issuer, validity, subject
+    let (tag, _, _, mut rest) = der_next(tbs)?;
+    if tag != 0xa0 {
+        rest = tbs;
+    }
+    for _ in 0..5 {
+        let (_, _, _, next) = der_next(rest)?;
+        rest = next;
+    }
+    let (tag, spki, _, _) = der_next(rest)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+    Some(spki)
+}
+
+/// The verifier installed in every client config this crate builds.
+pub(crate) struct PinningVerifier {
+    webpki: WebPkiVerifier,
+    pins: HashMap<String, HostPins>,
+}
+
+impl PinningVerifier {
+    /// With an empty `roots`, only `Exclusive` pins can succeed.
+    pub(crate) fn new(roots: RootCertStore, pins: HashMap<String, HostPins>) -> Self {
+        PinningVerifier {
+            webpki: WebPkiVerifier::new(roots, None),
+            pins,
+        }
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => ip.to_string(),
+            _ => String::new(),
+        };
+        let pins = match self.pins.get(&host) {
+            Some(pins) => pins,
+            None => {
+                return self.webpki.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    scts,
+                    ocsp_response,
+                    now,
+                )
+            }
+        };
+        if pins.mode == PinMode::Additional {
+            self.webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if pins.matches(end_entity) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            log::warn!("certificate for {} does not match its pins", host);
+            Err(Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+}
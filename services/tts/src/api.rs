@@ -12,9 +12,111 @@ pub(crate) enum Opcode {
     SetWordsPerMinute,
     /// Exits the server
     Quit,
+    /// Set the pitch, as a percentage of the voice's natural pitch
+    SetPitch,
+    /// Set the speech volume, as a percentage
+    SetVolume,
+    /// Select the voice and language
+    SetVoice,
+    /// Set how much punctuation is read out
+    SetPunctuation,
+    /// Turns character-by-character reading on or off
+    SetSpellOut,
+    /// Returns all of the settings in a `TtsPrefs`
+    GetPrefs,
+    /// Applies all of the settings in a `TtsPrefs` at once, e.g. when restoring them from the PDDB
+    SetPrefs,
+    /// Registers a server to be notified whenever a setting changes, so it can persist them
+    HookPrefsChanged,
 }
 
 #[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
 pub struct TtsFrontendMsg {
     pub text: xous_ipc::String::<2048>,
-}
\ No newline at end of file
+}
+
+pub const DEFAULT_WPM: u32 = 350;
+pub const PITCH_MIN: u32 = 50;
+pub const PITCH_MAX: u32 = 200;
+pub const DEFAULT_PITCH: u32 = 100;
+pub const DEFAULT_VOLUME: u32 = 100;
+
+/// The synthesizer backend has an English lexicon only; the language mainly selects the words used
+/// to read out punctuation and spelled characters.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum Language {
+    English,
+}
+
+/// Voices are variations of the backend's single voice model, shifted in pitch.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum VoiceKind {
+    Standard,
+    Low,
+    High,
+}
+impl VoiceKind {
+    /// the base pitch of the voice as a percentage, which the user's pitch setting is applied on top of
+    pub fn base_pitch(&self) -> u32 {
+        match self {
+            VoiceKind::Standard => 100,
+            VoiceKind::Low => 85,
+            VoiceKind::High => 130,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct Voice {
+    pub language: Language,
+    pub kind: VoiceKind,
+}
+impl Default for Voice {
+    fn default() -> Self {
+        Voice { language: Language::English, kind: VoiceKind::Standard }
+    }
+}
+
+/// How much punctuation is read out loud.
+#[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub enum Punctuation {
+    /// punctuation only shapes the phrasing of the speech, as it would when read by a person
+    None,
+    /// symbols that carry meaning are read out, e.g. `@`, `#` or `/`, but not commas and periods
+    Some,
+    /// every punctuation character is read out
+    All,
+}
+
+/// The complete set of user-adjustable speech settings.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub struct TtsPrefs {
+    pub wpm: u32,
+    /// percentage of the voice's base pitch, from `PITCH_MIN` to `PITCH_MAX`
+    pub pitch: u32,
+    /// percentage, from 0 to 100
+    pub volume: u32,
+    pub voice: Voice,
+    pub punctuation: Punctuation,
+    /// when set, text is read character-by-character, e.g. for reviewing passwords and codes
+    pub spell_out: bool,
+}
+impl Default for TtsPrefs {
+    fn default() -> Self {
+        TtsPrefs {
+            wpm: DEFAULT_WPM,
+            pitch: DEFAULT_PITCH,
+            volume: DEFAULT_VOLUME,
+            voice: Voice::default(),
+            // the synthesizer has always just paused at punctuation; reading it out is opt-in
+            punctuation: Punctuation::None,
+            spell_out: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+pub(crate) struct PrefsHook {
+    pub sid: [u32; 4],
+    pub id: u32,
+}
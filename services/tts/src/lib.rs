@@ -35,11 +35,68 @@ impl TtsFrontend {
         let buf = Buffer::into_buf(msg).or(Err(xous::Error::InternalError))?;
         buf.lend(self.conn, Opcode::TextToSpeechBlocking.to_u32().unwrap()).map(|_| ())
     }
+    /// Stops any speech that is playing right away.
+    pub fn stop(&self) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::CodecStop.to_usize().unwrap(), 0, 0, 0, 0)
+        ).map(|_| ())
+    }
     pub fn set_words_per_minute(&self, wpm: u32) -> Result<(), xous::Error> {
         send_message(self.conn,
             Message::new_scalar(Opcode::SetWordsPerMinute.to_usize().unwrap(), wpm as usize, 0, 0, 0)
         ).map(|_| ())
     }
+    /// Sets the pitch as a percentage of the voice's base pitch; it is clamped to `PITCH_MIN`..=`PITCH_MAX`.
+    /// The speaking rate is unaffected.
+    pub fn set_pitch(&self, pitch: u32) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetPitch.to_usize().unwrap(), pitch as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// Sets the volume of speech relative to other audio, as a percentage.
+    pub fn set_volume(&self, volume: u32) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetVolume.to_usize().unwrap(), volume as usize, 0, 0, 0)
+        ).map(|_| ())
+    }
+    pub fn set_voice(&self, voice: Voice) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetVoice.to_usize().unwrap(),
+                voice.language.to_usize().unwrap(), voice.kind.to_usize().unwrap(), 0, 0)
+        ).map(|_| ())
+    }
+    pub fn set_punctuation(&self, punctuation: Punctuation) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetPunctuation.to_usize().unwrap(), punctuation.to_usize().unwrap(), 0, 0, 0)
+        ).map(|_| ())
+    }
+    /// When enabled, all text is read out character-by-character, including spaces and capitals.
+    /// This is meant for reviewing passwords and codes, and should be turned off again afterwards.
+    pub fn set_spell_out(&self, enable: bool) -> Result<(), xous::Error> {
+        send_message(self.conn,
+            Message::new_scalar(Opcode::SetSpellOut.to_usize().unwrap(), if enable { 1 } else { 0 }, 0, 0, 0)
+        ).map(|_| ())
+    }
+    pub fn get_prefs(&self) -> Result<TtsPrefs, xous::Error> {
+        let mut buf = Buffer::into_buf(TtsPrefs::default()).or(Err(xous::Error::InternalError))?;
+        buf.lend_mut(self.conn, Opcode::GetPrefs.to_u32().unwrap()).or(Err(xous::Error::InternalError))?;
+        Ok(buf.to_original::<TtsPrefs, _>().unwrap())
+    }
+    /// Replaces all of the settings at once. Out-of-range values are clamped.
+    pub fn set_prefs(&self, prefs: TtsPrefs) -> Result<(), xous::Error> {
+        let buf = Buffer::into_buf(prefs).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::SetPrefs.to_u32().unwrap()).map(|_| ())
+    }
+    /// Asks for a scalar message with the given `id` to be sent to `sid` whenever a setting changes.
+    /// Only one such hook is kept; it is used by the status service to persist the settings in the PDDB.
+    pub fn hook_prefs_changed(&self, sid: xous::SID, id: u32) -> Result<(), xous::Error> {
+        let hook = PrefsHook {
+            sid: sid.to_array(),
+            id,
+        };
+        let buf = Buffer::into_buf(hook).or(Err(xous::Error::InternalError))?;
+        buf.lend(self.conn, Opcode::HookPrefsChanged.to_u32().unwrap()).map(|_| ())
+    }
 }
 
 use core::sync::atomic::{AtomicU32, Ordering};
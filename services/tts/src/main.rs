@@ -3,19 +3,24 @@
 
 mod api;
 use api::*;
+mod prosody;
+use prosody::*;
+mod verbalize;
+use verbalize::*;
 
 use xous_ipc::Buffer;
 use xous::{msg_scalar_unpack, Message, send_message};
 use num_traits::*;
-use codec::{ZERO_PCM, VolumeOps, FrameRing};
+use codec::{ZERO_PCM, VolumeOps, FrameRing, StreamPriority, SampleRate};
 use xous_tts_backend::*;
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::collections::VecDeque;
 
-const DEFAULT_WPM: u32 = 350;
 const WAIT_INTERVAL: usize = 50; // milliseconds to wait before polling if a phrase is finished.
-const MAX_BUF_DEPTH: usize = (8000 * core::mem::size_of::<u16>()) * 3; // 8000 samples/s * num seconds to buffer
+/// the rate the synthesizer renders at; the codec resamples it if the hardware is running at another rate
+const TTS_SAMPLE_RATE: SampleRate = SampleRate::Fs8000;
+const MAX_BUF_DEPTH: usize = (TTS_SAMPLE_RATE.hz() as usize * core::mem::size_of::<u16>()) * 3; // samples/s * num seconds to buffer
 const DRAIN_INTERVAL: usize = 100; // milliseconds to wait before checking if buffer has drained
 
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
@@ -26,6 +31,9 @@ pub(crate) enum WaveOp {
 #[derive(num_derive::FromPrimitive, num_derive::ToPrimitive, Debug)]
 pub(crate) enum CallbackOp {
     Callback,
+    Abort,
+    /// set the mixer volume of the speech stream
+    SetVolume,
     Quit,
 }
 
@@ -48,9 +56,11 @@ fn xmain() -> ! {
     let wav_cid = xous::connect(wav_sid).unwrap();
     let wavbuf = Arc::new(Mutex::new(VecDeque::<u16>::new()));
     let synth_done = Arc::new(AtomicBool::new(false));
+    let prosody = Arc::new(Mutex::new(Prosody::new()));
     std::thread::spawn({
         let wav_sid = wav_sid.clone();
         let wavbuf = wavbuf.clone();
+        let prosody = prosody.clone();
         // let tts_cid = tts_cid.clone();
         let synth_done = synth_done.clone();
         move || {
@@ -73,9 +83,7 @@ fn xmain() -> ! {
                         let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
                         let wavdat = buffer.to_original::<TtsBackendData, _>().unwrap();
                         let mut buf = wavbuf.lock().unwrap();
-                        for &d in wavdat.data[..wavdat.len as usize].iter() {
-                            buf.push_back(d);
-                        }
+                        prosody.lock().unwrap().process(&wavdat.data[..wavdat.len as usize], &mut buf);
                         match wavdat.control {
                             Some(TtsBeControl::End) => {
                                 // the buffer can still be quite full at this point, we have to wait until it drains naturally
@@ -114,6 +122,10 @@ fn xmain() -> ! {
         let just_initiated = just_initiated.clone();
         move || {
             let mut codec = codec::Codec::new(&xous_names::XousNames::new().unwrap()).unwrap();
+            // speech gets its own stream in the mixer, so it plays over (and ducks) any other audio
+            codec.register_stream(StreamPriority::Speech, 100).expect("couldn't register a speech stream");
+            codec.setup_stream(TTS_SAMPLE_RATE).expect("couldn't set the speech stream rate");
+            codec.hook_frame_callback(CallbackOp::Callback.to_u32().unwrap(), cb_cid).unwrap();
             let mut frame_count = 0;
             loop {
                 let msg = xous::receive_message(cb_sid).unwrap();
@@ -164,6 +176,14 @@ fn xmain() -> ! {
                             }
                         }
                     }),
+                    Some(CallbackOp::Abort) => {
+                        // the speech stream is owned by this thread's codec, so the abort has to be issued from here
+                        wavbuf.lock().unwrap().clear();
+                        codec.abort().unwrap();
+                    },
+                    Some(CallbackOp::SetVolume) => msg_scalar_unpack!(msg, volume, _, _, _, {
+                        codec.set_stream_volume(volume as u8).expect("couldn't set the speech stream volume");
+                    }),
                     Some(CallbackOp::Quit) => {
                         xous::return_scalar(msg.sender, 1).unwrap();
                         break;
@@ -176,19 +196,15 @@ fn xmain() -> ! {
         }
     });
     let mut codec = codec::Codec::new(&xns).unwrap();
-    codec.setup_8k_stream().expect("couldn't setup stream");
+    codec.setup_stream(TTS_SAMPLE_RATE).expect("couldn't setup stream");
     tt.sleep_ms(50).unwrap();
     codec.set_speaker_volume(VolumeOps::Set, Some(0.0)).unwrap();
     codec.set_headphone_volume(VolumeOps::RestoreDefault, None).unwrap();
-    codec.hook_frame_callback(CallbackOp::Callback.to_u32().unwrap(), cb_cid).unwrap();
 
-    let mut wpm = DEFAULT_WPM;
-    tts_be.tts_config(
-        wav_sid.to_array(),
-        WaveOp::Return.to_u32().unwrap(),
-        None,
-        Some(wpm)
-    ).unwrap();
+    let mut prefs = TtsPrefs::default();
+    apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+    // where to report changes of the settings, so they can be persisted
+    let mut prefs_hook: Option<(xous::CID, u32)> = None;
     loop {
         let msg = xous::receive_message(tts_sid).unwrap();
         match FromPrimitive::from_usize(msg.body.id()) {
@@ -197,8 +213,9 @@ fn xmain() -> ! {
                 let msg = buffer.to_original::<TtsFrontendMsg, _>().unwrap();
                 log::debug!("tts front end got string {}", msg.text.as_str().unwrap());
                 wavbuf.lock().unwrap().clear(); // this will truncate any buffered audio that is playing
+                prosody.lock().unwrap().reset();
                 synth_done.store(false, Ordering::SeqCst);
-                tts_be.tts_simple(msg.text.as_str().unwrap()).unwrap();
+                tts_be.tts_simple(&verbalize(msg.text.as_str().unwrap(), prefs.voice.language, prefs.punctuation, prefs.spell_out)).unwrap();
                 just_initiated.store(true, Ordering::SeqCst);
                 log::trace!("resuming codec");
                 codec.resume().unwrap();
@@ -208,8 +225,9 @@ fn xmain() -> ! {
                 let msg = buffer.to_original::<TtsFrontendMsg, _>().unwrap();
                 log::debug!("tts blocking front end got string {}", msg.text.as_str().unwrap());
                 wavbuf.lock().unwrap().clear(); // this will truncate any buffered audio that is playing
+                prosody.lock().unwrap().reset();
                 synth_done.store(false, Ordering::SeqCst);
-                tts_be.tts_simple(msg.text.as_str().unwrap()).unwrap();
+                tts_be.tts_simple(&verbalize(msg.text.as_str().unwrap(), prefs.voice.language, prefs.punctuation, prefs.spell_out)).unwrap();
                 just_initiated.store(true, Ordering::SeqCst);
                 log::trace!("resuming codec (blocking)");
                 codec.resume().unwrap();
@@ -224,12 +242,69 @@ fn xmain() -> ! {
             },
             Some(Opcode::CodecStop) => {
                 log::info!("stop called. Immediate stop and loss of audio data.");
-                codec.abort().unwrap();
+                send_message(cb_cid,
+                    Message::new_scalar(CallbackOp::Abort.to_usize().unwrap(), 0, 0, 0, 0)
+                ).expect("couldn't send abort to callback handler");
             }
             Some(Opcode::SetWordsPerMinute) => msg_scalar_unpack!(msg, wpm_arg, _, _, _, {
-                wpm = wpm_arg as u32;
-                tts_be.tts_config(wav_sid.to_array(), WaveOp::Return.to_u32().unwrap(), None, Some(wpm)).unwrap();
+                prefs.wpm = (wpm_arg as u32).max(1);
+                apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+                notify_prefs_changed(prefs_hook);
+            }),
+            Some(Opcode::SetPitch) => msg_scalar_unpack!(msg, pitch, _, _, _, {
+                prefs.pitch = (pitch as u32).max(PITCH_MIN).min(PITCH_MAX);
+                apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+                notify_prefs_changed(prefs_hook);
+            }),
+            Some(Opcode::SetVolume) => msg_scalar_unpack!(msg, volume, _, _, _, {
+                prefs.volume = (volume as u32).min(100);
+                apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+                notify_prefs_changed(prefs_hook);
+            }),
+            Some(Opcode::SetVoice) => msg_scalar_unpack!(msg, language, kind, _, _, {
+                match (FromPrimitive::from_usize(language), FromPrimitive::from_usize(kind)) {
+                    (Some(language), Some(kind)) => {
+                        prefs.voice = Voice { language, kind };
+                        apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+                        notify_prefs_changed(prefs_hook);
+                    }
+                    _ => log::warn!("unknown voice: {}/{}", language, kind),
+                }
+            }),
+            Some(Opcode::SetPunctuation) => msg_scalar_unpack!(msg, level, _, _, _, {
+                if let Some(level) = FromPrimitive::from_usize(level) {
+                    prefs.punctuation = level;
+                    notify_prefs_changed(prefs_hook);
+                } else {
+                    log::warn!("unknown punctuation level: {}", level);
+                }
+            }),
+            Some(Opcode::SetSpellOut) => msg_scalar_unpack!(msg, enable, _, _, _, {
+                prefs.spell_out = enable != 0;
+                notify_prefs_changed(prefs_hook);
             }),
+            Some(Opcode::GetPrefs) => {
+                let mut buffer = unsafe { Buffer::from_memory_message_mut(msg.body.memory_message_mut().unwrap()) };
+                buffer.replace(prefs).unwrap();
+            }
+            Some(Opcode::SetPrefs) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let mut new_prefs = buffer.to_original::<TtsPrefs, _>().unwrap();
+                new_prefs.wpm = new_prefs.wpm.max(1);
+                new_prefs.pitch = new_prefs.pitch.max(PITCH_MIN).min(PITCH_MAX);
+                new_prefs.volume = new_prefs.volume.min(100);
+                prefs = new_prefs;
+                apply_prefs(&prefs, &tts_be, &prosody, wav_sid, cb_cid);
+                notify_prefs_changed(prefs_hook);
+            }
+            Some(Opcode::HookPrefsChanged) => {
+                let buffer = unsafe { Buffer::from_memory_message(msg.body.memory_message().unwrap()) };
+                let hook = buffer.to_original::<PrefsHook, _>().unwrap();
+                if let Some((old_cid, _)) = prefs_hook.take() {
+                    unsafe{xous::disconnect(old_cid).ok()};
+                }
+                prefs_hook = Some((xous::connect(xous::SID::from_array(hook.sid)).unwrap(), hook.id));
+            }
             Some(Opcode::Quit) => {
                 send_message(wav_cid,
                     Message::new_blocking_scalar(WaveOp::Quit.to_usize().unwrap(), 0, 0, 0, 0)
@@ -247,6 +322,9 @@ fn xmain() -> ! {
             }
         }
     }
+    if let Some((cid, _)) = prefs_hook.take() {
+        unsafe{xous::disconnect(cid).ok()};
+    }
     // clean up our program
     log::trace!("main loop exit, destroying servers");
     xns.unregister_server(tts_sid).unwrap();
@@ -254,3 +332,26 @@ fn xmain() -> ! {
     log::trace!("quitting");
     xous::terminate_process(0)
 }
+
+/// Pushes the pitch, volume and rate settings out to the synthesizer, the `Prosody` filter, and the
+/// mixer. The volume is the speech stream's mixer volume, which is owned by the codec callback thread.
+fn apply_prefs(prefs: &TtsPrefs, tts_be: &TtsBackend, prosody: &Arc<Mutex<Prosody>>, wav_sid: xous::SID, cb_cid: xous::CID) {
+    let pitch = prefs.voice.kind.base_pitch() * prefs.pitch / 100;
+    prosody.lock().unwrap().set_pitch(pitch);
+    send_message(cb_cid,
+        Message::new_scalar(CallbackOp::SetVolume.to_usize().unwrap(), prefs.volume as usize, 0, 0, 0)
+    ).expect("couldn't set the speech volume");
+    tts_be.tts_config(
+        wav_sid.to_array(),
+        WaveOp::Return.to_u32().unwrap(),
+        None,
+        Some(backend_wpm(prefs.wpm, pitch))
+    ).unwrap();
+}
+
+fn notify_prefs_changed(hook: Option<(xous::CID, u32)>) {
+    if let Some((cid, id)) = hook {
+        // the listener may have gone away, which is not our problem
+        send_message(cid, Message::new_scalar(id as usize, 0, 0, 0, 0)).ok();
+    }
+}
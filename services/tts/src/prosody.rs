@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+
+/// Applies the pitch setting to the synthesizer's output.
+///
+/// The backend has no pitch control of its own, so pitch is shifted by resampling: playing the
+/// samples back faster raises the pitch, and also shortens the speech by the same factor. The
+/// speaking rate requested from the backend is scaled the other way (see `backend_wpm()`), so the
+/// two cancel out and only the pitch changes.
+pub(crate) struct Prosody {
+    /// input samples consumed per output sample, in 16.16 fixed point
+    step: u32,
+    /// position of the next output sample, measured from `prev`, in 16.16 fixed point
+    pos: u32,
+    /// the last input sample of the previous block, so interpolation is continuous across blocks
+    prev: i16,
+}
+
+impl Prosody {
+    pub(crate) fn new() -> Prosody {
+        Prosody {
+            step: 1 << 16,
+            pos: 0,
+            prev: 0,
+        }
+    }
+    /// `pitch` is a percentage of the natural pitch of the backend's voice
+    pub(crate) fn set_pitch(&mut self, pitch: u32) {
+        self.step = (pitch << 16) / 100;
+    }
+    /// forgets the interpolation history, for the start of a new utterance
+    pub(crate) fn reset(&mut self) {
+        self.pos = 0;
+        self.prev = 0;
+    }
+    /// Processes a block of samples from the backend and appends the result to `output`.
+    pub(crate) fn process(&mut self, input: &[u16], output: &mut VecDeque<u16>) {
+        if input.len() == 0 {
+            return;
+        }
+        if self.step == 1 << 16 {
+            // the common case needs no arithmetic
+            output.extend(input.iter());
+            self.prev = input[input.len() - 1] as i16;
+            return;
+        }
+        // the input is treated as the sequence [prev, input[0], .. input[n-1]]; an output sample
+        // at index `i` interpolates between element i and i+1 of that sequence.
+        let end = (input.len() as u64) << 16;
+        let mut pos = self.pos as u64;
+        while pos < end {
+            let index = (pos >> 16) as usize;
+            let frac = (pos & 0xFFFF) as i32;
+            let a = if index == 0 { self.prev as i32 } else { input[index - 1] as i16 as i32 };
+            let b = input[index] as i16 as i32;
+            let sample = a + (((b - a) * frac) >> 16);
+            output.push_back(sample as i16 as u16);
+            pos += self.step as u64;
+        }
+        self.pos = (pos - end) as u32;
+        self.prev = input[input.len() - 1] as i16;
+    }
+}
+
+/// The rate to request from the backend so speech comes out at `wpm` after a pitch shift of `pitch` percent.
+pub(crate) fn backend_wpm(wpm: u32, pitch: u32) -> u32 {
+    wpm * 100 / pitch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passthrough() {
+        let mut p = Prosody::new();
+        let input: Vec<u16> = (0..100).map(|i| (i * 300 - 15000) as i16 as u16).collect();
+        let mut out = VecDeque::new();
+        p.process(&input, &mut out);
+        assert_eq!(out.iter().copied().collect::<Vec<u16>>(), input);
+    }
+
+    #[test]
+    fn test_pitch_changes_length() {
+        let input = [1000i16 as u16; 400];
+        for &(pitch, expected) in [(200, 200), (50, 800), (125, 320)].iter() {
+            let mut p = Prosody::new();
+            p.set_pitch(pitch);
+            let mut out = VecDeque::new();
+            // in several blocks, to exercise the continuity across calls
+            for block in input.chunks(64) {
+                p.process(block, &mut out);
+            }
+            assert_eq!(out.len(), expected, "pitch {}", pitch);
+            // a DC input stays DC once past the interpolation from silence
+            assert!(out.iter().skip(2).all(|&s| s == 1000), "pitch {}", pitch);
+        }
+        assert_eq!(backend_wpm(350, 200), 175);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::api::*;
+
+/// The spoken name of a symbol, and the least punctuation setting at which it is read out.
+fn symbol_name(c: char, language: Language) -> Option<(&'static str, Punctuation)> {
+    match language {
+        Language::English => match c {
+            '.' => Some(("period", Punctuation::All)),
+            ',' => Some(("comma", Punctuation::All)),
+            ';' => Some(("semicolon", Punctuation::All)),
+            ':' => Some(("colon", Punctuation::All)),
+            '!' => Some(("exclamation mark", Punctuation::All)),
+            '?' => Some(("question mark", Punctuation::All)),
+            '\'' => Some(("apostrophe", Punctuation::All)),
+            '"' => Some(("quote", Punctuation::All)),
+            '(' => Some(("left paren", Punctuation::All)),
+            ')' => Some(("right paren", Punctuation::All)),
+            '-' => Some(("dash", Punctuation::All)),
+            '@' => Some(("at", Punctuation::Some)),
+            '#' => Some(("hash", Punctuation::Some)),
+            '$' => Some(("dollar", Punctuation::Some)),
+            '%' => Some(("percent", Punctuation::Some)),
+            '&' => Some(("and", Punctuation::Some)),
+            '*' => Some(("star", Punctuation::Some)),
+            '/' => Some(("slash", Punctuation::Some)),
+            '\\' => Some(("backslash", Punctuation::Some)),
+            '_' => Some(("underscore", Punctuation::Some)),
+            '+' => Some(("plus", Punctuation::Some)),
+            '=' => Some(("equals", Punctuation::Some)),
+            '<' => Some(("less than", Punctuation::Some)),
+            '>' => Some(("greater than", Punctuation::Some)),
+            '[' => Some(("left bracket", Punctuation::Some)),
+            ']' => Some(("right bracket", Punctuation::Some)),
+            '{' => Some(("left brace", Punctuation::Some)),
+            '}' => Some(("right brace", Punctuation::Some)),
+            '|' => Some(("bar", Punctuation::Some)),
+            '~' => Some(("tilde", Punctuation::Some)),
+            '^' => Some(("caret", Punctuation::Some)),
+            '`' => Some(("backtick", Punctuation::Some)),
+            _ => None,
+        }
+    }
+}
+
+fn capital(language: Language) -> &'static str {
+    match language {
+        Language::English => "capital",
+    }
+}
+
+fn space(language: Language) -> &'static str {
+    match language {
+        Language::English => "space",
+    }
+}
+
+/// Rewrites `text` into what the synthesizer should say, according to the punctuation and spell-out settings.
+pub(crate) fn verbalize(text: &str, language: Language, punctuation: Punctuation, spell_out: bool) -> String {
+    let mut said = String::with_capacity(text.len() * 2);
+    if spell_out {
+        // every character becomes a phrase of its own, so the synthesizer pauses between them
+        for c in text.chars() {
+            if said.len() > 0 {
+                said.push_str(", ");
+            }
+            if c.is_whitespace() {
+                said.push_str(space(language));
+            } else if let Some((name, _)) = symbol_name(c, language) {
+                said.push_str(name);
+            } else if c.is_uppercase() {
+                said.push_str(capital(language));
+                said.push(' ');
+                said.extend(c.to_lowercase());
+            } else {
+                said.push(c);
+            }
+        }
+        return said;
+    }
+    for c in text.chars() {
+        match symbol_name(c, language) {
+            Some((name, level)) if level <= punctuation => {
+                said.push(' ');
+                said.push_str(name);
+                if level == Punctuation::All {
+                    // keep sentence punctuation too, so it still shapes the phrasing
+                    said.push(c);
+                }
+                said.push(' ');
+            }
+            _ => said.push(c),
+        }
+    }
+    // tidy up the spaces introduced around symbol names
+    said.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punctuation_levels() {
+        let text = "Mail me@example.com, ok?";
+        assert_eq!(verbalize(text, Language::English, Punctuation::None, false), text);
+        assert_eq!(verbalize(text, Language::English, Punctuation::Some, false), "Mail me at example.com, ok?");
+        assert_eq!(verbalize(text, Language::English, Punctuation::All, false),
+            "Mail me at example period. com comma, ok question mark?");
+    }
+
+    #[test]
+    fn test_spell_out() {
+        assert_eq!(verbalize("aB3 #", Language::English, Punctuation::None, true),
+            "a, capital b, 3, space, hash");
+        assert_eq!(verbalize("", Language::English, Punctuation::All, true), "");
+    }
+}
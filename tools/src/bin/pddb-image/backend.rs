@@ -0,0 +1,39 @@
+// The parts of the PDDB backend that define the on-disk format are included straight from the
+// service, so this tool reads images with the same structures and the same page decoding the
+// PDDB uses. The parts that talk to the hardware are left behind; `image.rs` stands in for them
+// on a flat file.
+#![allow(dead_code)]
+
+/// size of a physical page. On hardware this is the SPINOR erase size.
+pub const PAGE_SIZE: usize = 4096;
+
+#[path = "../../../../services/pddb/src/backend/murmur3.rs"]
+mod murmur3;
+pub(crate) use murmur3::*;
+#[path = "../../../../services/pddb/src/backend/types.rs"]
+mod types;
+pub use types::*;
+#[path = "../../../../services/pddb/src/backend/layout.rs"]
+mod layout;
+pub use layout::*;
+#[path = "../../../../services/pddb/src/backend/fastspace.rs"]
+mod fastspace;
+pub use fastspace::*;
+#[path = "../../../../services/pddb/src/backend/pagetable.rs"]
+mod pagetable;
+pub use pagetable::*;
+#[path = "../../../../services/pddb/src/backend/key.rs"]
+mod key;
+pub use key::*;
+#[path = "../../../../services/pddb/src/backend/pagecodec.rs"]
+mod pagecodec;
+pub(crate) use pagecodec::*;
+
+/// `Pte::new` draws its nonce from the PDDB's entropy pool. This tool only ever decodes
+/// page table entries, so it never needs one.
+pub(crate) struct TrngPool {}
+impl TrngPool {
+    pub fn get_u32(&mut self) -> u32 {
+        unreachable!("pddb-image does not create page table entries")
+    }
+}
@@ -0,0 +1,404 @@
+use crate::api::*;
+use crate::backend::*;
+
+use aes::cipher::{generic_array::GenericArray, NewBlockCipher};
+use aes::{Aes256, BLOCK_SIZE};
+use aes_gcm_siv::aead::NewAead;
+use aes_gcm_siv::{Aes256GcmSiv, Key};
+use core::mem::size_of;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+/// One record of the key export written by the hosted PDDB (see `dump_keys` in
+/// `services/pddb/src/backend/hosted.rs`).
+pub struct BasisKeys {
+    pub name: String,
+    /// data key
+    pub data: [u8; 32],
+    /// page table key
+    pub pt: [u8; 32],
+}
+
+/// Reads a key export: a u32 count, followed by that many records of a NUL-padded
+/// 64-byte basis name, the 32-byte data key, and the 32-byte page table key.
+pub fn read_keyfile<P: AsRef<Path>>(path: P) -> Result<Vec<BasisKeys>> {
+    const RECORD_LEN: usize = BASIS_NAME_LEN + 32 + 32;
+    let mut raw = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut raw)?;
+    if raw.len() < 4 {
+        return Err(Error::new(ErrorKind::InvalidData, "key file is too short"));
+    }
+    let count = u32::from_le_bytes(raw[..4].try_into().unwrap()) as usize;
+    if raw.len() < 4 + count * RECORD_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "key file is truncated"));
+    }
+    let mut keys = Vec::new();
+    for record in raw[4..4 + count * RECORD_LEN].chunks(RECORD_LEN) {
+        let name_bytes = &record[..BASIS_NAME_LEN];
+        let name_len = name_bytes
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(BASIS_NAME_LEN);
+        let name = std::str::from_utf8(&name_bytes[..name_len]).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "basis name in key file is not valid utf-8",
+            )
+        })?;
+        keys.push(BasisKeys {
+            name: name.to_string(),
+            data: record[BASIS_NAME_LEN..BASIS_NAME_LEN + 32]
+                .try_into()
+                .unwrap(),
+            pt: record[BASIS_NAME_LEN + 32..].try_into().unwrap(),
+        });
+    }
+    Ok(keys)
+}
+
+/// A raw PDDB image, as dumped by a hosted run or read out of the PDDB region of FLASH.
+/// The region offsets are derived from the image length the same way `PddbOs::new()`
+/// derives them from `PDDB_A_LEN`.
+pub struct PddbImage {
+    raw: Vec<u8>,
+    dna: u64,
+    pt_len: usize,
+    mbbb_phys_base: usize,
+    data_phys_base: usize,
+}
+
+impl PddbImage {
+    pub fn open<P: AsRef<Path>>(path: P, dna: u64) -> Result<PddbImage> {
+        let mut raw = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut raw)?;
+        if raw.len() % PAGE_SIZE != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "image is not a whole number of pages",
+            ));
+        }
+        let pt_len = PageAlignedPa::from((raw.len() / PAGE_SIZE) * size_of::<Pte>()).as_usize();
+        let key_phys_base = pt_len;
+        let mbbb_phys_base = key_phys_base + PAGE_SIZE;
+        let fscb_phys_base = mbbb_phys_base + MBBB_PAGES * PAGE_SIZE;
+        let data_phys_base = fscb_phys_base + FSCB_PAGES * PAGE_SIZE;
+        if raw.len() <= data_phys_base {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "image is too small to hold a PDDB",
+            ));
+        }
+        Ok(PddbImage {
+            raw,
+            dna,
+            pt_len,
+            mbbb_phys_base,
+            data_phys_base,
+        })
+    }
+    pub fn data_pages(&self) -> usize {
+        (self.raw.len() - self.data_phys_base) / PAGE_SIZE
+    }
+    pub fn data_aad(&self, name: &str) -> Vec<u8> {
+        data_aad(name, self.dna)
+    }
+    fn data_page(&self, page: &PhysPage) -> Option<&[u8]> {
+        let start = self.data_phys_base + page.page_number() as usize * PAGE_SIZE;
+        self.raw.get(start..start + PAGE_SIZE)
+    }
+    fn mbbb_retrieve(&self) -> Option<&[u8]> {
+        let blank = [0xffu8; BLOCK_SIZE];
+        self.raw[self.mbbb_phys_base..self.mbbb_phys_base + MBBB_PAGES * PAGE_SIZE]
+            .chunks(PAGE_SIZE)
+            .find(|page| page[..BLOCK_SIZE] != blank)
+    }
+
+    /// Same scan as `PddbOs::pt_scan_key()`, minus the FSCB journal bookkeeping, which only
+    /// matters to a PDDB that is going to write.
+    pub fn pt_scan_key(&self, keys: &BasisKeys) -> HashMap<VirtAddr, PhysPage> {
+        let cipher_ecb = Aes256::new(GenericArray::from_slice(&keys.pt));
+        let cipher = Aes256GcmSiv::new(Key::from_slice(&keys.data));
+        let aad = self.data_aad(&keys.name);
+        pt_scan(
+            &self.raw[..self.pt_len],
+            self.mbbb_retrieve(),
+            &cipher_ecb,
+            |pp| self.data_decrypt_page(&cipher, &aad, pp),
+            |_| (),
+        )
+    }
+
+    /// Returns the decrypted page, journal number included, or None if it doesn't authenticate.
+    pub fn data_decrypt_page(
+        &self,
+        cipher: &Aes256GcmSiv,
+        aad: &[u8],
+        page: &PhysPage,
+    ) -> Option<Vec<u8>> {
+        decrypt_page(cipher, aad, self.data_page(page)?)
+    }
+
+    /// Decrypts a page stored with a key commitment.
+    pub fn data_decrypt_page_with_commit(
+        &self,
+        key: &[u8; 32],
+        aad: &[u8],
+        page: &PhysPage,
+    ) -> Option<Vec<u8>> {
+        decrypt_page_with_commit(key, aad, self.data_page(page)?)
+    }
+
+    /// Finds and checks the root record of a basis. Dictionaries are not read until
+    /// `ImageBasis::scan()` is called.
+    pub fn mount(&self, keys: &BasisKeys) -> Result<ImageBasis> {
+        let v2p_map = self.pt_scan_key(keys);
+        if v2p_map.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "no page table entries decrypt with this key",
+            ));
+        }
+        let aad = self.data_aad(&keys.name);
+        let root_page = v2p_map
+            .get(&VirtAddr::new(VPAGE_SIZE as u64).unwrap())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "basis has no root page"))?;
+        let vpage = self
+            .data_decrypt_page_with_commit(&keys.data, &aad, root_page)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "basis root did not authenticate"))?;
+        let basis_root = basis_root_decode(&vpage, &keys.name)?;
+        Ok(ImageBasis {
+            name: keys.name.clone(),
+            age: basis_root.age,
+            num_dicts: basis_root.num_dictionaries,
+            journal: journal_of(&vpage),
+            v2p_map,
+            cipher: Aes256GcmSiv::new(Key::from_slice(&keys.data)),
+            aad,
+            dicts: Vec::new(),
+        })
+    }
+}
+
+pub struct ImageKey {
+    pub name: String,
+    pub start: u64,
+    pub len: u64,
+    pub reserved: u64,
+    pub flags: KeyFlags,
+    pub age: u32,
+    pub descriptor_index: usize,
+}
+
+pub struct ImageDict {
+    pub index: usize,
+    pub name: String,
+    pub age: u32,
+    pub num_keys: u32,
+    pub free_key_index: u32,
+    pub keys: Vec<ImageKey>,
+}
+
+pub struct ImageBasis {
+    pub name: String,
+    pub age: u32,
+    pub num_dicts: u32,
+    pub journal: JournalType,
+    pub v2p_map: HashMap<VirtAddr, PhysPage>,
+    cipher: Aes256GcmSiv,
+    aad: Vec<u8>,
+    pub dicts: Vec<ImageDict>,
+}
+
+impl ImageBasis {
+    fn vpage(&self, img: &PddbImage, va: u64) -> Option<Vec<u8>> {
+        let pp = self.v2p_map.get(&VirtAddr::new(va)?)?;
+        img.data_decrypt_page(&self.cipher, &self.aad, pp)
+    }
+
+    /// Walks the dictionary index and every dictionary's key descriptors, in the same order the
+    /// PDDB does on a full cache fill. Anything that does not add up is returned as a finding.
+    pub fn scan(&mut self, img: &PddbImage) -> Vec<String> {
+        let mut findings = Vec::new();
+        self.dicts.clear();
+        let mut try_entry = 1;
+        while try_entry <= DICT_MAXCOUNT && self.dicts.len() < self.num_dicts as usize {
+            if let Some(data) = self.vpage(img, try_entry as u64 * DICT_VSIZE) {
+                let dict = dict_decode(&data);
+                if dict.flags.valid() {
+                    match std::str::from_utf8(&dict.name.data[..dict.name.len as usize]) {
+                        Ok(name) => {
+                            let mut idict = ImageDict {
+                                index: try_entry,
+                                name: name.to_string(),
+                                age: dict.age,
+                                num_keys: dict.num_keys,
+                                free_key_index: dict.free_key_index,
+                                keys: Vec::new(),
+                            };
+                            self.scan_keys(img, &mut idict, &mut findings);
+                            self.dicts.push(idict);
+                        }
+                        Err(_) => findings.push(format!(
+                            "dict at index {}: name is not valid utf-8",
+                            try_entry
+                        )),
+                    }
+                }
+            }
+            try_entry += 1;
+        }
+        if self.dicts.len() != self.num_dicts as usize {
+            findings.push(format!(
+                "basis root records {} dicts, found {}",
+                self.num_dicts,
+                self.dicts.len()
+            ));
+        }
+        let mut names = HashSet::new();
+        for dict in self.dicts.iter() {
+            if !names.insert(dict.name.as_str()) {
+                findings.push(format!("dict {} appears more than once", dict.name));
+            }
+        }
+        findings
+    }
+
+    fn scan_keys(&self, img: &PddbImage, dict: &mut ImageDict, findings: &mut Vec<String>) {
+        let mut try_entry = 1;
+        let mut index_cache: Option<(u64, Vec<u8>)> = None;
+        while try_entry < KEY_MAXCOUNT && dict.keys.len() < dict.num_keys as usize {
+            let req_vaddr =
+                dict.index as u64 * DICT_VSIZE + ((try_entry / DK_PER_VPAGE) * VPAGE_SIZE) as u64;
+            if index_cache.as_ref().map(|(va, _)| *va) != Some(req_vaddr) {
+                index_cache = self.vpage(img, req_vaddr).map(|data| (req_vaddr, data));
+            }
+            let page = match index_cache.as_ref() {
+                Some((_, page)) => page,
+                None => {
+                    // unallocated descriptor page; the PDDB skips past these, too
+                    try_entry += DK_PER_VPAGE - try_entry % DK_PER_VPAGE;
+                    continue;
+                }
+            };
+            let keydesc = key_descriptor_decode(page, try_entry);
+            if keydesc.flags.valid() {
+                match std::str::from_utf8(&keydesc.name.data[..keydesc.name.len as usize]) {
+                    Ok(name) => dict.keys.push(ImageKey {
+                        name: name.to_string(),
+                        start: keydesc.start,
+                        len: keydesc.len,
+                        reserved: keydesc.reserved,
+                        flags: keydesc.flags,
+                        age: keydesc.age,
+                        descriptor_index: try_entry,
+                    }),
+                    Err(_) => findings.push(format!(
+                        "{}: key at index {} has a name that is not valid utf-8",
+                        dict.name, try_entry
+                    )),
+                }
+            }
+            try_entry += 1;
+        }
+        if dict.keys.len() != dict.num_keys as usize {
+            findings.push(format!(
+                "{}: header records {} keys, found {}",
+                dict.name,
+                dict.num_keys,
+                dict.keys.len()
+            ));
+        }
+    }
+
+    pub fn find_key(&self, dict: &str, key: &str) -> Option<&ImageKey> {
+        self.dicts
+            .iter()
+            .find(|d| d.name == dict)?
+            .keys
+            .iter()
+            .find(|k| k.name == key)
+    }
+
+    /// Reads the contents of a key. Small and large pool keys are read the same way:
+    /// every pool is vpage-aligned, so a key's bytes are found by splitting its virtual
+    /// address into a vpage and an offset.
+    pub fn read_key(&self, img: &PddbImage, key: &ImageKey) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(key.len as usize);
+        let mut va = key.start;
+        while va < key.start + key.len {
+            let vpage_base = (va / VPAGE_SIZE as u64) * VPAGE_SIZE as u64;
+            let offset = (va - vpage_base) as usize;
+            let chunk = ((key.start + key.len - va) as usize).min(VPAGE_SIZE - offset);
+            let page = self.vpage(img, vpage_base).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "data page at va {:x} is unmapped or does not decrypt",
+                        vpage_base
+                    ),
+                )
+            })?;
+            let start = size_of::<JournalType>() + offset;
+            data.extend_from_slice(&page[start..start + chunk]);
+            va += chunk as u64;
+        }
+        Ok(data)
+    }
+
+    /// Structural checks on the keys found by `scan()`.
+    pub fn verify(&self, img: &PddbImage) -> Vec<String> {
+        let mut findings = Vec::new();
+        let mut extents = Vec::<(u64, u64, String)>::new();
+        for dict in self.dicts.iter() {
+            let small_base = SMALL_POOL_START + (dict.index as u64 - 1) * SMALL_POOL_STRIDE;
+            let mut names = HashSet::new();
+            for key in dict.keys.iter() {
+                let path = format!("{}:{}", dict.name, key.name);
+                if !names.insert(key.name.as_str()) {
+                    findings.push(format!("{}: key appears more than once", path));
+                }
+                if key.reserved < key.len {
+                    findings.push(format!(
+                        "{}: len {} exceeds reserved {}",
+                        path, key.len, key.reserved
+                    ));
+                }
+                if key.start >= LARGE_POOL_START {
+                    if key.reserved > LARGE_FILE_MAX_SIZE {
+                        findings.push(format!(
+                            "{}: reserved {} exceeds the large file limit",
+                            path, key.reserved
+                        ));
+                    }
+                } else if key.start >= small_base
+                    && key.start + key.reserved < small_base + SMALL_POOL_STRIDE
+                {
+                    if key.reserved > VPAGE_SIZE as u64 {
+                        findings.push(format!(
+                            "{}: small pool key reserves {} bytes",
+                            path, key.reserved
+                        ));
+                    }
+                } else {
+                    findings.push(format!(
+                        "{}: start {:x} is outside of the dict's small pool and the large pool",
+                        path, key.start
+                    ));
+                }
+                if let Err(e) = self.read_key(img, key) {
+                    findings.push(format!("{}: {}", path, e));
+                }
+                extents.push((key.start, key.start + key.reserved.max(key.len), path));
+            }
+        }
+        extents.sort();
+        for pair in extents.windows(2) {
+            if pair[0].1 > pair[1].0 {
+                findings.push(format!("{} overlaps {}", pair[0].2, pair[1].2));
+            }
+        }
+        findings
+    }
+}
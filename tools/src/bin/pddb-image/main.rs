@@ -0,0 +1,227 @@
+//! Host-side reader for PDDB images: lists, extracts and checks the contents of a `pddb.bin`
+//! dumped by a hosted run, or of the PDDB region read out of a device's FLASH. Basis keys come
+//! from the matching `.key` export; on hardware the system basis key is wrapped by the root keys,
+//! so a key file has to be supplied by whatever produced the dump.
+#[allow(dead_code)]
+#[path = "../../../../services/pddb/src/api.rs"]
+mod api;
+use api::*;
+mod backend;
+use backend::*;
+mod image;
+use image::*;
+
+use clap::{crate_version, App, Arg, ArgMatches, SubCommand};
+use std::io::Write;
+
+/// Opens the image and mounts every basis in the key file. Bases that fail to mount are reported
+/// and counted, but don't stop the others from being read.
+fn open(
+    matches: &ArgMatches,
+) -> Result<(PddbImage, Vec<ImageBasis>, usize), Box<dyn std::error::Error>> {
+    let name = matches.value_of("name").unwrap_or("pddb");
+    let image_path = matches
+        .value_of("image")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("tools/pddb-images/{}.bin", name));
+    let key_path = matches
+        .value_of("keys")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| format!("tools/pddb-images/{}.key", name));
+    let dna = u64::from_str_radix(
+        matches.value_of("dna").unwrap().trim_start_matches("0x"),
+        16,
+    )?;
+
+    let img = PddbImage::open(&image_path, dna).map_err(|e| format!("{}: {}", image_path, e))?;
+    let keys = read_keyfile(&key_path).map_err(|e| format!("{}: {}", key_path, e))?;
+    let mut bases = Vec::new();
+    let mut failed = 0;
+    for key in keys.iter() {
+        if let Some(only) = matches.value_of("basis") {
+            if key.name != only {
+                continue;
+            }
+        }
+        match img.mount(key) {
+            Ok(basis) => bases.push(basis),
+            Err(e) => {
+                eprintln!("basis {}: {}", key.name, e);
+                failed += 1;
+            }
+        }
+    }
+    Ok((img, bases, failed))
+}
+
+fn list(img: &PddbImage, bases: &mut [ImageBasis]) {
+    for basis in bases.iter_mut() {
+        let findings = basis.scan(img);
+        println!(
+            "basis {} (age {}, journal {}, {} dicts, {} pages)",
+            basis.name,
+            basis.age,
+            basis.journal,
+            basis.num_dicts,
+            basis.v2p_map.len()
+        );
+        for dict in basis.dicts.iter() {
+            println!(
+                "  dict {} (index {}, age {}, {} keys)",
+                dict.name, dict.index, dict.age, dict.num_keys
+            );
+            for key in dict.keys.iter() {
+                println!(
+                    "    {} len {} reserved {} age {} start {:x}",
+                    key.name, key.len, key.reserved, key.age, key.start
+                );
+            }
+        }
+        for f in findings {
+            println!("  ! {}", f);
+        }
+    }
+}
+
+fn extract(
+    img: &PddbImage,
+    bases: &mut [ImageBasis],
+    sub: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dict = sub.value_of("dict").unwrap();
+    let key = sub.value_of("key").unwrap();
+    // later bases shadow earlier ones, the same as in a mounted PDDB
+    for basis in bases.iter_mut().rev() {
+        basis.scan(img);
+        if let Some(k) = basis.find_key(dict, key) {
+            let data = basis.read_key(img, k)?;
+            match sub.value_of("out") {
+                Some(path) => std::fs::write(path, &data)?,
+                None => std::io::stdout().write_all(&data)?,
+            }
+            return Ok(());
+        }
+    }
+    Err(format!("{}:{} not found", dict, key).into())
+}
+
+fn verify(img: &PddbImage, bases: &mut [ImageBasis]) -> usize {
+    let mut errors = 0;
+    for basis in bases.iter_mut() {
+        let mut findings = basis.scan(img);
+        findings.extend(basis.verify(img));
+        for f in findings.iter() {
+            println!("{}: {}", basis.name, f);
+        }
+        if findings.is_empty() {
+            println!(
+                "{}: {} dicts, {} keys OK",
+                basis.name,
+                basis.dicts.len(),
+                basis.dicts.iter().map(|d| d.keys.len()).sum::<usize>()
+            );
+        }
+        errors += findings.len();
+    }
+    // a physical page belongs to exactly one basis
+    let mut owner = std::collections::HashMap::<PhysAddr, &str>::new();
+    for basis in bases.iter() {
+        for pp in basis.v2p_map.values() {
+            if let Some(other) = owner.insert(pp.page_number(), basis.name.as_str()) {
+                println!(
+                    "page {:x} is mapped by both {} and {}",
+                    pp.page_number(),
+                    other,
+                    basis.name
+                );
+                errors += 1;
+            }
+        }
+    }
+    println!("{} data pages, {} in use", img.data_pages(), owner.len());
+    errors
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = App::new("pddb-image")
+        .version(crate_version!())
+        .about("Inspect PDDB disk images")
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .takes_value(true)
+                .value_name("name")
+                .help("image root name; reads tools/pddb-images/<name>.bin and <name>.key"),
+        )
+        .arg(
+            Arg::with_name("image")
+                .long("image")
+                .takes_value(true)
+                .value_name("image")
+                .help("PDDB image, overrides --name"),
+        )
+        .arg(
+            Arg::with_name("keys")
+                .long("keys")
+                .takes_value(true)
+                .value_name("keys")
+                .help("basis key export, overrides --name"),
+        )
+        .arg(
+            Arg::with_name("basis")
+                .long("basis")
+                .takes_value(true)
+                .value_name("basis")
+                .help("only open this basis"),
+        )
+        .arg(
+            Arg::with_name("dna")
+                .long("dna")
+                .takes_value(true)
+                .value_name("dna")
+                .default_value("0")
+                .help("SoC DNA of the device the image came from, in hex; hosted images use 0"),
+        )
+        .subcommand(SubCommand::with_name("list").about("list bases, dictionaries and keys"))
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("write out the contents of a key")
+                .arg(
+                    Arg::with_name("dict")
+                        .long("dict")
+                        .takes_value(true)
+                        .required(true)
+                        .help("dictionary name"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .takes_value(true)
+                        .required(true)
+                        .help("key name"),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .help("output file; stdout if omitted"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("check the structure of every basis; exits nonzero on findings"),
+        )
+        .get_matches();
+
+    let (img, mut bases, failed) = open(&matches)?;
+    match matches.subcommand() {
+        ("extract", Some(sub)) => extract(&img, &mut bases, sub)?,
+        ("verify", _) => {
+            if verify(&img, &mut bases) + failed != 0 {
+                std::process::exit(1);
+            }
+        }
+        _ => list(&img, &mut bases),
+    }
+    Ok(())
+}
@@ -45,6 +45,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "shellchat",
         "ime-frontend",
         "ime-plugin-shell",
+        "ime-plugin-dict",
+        "ime-plugin-cjk",
         "graphics-server",
         "ticktimer-server",
         "log-server",
@@ -55,6 +57,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "llio",
         "susres",
         "codec",
+        "audio-player",
         "sha2:0.9.8",
         "engine-25519",
         "spinor",
@@ -62,6 +65,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "jtag",
         "net",
         "dns",
+        "mdns",
         "pddb",
         "modals",
     ];